@@ -9,13 +9,17 @@
 use anyhow::Result;
 use app_da_node::{api::create_router, AppNode, AppNodeConfig};
 use celestia_adapter::Namespace;
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
+use merkle::HashFunction;
+use sdk::finance::FinanceApp;
 use serde::{Deserialize, Serialize};
-use state::StateOp;
+use state::{StateExport, StateOp};
 use std::net::SocketAddr;
 use std::path::PathBuf;
+use std::time::Duration;
 use tracing::{info, Level};
 use tracing_subscriber::FmtSubscriber;
+use transition_format::{finance::parse_public_key, SetPolicy};
 
 #[derive(Parser)]
 #[command(name = "appd")]
@@ -45,6 +49,66 @@ struct Cli {
     #[arg(long)]
     no_proving: bool,
 
+    /// With --no-proving, prove posted transitions in the background and
+    /// post their proofs as attachment blobs
+    #[arg(long)]
+    deferred_proving: bool,
+
+    /// Maximum number of transitions proven in parallel
+    #[arg(long, default_value = "4")]
+    max_in_flight: usize,
+
+    /// Longest time a mempool batch waits for more transactions (ms)
+    #[arg(long, default_value = "1000")]
+    batch_interval_ms: u64,
+
+    /// Maximum number of transactions per mempool batch
+    #[arg(long, default_value = "100")]
+    max_batch_size: usize,
+
+    /// Application executing typed transactions (POST /app/transaction)
+    #[arg(long, value_enum)]
+    app: Option<HostedApp>,
+
+    /// Refuse ready-made operations over the API (/transition and /tx);
+    /// the default with --app
+    #[arg(long, conflicts_with = "raw_transitions")]
+    no_raw_transitions: bool,
+
+    /// Accept ready-made operations over the API even with --app
+    #[arg(long)]
+    raw_transitions: bool,
+
+    /// Refuse writes not declared by an operation with business rules (no
+    /// bare Set operations); the default with --app
+    #[arg(long, conflicts_with = "allow_set")]
+    deny_set: bool,
+
+    /// Declare writes no operation covers as bare Set operations even with
+    /// --app
+    #[arg(long)]
+    allow_set: bool,
+
+    /// ed25519 public key (hex) allowed to sign mints; without one every
+    /// mint is refused
+    #[arg(long, value_parser = parse_public_key)]
+    mint_authority: Option<[u8; 32]>,
+
+    /// State tree hash function: sha256, blake3 or poseidon (fixed once the state exists)
+    #[arg(long, default_value = "sha256")]
+    hash_function: HashFunction,
+
+    /// Keep the state history (past sequences to query, diff, revert to or
+    /// reprove) for this many transitions and prune older versions; keeps
+    /// all history if unset
+    #[arg(long)]
+    history_window: Option<u64>,
+
+    /// Check every stored value against the committed state tree on startup
+    /// and repair the ones that do not match
+    #[arg(long)]
+    repair_state: bool,
+
     /// Log level
     #[arg(long, default_value = "info")]
     log_level: String,
@@ -84,10 +148,52 @@ enum Commands {
         /// Value to set
         value: String,
     },
+    /// Roll the state back to an earlier sequence
+    Revert {
+        /// Sequence to revert to
+        #[arg(long)]
+        to: u64,
+    },
+    /// Prove journaled transitions again and post the proofs
+    Reprove {
+        /// First sequence to reprove (up to the latest)
+        #[arg(long)]
+        from: u64,
+    },
+    /// Check every stored value against the committed state tree
+    Verify,
+    /// Export or import a state snapshot
+    Snapshot {
+        #[command(subcommand)]
+        command: SnapshotCommand,
+    },
     /// Demo: run example finance operations
     Demo,
 }
 
+#[derive(Subcommand)]
+enum SnapshotCommand {
+    /// Write the committed state to a snapshot file
+    Export {
+        /// Snapshot file to write
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Bootstrap an empty data directory from a snapshot file
+    Import {
+        /// Snapshot file to read
+        #[arg(long)]
+        file: PathBuf,
+    },
+}
+
+/// Applications the node can host.
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum HostedApp {
+    /// Accounts with balances and transfers (sdk::finance)
+    Finance,
+}
+
 #[derive(Serialize, Deserialize)]
 struct OperationsFile {
     operations: Vec<Operation>,
@@ -141,11 +247,29 @@ async fn main() -> Result<()> {
         celestia_rpc: cli.celestia_rpc,
         celestia_enabled: !cli.no_celestia,
         proving_enabled: !cli.no_proving,
+        hash_function: cli.hash_function,
+        repair_state: cli.repair_state,
+        deferred_proving: cli.deferred_proving,
+        max_in_flight: cli.max_in_flight,
+        batch_interval: Duration::from_millis(cli.batch_interval_ms),
+        max_batch_size: cli.max_batch_size,
+        history_window: cli.history_window,
+        raw_transitions: match (cli.raw_transitions, cli.no_raw_transitions) {
+            (true, _) => Some(true),
+            (_, true) => Some(false),
+            _ => None,
+        },
+        set_policy: match (cli.allow_set, cli.deny_set) {
+            (true, _) => Some(SetPolicy::Allow),
+            (_, true) => Some(SetPolicy::Deny),
+            _ => None,
+        },
+        mint_authority: cli.mint_authority,
     };
 
     match cli.command {
         Commands::Serve { bind } => {
-            run_server(config, bind).await?;
+            run_server(config, bind, cli.app).await?;
         }
         Commands::Apply { ops_file, ops } => {
             apply_transition(config, ops_file, ops).await?;
@@ -159,6 +283,23 @@ async fn main() -> Result<()> {
         Commands::Set { key, value } => {
             set_value(config, &key, &value).await?;
         }
+        Commands::Revert { to } => {
+            revert_state(config, to).await?;
+        }
+        Commands::Reprove { from } => {
+            reprove(config, from).await?;
+        }
+        Commands::Verify => {
+            verify_state(config).await?;
+        }
+        Commands::Snapshot { command } => match command {
+            SnapshotCommand::Export { out } => {
+                export_snapshot(config, out).await?;
+            }
+            SnapshotCommand::Import { file } => {
+                import_snapshot(config, file).await?;
+            }
+        },
         Commands::Demo => {
             run_demo(config).await?;
         }
@@ -167,10 +308,17 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
-async fn run_server(config: AppNodeConfig, bind: SocketAddr) -> Result<()> {
+async fn run_server(config: AppNodeConfig, bind: SocketAddr, app: Option<HostedApp>) -> Result<()> {
     info!("Starting app node with config: {:?}", config);
 
+    let app_id = config.app_id.clone();
     let node = AppNode::new(config).await?;
+    if let Some(app) = app {
+        match app {
+            HostedApp::Finance => node.set_application(FinanceApp::new(app_id)).await,
+        }
+        info!("Hosting application: {:?}", app);
+    }
     let state = node.state();
 
     info!("Current root: {}", hex::encode(node.root().await));
@@ -179,7 +327,14 @@ async fn run_server(config: AppNodeConfig, bind: SocketAddr) -> Result<()> {
     let router = create_router(state);
     let listener = tokio::net::TcpListener::bind(bind).await?;
 
-    axum::serve(listener, router).await?;
+    // Stop on Ctrl-C so the state store is dropped and records a clean
+    // shutdown (otherwise the next start recomputes the root from all values)
+    axum::serve(listener, router)
+        .with_graceful_shutdown(async {
+            tokio::signal::ctrl_c().await.ok();
+        })
+        .await?;
+    info!("Shutting down");
 
     Ok(())
 }
@@ -189,6 +344,7 @@ async fn apply_transition(
     ops_file: Option<PathBuf>,
     ops_json: Option<String>,
 ) -> Result<()> {
+    let posting = config.celestia_enabled;
     let node = AppNode::new(config).await?;
 
     let ops_data: OperationsFile = if let Some(file) = ops_file {
@@ -216,8 +372,23 @@ async fn apply_transition(
     println!("  Sequence: {}", result.sequence);
     println!("  Prev root: {}", hex::encode(result.prev_root));
     println!("  New root: {}", hex::encode(result.new_root));
-    if let Some(height) = result.celestia_height {
-        println!("  Celestia height: {}", height);
+
+    // The blob was only queued; post it before exiting
+    if posting {
+        let queued = node.post_queued().await?;
+        let height = node
+            .root_history()
+            .await?
+            .into_iter()
+            .find(|(sequence, _, _)| *sequence == result.sequence)
+            .and_then(|(_, _, height)| height);
+        match height {
+            Some(height) => println!("  Celestia height: {}", height),
+            None => println!(
+                "  Not posted yet, {} blob(s) queued for the next start",
+                queued
+            ),
+        }
     }
 
     Ok(())
@@ -230,7 +401,7 @@ async fn show_status(config: AppNodeConfig) -> Result<()> {
     println!("  Root: {}", hex::encode(node.root().await));
     println!("  Transition index: {}", node.transition_index().await);
 
-    let history = node.root_history().await;
+    let history = node.root_history().await?;
     println!("  Root history ({} entries):", history.len());
     for (seq, root, height) in history.iter().take(10) {
         print!("    {}: {}", seq, hex::encode(root));
@@ -240,6 +411,18 @@ async fn show_status(config: AppNodeConfig) -> Result<()> {
         println!();
     }
 
+    let outbox = node.outbox().await?;
+    if !outbox.is_empty() {
+        println!("  Queued blobs ({} entries):", outbox.len());
+        for entry in &outbox {
+            print!("    {}: {} attempts", entry.sequence, entry.attempts);
+            if let Some(error) = &entry.last_error {
+                print!(" (last error: {})", error);
+            }
+            println!();
+        }
+    }
+
     Ok(())
 }
 
@@ -282,6 +465,88 @@ async fn set_value(config: AppNodeConfig, key: &str, value: &str) -> Result<()>
     Ok(())
 }
 
+async fn revert_state(config: AppNodeConfig, sequence: u64) -> Result<()> {
+    let node = AppNode::new(config).await?;
+    let from = node.transition_index().await;
+
+    let root = node.revert_to(sequence).await?;
+
+    println!("State reverted:");
+    println!("  From sequence: {}", from);
+    println!("  To sequence: {}", sequence);
+    println!("  Root: {}", hex::encode(root));
+
+    Ok(())
+}
+
+async fn reprove(config: AppNodeConfig, from: u64) -> Result<()> {
+    let node = AppNode::new(config).await?;
+
+    let results = node.reprove(from).await?;
+
+    println!("Reproved {} transitions:", results.len());
+    for result in results {
+        print!(
+            "  {}: {} ({} proof bytes)",
+            result.sequence,
+            hex::encode(result.new_root),
+            result.proof_bytes.len()
+        );
+        match (result.celestia_height, result.attached) {
+            (Some(height), true) => print!(" (attached at celestia: {})", height),
+            (Some(height), false) => print!(" (celestia: {})", height),
+            (None, _) => {}
+        }
+        println!();
+    }
+
+    Ok(())
+}
+
+async fn verify_state(config: AppNodeConfig) -> Result<()> {
+    let node = AppNode::new(config).await?;
+
+    let root = node.verify_state().await?;
+
+    println!("State verified:");
+    println!("  Sequence: {}", node.transition_index().await);
+    println!("  Root: {}", hex::encode(root));
+
+    Ok(())
+}
+
+async fn export_snapshot(config: AppNodeConfig, out: PathBuf) -> Result<()> {
+    let node = AppNode::new(config).await?;
+
+    let export = node.export_snapshot().await?;
+    export.write_to(&out)?;
+
+    println!("Snapshot exported:");
+    println!("  File: {}", out.display());
+    println!("  Sequence: {}", export.transition_index);
+    println!("  Root: {}", hex::encode(export.root));
+    println!("  Entries: {}", export.entries.len());
+
+    Ok(())
+}
+
+async fn import_snapshot(mut config: AppNodeConfig, file: PathBuf) -> Result<()> {
+    let export = StateExport::read_from(&file)?;
+
+    // The store takes the snapshot's hash function
+    config.hash_function = export.hash_function;
+    let node = AppNode::new(config).await?;
+    let root = node.import_snapshot(&export).await?;
+
+    println!("Snapshot imported:");
+    println!("  File: {}", file.display());
+    println!("  Sequence: {}", export.transition_index);
+    println!("  Root: {}", hex::encode(root));
+    println!("  Entries: {}", export.entries.len());
+
+    Ok(())
+}
+
 async fn run_demo(config: AppNodeConfig) -> Result<()> {
     info!("Running demo with finance operations...");
 
@@ -373,7 +638,7 @@ async fn run_demo(config: AppNodeConfig) -> Result<()> {
 
     // Show history
     println!("\n=== Transition History ===");
-    for (seq, root, height) in node.root_history().await {
+    for (seq, root, height) in node.root_history().await? {
         print!("Sequence {}: {}", seq, hex::encode(root));
         if let Some(h) = height {
             print!(" (celestia: {})", h);
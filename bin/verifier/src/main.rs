@@ -5,10 +5,12 @@
 
 use anyhow::Result;
 use celestia_adapter::Namespace;
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
+use merkle::HashFunction;
 use tracing::{info, Level};
 use tracing_subscriber::FmtSubscriber;
-use verifier_lib::{ChainVerifier, VerifyConfig};
+use transition_format::finance::parse_public_key;
+use verifier_lib::{ChainVerifier, SetPolicy, VerifyConfig};
 
 #[derive(Parser)]
 #[command(name = "verifier")]
@@ -46,6 +48,25 @@ enum Commands {
         /// Expected first root (hex)
         #[arg(long)]
         expected_root: Option<String>,
+        /// Expected state tree hash function (sha256, blake3 or poseidon)
+        #[arg(long)]
+        hash_function: Option<HashFunction>,
+        /// Require proofs made under a policy refusing bare Set operations
+        /// (required with --app finance)
+        #[arg(long, conflicts_with = "allow_set", required_if_eq("app", "finance"))]
+        deny_set: bool,
+        /// Accept proofs made under a policy allowing bare Set operations
+        /// (refused by default)
+        #[arg(long)]
+        allow_set: bool,
+        /// Require mints checked against this ed25519 public key (hex)
+        /// (required with --app finance)
+        #[arg(long, value_parser = parse_public_key, required_if_eq("app", "finance"))]
+        mint_authority: Option<[u8; 32]>,
+        /// Application the chain belongs to (finance requires --deny-set
+        /// and --mint-authority)
+        #[arg(long, value_enum)]
+        app: Option<App>,
     },
     /// Check if Celestia node is ready
     Status,
@@ -53,6 +74,13 @@ enum Commands {
     Head,
 }
 
+/// Applications a chain can belong to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum App {
+    /// Accounts with balances and transfers (sdk::finance)
+    Finance,
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
@@ -70,7 +98,7 @@ async fn main() -> Result<()> {
     let subscriber = FmtSubscriber::builder().with_max_level(level).finish();
     tracing::subscriber::set_global_default(subscriber)?;
 
-    let config = VerifyConfig {
+    let mut config = VerifyConfig {
         celestia_rpc: cli.celestia_rpc,
         namespace: Namespace::from_string(&cli.namespace),
         ..Default::default()
@@ -82,8 +110,17 @@ async fn main() -> Result<()> {
             to,
             skip_proofs,
             expected_root,
+            hash_function,
+            deny_set,
+            allow_set,
+            mint_authority,
+            app,
         } => {
-            verify_range(config, from, to, skip_proofs, expected_root).await?;
+            config.expected_set_policy = deny_set.then_some(SetPolicy::Deny);
+            config.allow_set = allow_set;
+            config.expected_mint_authority = mint_authority;
+            config.finance_app = app == Some(App::Finance);
+            verify_range(config, from, to, skip_proofs, expected_root, hash_function).await?;
         }
         Commands::Status => {
             check_status(config).await?;
@@ -102,8 +139,10 @@ async fn verify_range(
     to: u64,
     skip_proofs: bool,
     expected_root: Option<String>,
+    hash_function: Option<HashFunction>,
 ) -> Result<()> {
     config.skip_proof_verification = skip_proofs;
+    config.expected_hash_function = hash_function;
 
     if let Some(root_hex) = expected_root {
         let root_bytes = hex::decode(&root_hex)?;
@@ -139,6 +178,40 @@ async fn verify_range(
             println!("First root: {}", hex::encode(result.first_root));
             println!("Latest root: {}", hex::encode(result.latest_root));
 
+            if !result.attached_proofs.is_empty() {
+                println!(
+                    "Proofs attached later: {} transitions",
+                    result.attached_proofs.len()
+                );
+            }
+
+            if !result.proof_terms.is_empty() {
+                let allowed = result
+                    .proof_terms
+                    .iter()
+                    .filter(|terms| terms.set_policy == SetPolicy::Allow)
+                    .count();
+                println!(
+                    "Set policy: {} proofs under deny, {} under allow",
+                    result.proof_terms.len() - allowed,
+                    allowed
+                );
+
+                let mut authorities: Vec<_> = result
+                    .proof_terms
+                    .iter()
+                    .map(|terms| terms.mint_authority)
+                    .collect();
+                authorities.sort();
+                authorities.dedup();
+                for authority in authorities {
+                    match authority {
+                        Some(key) => println!("Mint authority: {}", hex::encode(key)),
+                        None => println!("Mint authority: none (mints refused)"),
+                    }
+                }
+            }
+
             if !result.unverified_transitions.is_empty() {
                 println!(
                     "\nWarning: {} transitions had no proof (not verified):",
@@ -1,17 +1,21 @@
 //! HTTP API for the app node.
 
+use crate::application;
+use crate::mempool::{InclusionReceipt, Transaction};
 use crate::node::AppNodeState;
+use crate::sequencer::{SequencerError, TransitionRequest};
 use axum::{
     extract::{Query, State},
     http::StatusCode,
+    response::{IntoResponse, Response},
     routing::{get, post},
     Json, Router,
 };
 use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
-use blob_schema::TransitionBlobV1;
-use merkle::MerkleProof;
+use blob_schema::{TransitionBlob, PROOF_ATTACHMENT_VERSION};
+use merkle::{Hash32, HashFunction, MerkleProof, MultiProof};
 use serde::{Deserialize, Serialize};
-use state::StateOp;
+use state::{StateError, StateOp};
 use std::sync::Arc;
 use tokio::sync::RwLock;
 use tower_http::cors::{Any, CorsLayer};
@@ -33,11 +37,15 @@ pub fn create_router(state: Arc<RwLock<AppNodeState>>) -> Router {
         .route("/root/latest", get(get_latest_root))
         .route("/value", get(get_value))
         .route("/proof/merkle", get(get_merkle_proof))
+        .route("/proof/multi", post(get_multi_proof))
+        .route("/diff", get(get_diff))
         .route("/sync/status", get(get_sync_status))
         .route("/history", get(get_history))
         .route("/celestia/transition", get(get_celestia_transition))
         .route("/celestia/transitions", get(get_celestia_transitions))
         .route("/transition", post(apply_transition))
+        .route("/tx", post(submit_transaction))
+        .route("/app/transaction", post(execute_transaction))
         .layer(cors)
         .layer(TraceLayer::new_for_http())
         .with_state(state)
@@ -56,6 +64,7 @@ struct RootResponse {
     root: String,
     transition_index: u64,
     celestia_height: Option<u64>,
+    hash_function: HashFunction,
 }
 
 #[derive(Serialize)]
@@ -63,32 +72,131 @@ struct ValueResponse {
     key: String,
     value: Option<String>,
     root: String,
+    sequence: u64,
+    proof: MerkleProofResponse,
+}
+
+#[derive(Serialize)]
+struct ProofResponse {
+    #[serde(flatten)]
     proof: MerkleProofResponse,
+    root: String,
+    sequence: u64,
 }
 
 #[derive(Serialize)]
 struct MerkleProofResponse {
     key_hash: String,
     value: Option<String>,
+    /// Hex bitmap of non-empty siblings, present when `siblings` is compressed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sibling_bitmap: Option<String>,
     siblings: Vec<String>,
+    hash_function: HashFunction,
 }
 
-impl From<MerkleProof> for MerkleProofResponse {
-    fn from(proof: MerkleProof) -> Self {
+impl MerkleProofResponse {
+    /// Build a response, eliding empty siblings if `compressed` is set.
+    fn from_proof(proof: MerkleProof, compressed: bool) -> Self {
+        if !compressed {
+            return Self {
+                key_hash: hex::encode(proof.key),
+                value: proof.value.map(|v| BASE64.encode(&v)),
+                sibling_bitmap: None,
+                siblings: proof.siblings.iter().map(hex::encode).collect(),
+                hash_function: proof.hash_function,
+            };
+        }
+
+        let proof = proof.compress();
         Self {
             key_hash: hex::encode(proof.key),
             value: proof.value.map(|v| BASE64.encode(&v)),
-            siblings: proof.siblings.iter().map(hex::encode).collect(),
+            sibling_bitmap: Some(hex::encode(proof.siblings.bitmap)),
+            siblings: proof.siblings.hashes.iter().map(hex::encode).collect(),
+            hash_function: proof.hash_function,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct MultiProofResponse {
+    root: String,
+    entries: Vec<MultiProofEntry>,
+    siblings: Vec<Option<String>>,
+    hash_function: HashFunction,
+}
+
+#[derive(Serialize)]
+struct MultiProofEntry {
+    key_hash: String,
+    value: Option<String>,
+}
+
+impl MultiProofResponse {
+    fn from_proof(root: &[u8; 32], proof: MultiProof) -> Self {
+        Self {
+            root: hex::encode(root),
+            entries: proof
+                .keys
+                .iter()
+                .zip(proof.values)
+                .map(|(key, value)| MultiProofEntry {
+                    key_hash: hex::encode(key),
+                    value: value.map(|v| BASE64.encode(&v)),
+                })
+                .collect(),
+            siblings: proof
+                .siblings
+                .iter()
+                .map(|s| s.as_ref().map(hex::encode))
+                .collect(),
+            hash_function: proof.hash_function,
         }
     }
 }
 
+#[derive(Serialize)]
+struct DiffResponse {
+    from: u64,
+    to: u64,
+    from_root: String,
+    to_root: String,
+    changes: Vec<DiffEntry>,
+    next: Option<String>, // Key hash to pass as `after` for more changes
+}
+
+#[derive(Serialize)]
+struct DiffEntry {
+    key_hash: String,
+    key: Option<String>, // Base64 encoded, if known
+    old_value: Option<String>,
+    new_value: Option<String>,
+    old_proof: MerkleProofResponse,
+    new_proof: MerkleProofResponse,
+}
+
 #[derive(Serialize)]
 struct SyncStatusResponse {
     transition_index: u64,
     latest_root: String,
     celestia_enabled: bool,
     last_celestia_height: Option<u64>,
+    outbox: OutboxStatus,
+}
+
+#[derive(Serialize)]
+struct OutboxStatus {
+    pending: usize,
+    failed: usize,
+    submissions: Vec<PendingSubmission>,
+}
+
+#[derive(Serialize)]
+struct PendingSubmission {
+    sequence: u64,
+    attempts: u32,
+    last_error: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -108,6 +216,21 @@ struct ErrorResponse {
     error: String,
 }
 
+#[derive(Serialize)]
+struct RollbackErrorResponse {
+    error: String,
+    rolled_back: RolledBackTransition,
+}
+
+/// A transition discarded before commit; the state stays at `root`.
+#[derive(Serialize)]
+struct RolledBackTransition {
+    sequence: u64,
+    root: String,
+    discarded_root: String,
+    operations: usize,
+}
+
 #[derive(Serialize)]
 struct TransitionResponse {
     sequence: u64,
@@ -117,11 +240,12 @@ struct TransitionResponse {
     proof: String,
     proof_size_bytes: usize,
     program_hash: String,
+    hash_function: HashFunction,
     celestia_height: u64,
 }
 
 impl TransitionResponse {
-    fn from_blob(blob: &TransitionBlobV1, height: u64) -> Self {
+    fn from_blob(blob: &TransitionBlob, height: u64) -> Self {
         Self {
             sequence: blob.sequence,
             prev_root: hex::encode(blob.prev_root),
@@ -130,6 +254,7 @@ impl TransitionResponse {
             proof: BASE64.encode(&blob.proof),
             proof_size_bytes: blob.proof.len(),
             program_hash: hex::encode(blob.program_hash),
+            hash_function: blob.hash_function,
             celestia_height: height,
         }
     }
@@ -176,6 +301,49 @@ struct ApplyTransitionResponse {
     proof_size_bytes: usize,
 }
 
+#[derive(Deserialize)]
+struct AppTransactionRequest {
+    input: serde_json::Value,
+    #[serde(default)]
+    private_input: serde_json::Value,
+}
+
+#[derive(Serialize)]
+struct AppTransactionResponse {
+    #[serde(flatten)]
+    receipt: InclusionReceiptResponse,
+    output: serde_json::Value,
+}
+
+#[derive(Serialize)]
+struct InclusionReceiptResponse {
+    tx_id: String,
+    sequence: u64,
+    index: usize,
+    batch_size: usize,
+    new_root: String,
+    celestia_height: Option<u64>,
+}
+
+impl From<&InclusionReceipt> for InclusionReceiptResponse {
+    fn from(receipt: &InclusionReceipt) -> Self {
+        Self {
+            tx_id: hex::encode(receipt.tx_id),
+            sequence: receipt.sequence,
+            index: receipt.index,
+            batch_size: receipt.batch_size,
+            new_root: hex::encode(receipt.new_root),
+            celestia_height: receipt.celestia_height,
+        }
+    }
+}
+
+/// Maximum number of keys in a single multi-proof request.
+const MAX_MULTI_PROOF_KEYS: usize = 1000;
+
+/// Maximum number of changes in a single diff response.
+const MAX_DIFF_CHANGES: usize = 1000;
+
 // Query parameters
 
 #[derive(Deserialize)]
@@ -183,6 +351,12 @@ struct ValueQuery {
     key: String,
     #[serde(default)]
     encoding: Option<String>, // "hex" or "utf8" (default)
+    #[serde(default)]
+    compressed: bool,
+    #[serde(default)]
+    sequence: Option<u64>, // Past sequence (default: latest)
+    #[serde(default)]
+    root: Option<String>, // Past root, hex (looked up in root history)
 }
 
 #[derive(Deserialize)]
@@ -190,6 +364,31 @@ struct ProofQuery {
     key: String,
     #[serde(default)]
     encoding: Option<String>,
+    #[serde(default)]
+    compressed: bool,
+    #[serde(default)]
+    sequence: Option<u64>,
+    #[serde(default)]
+    root: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct MultiProofRequest {
+    keys: Vec<String>,
+    #[serde(default)]
+    encoding: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct DiffQuery {
+    from: u64,
+    to: u64,
+    #[serde(default)]
+    compressed: bool,
+    #[serde(default)]
+    after: Option<String>, // Key hash, hex (list the changes after it)
+    #[serde(default)]
+    limit: Option<usize>, // Default and maximum: MAX_DIFF_CHANGES
 }
 
 #[derive(Deserialize)]
@@ -212,17 +411,31 @@ async fn health() -> Json<HealthResponse> {
     })
 }
 
-async fn get_latest_root(State(state): State<ApiState>) -> Json<RootResponse> {
+async fn get_latest_root(
+    State(state): State<ApiState>,
+) -> Result<Json<RootResponse>, (StatusCode, Json<ErrorResponse>)> {
     let state = state.read().await;
     let root = state.store.root();
     let transition_index = state.store.transition_index();
-    let celestia_height = state.root_history.last().and_then(|(_, h)| *h);
+    let celestia_height = latest_celestia_height(&state)?;
 
-    Json(RootResponse {
+    Ok(Json(RootResponse {
         root: hex::encode(root),
         transition_index,
         celestia_height,
-    })
+        hash_function: state.store.hash_function(),
+    }))
+}
+
+/// Celestia height of the latest transition, if it was posted.
+fn latest_celestia_height(
+    state: &AppNodeState,
+) -> Result<Option<u64>, (StatusCode, Json<ErrorResponse>)> {
+    let record = state
+        .store
+        .transition_record(state.store.transition_index())
+        .map_err(state_error)?;
+    Ok(record.and_then(|r| r.celestia_height))
 }
 
 async fn get_value(
@@ -232,63 +445,229 @@ async fn get_value(
     let key = decode_key(&query.key, query.encoding.as_deref())?;
 
     let state = state.read().await;
-    let (value, proof) = state.store.get_with_proof(&key).map_err(|e| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse {
-                error: e.to_string(),
-            }),
-        )
-    })?;
-
-    let root = state.store.root();
+    let (proof, root, sequence) = proof_at(&state, &key, query.sequence, query.root.as_deref())?;
 
     Ok(Json(ValueResponse {
         key: query.key,
-        value: value.map(|v| BASE64.encode(&v)),
+        value: proof.value.as_ref().map(|v| BASE64.encode(v)),
         root: hex::encode(root),
-        proof: proof.into(),
+        sequence,
+        proof: MerkleProofResponse::from_proof(proof, query.compressed),
     }))
 }
 
 async fn get_merkle_proof(
     State(state): State<ApiState>,
     Query(query): Query<ProofQuery>,
-) -> Result<Json<MerkleProofResponse>, (StatusCode, Json<ErrorResponse>)> {
+) -> Result<Json<ProofResponse>, (StatusCode, Json<ErrorResponse>)> {
     let key = decode_key(&query.key, query.encoding.as_deref())?;
 
     let state = state.read().await;
-    let proof = state.store.get_proof(&key);
+    let (proof, root, sequence) = proof_at(&state, &key, query.sequence, query.root.as_deref())?;
+
+    Ok(Json(ProofResponse {
+        proof: MerkleProofResponse::from_proof(proof, query.compressed),
+        root: hex::encode(root),
+        sequence,
+    }))
+}
+
+/// Prove `key` against the latest state, or a past one selected by
+/// `sequence` or `root`. Returns the proof with its root and sequence.
+fn proof_at(
+    state: &AppNodeState,
+    key: &[u8],
+    sequence: Option<u64>,
+    root: Option<&str>,
+) -> Result<(MerkleProof, Hash32, u64), (StatusCode, Json<ErrorResponse>)> {
+    let (sequence, expected_root) = match (sequence, root) {
+        (None, None) => {
+            let proof = state.store.get_proof(key).map_err(state_error)?;
+            return Ok((proof, state.store.root(), state.store.transition_index()));
+        }
+        (Some(sequence), None) => (sequence, None),
+        (None, Some(root)) => {
+            let root = decode_root(root)?;
+            let (sequence, _, _) = state
+                .store
+                .root_history()
+                .map_err(state_error)?
+                .into_iter()
+                .rfind(|(_, r, _)| *r == root)
+                .ok_or_else(|| {
+                    (
+                        StatusCode::NOT_FOUND,
+                        Json(ErrorResponse {
+                            error: format!("root not in history: {}", hex::encode(root)),
+                        }),
+                    )
+                })?;
+            (sequence, Some(root))
+        }
+        (Some(_), Some(_)) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponse {
+                    error: "use either sequence or root, not both".to_string(),
+                }),
+            ))
+        }
+    };
+
+    let snapshot = state.store.at_sequence(sequence).map_err(state_error)?;
+    let snapshot_root = snapshot.root();
+    if expected_root.is_some_and(|root| root != snapshot_root) {
+        return Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("root history does not match state at sequence {}", sequence),
+            }),
+        ));
+    }
 
-    Ok(Json(proof.into()))
+    let proof = snapshot.get_proof(key).map_err(state_error)?;
+    Ok((proof, snapshot_root, sequence))
 }
 
-async fn get_sync_status(State(state): State<ApiState>) -> Json<SyncStatusResponse> {
+async fn get_multi_proof(
+    State(state): State<ApiState>,
+    Json(request): Json<MultiProofRequest>,
+) -> Result<Json<MultiProofResponse>, (StatusCode, Json<ErrorResponse>)> {
+    if request.keys.is_empty() || request.keys.len() > MAX_MULTI_PROOF_KEYS {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: format!("expected 1 to {} keys", MAX_MULTI_PROOF_KEYS),
+            }),
+        ));
+    }
+
+    let keys = request
+        .keys
+        .iter()
+        .map(|k| decode_key(k, request.encoding.as_deref()))
+        .collect::<Result<Vec<_>, _>>()?;
+
     let state = state.read().await;
+    let proof = state.store.get_multi_proof(&keys).map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: e.to_string(),
+            }),
+        )
+    })?;
 
-    Json(SyncStatusResponse {
+    Ok(Json(MultiProofResponse::from_proof(
+        &state.store.root(),
+        proof,
+    )))
+}
+
+async fn get_diff(
+    State(state): State<ApiState>,
+    Query(query): Query<DiffQuery>,
+) -> Result<Json<DiffResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let limit = query.limit.unwrap_or(MAX_DIFF_CHANGES);
+    if limit == 0 || limit > MAX_DIFF_CHANGES {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: format!("expected a limit of 1 to {}", MAX_DIFF_CHANGES),
+            }),
+        ));
+    }
+    let after = query.after.as_deref().map(decode_key_hash).transpose()?;
+
+    // Only the views are taken under the lock
+    let (old, new) = {
+        let state = state.read().await;
+        (
+            state.store.at_sequence(query.from).map_err(state_error)?,
+            state.store.at_sequence(query.to).map_err(state_error)?,
+        )
+    };
+
+    // One change past the limit tells whether there are more
+    let mut diff = old
+        .diff(&new, after.as_ref(), limit + 1)
+        .map_err(state_error)?;
+    let next = (diff.changes.len() > limit).then(|| {
+        diff.changes.truncate(limit);
+        hex::encode(diff.changes[limit - 1].key_hash)
+    });
+
+    let changes = diff
+        .changes
+        .into_iter()
+        .map(|change| DiffEntry {
+            key_hash: hex::encode(change.key_hash),
+            key: change.key.as_ref().map(|k| BASE64.encode(k)),
+            old_value: change.old_value().map(|v| BASE64.encode(v)),
+            new_value: change.new_value().map(|v| BASE64.encode(v)),
+            old_proof: MerkleProofResponse::from_proof(change.old_proof, query.compressed),
+            new_proof: MerkleProofResponse::from_proof(change.new_proof, query.compressed),
+        })
+        .collect();
+
+    Ok(Json(DiffResponse {
+        from: diff.from,
+        to: diff.to,
+        from_root: hex::encode(diff.from_root),
+        to_root: hex::encode(diff.to_root),
+        changes,
+        next,
+    }))
+}
+
+async fn get_sync_status(
+    State(state): State<ApiState>,
+) -> Result<Json<SyncStatusResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let state = state.read().await;
+
+    let submissions: Vec<PendingSubmission> = state
+        .store
+        .outbox_entries()
+        .map_err(state_error)?
+        .into_iter()
+        .map(|entry| PendingSubmission {
+            sequence: entry.sequence,
+            attempts: entry.attempts,
+            last_error: entry.last_error,
+        })
+        .collect();
+
+    Ok(Json(SyncStatusResponse {
         transition_index: state.store.transition_index(),
         latest_root: hex::encode(state.store.root()),
         celestia_enabled: state.config.celestia_enabled,
-        last_celestia_height: state.root_history.last().and_then(|(_, h)| *h),
-    })
+        last_celestia_height: latest_celestia_height(&state)?,
+        outbox: OutboxStatus {
+            pending: submissions.len(),
+            failed: submissions.iter().filter(|s| s.attempts > 0).count(),
+            submissions,
+        },
+    }))
 }
 
-async fn get_history(State(state): State<ApiState>) -> Json<HistoryResponse> {
+async fn get_history(
+    State(state): State<ApiState>,
+) -> Result<Json<HistoryResponse>, (StatusCode, Json<ErrorResponse>)> {
     let state = state.read().await;
 
     let entries = state
-        .root_history
-        .iter()
-        .enumerate()
-        .map(|(i, (root, height))| HistoryEntry {
-            sequence: i as u64,
+        .store
+        .root_history()
+        .map_err(state_error)?
+        .into_iter()
+        .map(|(sequence, root, height)| HistoryEntry {
+            sequence,
             root: hex::encode(root),
-            celestia_height: *height,
+            celestia_height: height,
         })
         .collect();
 
-    Json(HistoryResponse { entries })
+    Ok(Json(HistoryResponse { entries }))
 }
 
 async fn get_celestia_transition(
@@ -310,16 +689,20 @@ async fn get_celestia_transition(
             )
         })?;
 
-    let blob = blobs.first().ok_or_else(|| {
-        (
-            StatusCode::NOT_FOUND,
-            Json(ErrorResponse {
-                error: format!("No transition found at height {}", query.height),
-            }),
-        )
-    })?;
+    // Skip proof attachments posted at the same height
+    let blob = blobs
+        .iter()
+        .find(|blob| blob.data.first() != Some(&PROOF_ATTACHMENT_VERSION))
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                Json(ErrorResponse {
+                    error: format!("No transition found at height {}", query.height),
+                }),
+            )
+        })?;
 
-    let transition = TransitionBlobV1::decode(&blob.data).map_err(|e| {
+    let transition = TransitionBlob::decode(&blob.data).map_err(|e| {
         (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(ErrorResponse {
@@ -352,7 +735,7 @@ async fn get_celestia_transitions(
 
     let mut transitions = Vec::new();
     for (height, blob) in blobs {
-        if let Ok(transition) = TransitionBlobV1::decode(&blob.data) {
+        if let Ok(transition) = TransitionBlob::decode(&blob.data) {
             transitions.push(TransitionResponse::from_blob(&transition, height));
         }
     }
@@ -363,14 +746,134 @@ async fn get_celestia_transitions(
 async fn apply_transition(
     State(state): State<ApiState>,
     Json(request): Json<ApplyTransitionRequest>,
-) -> Result<Json<ApplyTransitionResponse>, (StatusCode, Json<ErrorResponse>)> {
-    // Convert operations (no lock needed for parsing)
+) -> Result<Json<ApplyTransitionResponse>, ApiError> {
+    check_raw_transitions(&state).await?;
+
+    // No lock needed for parsing
+    let ParsedTransition {
+        ops,
+        public_inputs,
+        private_inputs,
+        verifiable_ops,
+    } = parse_transition_request(request)?;
+
+    // The sequencer stages, proves, commits and posts in submission order
+    let sequencer = state.read().await.sequencer().map_err(sequencer_error)?;
+    let result = sequencer
+        .submit(TransitionRequest {
+            ops,
+            public_inputs,
+            private_inputs,
+            verifiable_ops,
+        })
+        .await
+        .map_err(sequencer_error)?;
+
+    Ok(Json(ApplyTransitionResponse {
+        sequence: result.sequence,
+        prev_root: hex::encode(result.prev_root),
+        new_root: hex::encode(result.new_root),
+        celestia_height: result.celestia_height,
+        proof_size_bytes: result.proof_bytes.len(),
+    }))
+}
+
+async fn submit_transaction(
+    State(state): State<ApiState>,
+    Json(request): Json<ApplyTransitionRequest>,
+) -> Result<Json<InclusionReceiptResponse>, ApiError> {
+    check_raw_transitions(&state).await?;
+
+    let ParsedTransition {
+        ops,
+        public_inputs,
+        private_inputs,
+        verifiable_ops,
+    } = parse_transition_request(request)?;
+
+    // The mempool folds the transaction into the next batch
+    let mempool = state.read().await.mempool().map_err(sequencer_error)?;
+    let receipt = mempool
+        .submit(Transaction {
+            ops,
+            public_inputs,
+            private_inputs,
+            verifiable_ops,
+        })
+        .await
+        .map_err(sequencer_error)?;
+
+    Ok(Json(InclusionReceiptResponse::from(&receipt)))
+}
+
+async fn execute_transaction(
+    State(state): State<ApiState>,
+    Json(request): Json<AppTransactionRequest>,
+) -> Result<Json<AppTransactionResponse>, ApiError> {
+    let (app, mempool) = {
+        let state = state.read().await;
+        let app = state.application.clone().ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                Json(ErrorResponse {
+                    error: "no application is hosted".to_string(),
+                }),
+            )
+        })?;
+        (app, state.mempool().map_err(sequencer_error)?)
+    };
+
+    // The mempool folds the transaction into the next batch, and the
+    // application runs on the state the batch applies it to
+    let (receipt, output) = mempool
+        .execute(application::executor(
+            app,
+            request.input,
+            request.private_input,
+        ))
+        .await
+        .map_err(sequencer_error)?;
+
+    Ok(Json(AppTransactionResponse {
+        receipt: InclusionReceiptResponse::from(&receipt),
+        output,
+    }))
+}
+
+/// Refuse ready-made operations if the node only takes application
+/// transactions.
+async fn check_raw_transitions(state: &ApiState) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    if state.read().await.raw_transitions() {
+        return Ok(());
+    }
+    Err((
+        StatusCode::FORBIDDEN,
+        Json(ErrorResponse {
+            error: "raw transitions are disabled, submit to /app/transaction".to_string(),
+        }),
+    ))
+}
+
+/// A decoded transition request.
+struct ParsedTransition {
+    ops: Vec<StateOp>,
+    public_inputs: Vec<u8>,
+    private_inputs: Vec<u8>,
+    verifiable_ops: Vec<VerifiableOperation>,
+}
+
+fn parse_transition_request(
+    request: ApplyTransitionRequest,
+) -> Result<ParsedTransition, (StatusCode, Json<ErrorResponse>)> {
+    // Convert operations
     let mut ops = Vec::new();
     for op in request.operations {
         let key = decode_key(&op.key, op.encoding.as_deref())?;
+        check_user_key(&key)?;
         let state_op = match op.op_type.as_str() {
             "delete" => StateOp::Delete { key },
-            "insert" | _ => {
+            // "insert" and anything else
+            _ => {
                 let value = if let Some(v) = op.value {
                     BASE64.decode(&v).map_err(|e| {
                         (
@@ -464,176 +967,133 @@ async fn apply_transition(
         });
     }
 
-    // Phase 1: Apply state changes while holding the write lock
-    // Extract everything we need for proving, then release the lock
-    let (prev_root, new_root, sequence, input, proving_enabled, app_id, namespace, celestia_enabled, celestia_client) = {
-        let mut state_guard = state.write().await;
-
-        let prev_root = state_guard.store.root();
-        let sequence = state_guard.store.transition_index() + 1;
-
-        // Apply operations and collect witnesses
-        let witnesses = state_guard.store.apply_batch(ops).map_err(|e| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse {
-                    error: format!("failed to apply operations: {}", e),
-                }),
-            )
-        })?;
+    Ok(ParsedTransition {
+        ops,
+        public_inputs,
+        private_inputs,
+        verifiable_ops,
+    })
+}
 
-        // Commit the state changes
-        let new_root = state_guard.store.commit().map_err(|e| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse {
-                    error: format!("failed to commit state: {}", e),
-                }),
-            )
-        })?;
+/// Error response of the endpoints that go through the sequencer.
+enum ApiError {
+    Error(StatusCode, Json<ErrorResponse>),
+    /// A transition discarded before commit.
+    RolledBack(Json<RollbackErrorResponse>),
+}
 
-        // Build transition input with operations for business logic verification
-        let input = transition_format::TransitionInput::new(
-            prev_root,
-            public_inputs.clone(),
-            private_inputs,
-            witnesses,
-        )
-        .with_operations(verifiable_ops);
+impl From<(StatusCode, Json<ErrorResponse>)> for ApiError {
+    fn from((status, error): (StatusCode, Json<ErrorResponse>)) -> Self {
+        Self::Error(status, error)
+    }
+}
 
-        // Extract config and client for use outside the lock
-        let proving_enabled = state_guard.config.proving_enabled;
-        let app_id = state_guard.config.app_id.clone();
-        let namespace = state_guard.config.namespace.clone();
-        let celestia_enabled = state_guard.config.celestia_enabled;
-        let celestia_client = state_guard.celestia.clone();
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        match self {
+            Self::Error(status, error) => (status, error).into_response(),
+            Self::RolledBack(error) => (StatusCode::INTERNAL_SERVER_ERROR, error).into_response(),
+        }
+    }
+}
 
-        (prev_root, new_root, sequence, input, proving_enabled, app_id, namespace, celestia_enabled, celestia_client)
-        // Lock is released here
+/// Error response for a transition that was not applied.
+///
+/// Transitions the guest would refuse are the client's fault, so only
+/// storage failures and rollbacks are reported as server errors.
+fn sequencer_error(e: SequencerError) -> ApiError {
+    let status = match e {
+        SequencerError::RolledBack {
+            sequence,
+            root,
+            discarded_root,
+            operations,
+            reason,
+        } => {
+            return ApiError::RolledBack(Json(RollbackErrorResponse {
+                error: reason,
+                rolled_back: RolledBackTransition {
+                    sequence,
+                    root: hex::encode(root),
+                    discarded_root: hex::encode(discarded_root),
+                    operations,
+                },
+            }))
+        }
+        SequencerError::Rejected(_) | SequencerError::Application(_) => {
+            StatusCode::UNPROCESSABLE_ENTITY
+        }
+        SequencerError::Storage(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        SequencerError::Stopped => StatusCode::SERVICE_UNAVAILABLE,
     };
+    ApiError::Error(
+        status,
+        Json(ErrorResponse {
+            error: e.to_string(),
+        }),
+    )
+}
 
-    // Phase 2: Generate proof WITHOUT holding the lock
-    // This allows other API requests to proceed during proving
-    let (proof_bytes, output) = if proving_enabled {
-        // SP1 proving is CPU-intensive, run it in a blocking thread pool
-        let result = tokio::task::spawn_blocking(move || {
-            let prover = zk_host_harness::TransitionProver::new();
-            prover.prove(&input)
-        })
-        .await
-        .map_err(|e| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse {
-                    error: format!("proving task failed: {}", e),
-                }),
-            )
-        })?
-        .map_err(|e| {
+// Helper functions
+
+fn decode_root(root: &str) -> Result<Hash32, (StatusCode, Json<ErrorResponse>)> {
+    hex::decode(root)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| {
             (
-                StatusCode::INTERNAL_SERVER_ERROR,
+                StatusCode::BAD_REQUEST,
                 Json(ErrorResponse {
-                    error: format!("proof generation failed: {}", e),
+                    error: "invalid root: expected 32 bytes of hex".to_string(),
                 }),
             )
-        })?;
-        (result.proof_bytes, result.output)
-    } else {
-        // Execute without proof (also CPU-intensive)
-        let result = tokio::task::spawn_blocking(move || {
-            let prover = zk_host_harness::TransitionProver::new();
-            prover.execute(&input)
         })
-        .await
-        .map_err(|e| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse {
-                    error: format!("execution task failed: {}", e),
-                }),
-            )
-        })?
-        .map_err(|e| {
+}
+
+fn decode_key_hash(key_hash: &str) -> Result<Hash32, (StatusCode, Json<ErrorResponse>)> {
+    hex::decode(key_hash)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| {
             (
-                StatusCode::INTERNAL_SERVER_ERROR,
+                StatusCode::BAD_REQUEST,
                 Json(ErrorResponse {
-                    error: format!("execution failed: {}", e),
+                    error: "invalid key hash: expected 32 bytes of hex".to_string(),
                 }),
             )
-        })?;
-        (Vec::new(), result)
+        })
+}
+
+fn state_error(e: StateError) -> (StatusCode, Json<ErrorResponse>) {
+    let status = match e {
+        StateError::UnknownSequence(_) => StatusCode::NOT_FOUND,
+        StateError::ReservedKey(_) => StatusCode::BAD_REQUEST,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
     };
+    (
+        status,
+        Json(ErrorResponse {
+            error: e.to_string(),
+        }),
+    )
+}
 
-    // Verify the output matches our computation
-    if output.prev_root != prev_root || output.new_root != new_root {
+/// Reject keys that would collide with node metadata.
+fn check_user_key(key: &[u8]) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    if state::is_reserved_key(key) {
         return Err((
-            StatusCode::INTERNAL_SERVER_ERROR,
+            StatusCode::BAD_REQUEST,
             Json(ErrorResponse {
-                error: "proof output mismatch".to_string(),
+                error: format!(
+                    "key is reserved for node metadata: {}",
+                    String::from_utf8_lossy(key)
+                ),
             }),
         ));
     }
-
-    // Create blob
-    let blob = blob_schema::TransitionBlobV1::new(
-        app_id,
-        sequence,
-        prev_root,
-        new_root,
-        public_inputs,
-        proof_bytes.clone(),
-        zk_host_harness::program_hash(),
-    )
-    .with_timestamp(
-        std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs(),
-    );
-
-    // Phase 3: Post to Celestia WITHOUT holding the lock
-    let celestia_result = if celestia_enabled {
-        let blob_bytes = blob.encode().map_err(|e| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse {
-                    error: format!("blob encoding failed: {}", e),
-                }),
-            )
-        })?;
-        match celestia_client
-            .submit_blob(&namespace, &blob_bytes)
-            .await
-        {
-            Ok(result) => Some(result),
-            Err(e) => {
-                tracing::warn!("Failed to post to Celestia: {}", e);
-                None
-            }
-        }
-    } else {
-        None
-    };
-
-    // Phase 4: Briefly re-acquire lock to update root history
-    {
-        let mut state_guard = state.write().await;
-        state_guard
-            .root_history
-            .push((new_root, celestia_result.as_ref().map(|r| r.height)));
-    }
-
-    Ok(Json(ApplyTransitionResponse {
-        sequence,
-        prev_root: hex::encode(prev_root),
-        new_root: hex::encode(new_root),
-        celestia_height: celestia_result.as_ref().map(|r| r.height),
-        proof_size_bytes: proof_bytes.len(),
-    }))
+    Ok(())
 }
 
-// Helper functions
-
 fn decode_key(
     key: &str,
     encoding: Option<&str>,
@@ -667,7 +1127,17 @@ fn parse_operation_type(value: &serde_json::Value) -> Result<OperationType, Stri
             .get("initial_balance")
             .and_then(|v| v.as_u64())
             .ok_or("CreateAccount must have initial_balance")?;
-        Ok(OperationType::CreateAccount { initial_balance })
+        let signature = create_account
+            .get("signature")
+            .and_then(|v| v.as_str())
+            .ok_or("CreateAccount must have signature")?;
+        let signature = BASE64
+            .decode(signature)
+            .map_err(|e| format!("invalid base64 CreateAccount signature: {}", e))?;
+        Ok(OperationType::CreateAccount {
+            initial_balance,
+            signature,
+        })
     } else if let Some(transfer) = obj.get("Transfer") {
         let from = transfer
             .get("from")
@@ -685,19 +1155,45 @@ fn parse_operation_type(value: &serde_json::Value) -> Result<OperationType, Stri
             .get("amount")
             .and_then(|v| v.as_u64())
             .ok_or("Transfer must have amount")?;
-        Ok(OperationType::Transfer { from, to, amount })
+        let signature = transfer
+            .get("signature")
+            .and_then(|v| v.as_str())
+            .ok_or("Transfer must have signature")?;
+        let signature = BASE64
+            .decode(signature)
+            .map_err(|e| format!("invalid base64 Transfer signature: {}", e))?;
+        Ok(OperationType::Transfer {
+            from,
+            to,
+            amount,
+            signature,
+        })
     } else if let Some(mint) = obj.get("Mint") {
         let amount = mint
             .get("amount")
             .and_then(|v| v.as_u64())
             .ok_or("Mint must have amount")?;
-        Ok(OperationType::Mint { amount })
+        let signature = mint
+            .get("signature")
+            .and_then(|v| v.as_str())
+            .ok_or("Mint must have signature")?;
+        let signature = BASE64
+            .decode(signature)
+            .map_err(|e| format!("invalid base64 Mint signature: {}", e))?;
+        Ok(OperationType::Mint { amount, signature })
     } else if let Some(burn) = obj.get("Burn") {
         let amount = burn
             .get("amount")
             .and_then(|v| v.as_u64())
             .ok_or("Burn must have amount")?;
-        Ok(OperationType::Burn { amount })
+        let signature = burn
+            .get("signature")
+            .and_then(|v| v.as_str())
+            .ok_or("Burn must have signature")?;
+        let signature = BASE64
+            .decode(signature)
+            .map_err(|e| format!("invalid base64 Burn signature: {}", e))?;
+        Ok(OperationType::Burn { amount, signature })
     } else {
         Err("unknown operation type".to_string())
     }
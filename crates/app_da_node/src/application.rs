@@ -0,0 +1,88 @@
+//! Hosting of an [`sdk::Application`] by the node.
+//!
+//! With an application hosted, clients submit typed transactions instead of
+//! ready-made operations. The mempool batches them, and the sequencer runs
+//! [`Application::apply`] against a [`Context`] on the state each transaction
+//! is applied to: the writes it makes and the operations it records become
+//! its part of the transition, and its inputs are proven and posted
+//! bincode-encoded.
+
+use crate::sequencer::{Execution, Executor, TransitionRequest};
+use sdk::{Application, Context, SdkError};
+use serde_json::Value;
+use std::sync::Arc;
+
+/// An application exchanging its inputs and output as JSON.
+///
+/// Implemented for every [`Application`] that can be shared between
+/// threads.
+pub trait HostedApplication: Send + Sync {
+    /// Decode the inputs and apply them to `ctx`.
+    fn apply_json(
+        &self,
+        ctx: &mut Context,
+        public: Value,
+        private: Value,
+    ) -> sdk::Result<AppliedTransaction>;
+}
+
+/// Encoded inputs and output of an applied transaction.
+#[derive(Debug, Clone)]
+pub struct AppliedTransaction {
+    /// Public inputs (bincode).
+    pub public_inputs: Vec<u8>,
+    /// Private inputs (bincode).
+    pub private_inputs: Vec<u8>,
+    /// Output of the application.
+    pub output: Value,
+}
+
+impl<A> HostedApplication for A
+where
+    A: Application + Send + Sync,
+{
+    fn apply_json(
+        &self,
+        ctx: &mut Context,
+        public: Value,
+        private: Value,
+    ) -> sdk::Result<AppliedTransaction> {
+        let public: A::PublicInput = serde_json::from_value(public)
+            .map_err(|e| SdkError::Serialization(format!("invalid public input: {}", e)))?;
+        let private: A::PrivateInput = serde_json::from_value(private)
+            .map_err(|e| SdkError::Serialization(format!("invalid private input: {}", e)))?;
+        let public_inputs =
+            bincode::serialize(&public).map_err(|e| SdkError::Serialization(e.to_string()))?;
+        let private_inputs =
+            bincode::serialize(&private).map_err(|e| SdkError::Serialization(e.to_string()))?;
+
+        let output = self.apply(ctx, public, private)?;
+
+        Ok(AppliedTransaction {
+            public_inputs,
+            private_inputs,
+            output: serde_json::to_value(&output)
+                .map_err(|e| SdkError::Serialization(e.to_string()))?,
+        })
+    }
+}
+
+/// Build the sequencer job applying a transaction of `app`.
+pub(crate) fn executor(app: Arc<dyn HostedApplication>, public: Value, private: Value) -> Executor {
+    Arc::new(move |store| {
+        let mut ctx = Context::new(store);
+        let applied = app
+            .apply_json(&mut ctx, public.clone(), private.clone())
+            .map_err(|e| e.to_string())?;
+
+        Ok(Execution {
+            request: TransitionRequest {
+                ops: ctx.operations().to_vec(),
+                public_inputs: applied.public_inputs,
+                private_inputs: applied.private_inputs,
+                verifiable_ops: ctx.verifiable_operations().to_vec(),
+            },
+            output: applied.output,
+        })
+    })
+}
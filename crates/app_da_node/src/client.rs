@@ -3,11 +3,12 @@
 //! This module provides a client for interacting with the App DA Node
 //! HTTP API without directly accessing the database.
 
+use crate::mempool::InclusionReceipt;
 use anyhow::{Context, Result};
 use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
-use merkle::{Hash32, MerkleProof};
-use serde::{Deserialize, Serialize};
-use state::StateOp;
+use merkle::{CompressedSiblings, Hash32, HashFunction, MerkleProof, MultiProof};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use state::{KeyChange, StateDiff, StateOp};
 use transition_format::{OperationType, VerifiableOperation};
 
 /// HTTP client for the App DA Node API.
@@ -55,13 +56,14 @@ impl AppNodeClient {
                 .unwrap(),
             transition_index: data.transition_index,
             celestia_height: data.celestia_height,
+            hash_function: data.hash_function,
         })
     }
 
     /// Get a value by key.
     pub async fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
         let key_str = String::from_utf8_lossy(key);
-        let url = format!("{}/value?key={}", self.base_url, key_str);
+        let url = format!("{}/value?key={}&compressed=true", self.base_url, key_str);
         let response = self.client.get(&url).send().await?;
 
         if !response.status().is_success() {
@@ -80,7 +82,7 @@ impl AppNodeClient {
     /// Get a value with its Merkle proof.
     pub async fn get_with_proof(&self, key: &[u8]) -> Result<(Option<Vec<u8>>, MerkleProof)> {
         let key_str = String::from_utf8_lossy(key);
-        let url = format!("{}/value?key={}", self.base_url, key_str);
+        let url = format!("{}/value?key={}&compressed=true", self.base_url, key_str);
         let response = self.client.get(&url).send().await?;
 
         if !response.status().is_success() {
@@ -100,6 +102,122 @@ impl AppNodeClient {
         Ok((value, proof))
     }
 
+    /// Get a value with its Merkle proof at a past version of the state.
+    ///
+    /// The proof verifies against the returned root, which is the root the
+    /// node recorded for that sequence.
+    pub async fn get_with_proof_at(
+        &self,
+        key: &[u8],
+        version: StateVersion,
+    ) -> Result<VersionedValue> {
+        let selector = match version {
+            StateVersion::Sequence(sequence) => format!("sequence={}", sequence),
+            StateVersion::Root(root) => format!("root={}", hex::encode(root)),
+        };
+        let url = format!(
+            "{}/value?key={}&encoding=hex&compressed=true&{}",
+            self.base_url,
+            hex::encode(key),
+            selector
+        );
+        let response = self.client.get(&url).send().await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let error_text = response.text().await.unwrap_or_default();
+            anyhow::bail!("API error {}: {}", status, error_text);
+        }
+
+        let data: ValueResponse = response.json().await?;
+        let root: Hash32 = hex::decode(&data.root)?
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("invalid root length"))?;
+        let proof = parse_merkle_proof(&data.proof)?;
+
+        Ok(VersionedValue {
+            value: proof.value.clone(),
+            proof,
+            root,
+            sequence: data.sequence,
+        })
+    }
+
+    /// List the keys changed between two sequences, with proofs against both roots.
+    ///
+    /// Fetches the changes a page at a time.
+    pub async fn diff(&self, from: u64, to: u64) -> Result<StateDiff> {
+        let mut changes = Vec::new();
+        let mut after: Option<String> = None;
+        loop {
+            let mut url = format!(
+                "{}/diff?from={}&to={}&compressed=true",
+                self.base_url, from, to
+            );
+            if let Some(after) = &after {
+                url.push_str(&format!("&after={}", after));
+            }
+            let response = self.client.get(&url).send().await?;
+
+            if !response.status().is_success() {
+                let status = response.status();
+                let error_text = response.text().await.unwrap_or_default();
+                anyhow::bail!("API error {}: {}", status, error_text);
+            }
+
+            let data: DiffResponse = response.json().await?;
+            for entry in &data.changes {
+                changes.push(KeyChange {
+                    key_hash: parse_hash(&entry.key_hash)?,
+                    key: entry.key.as_ref().map(|k| BASE64.decode(k)).transpose()?,
+                    old_proof: parse_merkle_proof(&entry.old_proof)?,
+                    new_proof: parse_merkle_proof(&entry.new_proof)?,
+                });
+            }
+
+            if data.next.is_none() {
+                return Ok(StateDiff {
+                    from: data.from,
+                    to: data.to,
+                    from_root: parse_hash(&data.from_root)?,
+                    to_root: parse_hash(&data.to_root)?,
+                    changes,
+                });
+            }
+            after = data.next;
+        }
+    }
+
+    /// Get a single proof for many keys, with the root it was generated against.
+    pub async fn get_multi_proof(&self, keys: &[Vec<u8>]) -> Result<(Hash32, MultiProof)> {
+        let url = format!("{}/proof/multi", self.base_url);
+        let request = MultiProofRequest {
+            keys: keys.iter().map(hex::encode).collect(),
+            encoding: Some("hex".to_string()),
+        };
+
+        let response = self
+            .client
+            .post(&url)
+            .json(&request)
+            .send()
+            .await
+            .context("failed to send multi-proof request")?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let error_text = response.text().await.unwrap_or_default();
+            anyhow::bail!("API error {}: {}", status, error_text);
+        }
+
+        let data: MultiProofResponse = response.json().await?;
+        let root: Hash32 = hex::decode(&data.root)?
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("invalid root length"))?;
+
+        Ok((root, parse_multi_proof(&data)?))
+    }
+
     /// Get the current root.
     pub async fn root(&self) -> Result<Hash32> {
         let info = self.get_latest_root().await?;
@@ -141,80 +259,7 @@ impl AppNodeClient {
         verifiable_ops: Vec<VerifiableOperation>,
     ) -> Result<TransitionResult> {
         let url = format!("{}/transition", self.base_url);
-
-        // Convert operations
-        let operations: Vec<OperationRequest> = ops
-            .into_iter()
-            .map(|op| match op {
-                StateOp::Insert { key, value } => OperationRequest {
-                    op_type: "insert".to_string(),
-                    key: String::from_utf8_lossy(&key).to_string(),
-                    value: Some(BASE64.encode(&value)),
-                    encoding: None,
-                },
-                StateOp::Delete { key } => OperationRequest {
-                    op_type: "delete".to_string(),
-                    key: String::from_utf8_lossy(&key).to_string(),
-                    value: None,
-                    encoding: None,
-                },
-            })
-            .collect();
-
-        // Convert verifiable operations
-        let verifiable_operations: Vec<VerifiableOperationRequest> = verifiable_ops
-            .into_iter()
-            .map(|vop| {
-                let op_type_json = match vop.op_type {
-                    OperationType::Set => serde_json::json!("Set"),
-                    OperationType::CreateAccount { initial_balance } => {
-                        serde_json::json!({
-                            "CreateAccount": {
-                                "initial_balance": initial_balance
-                            }
-                        })
-                    }
-                    OperationType::Transfer { from, to, amount } => {
-                        serde_json::json!({
-                            "Transfer": {
-                                "from": String::from_utf8_lossy(&from),
-                                "to": String::from_utf8_lossy(&to),
-                                "amount": amount
-                            }
-                        })
-                    }
-                    OperationType::Mint { amount } => {
-                        serde_json::json!({
-                            "Mint": {
-                                "amount": amount
-                            }
-                        })
-                    }
-                    OperationType::Burn { amount } => {
-                        serde_json::json!({
-                            "Burn": {
-                                "amount": amount
-                            }
-                        })
-                    }
-                };
-
-                VerifiableOperationRequest {
-                    op_type: op_type_json,
-                    key: String::from_utf8_lossy(&vop.key).to_string(),
-                    old_value: vop.old_value.map(|v| BASE64.encode(&v)),
-                    new_value: vop.new_value.map(|v| BASE64.encode(&v)),
-                    witness_index: vop.witness_index,
-                }
-            })
-            .collect();
-
-        let request = ApplyTransitionRequest {
-            operations,
-            public_inputs: Some(BASE64.encode(&public_inputs)),
-            private_inputs: Some(BASE64.encode(&private_inputs)),
-            verifiable_operations,
-        };
+        let request = transition_request(ops, public_inputs, private_inputs, verifiable_ops);
 
         let response = self
             .client
@@ -231,17 +276,193 @@ impl AppNodeClient {
         }
 
         let data: ApplyTransitionResponse = response.json().await?;
+        transition_result(&data)
+    }
 
-        Ok(TransitionResult {
-            sequence: data.sequence,
-            prev_root: hex::decode(&data.prev_root)?
-                .try_into()
-                .map_err(|_| anyhow::anyhow!("invalid prev_root length"))?,
-            new_root: hex::decode(&data.new_root)?
-                .try_into()
-                .map_err(|_| anyhow::anyhow!("invalid new_root length"))?,
-            celestia_height: data.celestia_height,
+    /// Submit a typed transaction to the node's hosted application, wait
+    /// until the batch including it is committed and return the inclusion
+    /// receipt with the application's output.
+    pub async fn execute<O: DeserializeOwned>(
+        &self,
+        input: &impl Serialize,
+        private_input: &impl Serialize,
+    ) -> Result<(InclusionReceipt, O)> {
+        let url = format!("{}/app/transaction", self.base_url);
+        let request = serde_json::json!({
+            "input": input,
+            "private_input": private_input,
+        });
+
+        let response = self
+            .client
+            .post(&url)
+            .json(&request)
+            .send()
+            .await
+            .context("failed to send application transaction")?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let error_text = response.text().await.unwrap_or_default();
+            anyhow::bail!("API error {}: {}", status, error_text);
+        }
+
+        let data: AppTransactionResponse = response.json().await?;
+        let output =
+            serde_json::from_value(data.output).context("failed to decode application output")?;
+        Ok((inclusion_receipt(&data.receipt)?, output))
+    }
+
+    /// Submit a single transaction to the mempool and wait until the batch
+    /// including it is committed.
+    pub async fn submit_transaction(
+        &self,
+        ops: Vec<StateOp>,
+        public_inputs: Vec<u8>,
+        private_inputs: Vec<u8>,
+        verifiable_ops: Vec<VerifiableOperation>,
+    ) -> Result<InclusionReceipt> {
+        let url = format!("{}/tx", self.base_url);
+        let request = transition_request(ops, public_inputs, private_inputs, verifiable_ops);
+
+        let response = self
+            .client
+            .post(&url)
+            .json(&request)
+            .send()
+            .await
+            .context("failed to send transaction")?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let error_text = response.text().await.unwrap_or_default();
+            anyhow::bail!("API error {}: {}", status, error_text);
+        }
+
+        let data: InclusionReceiptResponse = response.json().await?;
+        inclusion_receipt(&data)
+    }
+}
+
+fn inclusion_receipt(data: &InclusionReceiptResponse) -> Result<InclusionReceipt> {
+    Ok(InclusionReceipt {
+        tx_id: hex::decode(&data.tx_id)?
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("invalid tx_id length"))?,
+        sequence: data.sequence,
+        index: data.index,
+        batch_size: data.batch_size,
+        new_root: hex::decode(&data.new_root)?
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("invalid new_root length"))?,
+        celestia_height: data.celestia_height,
+    })
+}
+
+fn transition_result(data: &ApplyTransitionResponse) -> Result<TransitionResult> {
+    Ok(TransitionResult {
+        sequence: data.sequence,
+        prev_root: hex::decode(&data.prev_root)?
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("invalid prev_root length"))?,
+        new_root: hex::decode(&data.new_root)?
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("invalid new_root length"))?,
+        celestia_height: data.celestia_height,
+    })
+}
+
+/// Build the API request body of a transition or transaction.
+fn transition_request(
+    ops: Vec<StateOp>,
+    public_inputs: Vec<u8>,
+    private_inputs: Vec<u8>,
+    verifiable_ops: Vec<VerifiableOperation>,
+) -> ApplyTransitionRequest {
+    // Convert operations
+    let operations: Vec<OperationRequest> = ops
+        .into_iter()
+        .map(|op| match op {
+            StateOp::Insert { key, value } => OperationRequest {
+                op_type: "insert".to_string(),
+                key: String::from_utf8_lossy(&key).to_string(),
+                value: Some(BASE64.encode(&value)),
+                encoding: None,
+            },
+            StateOp::Delete { key } => OperationRequest {
+                op_type: "delete".to_string(),
+                key: String::from_utf8_lossy(&key).to_string(),
+                value: None,
+                encoding: None,
+            },
+        })
+        .collect();
+
+    // Convert verifiable operations
+    let verifiable_operations: Vec<VerifiableOperationRequest> = verifiable_ops
+        .into_iter()
+        .map(|vop| {
+            let op_type_json = match vop.op_type {
+                OperationType::Set => serde_json::json!("Set"),
+                OperationType::CreateAccount {
+                    initial_balance,
+                    signature,
+                } => {
+                    serde_json::json!({
+                        "CreateAccount": {
+                            "initial_balance": initial_balance,
+                            "signature": BASE64.encode(&signature)
+                        }
+                    })
+                }
+                OperationType::Transfer {
+                    from,
+                    to,
+                    amount,
+                    signature,
+                } => {
+                    serde_json::json!({
+                        "Transfer": {
+                            "from": String::from_utf8_lossy(&from),
+                            "to": String::from_utf8_lossy(&to),
+                            "amount": amount,
+                            "signature": BASE64.encode(&signature)
+                        }
+                    })
+                }
+                OperationType::Mint { amount, signature } => {
+                    serde_json::json!({
+                        "Mint": {
+                            "amount": amount,
+                            "signature": BASE64.encode(&signature)
+                        }
+                    })
+                }
+                OperationType::Burn { amount, signature } => {
+                    serde_json::json!({
+                        "Burn": {
+                            "amount": amount,
+                            "signature": BASE64.encode(&signature)
+                        }
+                    })
+                }
+            };
+
+            VerifiableOperationRequest {
+                op_type: op_type_json,
+                key: String::from_utf8_lossy(&vop.key).to_string(),
+                old_value: vop.old_value.map(|v| BASE64.encode(&v)),
+                new_value: vop.new_value.map(|v| BASE64.encode(&v)),
+                witness_index: vop.witness_index,
+            }
         })
+        .collect();
+
+    ApplyTransitionRequest {
+        operations,
+        public_inputs: Some(BASE64.encode(&public_inputs)),
+        private_inputs: Some(BASE64.encode(&private_inputs)),
+        verifiable_operations,
     }
 }
 
@@ -279,11 +500,16 @@ struct RootResponse {
     root: String,
     transition_index: u64,
     celestia_height: Option<u64>,
+    #[serde(default)]
+    hash_function: HashFunction,
 }
 
 #[derive(Deserialize)]
 struct ValueResponse {
     value: Option<String>,
+    root: String,
+    #[serde(default)]
+    sequence: u64,
     proof: MerkleProofResponse,
 }
 
@@ -291,7 +517,50 @@ struct ValueResponse {
 struct MerkleProofResponse {
     key_hash: String,
     value: Option<String>,
+    #[serde(default)]
+    sibling_bitmap: Option<String>,
     siblings: Vec<String>,
+    #[serde(default)]
+    hash_function: HashFunction,
+}
+
+#[derive(Deserialize)]
+struct DiffResponse {
+    from: u64,
+    to: u64,
+    from_root: String,
+    to_root: String,
+    changes: Vec<DiffEntry>,
+    next: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct DiffEntry {
+    key_hash: String,
+    key: Option<String>,
+    old_proof: MerkleProofResponse,
+    new_proof: MerkleProofResponse,
+}
+
+#[derive(Serialize)]
+struct MultiProofRequest {
+    keys: Vec<String>,
+    encoding: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct MultiProofResponse {
+    root: String,
+    entries: Vec<MultiProofEntry>,
+    siblings: Vec<Option<String>>,
+    #[serde(default)]
+    hash_function: HashFunction,
+}
+
+#[derive(Deserialize)]
+struct MultiProofEntry {
+    key_hash: String,
+    value: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -302,6 +571,23 @@ struct ApplyTransitionResponse {
     celestia_height: Option<u64>,
 }
 
+#[derive(Deserialize)]
+struct AppTransactionResponse {
+    #[serde(flatten)]
+    receipt: InclusionReceiptResponse,
+    output: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+struct InclusionReceiptResponse {
+    tx_id: String,
+    sequence: u64,
+    index: usize,
+    batch_size: usize,
+    new_root: String,
+    celestia_height: Option<u64>,
+}
+
 #[derive(Deserialize)]
 struct HistoryResponse {
     entries: Vec<HistoryEntry>,
@@ -322,6 +608,25 @@ pub struct RootInfo {
     pub root: Hash32,
     pub transition_index: u64,
     pub celestia_height: Option<u64>,
+    pub hash_function: HashFunction,
+}
+
+/// A past version of the state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateVersion {
+    /// The state after the given sequence.
+    Sequence(u64),
+    /// The state with the given root (looked up in the node's root history).
+    Root(Hash32),
+}
+
+/// A value with its proof at a specific version of the state.
+#[derive(Debug, Clone)]
+pub struct VersionedValue {
+    pub value: Option<Vec<u8>>,
+    pub proof: MerkleProof,
+    pub root: Hash32,
+    pub sequence: u64,
 }
 
 /// Result of applying a transition.
@@ -335,6 +640,12 @@ pub struct TransitionResult {
 
 // Helper functions
 
+fn parse_hash(hex_str: &str) -> Result<Hash32> {
+    hex::decode(hex_str)?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("invalid hash length"))
+}
+
 fn parse_merkle_proof(response: &MerkleProofResponse) -> Result<MerkleProof> {
     let key: Hash32 = hex::decode(&response.key_hash)?
         .try_into()
@@ -356,9 +667,55 @@ fn parse_merkle_proof(response: &MerkleProofResponse) -> Result<MerkleProof> {
         })
         .collect();
 
+    // Compressed responses only carry the non-empty siblings
+    let siblings = match &response.sibling_bitmap {
+        Some(bitmap) => CompressedSiblings {
+            bitmap: hex::decode(bitmap)?
+                .try_into()
+                .map_err(|_| anyhow::anyhow!("invalid sibling bitmap length"))?,
+            hashes: siblings?,
+        }
+        .decompress(response.hash_function)?,
+        None => siblings?,
+    };
+
     Ok(MerkleProof {
         key,
         value,
+        siblings,
+        hash_function: response.hash_function,
+    })
+}
+
+fn parse_multi_proof(response: &MultiProofResponse) -> Result<MultiProof> {
+    let mut keys = Vec::with_capacity(response.entries.len());
+    let mut values = Vec::with_capacity(response.entries.len());
+    for entry in &response.entries {
+        let key: Hash32 = hex::decode(&entry.key_hash)?
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("invalid key hash length"))?;
+        keys.push(key);
+        values.push(entry.value.as_ref().map(|v| BASE64.decode(v)).transpose()?);
+    }
+
+    let siblings: Result<Vec<Option<Hash32>>> = response
+        .siblings
+        .iter()
+        .map(|s| {
+            s.as_ref()
+                .map(|s| {
+                    hex::decode(s)?
+                        .try_into()
+                        .map_err(|_| anyhow::anyhow!("invalid sibling hash length"))
+                })
+                .transpose()
+        })
+        .collect();
+
+    Ok(MultiProof {
+        keys,
+        values,
         siblings: siblings?,
+        hash_function: response.hash_function,
     })
 }
@@ -0,0 +1,147 @@
+//! Background proving of transitions posted without a proof.
+//!
+//! With proving disabled and deferred proving enabled, the node posts each
+//! transition with an empty proof right away and queues it here. The worker
+//! proves it from the journal, posts a [`ProofAttachmentV1`] referencing the
+//! posted blob and marks the transition record as proven.
+
+use crate::node::AppNodeState;
+use anyhow::{anyhow, Result};
+use blob_schema::ProofAttachmentV1;
+use celestia_adapter::{CelestiaClient, Namespace, SubmitResult};
+use merkle::Hash32;
+use state::{StateStore, TransitionRecord};
+use std::sync::Weak;
+use tokio::sync::{mpsc, RwLock};
+use tracing::{info, warn};
+use transition_format::TransitionInput;
+use zk_host_harness::{program_hash, TransitionProver};
+
+/// Queue of transition sequences to prove in the background.
+pub type DeferredQueue = mpsc::UnboundedSender<u64>;
+
+/// Start the background prover.
+///
+/// The worker stops once the node state is dropped.
+pub(crate) fn spawn(state: Weak<RwLock<AppNodeState>>) -> DeferredQueue {
+    let (queue, mut pending) = mpsc::unbounded_channel();
+
+    tokio::spawn(async move {
+        while let Some(sequence) = pending.recv().await {
+            let Some(state) = state.upgrade() else {
+                break;
+            };
+            if let Err(e) = attach_proof(&state, sequence).await {
+                warn!("Deferred proof of transition {} failed: {:#}", sequence, e);
+            }
+        }
+    });
+
+    queue
+}
+
+/// Sequences of posted transitions that still wait for a proof.
+pub(crate) fn unproven(state: &AppNodeState) -> Result<Vec<u64>> {
+    Ok(state
+        .store
+        .transition_records()?
+        .into_iter()
+        .filter(|record| !record.proven && record.celestia_height.is_some())
+        .map(|record| record.sequence)
+        .collect())
+}
+
+/// Prove one transition and post its proof attachment.
+async fn attach_proof(state: &RwLock<AppNodeState>, sequence: u64) -> Result<()> {
+    // Read what proving needs, then prove without holding the lock
+    let (record, input, app_id, namespace, celestia) = {
+        let state = state.read().await;
+        let record = state
+            .store
+            .transition_record(sequence)?
+            .ok_or_else(|| anyhow!("no record of transition {}", sequence))?;
+        if record.proven {
+            return Ok(());
+        }
+        let journal = state
+            .store
+            .journal_entry(sequence)?
+            .ok_or_else(|| anyhow!("no journal entry for transition {}", sequence))?;
+        (
+            record,
+            TransitionInput::decode(&journal)?,
+            state.config.app_id.clone(),
+            state.config.namespace.clone(),
+            state.celestia.clone(),
+        )
+    };
+    let blob_hash = record
+        .blob_hash
+        .ok_or_else(|| anyhow!("transition {} has no posted blob", sequence))?;
+
+    info!("Proving transition {} in the background", sequence);
+    let proof =
+        tokio::task::spawn_blocking(move || TransitionProver::new().prove(&input)).await??;
+    if proof.output.prev_root != record.prev_root || proof.output.new_root != record.new_root {
+        return Err(anyhow!("proof output mismatch"));
+    }
+
+    let result = post_attachment(
+        &celestia,
+        &namespace,
+        app_id,
+        sequence,
+        blob_hash,
+        proof.proof_bytes,
+    )
+    .await?;
+    info!(
+        "Proof of transition {} attached at height {}",
+        sequence, result.height
+    );
+
+    let mut state = state.write().await;
+    mark_proven(&mut state.store, &record)
+}
+
+/// Mark the transition `proven` was read from as proven.
+///
+/// The record is read again: if the transition was reverted while being
+/// proven, and possibly committed again with another blob, the proof is not
+/// the stored transition's and nothing is written. Otherwise the current
+/// record is kept, with only `proven` set.
+pub(crate) fn mark_proven(store: &mut StateStore, proven: &TransitionRecord) -> Result<()> {
+    let current = store
+        .transition_record(proven.sequence)?
+        .filter(|current| {
+            current.prev_root == proven.prev_root
+                && current.new_root == proven.new_root
+                && current.blob_hash == proven.blob_hash
+        })
+        .ok_or_else(|| anyhow!("transition {} changed while being proven", proven.sequence))?;
+    store.update_transition_record(current.with_proven(true))?;
+    Ok(())
+}
+
+/// Post `proof` as an attachment to the transition blob with hash
+/// `blob_hash`.
+pub(crate) async fn post_attachment(
+    celestia: &CelestiaClient,
+    namespace: &Namespace,
+    app_id: Vec<u8>,
+    sequence: u64,
+    blob_hash: Hash32,
+    proof: Vec<u8>,
+) -> Result<SubmitResult> {
+    let attachment = ProofAttachmentV1::new(app_id, sequence, blob_hash, proof, program_hash())
+        .with_timestamp(
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+        );
+    celestia
+        .submit_blob(namespace, &attachment.encode()?)
+        .await
+        .map_err(|e| anyhow::Error::from(e).context("failed to post proof attachment"))
+}
@@ -3,14 +3,24 @@
 //! This crate provides the core functionality for an app-specific DA node that:
 //! - Stores application state with Merkle commitment
 //! - Applies state transitions and generates ZK proofs
+//! - Hosts an application executing typed transactions
+//! - Batches individual transactions into transitions
 //! - Posts proofs to Celestia DA
 //! - Serves state queries with Merkle proofs
 //! - Verifies proofs from Celestia for syncing
 
 pub mod api;
+pub mod application;
 pub mod client;
+mod deferred;
+pub mod mempool;
 pub mod node;
+mod outbox;
+pub mod sequencer;
 pub mod sync;
 
-pub use client::{AppNodeClient, RootInfo, TransitionResult as ClientTransitionResult};
+pub use client::{
+    AppNodeClient, RootInfo, StateVersion, TransitionResult as ClientTransitionResult,
+    VersionedValue,
+};
 pub use node::{AppNode, AppNodeConfig};
@@ -0,0 +1,373 @@
+//! Mempool batching individual transactions into transitions.
+//!
+//! Transactions submitted to the mempool are folded into one transition (one
+//! proof, one blob) per batch. A batch is closed once it holds
+//! [`AppNodeConfig::max_batch_size`](crate::AppNodeConfig::max_batch_size)
+//! transactions or
+//! [`AppNodeConfig::batch_interval`](crate::AppNodeConfig::batch_interval)
+//! after its first transaction arrived, and handed to the sequencer. Every
+//! transaction gets a receipt naming the transition it was included in.
+//!
+//! A batch's public and private inputs are the bincode-encoded lists of its
+//! transactions' inputs, in batch order. The operations are concatenated, so
+//! each transaction's verifiable operations are shifted to point at its own
+//! witnesses.
+//!
+//! Transactions of a hosted application are batched the same way. A batch
+//! holding any is queued as one [`Executor`] running each transaction in
+//! turn on the state the batch is applied to, so every transaction sees the
+//! writes of those before it.
+
+use crate::node::TransitionResult;
+use crate::sequencer::{Execution, Executor, Sequencer, SequencerError, TransitionRequest, Work};
+use merkle::Hash32;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use state::StateOp;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tracing::{info, warn};
+use transition_format::VerifiableOperation;
+
+/// A transaction waiting to be included in a transition.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Transaction {
+    /// State operations.
+    pub ops: Vec<StateOp>,
+    /// Public inputs.
+    pub public_inputs: Vec<u8>,
+    /// Private inputs.
+    pub private_inputs: Vec<u8>,
+    /// Operations checked by the guest, with witness indices relative to
+    /// this transaction's operations.
+    pub verifiable_ops: Vec<VerifiableOperation>,
+}
+
+impl Transaction {
+    /// Identifier of the transaction (SHA-256 of its bincode encoding).
+    pub fn id(&self) -> Hash32 {
+        let encoded = bincode::serialize(self).expect("encoding should not fail for a transaction");
+        Sha256::digest(encoded).into()
+    }
+}
+
+impl From<TransitionRequest> for Transaction {
+    fn from(request: TransitionRequest) -> Self {
+        Self {
+            ops: request.ops,
+            public_inputs: request.public_inputs,
+            private_inputs: request.private_inputs,
+            verifiable_ops: request.verifiable_ops,
+        }
+    }
+}
+
+/// Proof that a transaction was included in a committed transition.
+#[derive(Debug, Clone)]
+pub struct InclusionReceipt {
+    /// Transaction identifier.
+    pub tx_id: Hash32,
+    /// Sequence of the transition.
+    pub sequence: u64,
+    /// Position of the transaction within the batch.
+    pub index: usize,
+    /// Number of transactions in the batch.
+    pub batch_size: usize,
+    /// State root after the transition.
+    pub new_root: Hash32,
+    /// Celestia height where the transition's blob was posted (`None` while
+    /// it is queued).
+    pub celestia_height: Option<u64>,
+}
+
+/// Handle for submitting transactions to the mempool.
+#[derive(Clone)]
+pub struct Mempool {
+    txs: mpsc::UnboundedSender<Pending>,
+}
+
+impl Mempool {
+    /// Add a transaction and wait for its inclusion.
+    pub async fn submit(&self, tx: Transaction) -> Result<InclusionReceipt, SequencerError> {
+        let (receipt, _) = self.queue(Submission::Ready(tx)).await?;
+        Ok(receipt)
+    }
+
+    /// Add a transaction of a hosted application and wait for its inclusion
+    /// and output.
+    ///
+    /// Its identifier is that of the [`Transaction`] it made.
+    pub async fn execute(
+        &self,
+        executor: Executor,
+    ) -> Result<(InclusionReceipt, Value), SequencerError> {
+        self.queue(Submission::Execute(executor)).await
+    }
+
+    async fn queue(
+        &self,
+        submission: Submission,
+    ) -> Result<(InclusionReceipt, Value), SequencerError> {
+        let (reply, receipt) = oneshot::channel();
+        self.txs
+            .send(Pending { submission, reply })
+            .map_err(|_| SequencerError::Stopped)?;
+        receipt.await.map_err(|_| SequencerError::Stopped)?
+    }
+}
+
+/// A submitted transaction.
+#[derive(Clone)]
+enum Submission {
+    /// Ready-made operations.
+    Ready(Transaction),
+    /// A transaction of a hosted application, executed when its batch is
+    /// staged.
+    Execute(Executor),
+}
+
+/// A transaction and where to send its receipt (with the application's
+/// output, `null` for ready-made transactions).
+struct Pending {
+    submission: Submission,
+    reply: oneshot::Sender<Result<(InclusionReceipt, Value), SequencerError>>,
+}
+
+/// Identifier and output of an executed transaction, in the output of its
+/// batch.
+#[derive(Serialize, Deserialize)]
+struct Executed {
+    tx_id: Hash32,
+    output: Value,
+}
+
+/// Start the batcher.
+///
+/// It stops once every [`Mempool`] handle is dropped.
+pub(crate) fn spawn(
+    sequencer: Sequencer,
+    batch_interval: Duration,
+    max_batch_size: usize,
+) -> Mempool {
+    let (txs, pending) = mpsc::unbounded_channel();
+    tokio::spawn(run(
+        sequencer,
+        pending,
+        batch_interval,
+        max_batch_size.max(1),
+    ));
+    Mempool { txs }
+}
+
+async fn run(
+    sequencer: Sequencer,
+    mut pending: mpsc::UnboundedReceiver<Pending>,
+    batch_interval: Duration,
+    max_batch_size: usize,
+) {
+    while let Some(first) = pending.recv().await {
+        let mut batch = vec![first];
+        let deadline = tokio::time::sleep(batch_interval);
+        tokio::pin!(deadline);
+
+        while batch.len() < max_batch_size {
+            tokio::select! {
+                tx = pending.recv() => match tx {
+                    Some(tx) => batch.push(tx),
+                    None => break,
+                },
+                _ = &mut deadline => break,
+            }
+        }
+
+        dispatch(&sequencer, batch);
+    }
+}
+
+/// Queue a batch with the sequencer and answer its transactions once it is
+/// committed.
+///
+/// If the batch fails, its transactions are queued again one per
+/// transition, so one bad transaction does not fail the others. They are
+/// sequenced after any batches queued in the meantime.
+fn dispatch(sequencer: &Sequencer, batch: Vec<Pending>) {
+    let (submissions, replies): (Vec<Submission>, Vec<_>) =
+        batch.into_iter().map(|p| (p.submission, p.reply)).unzip();
+    info!("Sequencing a batch of {} transactions", submissions.len());
+
+    let receipts = enqueue(sequencer, submissions.clone());
+    let sequencer = sequencer.clone();
+    tokio::spawn(async move {
+        let result = match receipts {
+            Ok(receipts) => receipts.await,
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Ok(receipts) => {
+                for (receipt, reply) in receipts.into_iter().zip(replies) {
+                    let _ = reply.send(Ok(receipt));
+                }
+            }
+            Err(e) if submissions.len() > 1 => {
+                warn!(
+                    "Batch of {} transactions failed, applying them one by one: {}",
+                    submissions.len(),
+                    e
+                );
+                let receipts: Vec<_> = submissions
+                    .into_iter()
+                    .map(|submission| enqueue(&sequencer, vec![submission]))
+                    .collect();
+                for (reply, receipts) in replies.into_iter().zip(receipts) {
+                    let result = match receipts {
+                        Ok(receipts) => receipts.await,
+                        Err(e) => Err(e),
+                    };
+                    let _ = reply.send(result.map(|mut receipts| receipts.remove(0)));
+                }
+            }
+            Err(e) => {
+                for reply in replies {
+                    let _ = reply.send(Err(e.clone()));
+                }
+            }
+        }
+    });
+}
+
+/// Queue transactions as one transition and return their receipts, in
+/// batch order, as a future.
+fn enqueue(
+    sequencer: &Sequencer,
+    submissions: Vec<Submission>,
+) -> Result<
+    impl Future<Output = Result<Vec<(InclusionReceipt, Value)>, SequencerError>>,
+    SequencerError,
+> {
+    let batch_size = submissions.len();
+    let ready: Option<Vec<Transaction>> = submissions
+        .iter()
+        .map(|submission| match submission {
+            Submission::Ready(tx) => Some(tx.clone()),
+            Submission::Execute(_) => None,
+        })
+        .collect();
+
+    // Without application transactions the operations are known already
+    let receipt = match &ready {
+        Some(txs) => sequencer.queue(Work::Apply(batch_request(txs)))?,
+        None => sequencer.queue(Work::Execute(batch_executor(submissions)))?,
+    };
+
+    Ok(async move {
+        let (result, output) = receipt.await?;
+        let executed: Vec<Executed> = match ready {
+            Some(txs) => txs
+                .iter()
+                .map(|tx| Executed {
+                    tx_id: tx.id(),
+                    output: Value::Null,
+                })
+                .collect(),
+            None => serde_json::from_value(output)
+                .expect("batch executors output the executed transactions"),
+        };
+
+        Ok(executed
+            .into_iter()
+            .enumerate()
+            .map(|(index, executed)| {
+                let receipt = inclusion_receipt(executed.tx_id, index, batch_size, &result);
+                (receipt, executed.output)
+            })
+            .collect())
+    })
+}
+
+/// Build the sequencer job running a batch's transactions in turn on the
+/// state it is applied to.
+///
+/// Ready-made operations are written before the next transaction runs. The
+/// output lists the identifier and output of every transaction.
+fn batch_executor(submissions: Vec<Submission>) -> Executor {
+    Arc::new(move |store| {
+        let mut txs = Vec::with_capacity(submissions.len());
+        let mut outputs = Vec::with_capacity(submissions.len());
+        for submission in &submissions {
+            match submission {
+                Submission::Ready(tx) => {
+                    store
+                        .apply_batch(tx.ops.clone())
+                        .map_err(|e| e.to_string())?;
+                    txs.push(tx.clone());
+                    outputs.push(Value::Null);
+                }
+                Submission::Execute(executor) => {
+                    let execution = executor(store)?;
+                    txs.push(Transaction::from(execution.request));
+                    outputs.push(execution.output);
+                }
+            }
+        }
+
+        let executed: Vec<Executed> = txs
+            .iter()
+            .zip(outputs)
+            .map(|(tx, output)| Executed {
+                tx_id: tx.id(),
+                output,
+            })
+            .collect();
+        Ok(Execution {
+            request: batch_request(&txs),
+            output: serde_json::to_value(executed).map_err(|e| e.to_string())?,
+        })
+    })
+}
+
+/// Fold transactions into one transition request.
+fn batch_request(txs: &[Transaction]) -> TransitionRequest {
+    let mut ops = Vec::new();
+    let mut verifiable_ops = Vec::new();
+    for tx in txs {
+        let offset = ops.len();
+        verifiable_ops.extend(tx.verifiable_ops.iter().cloned().map(|mut vop| {
+            vop.witness_index += offset;
+            vop
+        }));
+        ops.extend(tx.ops.iter().cloned());
+    }
+
+    TransitionRequest {
+        ops,
+        public_inputs: encode_inputs(txs.iter().map(|tx| &tx.public_inputs)),
+        private_inputs: encode_inputs(txs.iter().map(|tx| &tx.private_inputs)),
+        verifiable_ops,
+    }
+}
+
+/// Encode the transactions' inputs as one list.
+fn encode_inputs<'a>(inputs: impl Iterator<Item = &'a Vec<u8>>) -> Vec<u8> {
+    let inputs: Vec<&Vec<u8>> = inputs.collect();
+    bincode::serialize(&inputs).expect("encoding should not fail for byte lists")
+}
+
+fn inclusion_receipt(
+    tx_id: Hash32,
+    index: usize,
+    batch_size: usize,
+    result: &TransitionResult,
+) -> InclusionReceipt {
+    InclusionReceipt {
+        tx_id,
+        sequence: result.sequence,
+        index,
+        batch_size,
+        new_root: result.new_root,
+        celestia_height: result.celestia_height,
+    }
+}
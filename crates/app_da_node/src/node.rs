@@ -1,15 +1,23 @@
 //! Core app node implementation.
 
+use crate::application::{self, HostedApplication};
+use crate::deferred::{self, DeferredQueue};
+use crate::mempool::{self, InclusionReceipt, Mempool, Transaction};
+use crate::outbox::{self, OutboxSignal};
+use crate::sequencer::{self, Sequencer, SequencerError, TransitionRequest};
 use anyhow::Result;
-use blob_schema::TransitionBlobV1;
-use celestia_adapter::{CelestiaClient, Namespace};
-use merkle::{Hash32, MerkleProof};
-use state::{StateOp, StateStore};
+use blob_schema::TransitionBlob;
+use celestia_adapter::{CelestiaClient, Namespace, SubmitResult};
+use merkle::{Hash32, HashFunction, MerkleProof};
+use sdk::Application;
+use serde_json::Value;
+use state::{OutboxEntry, StagedTransition, StateExport, StateOp, StateStore, TransitionRecord};
 use std::path::PathBuf;
 use std::sync::Arc;
-use tokio::sync::RwLock;
-use tracing::{debug, info};
-use transition_format::{TransitionInput, VerifiableOperation};
+use std::time::Duration;
+use tokio::sync::{Mutex, RwLock};
+use tracing::{info, warn};
+use transition_format::{SetPolicy, TransitionInput, VerifiableOperation};
 use zk_host_harness::{program_hash, TransitionProver};
 
 /// Configuration for the app node.
@@ -27,6 +35,38 @@ pub struct AppNodeConfig {
     pub celestia_enabled: bool,
     /// Whether to generate real proofs (disable for faster testing).
     pub proving_enabled: bool,
+    /// Hash function of the state tree (fixed once the store is created).
+    pub hash_function: HashFunction,
+    /// Rewrite stored values that do not match the committed tree on startup
+    /// instead of refusing to start.
+    pub repair_state: bool,
+    /// With proving disabled, prove posted transitions in the background and
+    /// post proof attachment blobs for them.
+    pub deferred_proving: bool,
+    /// Maximum number of transitions the sequencer proves in parallel.
+    pub max_in_flight: usize,
+    /// Longest time a mempool batch waits for more transactions after its
+    /// first one.
+    pub batch_interval: Duration,
+    /// Maximum number of transactions per mempool batch.
+    pub max_batch_size: usize,
+    /// Number of past transitions whose state stays queryable and can be
+    /// reverted or reproven; older history is pruned after each commit. If
+    /// None, all history is kept.
+    pub history_window: Option<u64>,
+    /// Whether API clients may submit ready-made operations (`/transition`
+    /// and `/tx`). If None, only while no application is hosted, so every
+    /// transition over the API of a hosted application comes from it.
+    pub raw_transitions: Option<bool>,
+    /// Whether transitions may write without business rules. Under
+    /// [`SetPolicy::Allow`] writes no operation declares are declared as
+    /// `Set` operations; under [`SetPolicy::Deny`] they are rejected. If
+    /// None, `Deny` while an application is hosted (so raw writes cannot
+    /// bypass its rules) and `Allow` otherwise.
+    pub set_policy: Option<SetPolicy>,
+    /// ed25519 public key allowed to sign mints. Without one, transitions
+    /// minting tokens are rejected.
+    pub mint_authority: Option<[u8; 32]>,
 }
 
 impl Default for AppNodeConfig {
@@ -38,6 +78,16 @@ impl Default for AppNodeConfig {
             celestia_rpc: celestia_adapter::DEFAULT_RPC_URL.to_string(),
             celestia_enabled: true,
             proving_enabled: true,
+            hash_function: HashFunction::default(),
+            repair_state: false,
+            deferred_proving: false,
+            max_in_flight: 4,
+            batch_interval: Duration::from_secs(1),
+            max_batch_size: 100,
+            history_window: None,
+            raw_transitions: None,
+            set_policy: None,
+            mint_authority: None,
         }
     }
 }
@@ -52,8 +102,22 @@ pub struct AppNodeState {
     pub prover: TransitionProver,
     /// Configuration.
     pub config: AppNodeConfig,
-    /// Historical roots (sequence -> (root, celestia_height)).
-    pub root_history: Vec<(Hash32, Option<u64>)>,
+    /// Held from staging a transition until it is committed or discarded,
+    /// so transitions proven outside the state lock do not interleave.
+    pub transition_lock: Arc<Mutex<()>>,
+    /// Held while a queued blob is posted, so the sequencer and the
+    /// submitter never post the same blob twice.
+    pub posting_lock: Arc<Mutex<()>>,
+    /// Background prover queue (deferred proving only).
+    pub deferred_prover: Option<DeferredQueue>,
+    /// Wakes the submitter of queued blobs (Celestia posting only).
+    pub outbox: Option<OutboxSignal>,
+    /// Queue of the transition sequencer.
+    pub sequencer: Option<Sequencer>,
+    /// Transaction mempool, batching into the sequencer.
+    pub mempool: Option<Mempool>,
+    /// Application executing typed transactions, if one is hosted.
+    pub application: Option<Arc<dyn HostedApplication>>,
 }
 
 /// The application DA node.
@@ -68,9 +132,14 @@ impl AppNode {
         // Create data directory if needed
         std::fs::create_dir_all(&config.data_dir)?;
 
-        // Open state store
-        let store = StateStore::open(config.data_dir.join("state"))?;
-        let initial_root = store.root();
+        // Open state store (recomputes the root from all values unless the
+        // last shutdown was clean, and always when repairing)
+        let state_dir = config.data_dir.join("state");
+        let store = if config.repair_state {
+            StateStore::open_repaired(state_dir, config.hash_function)?
+        } else {
+            StateStore::open_with_hasher(state_dir, config.hash_function)?
+        };
 
         // Create Celestia client
         let celestia = CelestiaClient::with_url(&config.celestia_rpc);
@@ -78,41 +147,79 @@ impl AppNode {
         // Create prover
         let prover = TransitionProver::new();
 
-        // Initialize root history with genesis
-        let root_history = vec![(initial_root, None)];
-
-        let state = AppNodeState {
+        Self::start(AppNodeState {
             store,
             celestia,
             prover,
             config,
-            root_history,
-        };
-
-        Ok(Self {
-            state: Arc::new(RwLock::new(state)),
+            transition_lock: Arc::new(Mutex::new(())),
+            posting_lock: Arc::new(Mutex::new(())),
+            deferred_prover: None,
+            outbox: None,
+            sequencer: None,
+            mempool: None,
+            application: None,
         })
+        .await
     }
 
     /// Create an in-memory node for testing.
     pub async fn in_memory(config: AppNodeConfig) -> Result<Self> {
-        let store = StateStore::in_memory()?;
-        let initial_root = store.root();
+        let store = StateStore::in_memory_with_hasher(config.hash_function)?;
         let celestia = CelestiaClient::with_url(&config.celestia_rpc);
         let prover = TransitionProver::new();
-        let root_history = vec![(initial_root, None)];
 
-        let state = AppNodeState {
+        Self::start(AppNodeState {
             store,
             celestia,
             prover,
             config,
-            root_history,
-        };
-
-        Ok(Self {
-            state: Arc::new(RwLock::new(state)),
+            transition_lock: Arc::new(Mutex::new(())),
+            posting_lock: Arc::new(Mutex::new(())),
+            deferred_prover: None,
+            outbox: None,
+            sequencer: None,
+            mempool: None,
+            application: None,
         })
+        .await
+    }
+
+    /// Wrap the state and start the background tasks: the sequencer and the
+    /// mempool feeding it, the submitter of queued blobs if posting is enabled, and the background
+    /// prover if it is enabled, queueing posted transitions that are still
+    /// unproven.
+    async fn start(state: AppNodeState) -> Result<Self> {
+        let config = &state.config;
+        let posting = config.celestia_enabled;
+        let deferred = config.deferred_proving && !config.proving_enabled && posting;
+        let max_in_flight = config.max_in_flight;
+        let (batch_interval, max_batch_size) = (config.batch_interval, config.max_batch_size);
+        let state = Arc::new(RwLock::new(state));
+
+        let sequencer = sequencer::spawn(Arc::downgrade(&state), max_in_flight);
+        let mempool = mempool::spawn(sequencer.clone(), batch_interval, max_batch_size);
+        {
+            let mut state = state.write().await;
+            state.sequencer = Some(sequencer);
+            state.mempool = Some(mempool);
+        }
+
+        if posting {
+            let signal = outbox::spawn(Arc::downgrade(&state));
+            state.write().await.outbox = Some(signal);
+        }
+
+        if deferred {
+            let queue = deferred::spawn(Arc::downgrade(&state));
+            let mut state = state.write().await;
+            for sequence in deferred::unproven(&state)? {
+                queue.send(sequence)?;
+            }
+            state.deferred_prover = Some(queue);
+        }
+
+        Ok(Self { state })
     }
 
     /// Get the shared state.
@@ -145,6 +252,12 @@ impl AppNode {
     }
 
     /// Apply a batch of operations and generate a proof.
+    ///
+    /// Goes through the sequencer, so concurrent calls are committed in the
+    /// order they were submitted. The state is only committed once the
+    /// transition has been proven; on failure it stays at the previous root.
+    /// The blob is queued with the commit and posted in order by the
+    /// outbox's submitter, so the result carries no Celestia height.
     pub async fn apply_transition(
         &self,
         ops: Vec<StateOp>,
@@ -152,107 +265,387 @@ impl AppNode {
         private_inputs: Vec<u8>,
         verifiable_ops: Vec<VerifiableOperation>,
     ) -> Result<TransitionResult> {
+        info!("Applying transition: {} operations", ops.len());
+
+        let sequencer = self.state.read().await.sequencer()?;
+        let result = sequencer
+            .submit(TransitionRequest {
+                ops,
+                public_inputs,
+                private_inputs,
+                verifiable_ops,
+            })
+            .await?;
+        Ok(result)
+    }
+
+    /// Submit a single transaction to the mempool and wait until the batch
+    /// including it is committed.
+    pub async fn submit_transaction(&self, tx: Transaction) -> Result<InclusionReceipt> {
+        let mempool = self.state.read().await.mempool()?;
+        Ok(mempool.submit(tx).await?)
+    }
+
+    /// Host an application; its transactions can then be submitted with
+    /// [`Self::execute`].
+    pub async fn set_application(&self, app: impl Application + Send + Sync + 'static) {
+        self.state.write().await.application = Some(Arc::new(app));
+    }
+
+    /// Submit a typed transaction of the hosted application to the mempool
+    /// and wait until the batch including it is committed.
+    ///
+    /// The application runs on the state the transaction is applied to,
+    /// after the transactions batched before it, and the operations and
+    /// witnesses are derived from it. Returns the inclusion receipt with the
+    /// application's output.
+    pub async fn execute(
+        &self,
+        public: Value,
+        private: Value,
+    ) -> Result<(InclusionReceipt, Value)> {
+        let (app, mempool) = {
+            let state = self.state.read().await;
+            let app = state
+                .application
+                .clone()
+                .ok_or_else(|| anyhow::anyhow!("no application is hosted"))?;
+            (app, state.mempool()?)
+        };
+        Ok(mempool
+            .execute(application::executor(app, public, private))
+            .await?)
+    }
+
+    /// Revert the state and root history to an earlier sequence.
+    ///
+    /// Blobs already posted to Celestia are not touched; transitions applied
+    /// afterwards reuse the reverted sequence numbers.
+    pub async fn revert_to(&self, sequence: u64) -> Result<Hash32> {
+        let transition_lock = Arc::clone(&self.state.read().await.transition_lock);
+        let _transition = transition_lock.lock().await;
         let mut state = self.state.write().await;
 
-        let prev_root = state.store.root();
-        let sequence = state.store.transition_index() + 1;
+        let root = state.store.revert_to(sequence)?;
+
+        info!("Reverted to sequence {}: {}", sequence, hex::encode(root));
+        Ok(root)
+    }
+
+    /// Check every stored value against the committed state tree.
+    ///
+    /// Returns the checked root. Reads the whole state; opening the node
+    /// after a clean shutdown only checks the latest commit.
+    pub async fn verify_state(&self) -> Result<Hash32> {
+        let state = self.state.read().await;
+        state.store.check_integrity()?;
+        Ok(state.store.root())
+    }
 
-        info!("Applying transition {}: {} operations", sequence, ops.len());
+    /// Export the committed state for a snapshot file.
+    pub async fn export_snapshot(&self) -> Result<StateExport> {
+        let state = self.state.read().await;
+        Ok(state.store.export()?)
+    }
 
-        // Apply operations and collect witnesses
-        let witnesses = state.store.apply_batch(ops)?;
+    /// Bootstrap the node's empty state from a snapshot.
+    ///
+    /// The snapshot's entries must give its root; the node then continues
+    /// from its transition index.
+    pub async fn import_snapshot(&self, export: &StateExport) -> Result<Hash32> {
+        let transition_lock = Arc::clone(&self.state.read().await.transition_lock);
+        let _transition = transition_lock.lock().await;
+        let mut state = self.state.write().await;
 
-        // Commit the state changes
-        let new_root = state.store.commit()?;
+        let root = state.store.import(export)?;
 
-        debug!(
-            "State updated: {} -> {}",
-            hex::encode(prev_root),
-            hex::encode(new_root)
+        info!(
+            "Imported snapshot at sequence {}: {}",
+            export.transition_index,
+            hex::encode(root)
         );
+        Ok(root)
+    }
+
+    /// Prove the journaled transitions from `from` to the latest one again.
+    ///
+    /// For transitions applied with proving disabled or whose proofs were
+    /// lost. Always proves, whatever the config says. The state is not
+    /// changed. A transition already on Celestia gets a proof attachment
+    /// for its posted blob; a blob still queued for posting is replaced by
+    /// a proven one. The transition lock is not held while proving, so the
+    /// node keeps committing transitions meanwhile.
+    pub async fn reprove(&self, from: u64) -> Result<Vec<ReprovedTransition>> {
+        let latest = self.state.read().await.store.transition_index();
+        if from == 0 || from > latest {
+            anyhow::bail!("no transition {} to reprove (latest is {})", from, latest);
+        }
 
-        // Build transition input with operations for business logic verification
-        let input =
-            TransitionInput::new(prev_root, public_inputs.clone(), private_inputs, witnesses)
-                .with_operations(verifiable_ops);
+        let mut results = Vec::new();
+        for sequence in from..=latest {
+            let (input, prev_root, new_root) = {
+                let state = self.state.read().await;
+                let journal = state.store.journal_entry(sequence)?.ok_or_else(|| {
+                    anyhow::anyhow!("no journal entry for transition {}", sequence)
+                })?;
+                (
+                    TransitionInput::decode(&journal)?,
+                    state.store.at_sequence(sequence - 1)?.root(),
+                    state.store.at_sequence(sequence)?.root(),
+                )
+            };
+
+            info!("Reproving transition {}", sequence);
+            let public_inputs = input.public_inputs.clone();
+            let proof = tokio::task::spawn_blocking(move || TransitionProver::new().prove(&input))
+                .await??;
+            if proof.output.prev_root != prev_root || proof.output.new_root != new_root {
+                anyhow::bail!("proof output mismatch for transition {}", sequence);
+            }
+            let proof_bytes = proof.proof_bytes;
 
-        // Generate proof (or just execute for testing)
-        let (proof_bytes, output) = if state.config.proving_enabled {
-            let result = state.prover.prove(&input)?;
-            (result.proof_bytes, result.output)
-        } else {
-            let output = state.prover.execute(&input)?;
-            (Vec::new(), output)
-        };
+            let mut state = self.state.write().await;
+            if state.store.at_sequence(sequence - 1)?.root() != prev_root
+                || state.store.at_sequence(sequence)?.root() != new_root
+            {
+                anyhow::bail!("transition {} was reverted while being reproven", sequence);
+            }
+            let record = state.store.transition_record(sequence)?;
+            let posted = record.filter(|record| record.celestia_height.is_some());
+            let blob = state.transition_blob(
+                sequence,
+                prev_root,
+                new_root,
+                public_inputs,
+                proof_bytes.clone(),
+            );
+            let mut result = ReprovedTransition {
+                sequence,
+                new_root,
+                proof_bytes,
+                attached: posted.is_some(),
+                celestia_height: None,
+            };
+
+            if let Some(record) = posted {
+                // A new blob would post the sequence twice: attach the
+                // proof to the posted blob instead
+                let blob_hash = record
+                    .blob_hash
+                    .ok_or_else(|| anyhow::anyhow!("transition {} has no posted blob", sequence))?;
+                let (celestia, namespace, app_id) = (
+                    state.celestia.clone(),
+                    state.config.namespace.clone(),
+                    state.config.app_id.clone(),
+                );
+                drop(state);
+                let posted = deferred::post_attachment(
+                    &celestia,
+                    &namespace,
+                    app_id,
+                    sequence,
+                    blob_hash,
+                    result.proof_bytes.clone(),
+                )
+                .await?;
+                info!(
+                    "Proof of transition {} attached at height {}",
+                    sequence, posted.height
+                );
+                result.celestia_height = Some(posted.height);
+                let mut state = self.state.write().await;
+                deferred::mark_proven(&mut state.store, &record)?;
+            } else if state.store.outbox_entry(sequence)?.is_some() {
+                // The outbox posts the proven blob in the old one's place
+                state
+                    .store
+                    .update_transition_record(transition_record(&blob, None))?;
+                state
+                    .store
+                    .update_outbox_entry(&OutboxEntry::new(sequence, blob.encode()?))?;
+                state.wake_outbox();
+            } else if state.config.celestia_enabled {
+                let (celestia, namespace) =
+                    (state.celestia.clone(), state.config.namespace.clone());
+                drop(state);
+                let posted = post_blob(&celestia, &namespace, &blob).await?;
+                result.celestia_height = Some(posted.height);
+                let mut state = self.state.write().await;
+                state
+                    .store
+                    .update_transition_record(transition_record(&blob, Some(&posted)))?;
+            } else {
+                state
+                    .store
+                    .update_transition_record(transition_record(&blob, None))?;
+            }
+
+            results.push(result);
+        }
 
-        // Verify the output matches our computation
-        assert_eq!(output.prev_root, prev_root);
-        assert_eq!(output.new_root, new_root);
+        Ok(results)
+    }
 
-        // Create blob
-        let blob = TransitionBlobV1::new(
-            state.config.app_id.clone(),
+    /// Get the blobs queued for posting, oldest first.
+    pub async fn outbox(&self) -> Result<Vec<OutboxEntry>> {
+        let state = self.state.read().await;
+        Ok(state.store.outbox_entries()?)
+    }
+
+    /// Post the queued blobs now instead of waiting for the submitter,
+    /// stopping at the first failure. Returns how many are still queued.
+    pub async fn post_queued(&self) -> Result<usize> {
+        outbox::post_queued(&self.state).await
+    }
+
+    /// Get root history as (sequence, root, celestia height).
+    pub async fn root_history(&self) -> Result<Vec<(u64, Hash32, Option<u64>)>> {
+        let state = self.state.read().await;
+        Ok(state.store.root_history()?)
+    }
+}
+
+impl AppNodeState {
+    /// Build the blob of a transition, stamped with the current time.
+    pub(crate) fn transition_blob(
+        &self,
+        sequence: u64,
+        prev_root: Hash32,
+        new_root: Hash32,
+        public_inputs: Vec<u8>,
+        proof_bytes: Vec<u8>,
+    ) -> TransitionBlob {
+        TransitionBlob::new(
+            self.config.app_id.clone(),
             sequence,
             prev_root,
             new_root,
             public_inputs,
-            proof_bytes.clone(),
+            proof_bytes,
             program_hash(),
         )
+        .with_hash_function(self.store.hash_function())
         .with_timestamp(
             std::time::SystemTime::now()
                 .duration_since(std::time::UNIX_EPOCH)
                 .unwrap()
                 .as_secs(),
-        );
+        )
+    }
 
-        // Post to Celestia if enabled
-        let celestia_result = if state.config.celestia_enabled {
-            let blob_bytes = blob.encode()?;
-            info!("Posting blob to Celestia: {} bytes", blob_bytes.len());
-            match state
-                .celestia
-                .submit_blob(&state.config.namespace, &blob_bytes)
-                .await
-            {
-                Ok(result) => {
-                    info!("Blob posted at height {}", result.height);
-                    Some(result)
-                }
-                Err(e) => {
-                    tracing::warn!("Failed to post to Celestia: {}", e);
-                    None
-                }
+    /// Queue a posted transition without a proof for the background prover.
+    ///
+    /// The transition is already committed, so a stopped prover is only
+    /// logged; it picks the transition up again on the next start.
+    pub(crate) fn defer_proof(&self, blob: &TransitionBlob, celestia: Option<&SubmitResult>) {
+        if let (Some(queue), Some(_), true) =
+            (&self.deferred_prover, celestia, blob.proof.is_empty())
+        {
+            if queue.send(blob.sequence).is_err() {
+                warn!(
+                    "Background prover stopped, transition {} stays unproven",
+                    blob.sequence
+                );
             }
-        } else {
-            None
-        };
+        }
+    }
 
-        // Update root history
-        state
-            .root_history
-            .push((new_root, celestia_result.as_ref().map(|r| r.height)));
+    /// Whether API clients may submit ready-made operations.
+    pub fn raw_transitions(&self) -> bool {
+        self.config
+            .raw_transitions
+            .unwrap_or(self.application.is_none())
+    }
 
-        Ok(TransitionResult {
-            sequence,
-            prev_root,
-            new_root,
-            proof_bytes,
-            blob,
-            celestia_height: celestia_result.as_ref().map(|r| r.height),
+    /// Set policy transitions are proven under.
+    pub fn set_policy(&self) -> SetPolicy {
+        self.config.set_policy.unwrap_or(match self.application {
+            Some(_) => SetPolicy::Deny,
+            None => SetPolicy::Allow,
         })
     }
 
-    /// Get root history.
-    pub async fn root_history(&self) -> Vec<(u64, Hash32, Option<u64>)> {
-        let state = self.state.read().await;
-        state
-            .root_history
-            .iter()
-            .enumerate()
-            .map(|(i, (root, height))| (i as u64, *root, *height))
-            .collect()
+    /// Get the sequencer handle.
+    pub fn sequencer(&self) -> Result<Sequencer, SequencerError> {
+        self.sequencer.clone().ok_or(SequencerError::Stopped)
     }
+
+    /// Get the mempool handle.
+    pub fn mempool(&self) -> Result<Mempool, SequencerError> {
+        self.mempool.clone().ok_or(SequencerError::Stopped)
+    }
+
+    /// Queue the encoded blob with the commit of `staged` if posting is
+    /// enabled.
+    pub(crate) fn queue_for_posting(
+        &self,
+        staged: StagedTransition,
+        blob_bytes: Vec<u8>,
+    ) -> StagedTransition {
+        if self.config.celestia_enabled {
+            staged.with_outbox(blob_bytes)
+        } else {
+            staged
+        }
+    }
+
+    /// Wake the submitter to post newly queued blobs.
+    ///
+    /// Queued blobs are stored, so a stopped submitter only delays them
+    /// until the next start.
+    pub(crate) fn wake_outbox(&self) {
+        if let Some(signal) = &self.outbox {
+            if signal.send(()).is_err() {
+                warn!("Outbox submitter stopped, queued blobs are posted on the next start");
+            }
+        }
+    }
+}
+
+/// Post a blob to Celestia.
+async fn post_blob(
+    celestia: &CelestiaClient,
+    namespace: &Namespace,
+    blob: &TransitionBlob,
+) -> Result<SubmitResult> {
+    let blob_bytes = blob.encode()?;
+    info!("Posting blob to Celestia: {} bytes", blob_bytes.len());
+    let result = celestia
+        .submit_blob(namespace, &blob_bytes)
+        .await
+        .map_err(|e| anyhow::Error::from(e).context("failed to post to Celestia"))?;
+    info!("Blob posted at height {}", result.height);
+    Ok(result)
+}
+
+/// Build the record of a transition from its blob and where it was posted.
+pub(crate) fn transition_record(
+    blob: &TransitionBlob,
+    celestia: Option<&SubmitResult>,
+) -> TransitionRecord {
+    let record = TransitionRecord::new(blob.sequence, blob.prev_root, blob.new_root)
+        .with_blob(blob.hash(), blob.program_hash)
+        .with_timestamp(blob.timestamp)
+        .with_proven(!blob.proof.is_empty());
+    match celestia {
+        Some(result) => record.with_celestia(result.height, result.commitment.clone()),
+        None => record,
+    }
+}
+
+/// Result of reproving a transition.
+#[derive(Debug)]
+pub struct ReprovedTransition {
+    /// Sequence number of the transition.
+    pub sequence: u64,
+    /// State root after the transition.
+    pub new_root: Hash32,
+    /// Proof bytes.
+    pub proof_bytes: Vec<u8>,
+    /// Whether the proof was attached to a blob already on Celestia.
+    pub attached: bool,
+    /// Celestia height of the new blob or attachment (if posted).
+    pub celestia_height: Option<u64>,
 }
 
 /// Result of applying a transition.
@@ -267,7 +660,7 @@ pub struct TransitionResult {
     /// Proof bytes (empty if proving disabled).
     pub proof_bytes: Vec<u8>,
     /// The blob that was/would be posted.
-    pub blob: TransitionBlobV1,
-    /// Celestia height where blob was posted (if posted).
+    pub blob: TransitionBlob,
+    /// Celestia height where blob was posted (`None` while it is queued).
     pub celestia_height: Option<u64>,
 }
@@ -0,0 +1,182 @@
+//! Posting of queued transition blobs to Celestia.
+//!
+//! With posting enabled, every transition is committed with its blob in the
+//! store's outbox, so no blob is on Celestia without its commit. The
+//! sequencer only queues the blob and wakes the submitter, which posts the
+//! queued blobs strictly in sequence order, retrying the oldest with
+//! exponential backoff, and records where each one landed.
+
+use crate::node::{transition_record, AppNodeState};
+use anyhow::Result;
+use blob_schema::TransitionBlob;
+use state::OutboxEntry;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use tokio::sync::{mpsc, RwLock};
+use tracing::{info, warn};
+
+/// Wakes the submitter after a blob has been queued.
+pub type OutboxSignal = mpsc::UnboundedSender<()>;
+
+/// Delay before the first retry; doubled after every failed attempt.
+const RETRY_BASE: Duration = Duration::from_secs(1);
+/// Longest delay between attempts.
+const RETRY_MAX: Duration = Duration::from_secs(300);
+
+/// Outcome of one pass of the submitter.
+enum Submission {
+    /// The oldest queued blob was posted (or dropped by a revert).
+    Posted,
+    /// Nothing is queued.
+    Empty,
+    /// Posting the oldest queued blob failed.
+    Failed { attempts: u32 },
+}
+
+/// Start the submitter.
+///
+/// It drains the outbox right away, so blobs queued before a restart are
+/// posted first, and stops once the node state is dropped.
+pub(crate) fn spawn(state: Weak<RwLock<AppNodeState>>) -> OutboxSignal {
+    let (signal, mut wake) = mpsc::unbounded_channel();
+
+    tokio::spawn(async move {
+        loop {
+            let Some(node) = state.upgrade() else {
+                break;
+            };
+            let submission = submit_next(&node).await;
+            drop(node);
+
+            match submission {
+                Ok(Submission::Posted) => {}
+                Ok(Submission::Empty) => {
+                    if wake.recv().await.is_none() {
+                        break;
+                    }
+                }
+                Ok(Submission::Failed { attempts }) => {
+                    tokio::time::sleep(retry_delay(attempts)).await;
+                }
+                Err(e) => {
+                    warn!("Outbox submitter failed: {:#}", e);
+                    tokio::time::sleep(RETRY_MAX).await;
+                }
+            }
+        }
+    });
+
+    signal
+}
+
+/// Post the queued blobs in order until the outbox is empty or a post
+/// fails, returning how many are still queued.
+///
+/// For callers that cannot wait for the submitter, e.g. before exiting.
+pub(crate) async fn post_queued(state: &RwLock<AppNodeState>) -> Result<usize> {
+    while let Submission::Posted = submit_next(state).await? {}
+    Ok(state.read().await.store.outbox_entries()?.len())
+}
+
+/// Post the oldest queued blob.
+async fn submit_next(state: &RwLock<AppNodeState>) -> Result<Submission> {
+    let posting_lock = Arc::clone(&state.read().await.posting_lock);
+    let _posting = posting_lock.lock().await;
+
+    let (entry, namespace, celestia) = {
+        let state = state.read().await;
+        let Some(entry) = state.store.outbox_entries()?.into_iter().next() else {
+            return Ok(Submission::Empty);
+        };
+        (
+            entry,
+            state.config.namespace.clone(),
+            state.celestia.clone(),
+        )
+    };
+
+    let submitted = celestia.submit_blob(&namespace, &entry.blob).await;
+
+    let mut state = state.write().await;
+    if let (Ok(result), true) = (&submitted, replaced(&state, &entry)?) {
+        // Reproving swapped in a proven blob of the same transition while
+        // the old one was being posted: the posted one stands
+        let blob = TransitionBlob::decode(&entry.blob)?;
+        state
+            .store
+            .complete_outbox_entry(transition_record(&blob, Some(result)))?;
+        warn!(
+            "Transition {} was reproven while its old blob was being posted at height {}, reprove it again to attach the proof",
+            entry.sequence, result.height
+        );
+        state.defer_proof(&blob, Some(result));
+        return Ok(Submission::Posted);
+    }
+    if !still_queued(&state, &entry)? {
+        warn!(
+            "Transition {} was reverted while its blob was being posted",
+            entry.sequence
+        );
+        return Ok(Submission::Posted);
+    }
+
+    match submitted {
+        Ok(result) => {
+            let blob = TransitionBlob::decode(&entry.blob)?;
+            state
+                .store
+                .complete_outbox_entry(transition_record(&blob, Some(&result)))?;
+            info!(
+                "Queued transition {} posted at height {}",
+                entry.sequence, result.height
+            );
+            state.defer_proof(&blob, Some(&result));
+            Ok(Submission::Posted)
+        }
+        Err(e) => {
+            let entry = entry.failed(e.to_string());
+            state.store.update_outbox_entry(&entry)?;
+            warn!(
+                "Posting transition {} failed (attempt {}), retrying in {:?}: {}",
+                entry.sequence,
+                entry.attempts,
+                retry_delay(entry.attempts),
+                e
+            );
+            Ok(Submission::Failed {
+                attempts: entry.attempts,
+            })
+        }
+    }
+}
+
+/// Whether `entry` is still the queued blob of its sequence (a revert may
+/// have dropped it, or replaced it with a new transition's blob).
+fn still_queued(state: &AppNodeState, entry: &OutboxEntry) -> Result<bool> {
+    Ok(state
+        .store
+        .outbox_entry(entry.sequence)?
+        .is_some_and(|queued| queued.blob == entry.blob))
+}
+
+/// Whether reproving replaced `entry` with another blob of the same
+/// transition (same roots).
+fn replaced(state: &AppNodeState, entry: &OutboxEntry) -> Result<bool> {
+    let Some(queued) = state.store.outbox_entry(entry.sequence)? else {
+        return Ok(false);
+    };
+    if queued.blob == entry.blob {
+        return Ok(false);
+    }
+    let (old, new) = (
+        TransitionBlob::decode(&entry.blob)?,
+        TransitionBlob::decode(&queued.blob)?,
+    );
+    Ok(old.prev_root == new.prev_root && old.new_root == new.new_root)
+}
+
+/// Delay after the given number of failed attempts.
+fn retry_delay(attempts: u32) -> Duration {
+    let factor = 1u32 << attempts.saturating_sub(1).min(16);
+    RETRY_BASE.saturating_mul(factor).min(RETRY_MAX)
+}
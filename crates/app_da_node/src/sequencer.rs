@@ -0,0 +1,420 @@
+//! Ordered pipeline for applying transitions.
+//!
+//! All transitions go through a single sequencer task. It stages each one on
+//! top of the transitions still in flight, proves up to
+//! [`AppNodeConfig::max_in_flight`](crate::AppNodeConfig::max_in_flight) of
+//! them in parallel, and commits them strictly in sequence order, queueing
+//! their blobs in the outbox for posting.
+//! If a transition fails, the ones staged on top of it are staged again on
+//! the committed state and proven again.
+//!
+//! Transactions of a hosted application are queued as [`Executor`]s instead
+//! of ready-made operations: the sequencer runs them on the state they will
+//! be applied to, so their operations and witnesses are derived server-side,
+//! and runs them again if they have to be staged again.
+
+use crate::node::{transition_record, AppNodeState, TransitionResult};
+use anyhow::anyhow;
+use merkle::Hash32;
+use serde_json::Value;
+use state::{StagedTransition, StateOp, StateStore};
+use std::collections::VecDeque;
+use std::future::Future;
+use std::sync::{Arc, Weak};
+use thiserror::Error;
+use tokio::sync::{mpsc, oneshot, OwnedMutexGuard, RwLock};
+use tokio::task::{JoinError, JoinHandle};
+use tracing::{debug, info, warn};
+use transition_format::{SetPolicy, TransitionInput, TransitionOutput, VerifiableOperation};
+use zk_host_harness::TransitionProver;
+
+/// Operations and inputs of a transition to apply.
+#[derive(Debug, Clone)]
+pub struct TransitionRequest {
+    /// State operations.
+    pub ops: Vec<StateOp>,
+    /// Public inputs (posted in the blob).
+    pub public_inputs: Vec<u8>,
+    /// Private inputs (only seen by the prover).
+    pub private_inputs: Vec<u8>,
+    /// Operations checked by the guest's business logic.
+    pub verifiable_ops: Vec<VerifiableOperation>,
+}
+
+/// Runs a transaction on the state it will be applied to and returns the
+/// transition it makes. Writes it leaves in the store are discarded.
+pub type Executor = Arc<dyn Fn(&mut StateStore) -> Result<Execution, String> + Send + Sync>;
+
+/// A transition derived by an [`Executor`].
+#[derive(Debug, Clone)]
+pub struct Execution {
+    /// Operations and inputs of the transition.
+    pub request: TransitionRequest,
+    /// Output returned to the submitter.
+    pub output: Value,
+}
+
+/// Why a transition was not applied.
+#[derive(Debug, Clone, Error)]
+pub enum SequencerError {
+    /// The operations break the guest's rules, so they would never be
+    /// proven; no sequence was assigned.
+    #[error("failed to apply operations: {0}")]
+    Rejected(String),
+
+    /// The state store failed while staging; no sequence was assigned.
+    #[error("failed to stage operations: {0}")]
+    Storage(String),
+
+    /// The application refused the transaction; no sequence was assigned.
+    #[error("transaction rejected by the application: {0}")]
+    Application(String),
+
+    /// The transition was discarded before commit.
+    #[error(
+        "transition {sequence} rolled back, state remains at root {}: {reason}",
+        hex::encode(.root)
+    )]
+    RolledBack {
+        sequence: u64,
+        /// Root the state stays at.
+        root: Hash32,
+        /// Root the transition would have produced.
+        discarded_root: Hash32,
+        operations: usize,
+        reason: String,
+    },
+
+    /// The sequencer is not running.
+    #[error("sequencer stopped")]
+    Stopped,
+}
+
+/// Handle for submitting transitions to the sequencer.
+#[derive(Clone)]
+pub struct Sequencer {
+    jobs: mpsc::UnboundedSender<Job>,
+}
+
+impl Sequencer {
+    /// Queue a transition and wait for its receipt.
+    pub async fn submit(
+        &self,
+        request: TransitionRequest,
+    ) -> Result<TransitionResult, SequencerError> {
+        self.enqueue(request)?.await
+    }
+
+    /// Queue a transition right away and return its receipt as a future.
+    ///
+    /// Transitions are sequenced in the order they were queued, so use this
+    /// instead of [`Self::submit`] when queueing from several tasks must
+    /// keep an order.
+    pub fn enqueue(
+        &self,
+        request: TransitionRequest,
+    ) -> Result<impl Future<Output = Result<TransitionResult, SequencerError>>, SequencerError>
+    {
+        let receipt = self.queue(Work::Apply(request))?;
+        Ok(async move { receipt.await.map(|(result, _)| result) })
+    }
+
+    /// Queue a transaction of a hosted application and wait for its receipt
+    /// and output.
+    pub async fn execute(
+        &self,
+        executor: Executor,
+    ) -> Result<(TransitionResult, Value), SequencerError> {
+        self.queue(Work::Execute(executor))?.await
+    }
+
+    /// Queue a job right away and return its receipt and output as a
+    /// future.
+    pub(crate) fn queue(
+        &self,
+        work: Work,
+    ) -> Result<
+        impl Future<Output = Result<(TransitionResult, Value), SequencerError>>,
+        SequencerError,
+    > {
+        let (reply, receipt) = oneshot::channel();
+        self.jobs
+            .send(Job { work, reply })
+            .map_err(|_| SequencerError::Stopped)?;
+        Ok(async move { receipt.await.map_err(|_| SequencerError::Stopped)? })
+    }
+}
+
+/// What a queued job applies.
+pub(crate) enum Work {
+    /// Ready-made operations.
+    Apply(TransitionRequest),
+    /// A transaction executed when it is staged.
+    Execute(Executor),
+}
+
+/// A queued transition and where to send its receipt.
+struct Job {
+    work: Work,
+    reply: oneshot::Sender<Result<(TransitionResult, Value), SequencerError>>,
+}
+
+/// A staged transition being proven.
+struct InFlight {
+    job: Job,
+    request: TransitionRequest,
+    output: Value,
+    staged: StagedTransition,
+    proving: JoinHandle<anyhow::Result<(Vec<u8>, TransitionOutput)>>,
+}
+
+/// Start the sequencer.
+///
+/// It stops once the node state is dropped and the transitions in flight
+/// are done.
+pub(crate) fn spawn(state: Weak<RwLock<AppNodeState>>, max_in_flight: usize) -> Sequencer {
+    let (jobs, pending) = mpsc::unbounded_channel();
+    tokio::spawn(run(state, pending, max_in_flight.max(1)));
+    Sequencer { jobs }
+}
+
+async fn run(
+    state: Weak<RwLock<AppNodeState>>,
+    mut pending: mpsc::UnboundedReceiver<Job>,
+    max_in_flight: usize,
+) {
+    let mut in_flight: VecDeque<InFlight> = VecDeque::new();
+    // Held while transitions are in flight, so reverts and imports wait
+    let mut transition_lock: Option<OwnedMutexGuard<()>> = None;
+    let mut open = true;
+
+    loop {
+        if in_flight.is_empty() {
+            transition_lock = None;
+            if !open {
+                break;
+            }
+        }
+
+        tokio::select! {
+            job = pending.recv(), if open && in_flight.len() < max_in_flight => {
+                let Some(job) = job else {
+                    open = false;
+                    continue;
+                };
+                let Some(state) = state.upgrade() else {
+                    let _ = job.reply.send(Err(SequencerError::Stopped));
+                    break;
+                };
+                stage(&state, &mut transition_lock, &mut in_flight, job).await;
+            }
+            proven = async { (&mut in_flight.front_mut().unwrap().proving).await },
+                if !in_flight.is_empty() =>
+            {
+                let InFlight { job, request, output, staged, .. } = in_flight.pop_front().unwrap();
+                let Some(state) = state.upgrade() else {
+                    let _ = job.reply.send(Err(SequencerError::Stopped));
+                    break;
+                };
+
+                let result = commit(&state, staged, &request, proven).await;
+                let failed = result.is_err();
+                let _ = job.reply.send(result.map(|result| (result, output)));
+
+                // Later transitions were staged on top of the failed one
+                if failed {
+                    let jobs: Vec<Job> = in_flight
+                        .drain(..)
+                        .map(|flight| {
+                            flight.proving.abort();
+                            flight.job
+                        })
+                        .collect();
+                    for job in jobs {
+                        stage(&state, &mut transition_lock, &mut in_flight, job).await;
+                    }
+                }
+            }
+            else => break,
+        }
+    }
+}
+
+/// Stage a transition on top of those in flight and start proving it.
+async fn stage(
+    state: &Arc<RwLock<AppNodeState>>,
+    transition_lock: &mut Option<OwnedMutexGuard<()>>,
+    in_flight: &mut VecDeque<InFlight>,
+    job: Job,
+) {
+    if transition_lock.is_none() {
+        let lock = Arc::clone(&state.read().await.transition_lock);
+        *transition_lock = Some(lock.lock_owned().await);
+    }
+
+    let mut state = state.write().await;
+    let (request, output) = match &job.work {
+        Work::Apply(request) => (request.clone(), Value::Null),
+        Work::Execute(executor) => {
+            let executed = state
+                .store
+                .with_staged(in_flight.iter().map(|f| &f.staged), |store| executor(store));
+            match executed {
+                Ok((_, Ok(execution))) => (execution.request, execution.output),
+                Ok((_, Err(e))) => {
+                    let _ = job.reply.send(Err(SequencerError::Application(e)));
+                    return;
+                }
+                Err(e) => {
+                    let _ = job.reply.send(Err(SequencerError::Storage(e.to_string())));
+                    return;
+                }
+            }
+        }
+    };
+    let staged = match state
+        .store
+        .stage_after(in_flight.iter().map(|f| &f.staged), request.ops.clone())
+    {
+        Ok(staged) => staged,
+        Err(e) => {
+            let _ = job.reply.send(Err(SequencerError::Storage(e.to_string())));
+            return;
+        }
+    };
+
+    debug!(
+        "Staged transition {}: {} -> {}",
+        staged.sequence,
+        hex::encode(staged.prev_root),
+        hex::encode(staged.new_root)
+    );
+
+    // Build transition input with operations for business logic verification
+    let set_policy = state.set_policy();
+    let mut input = TransitionInput::new(
+        staged.prev_root,
+        request.public_inputs.clone(),
+        request.private_inputs.clone(),
+        staged.witnesses.clone(),
+    )
+    .with_operations(request.verifiable_ops.clone())
+    .with_hash_function(state.store.hash_function())
+    .with_set_policy(set_policy)
+    .with_mint_authority(state.config.mint_authority)
+    .with_app_id(state.config.app_id.clone());
+    if set_policy == SetPolicy::Allow {
+        let keys: Vec<&[u8]> = staged.ops.iter().map(StateOp::key).collect();
+        input = input.with_set_operations(&keys);
+    }
+
+    // The guest would refuse to prove writes the operations do not declare,
+    // or operations breaking their business rules
+    if let Err(e) = input
+        .bind_operations()
+        .and_then(|()| input.verify_operations())
+    {
+        let _ = job.reply.send(Err(SequencerError::Rejected(e.to_string())));
+        return;
+    }
+    let staged = staged.with_journal(input.encode());
+
+    // SP1 proving is CPU-intensive, run it in the blocking thread pool
+    let proving_enabled = state.config.proving_enabled;
+    let proving = tokio::task::spawn_blocking(move || {
+        let prover = TransitionProver::new();
+        if proving_enabled {
+            let result = prover
+                .prove(&input)
+                .map_err(|e| anyhow!("proof generation failed: {}", e))?;
+            Ok((result.proof_bytes, result.output))
+        } else {
+            let output = prover
+                .execute(&input)
+                .map_err(|e| anyhow!("execution failed: {}", e))?;
+            Ok((Vec::new(), output))
+        }
+    });
+
+    in_flight.push_back(InFlight {
+        job,
+        request,
+        output,
+        staged,
+        proving,
+    });
+}
+
+/// Commit the oldest transition in flight once it is proven and queue its
+/// blob for posting.
+async fn commit(
+    state: &RwLock<AppNodeState>,
+    staged: StagedTransition,
+    request: &TransitionRequest,
+    proven: Result<anyhow::Result<(Vec<u8>, TransitionOutput)>, JoinError>,
+) -> Result<TransitionResult, SequencerError> {
+    let (sequence, prev_root, new_root) = (staged.sequence, staged.prev_root, staged.new_root);
+    let operations = staged.ops.len();
+    let rolled_back = |reason: String| SequencerError::RolledBack {
+        sequence,
+        root: prev_root,
+        discarded_root: new_root,
+        operations,
+        reason,
+    };
+
+    let (proof_bytes, output) = proven
+        .map_err(|e| rolled_back(format!("proving task failed: {}", e)))?
+        .map_err(|e| rolled_back(e.to_string()))?;
+
+    // Verify the output matches our computation
+    if output.prev_root != prev_root || output.new_root != new_root {
+        return Err(rolled_back("proof output mismatch".to_string()));
+    }
+
+    // Commit the state changes together with the transition record and
+    // the blob queued for posting, so a failed commit posts nothing
+    let blob = {
+        let mut state = state.write().await;
+        let blob = state.transition_blob(
+            sequence,
+            prev_root,
+            new_root,
+            request.public_inputs.clone(),
+            proof_bytes.clone(),
+        );
+        let blob_bytes = blob
+            .encode()
+            .map_err(|e| rolled_back(format!("blob encoding failed: {}", e)))?;
+        let record = transition_record(&blob, None);
+        let staged = state.queue_for_posting(staged, blob_bytes);
+        state
+            .store
+            .commit_staged_with_record(staged, record)
+            .map_err(|e| rolled_back(format!("failed to commit state: {}", e)))?;
+        if let Some(window) = state.config.history_window {
+            // The transition is committed either way
+            if let Err(e) = state.store.prune_to(sequence.saturating_sub(window)) {
+                warn!("Failed to prune the state history: {}", e);
+            }
+        }
+        // The submitter posts it, so a slow submission holds up no commit
+        state.wake_outbox();
+        blob
+    };
+
+    info!(
+        "Committed transition {}: {}",
+        sequence,
+        hex::encode(new_root)
+    );
+
+    Ok(TransitionResult {
+        sequence,
+        prev_root,
+        new_root,
+        proof_bytes,
+        blob,
+        celestia_height: None,
+    })
+}
@@ -1,10 +1,14 @@
 //! Celestia sync functionality for verifying proof chain.
 
 use anyhow::Result;
-use blob_schema::TransitionBlobV1;
+use blob_schema::{DaBlob, ProofAttachmentV1, TransitionBlob};
 use celestia_adapter::{CelestiaClient, Namespace};
 use merkle::Hash32;
 use tracing::{debug, info};
+use transition_format::SetPolicy;
+use verifier_lib::{
+    check_attachments, verify_proof, AttachedProof, ProofTerms, VerifyConfig, VerifyError,
+};
 use zk_host_harness::{program_hash, TransitionVerifier};
 
 /// Result of syncing from Celestia.
@@ -12,6 +16,9 @@ use zk_host_harness::{program_hash, TransitionVerifier};
 pub struct SyncResult {
     /// Number of transitions verified.
     pub transitions_verified: u64,
+    /// Transitions posted without a proof whose proof came from an
+    /// attachment blob.
+    pub attached_proofs: Vec<u64>,
     /// Starting root.
     pub first_root: Hash32,
     /// Final root after all transitions.
@@ -28,8 +35,7 @@ pub struct SyncResult {
 pub struct CelestiaSyncer {
     client: CelestiaClient,
     verifier: TransitionVerifier,
-    namespace: Namespace,
-    expected_program_hash: Hash32,
+    config: VerifyConfig,
 }
 
 impl CelestiaSyncer {
@@ -38,11 +44,28 @@ impl CelestiaSyncer {
         Self {
             client: CelestiaClient::with_url(celestia_rpc),
             verifier: TransitionVerifier::new(),
-            namespace,
-            expected_program_hash: program_hash(),
+            config: VerifyConfig {
+                celestia_rpc: celestia_rpc.to_string(),
+                namespace,
+                expected_program_hash: Some(program_hash()),
+                allow_set: true,
+                ..Default::default()
+            },
         }
     }
 
+    /// Require proofs to have been made under the app's set policy.
+    pub fn with_set_policy(mut self, set_policy: SetPolicy) -> Self {
+        self.config.expected_set_policy = Some(set_policy);
+        self
+    }
+
+    /// Require mints to have been checked against the app's mint authority.
+    pub fn with_mint_authority(mut self, mint_authority: [u8; 32]) -> Self {
+        self.config.expected_mint_authority = Some(mint_authority);
+        self
+    }
+
     /// Sync from a height range and verify all proofs.
     pub async fn sync_range(
         &self,
@@ -57,22 +80,26 @@ impl CelestiaSyncer {
 
         let blobs = self
             .client
-            .get_blobs_range(&self.namespace, from_height, to_height)
+            .get_blobs_range(&self.config.namespace, from_height, to_height)
             .await?;
 
         if blobs.is_empty() {
             return Err(anyhow::anyhow!("no blobs found in range"));
         }
 
-        let mut transitions: Vec<(u64, TransitionBlobV1)> = Vec::new();
+        let mut transitions: Vec<(u64, TransitionBlob)> = Vec::new();
+        let mut attachments: Vec<ProofAttachmentV1> = Vec::new();
         let mut warnings = Vec::new();
 
-        // Decode all blobs
+        // Decode all blobs, setting proof attachments aside
         for (height, blob) in blobs {
-            match TransitionBlobV1::decode(&blob.data) {
-                Ok(transition) => {
+            match DaBlob::decode(&blob.data) {
+                Ok(DaBlob::Transition(transition)) => {
                     transitions.push((height, transition));
                 }
+                Ok(DaBlob::ProofAttachment(attachment)) => {
+                    attachments.push(attachment);
+                }
                 Err(e) => {
                     warnings.push(format!("Failed to decode blob at height {}: {}", height, e));
                 }
@@ -87,11 +114,16 @@ impl CelestiaSyncer {
         transitions.sort_by_key(|(_, t)| t.sequence);
 
         // Verify chain
+        let expected_program_hash = self
+            .config
+            .expected_program_hash
+            .unwrap_or_else(program_hash);
         let first_transition = &transitions[0].1;
         let mut current_root = expected_prev_root.unwrap_or(first_transition.prev_root);
         let first_root = current_root;
         let first_height = transitions[0].0;
         let mut last_height = first_height;
+        let mut attached_proofs = Vec::new();
 
         for (height, transition) in &transitions {
             debug!(
@@ -100,7 +132,7 @@ impl CelestiaSyncer {
             );
 
             // Verify program hash
-            if transition.program_hash != self.expected_program_hash {
+            if transition.program_hash != expected_program_hash {
                 warnings.push(format!(
                     "Transition {} has unexpected program hash",
                     transition.sequence
@@ -120,35 +152,25 @@ impl CelestiaSyncer {
 
             // Verify proof (if not empty)
             if !transition.proof.is_empty() {
-                match self.verifier.verify(&transition.proof) {
-                    Ok(output) => {
-                        if output.prev_root != transition.prev_root {
-                            return Err(anyhow::anyhow!(
-                                "Proof prev_root mismatch at transition {}",
-                                transition.sequence
-                            ));
-                        }
-                        if output.new_root != transition.new_root {
-                            return Err(anyhow::anyhow!(
-                                "Proof new_root mismatch at transition {}",
-                                transition.sequence
-                            ));
-                        }
-                        debug!("Proof verified for transition {}", transition.sequence);
-                    }
-                    Err(e) => {
-                        return Err(anyhow::anyhow!(
-                            "Proof verification failed at transition {}: {}",
-                            transition.sequence,
-                            e
-                        ));
-                    }
-                }
+                self.check_proof(&transition.proof, transition)?;
             } else {
-                warnings.push(format!(
-                    "Transition {} has no proof (skipping verification)",
-                    transition.sequence
-                ));
+                // Use a proof attached later if the transition was posted
+                // without one
+                let attachment =
+                    check_attachments(&attachments, transition, &expected_program_hash, |proof| {
+                        self.check_proof(proof, transition)
+                    });
+                match attachment {
+                    AttachedProof::Verified(_) => attached_proofs.push(transition.sequence),
+                    AttachedProof::Failed(e) => warnings.push(format!(
+                        "Transition {} has no attachment that verifies (skipping verification): {}",
+                        transition.sequence, e
+                    )),
+                    AttachedProof::Missing => warnings.push(format!(
+                        "Transition {} has no proof (skipping verification)",
+                        transition.sequence
+                    )),
+                }
             }
 
             current_root = transition.new_root;
@@ -164,6 +186,7 @@ impl CelestiaSyncer {
 
         Ok(SyncResult {
             transitions_verified: transitions.len() as u64,
+            attached_proofs,
             first_root,
             latest_root: current_root,
             first_height,
@@ -172,6 +195,18 @@ impl CelestiaSyncer {
         })
     }
 
+    /// Verify `proof` and check its output matches the transition and its
+    /// terms the expected set policy and mint authority.
+    fn check_proof(
+        &self,
+        proof: &[u8],
+        transition: &TransitionBlob,
+    ) -> Result<ProofTerms, VerifyError> {
+        let terms = verify_proof(&self.verifier, &self.config, proof, transition)?;
+        debug!("Proof verified for transition {}", transition.sequence);
+        Ok(terms)
+    }
+
     /// Get the current head height from Celestia.
     pub async fn head_height(&self) -> Result<u64> {
         Ok(self.client.get_head_height().await?)
@@ -2,13 +2,31 @@
 //!
 //! This crate defines the canonical encoding format for state transition blobs.
 //! All blobs are deterministically encoded using bincode for verification.
+//!
+//! Two kinds of blobs share an app's namespace: transitions
+//! ([`TransitionBlob`]) and proofs attached to transitions that were posted
+//! without one ([`ProofAttachmentV1`]). [`DaBlob::decode`] tells them apart
+//! by their leading version byte.
+//!
+//! Transition blobs of schema version 1 (posted before the hash function was
+//! recorded) still decode, as SHA-256 transitions, so existing chains can be
+//! replayed.
 
+use merkle::HashFunction;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use thiserror::Error;
 
 /// Current schema version for transition blobs.
-pub const SCHEMA_VERSION: u8 = 1;
+pub const SCHEMA_VERSION: u8 = 2;
+
+/// First schema version of transition blobs (no hash function recorded).
+pub const SCHEMA_VERSION_1: u8 = 1;
+
+/// Version byte of proof attachment blobs.
+///
+/// The high bit keeps it apart from transition blob schema versions.
+pub const PROOF_ATTACHMENT_VERSION: u8 = 0x81;
 
 /// Errors that can occur during blob encoding/decoding.
 #[derive(Error, Debug)]
@@ -24,12 +42,14 @@ pub enum BlobError {
 /// A 32-byte hash/root value.
 pub type Hash32 = [u8; 32];
 
-/// Transition blob version 1.
+/// Transition blob.
 ///
 /// Contains all data needed for independent verification of a state transition.
+/// Encoded in the layout of its `version` ([`SCHEMA_VERSION`] for new blobs).
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
-pub struct TransitionBlobV1 {
-    /// Schema version (must be 1).
+pub struct TransitionBlob {
+    /// Schema version ([`SCHEMA_VERSION`], or [`SCHEMA_VERSION_1`] for a
+    /// blob decoded from the old layout).
     pub version: u8,
     /// Application identifier (namespace-derived or app-specific).
     pub app_id: Vec<u8>,
@@ -51,9 +71,69 @@ pub struct TransitionBlobV1 {
     pub timestamp: Option<u64>,
     /// Sequencer signature over the transition (optional).
     pub sequencer_signature: Option<Vec<u8>>,
+    /// Hash function of the app's state tree (added in version 2).
+    pub hash_function: HashFunction,
+}
+
+/// Former name of [`TransitionBlob`], kept from schema version 1 (the type
+/// now encodes [`SCHEMA_VERSION`]).
+pub type TransitionBlobV1 = TransitionBlob;
+
+/// Layout of schema version 1: a [`TransitionBlob`] without the hash
+/// function, which was always SHA-256.
+#[derive(Serialize, Deserialize)]
+struct LegacyTransitionBlob {
+    version: u8,
+    app_id: Vec<u8>,
+    sequence: u64,
+    prev_root: Hash32,
+    new_root: Hash32,
+    public_inputs: Vec<u8>,
+    public_outputs: Vec<u8>,
+    proof: Vec<u8>,
+    program_hash: Hash32,
+    timestamp: Option<u64>,
+    sequencer_signature: Option<Vec<u8>>,
 }
 
-impl TransitionBlobV1 {
+impl From<LegacyTransitionBlob> for TransitionBlob {
+    fn from(blob: LegacyTransitionBlob) -> Self {
+        Self {
+            version: blob.version,
+            app_id: blob.app_id,
+            sequence: blob.sequence,
+            prev_root: blob.prev_root,
+            new_root: blob.new_root,
+            public_inputs: blob.public_inputs,
+            public_outputs: blob.public_outputs,
+            proof: blob.proof,
+            program_hash: blob.program_hash,
+            timestamp: blob.timestamp,
+            sequencer_signature: blob.sequencer_signature,
+            hash_function: HashFunction::Sha256,
+        }
+    }
+}
+
+impl From<&TransitionBlob> for LegacyTransitionBlob {
+    fn from(blob: &TransitionBlob) -> Self {
+        Self {
+            version: blob.version,
+            app_id: blob.app_id.clone(),
+            sequence: blob.sequence,
+            prev_root: blob.prev_root,
+            new_root: blob.new_root,
+            public_inputs: blob.public_inputs.clone(),
+            public_outputs: blob.public_outputs.clone(),
+            proof: blob.proof.clone(),
+            program_hash: blob.program_hash,
+            timestamp: blob.timestamp,
+            sequencer_signature: blob.sequencer_signature.clone(),
+        }
+    }
+}
+
+impl TransitionBlob {
     /// Create a new transition blob.
     pub fn new(
         app_id: Vec<u8>,
@@ -76,6 +156,7 @@ impl TransitionBlobV1 {
             program_hash,
             timestamp: None,
             sequencer_signature: None,
+            hash_function: HashFunction::default(),
         }
     }
 
@@ -91,6 +172,12 @@ impl TransitionBlobV1 {
         self
     }
 
+    /// Set the hash function of the state tree.
+    pub fn with_hash_function(mut self, hash_function: HashFunction) -> Self {
+        self.hash_function = hash_function;
+        self
+    }
+
     /// Set sequencer signature.
     pub fn with_signature(mut self, sig: Vec<u8>) -> Self {
         self.sequencer_signature = Some(sig);
@@ -98,20 +185,29 @@ impl TransitionBlobV1 {
     }
 
     /// Encode the blob to bytes using bincode (deterministic).
+    ///
+    /// A blob decoded from schema version 1 is encoded in that layout again,
+    /// so its hash matches the posted bytes.
     pub fn encode(&self) -> Result<Vec<u8>, BlobError> {
+        if self.version == SCHEMA_VERSION_1 {
+            return bincode::serialize(&LegacyTransitionBlob::from(self)).map_err(BlobError::from);
+        }
         bincode::serialize(self).map_err(BlobError::from)
     }
 
-    /// Decode a blob from bytes.
+    /// Decode a blob from bytes (schema version 2 or 1).
     pub fn decode(bytes: &[u8]) -> Result<Self, BlobError> {
-        let blob: Self = bincode::deserialize(bytes)?;
-        if blob.version != SCHEMA_VERSION {
-            return Err(BlobError::InvalidVersion {
+        // Check the leading version byte first: other layouts won't parse
+        match bytes.first() {
+            Some(&SCHEMA_VERSION_1) => {
+                Ok(bincode::deserialize::<LegacyTransitionBlob>(bytes)?.into())
+            }
+            Some(&version) if version != SCHEMA_VERSION => Err(BlobError::InvalidVersion {
                 expected: SCHEMA_VERSION,
-                got: blob.version,
-            });
+                got: version,
+            }),
+            _ => Ok(bincode::deserialize(bytes)?),
         }
-        Ok(blob)
     }
 
     /// Compute the hash of this blob (for indexing/signing).
@@ -137,6 +233,109 @@ impl TransitionBlobV1 {
     }
 }
 
+/// Proof for a transition that was posted without one.
+///
+/// References the transition by sequence and blob hash, so it only upgrades
+/// the exact blob it was proven for.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ProofAttachmentV1 {
+    /// Schema version (must be [`PROOF_ATTACHMENT_VERSION`]).
+    pub version: u8,
+    /// Application identifier.
+    pub app_id: Vec<u8>,
+    /// Sequence of the transition the proof is for.
+    pub sequence: u64,
+    /// Hash of the transition blob the proof is for.
+    pub blob_hash: Hash32,
+    /// SP1 proof bytes.
+    pub proof: Vec<u8>,
+    /// Hash of the ZK program ELF that produced the proof.
+    pub program_hash: Hash32,
+    /// Unix timestamp (optional).
+    pub timestamp: Option<u64>,
+}
+
+impl ProofAttachmentV1 {
+    /// Create an attachment of `proof` to the transition blob with hash
+    /// `blob_hash`.
+    pub fn new(
+        app_id: Vec<u8>,
+        sequence: u64,
+        blob_hash: Hash32,
+        proof: Vec<u8>,
+        program_hash: Hash32,
+    ) -> Self {
+        Self {
+            version: PROOF_ATTACHMENT_VERSION,
+            app_id,
+            sequence,
+            blob_hash,
+            proof,
+            program_hash,
+            timestamp: None,
+        }
+    }
+
+    /// Set timestamp.
+    pub fn with_timestamp(mut self, ts: u64) -> Self {
+        self.timestamp = Some(ts);
+        self
+    }
+
+    /// Whether this attachment is for `blob`.
+    pub fn attaches_to(&self, blob: &TransitionBlob) -> bool {
+        self.sequence == blob.sequence && self.blob_hash == blob.hash()
+    }
+
+    /// Encode the attachment to bytes using bincode (deterministic).
+    pub fn encode(&self) -> Result<Vec<u8>, BlobError> {
+        bincode::serialize(self).map_err(BlobError::from)
+    }
+
+    /// Decode an attachment from bytes.
+    pub fn decode(bytes: &[u8]) -> Result<Self, BlobError> {
+        if let Some(&version) = bytes.first() {
+            if version != PROOF_ATTACHMENT_VERSION {
+                return Err(BlobError::InvalidVersion {
+                    expected: PROOF_ATTACHMENT_VERSION,
+                    got: version,
+                });
+            }
+        }
+
+        Ok(bincode::deserialize(bytes)?)
+    }
+}
+
+/// Any blob posted to an app's namespace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DaBlob {
+    /// A state transition.
+    Transition(TransitionBlob),
+    /// A proof for an earlier transition.
+    ProofAttachment(ProofAttachmentV1),
+}
+
+impl DaBlob {
+    /// Decode a blob of either kind.
+    pub fn decode(bytes: &[u8]) -> Result<Self, BlobError> {
+        match bytes.first() {
+            Some(&PROOF_ATTACHMENT_VERSION) => {
+                Ok(Self::ProofAttachment(ProofAttachmentV1::decode(bytes)?))
+            }
+            _ => Ok(Self::Transition(TransitionBlob::decode(bytes)?)),
+        }
+    }
+
+    /// Sequence of the transition the blob is (or proves).
+    pub fn sequence(&self) -> u64 {
+        match self {
+            Self::Transition(blob) => blob.sequence,
+            Self::ProofAttachment(attachment) => attachment.sequence,
+        }
+    }
+}
+
 /// Helper to compute hash of arbitrary bytes.
 pub fn hash_bytes(data: &[u8]) -> Hash32 {
     let mut hasher = Sha256::new();
@@ -176,7 +375,8 @@ mod tests {
             [2u8; 32],
         )
         .with_timestamp(12345)
-        .with_public_outputs(b"outputs".to_vec());
+        .with_public_outputs(b"outputs".to_vec())
+        .with_hash_function(HashFunction::Poseidon);
 
         let encoded = blob.encode().unwrap();
         let decoded = TransitionBlobV1::decode(&encoded).unwrap();
@@ -200,6 +400,74 @@ mod tests {
         assert_eq!(hash1, hash2);
     }
 
+    #[test]
+    fn test_proof_attachment() {
+        let blob = TransitionBlobV1::new(
+            b"test-app".to_vec(),
+            3,
+            [0u8; 32],
+            [1u8; 32],
+            vec![],
+            vec![],
+            [2u8; 32],
+        );
+        let attachment = ProofAttachmentV1::new(
+            blob.app_id.clone(),
+            blob.sequence,
+            blob.hash(),
+            b"proof".to_vec(),
+            [2u8; 32],
+        )
+        .with_timestamp(12345);
+        assert!(attachment.attaches_to(&blob));
+        assert!(!attachment.attaches_to(&blob.clone().with_timestamp(1)));
+
+        let encoded = attachment.encode().unwrap();
+        assert_eq!(
+            DaBlob::decode(&encoded).unwrap(),
+            DaBlob::ProofAttachment(attachment)
+        );
+        assert_eq!(
+            DaBlob::decode(&blob.encode().unwrap()).unwrap(),
+            DaBlob::Transition(blob)
+        );
+        assert!(matches!(
+            TransitionBlobV1::decode(&encoded),
+            Err(BlobError::InvalidVersion { .. })
+        ));
+    }
+
+    #[test]
+    fn test_decode_version_1() {
+        let blob = TransitionBlob::new(
+            b"test-app".to_vec(),
+            1,
+            [0u8; 32],
+            [1u8; 32],
+            b"public".to_vec(),
+            b"proof".to_vec(),
+            [2u8; 32],
+        )
+        .with_timestamp(12345);
+        let legacy = LegacyTransitionBlob {
+            version: SCHEMA_VERSION_1,
+            ..LegacyTransitionBlob::from(&blob)
+        };
+        let encoded = bincode::serialize(&legacy).unwrap();
+
+        // Version 1 blobs are SHA-256 transitions and keep their hash
+        let decoded = TransitionBlob::decode(&encoded).unwrap();
+        assert_eq!(decoded.version, SCHEMA_VERSION_1);
+        assert_eq!(decoded.hash_function, HashFunction::Sha256);
+        assert_eq!(decoded.public_inputs, blob.public_inputs);
+        assert_eq!(decoded.encode().unwrap(), encoded);
+        assert_eq!(decoded.hash(), hash_bytes(&encoded));
+        assert!(matches!(
+            DaBlob::decode(&encoded),
+            Ok(DaBlob::Transition(_))
+        ));
+    }
+
     #[test]
     fn test_invalid_version() {
         let mut blob =
@@ -2,23 +2,26 @@
 //!
 //! This crate provides a sparse Merkle tree with:
 //! - Efficient proofs of inclusion/exclusion
+//! - Multi-key proofs sharing common siblings
+//! - Bitmap-compressed proofs and witnesses (empty siblings elided)
 //! - Update witnesses for ZK circuits
 //! - Serializable state for persistence
+//! - Pluggable node storage via [`TreeStore`]
+//! - Pluggable hash functions via [`TreeHasher`]
+//!
+//! Hashing, witnesses and tree parameters come from `merkle_core`, which the
+//! SP1 guest shares, and are re-exported here.
+
+pub use merkle_core::{
+    Blake3Hasher, CompressedSiblings, CompressedWitness, Hash32, HashFunction, PoseidonHasher,
+    Sha256Hasher, TreeHasher, UpdateWitness, EMPTY_HASH, SIBLING_BITMAP_LEN, TREE_DEPTH,
+};
 
+use merkle_core::{fold_path, get_bit, leaf_hash, CoreError};
 use serde::{Deserialize, Serialize};
-use sha2::{Digest, Sha256};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use thiserror::Error;
 
-/// Tree depth (using smaller depth for efficiency).
-pub const TREE_DEPTH: usize = 160;
-
-/// A 32-byte hash value.
-pub type Hash32 = [u8; 32];
-
-/// The empty/default hash for an empty subtree.
-pub const EMPTY_HASH: Hash32 = [0u8; 32];
-
 /// Errors that can occur during tree operations.
 #[derive(Error, Debug)]
 pub enum MerkleError {
@@ -28,38 +31,36 @@ pub enum MerkleError {
     KeyNotFound,
     #[error("serialization error: {0}")]
     Serialization(String),
+    #[error("storage error: {0}")]
+    Storage(String),
+    #[error("unknown hash function: {0}")]
+    UnknownHashFunction(String),
+    #[error("leaves out of key hash order")]
+    UnorderedLeaves,
+}
+
+impl From<CoreError> for MerkleError {
+    fn from(err: CoreError) -> Self {
+        match err {
+            CoreError::InvalidSiblings => MerkleError::InvalidProof,
+            CoreError::UnknownHashFunction(name) => MerkleError::UnknownHashFunction(name),
+        }
+    }
 }
 
-/// Hash two child nodes to produce parent hash.
+/// Hash two child nodes to produce parent hash (SHA-256).
 pub fn hash_nodes(left: &Hash32, right: &Hash32) -> Hash32 {
-    let mut hasher = Sha256::new();
-    hasher.update(left);
-    hasher.update(right);
-    hasher.finalize().into()
+    Sha256Hasher.hash_nodes(left, right)
 }
 
-/// Hash a leaf value.
+/// Hash a leaf value (SHA-256).
 pub fn hash_leaf(key: &Hash32, value: &[u8]) -> Hash32 {
-    let mut hasher = Sha256::new();
-    hasher.update([0x00]); // Leaf prefix
-    hasher.update(key);
-    hasher.update(value);
-    hasher.finalize().into()
+    Sha256Hasher.hash_leaf(key, value)
 }
 
-/// Hash a key to get its path in the tree (truncated to TREE_DEPTH bits).
+/// Hash a key to get its path in the tree (SHA-256, truncated to TREE_DEPTH bits).
 pub fn hash_key(key: &[u8]) -> Hash32 {
-    let mut hasher = Sha256::new();
-    hasher.update(key);
-    hasher.finalize().into()
-}
-
-/// Get the bit at a specific position in a hash (for tree traversal).
-/// Position 0 is the most significant bit.
-fn get_bit(hash: &Hash32, position: usize) -> bool {
-    let byte_idx = position / 8;
-    let bit_idx = 7 - (position % 8);
-    (hash[byte_idx] >> bit_idx) & 1 == 1
+    Sha256Hasher.hash_key(key)
 }
 
 /// A Merkle proof for inclusion/exclusion.
@@ -71,6 +72,8 @@ pub struct MerkleProof {
     pub value: Option<Vec<u8>>,
     /// Sibling hashes from leaf to root.
     pub siblings: Vec<Hash32>,
+    /// Hash function of the tree the proof was taken from.
+    pub hash_function: HashFunction,
 }
 
 impl MerkleProof {
@@ -81,92 +84,285 @@ impl MerkleProof {
 
     /// Get the computed root from this proof.
     pub fn compute_root(&self) -> Hash32 {
-        let leaf_hash = match &self.value {
-            Some(v) => hash_leaf(&self.key, v),
-            None => EMPTY_HASH,
-        };
+        let leaf_hash = leaf_hash(self.hash_function, &self.key, self.value.as_deref());
+        fold_path(self.hash_function, &self.key, leaf_hash, &self.siblings)
+    }
+}
 
-        let mut current = leaf_hash;
-        for (i, sibling) in self.siblings.iter().enumerate() {
-            let depth = TREE_DEPTH - 1 - i;
-            if get_bit(&self.key, depth) {
-                current = hash_nodes(sibling, &current);
-            } else {
-                current = hash_nodes(&current, sibling);
-            }
+/// A Merkle proof with empty siblings elided.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CompressedMerkleProof {
+    /// The key being proven.
+    pub key: Hash32,
+    /// The value (empty if proving non-membership).
+    pub value: Option<Vec<u8>>,
+    /// Compressed sibling path.
+    pub siblings: CompressedSiblings,
+    /// Hash function of the tree the proof was taken from.
+    pub hash_function: HashFunction,
+}
+
+impl CompressedMerkleProof {
+    /// Restore the full proof.
+    pub fn decompress(&self) -> Result<MerkleProof, MerkleError> {
+        Ok(MerkleProof {
+            key: self.key,
+            value: self.value.clone(),
+            siblings: self.siblings.decompress(self.hash_function)?,
+            hash_function: self.hash_function,
+        })
+    }
+
+    /// Verify this proof against a root.
+    pub fn verify(&self, root: &Hash32) -> bool {
+        matches!(self.decompress(), Ok(proof) if proof.verify(root))
+    }
+}
+
+impl MerkleProof {
+    /// Compress this proof by eliding empty siblings.
+    pub fn compress(&self) -> CompressedMerkleProof {
+        CompressedMerkleProof {
+            key: self.key,
+            value: self.value.clone(),
+            siblings: CompressedSiblings::compress(&self.siblings, self.hash_function),
+            hash_function: self.hash_function,
         }
+    }
+}
 
-        current
+/// Truncate a key hash to the path of its ancestor node at `depth`.
+///
+/// Bits at positions `>= depth` are cleared, so all keys below the same
+/// subtree map to the same path.
+fn path_prefix(key: &Hash32, depth: usize) -> Hash32 {
+    let mut path = [0u8; 32];
+    let full_bytes = depth / 8;
+    path[..full_bytes].copy_from_slice(&key[..full_bytes]);
+    let rem = depth % 8;
+    if rem > 0 {
+        path[full_bytes] = key[full_bytes] & (0xFFu8 << (8 - rem));
     }
+    path
 }
 
-/// Witness for updating a key in the tree.
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct UpdateWitness {
-    /// The key being updated.
-    pub key: Hash32,
-    /// Old value (None if key didn't exist).
-    pub old_value: Option<Vec<u8>>,
-    /// New value (None if deleting).
-    pub new_value: Option<Vec<u8>>,
-    /// Sibling hashes from leaf to root.
-    pub siblings: Vec<Hash32>,
+/// Path of the sibling of the node at `depth` on a key's path (`depth >= 1`).
+fn sibling_path(key: &Hash32, depth: usize) -> Hash32 {
+    let mut path = path_prefix(key, depth);
+    let bit = depth - 1;
+    path[bit / 8] ^= 1 << (7 - (bit % 8));
+    path
 }
 
-impl UpdateWitness {
-    /// Compute the old root from this witness.
-    pub fn compute_old_root(&self) -> Hash32 {
-        let leaf_hash = match &self.old_value {
-            Some(v) => hash_leaf(&self.key, v),
-            None => EMPTY_HASH,
-        };
+/// A proof of inclusion/exclusion for several keys against one root.
+///
+/// Siblings shared between the proven paths, or computable from other
+/// proven keys, are included only once. Siblings are listed level by level
+/// from the leaves up, and within a level by ascending path.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct MultiProof {
+    /// Proven key hashes (sorted ascending, no duplicates).
+    pub keys: Vec<Hash32>,
+    /// Values for each key (None if proving non-membership).
+    pub values: Vec<Option<Vec<u8>>>,
+    /// Sibling hashes not derivable from the proven paths
+    /// (None for an empty subtree).
+    pub siblings: Vec<Option<Hash32>>,
+    /// Hash function of the tree the proof was taken from.
+    pub hash_function: HashFunction,
+}
 
-        let mut current = leaf_hash;
-        for (i, sibling) in self.siblings.iter().enumerate() {
-            let depth = TREE_DEPTH - 1 - i;
-            if get_bit(&self.key, depth) {
-                current = hash_nodes(sibling, &current);
-            } else {
-                current = hash_nodes(&current, sibling);
+impl MultiProof {
+    /// Verify this proof against a root.
+    pub fn verify(&self, root: &Hash32) -> bool {
+        matches!(self.compute_root(), Ok(computed) if computed == *root)
+    }
+
+    /// Get the value proven for a key hash, if the key is part of this proof.
+    pub fn value(&self, key_hash: &Hash32) -> Option<Option<&[u8]>> {
+        self.keys
+            .binary_search(key_hash)
+            .ok()
+            .map(|i| self.values[i].as_deref())
+    }
+
+    /// Get the computed root from this proof.
+    pub fn compute_root(&self) -> Result<Hash32, MerkleError> {
+        if self.keys.is_empty()
+            || self.keys.len() != self.values.len()
+            || self.keys.windows(2).any(|w| w[0] >= w[1])
+        {
+            return Err(MerkleError::InvalidProof);
+        }
+
+        let empty_hashes = self.hash_function.empty_hashes();
+        let mut level = BTreeMap::new();
+        for (key, value) in self.keys.iter().zip(&self.values) {
+            let leaf_hash = leaf_hash(self.hash_function, key, value.as_deref());
+            if level
+                .insert(path_prefix(key, TREE_DEPTH), leaf_hash)
+                .is_some()
+            {
+                return Err(MerkleError::InvalidProof);
             }
         }
 
-        current
+        let mut siblings = self.siblings.iter();
+        let root = fold_multi_levels(self.hash_function, level, |depth, _| {
+            match siblings.next() {
+                Some(sibling) => Ok(sibling.unwrap_or(empty_hashes[depth])),
+                None => Err(MerkleError::InvalidProof),
+            }
+        })?;
+
+        if siblings.next().is_some() {
+            return Err(MerkleError::InvalidProof);
+        }
+        Ok(root)
     }
+}
 
-    /// Compute the new root from this witness.
-    pub fn compute_new_root(&self) -> Hash32 {
-        let leaf_hash = match &self.new_value {
-            Some(v) => hash_leaf(&self.key, v),
-            None => EMPTY_HASH,
-        };
+/// Hash a set of nodes at the leaf level up to the root.
+///
+/// `sibling` is called with `(depth, path)` for every sibling that is not
+/// itself on one of the paths, in the order used by [`MultiProof`].
+fn fold_multi_levels(
+    hasher: HashFunction,
+    mut level: BTreeMap<Hash32, Hash32>,
+    mut sibling: impl FnMut(usize, &Hash32) -> Result<Hash32, MerkleError>,
+) -> Result<Hash32, MerkleError> {
+    for depth in (1..=TREE_DEPTH).rev() {
+        let mut parents = BTreeMap::new();
+        let bit = depth - 1;
+
+        for (path, hash) in &level {
+            let sibling_path = sibling_path(path, depth);
+            let is_right = get_bit(path, bit);
+            if is_right && level.contains_key(&sibling_path) {
+                // Already combined when visiting the left child
+                continue;
+            }
 
-        let mut current = leaf_hash;
-        for (i, sibling) in self.siblings.iter().enumerate() {
-            let depth = TREE_DEPTH - 1 - i;
-            if get_bit(&self.key, depth) {
-                current = hash_nodes(sibling, &current);
+            let sibling_hash = match level.get(&sibling_path) {
+                Some(h) => *h,
+                None => sibling(depth, &sibling_path)?,
+            };
+            let parent = if is_right {
+                hasher.hash_nodes(&sibling_hash, hash)
             } else {
-                current = hash_nodes(&current, sibling);
-            }
+                hasher.hash_nodes(hash, &sibling_hash)
+            };
+            parents.insert(path_prefix(path, bit), parent);
         }
 
-        current
+        level = parents;
     }
+
+    level.into_values().next().ok_or(MerkleError::InvalidProof)
 }
 
-/// Sparse Merkle Tree using a simple key-value store approach.
+/// Storage backend for tree leaves and internal nodes.
 ///
-/// This implementation stores leaf values directly and computes
-/// internal nodes on-the-fly for proofs.
-#[derive(Debug, Clone)]
-pub struct SparseMerkleTree {
+/// Nodes are addressed by `(depth, path)`, where `path` is a key hash
+/// truncated to `depth` bits. Only non-empty subtrees are stored: a missing
+/// node is an empty subtree and writing `None` removes it.
+pub trait TreeStore {
+    /// Get the hash of a non-empty subtree.
+    fn get_node(&self, depth: usize, path: &Hash32) -> Result<Option<Hash32>, MerkleError>;
+
+    /// Set (`Some`) or remove (`None`) a subtree hash.
+    fn set_node(
+        &mut self,
+        depth: usize,
+        path: Hash32,
+        hash: Option<Hash32>,
+    ) -> Result<(), MerkleError>;
+
+    /// Get a leaf value by key hash.
+    fn get_leaf(&self, key_hash: &Hash32) -> Result<Option<Vec<u8>>, MerkleError>;
+
+    /// Set (`Some`) or remove (`None`) a leaf value.
+    fn set_leaf(&mut self, key_hash: Hash32, value: Option<Vec<u8>>) -> Result<(), MerkleError>;
+}
+
+/// In-memory tree storage.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStore {
     /// Leaf values indexed by key hash.
     leaves: HashMap<Hash32, Vec<u8>>,
-    /// Cached root (recomputed on modification).
-    cached_root: Option<Hash32>,
+    /// Hashes of non-empty subtrees indexed by (depth, path prefix).
+    nodes: HashMap<(usize, Hash32), Hash32>,
+}
+
+impl MemoryStore {
+    /// Create an empty store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Iterate over all leaves as (key hash, value).
+    pub fn leaves(&self) -> impl Iterator<Item = (&Hash32, &Vec<u8>)> {
+        self.leaves.iter()
+    }
+}
+
+impl TreeStore for MemoryStore {
+    fn get_node(&self, depth: usize, path: &Hash32) -> Result<Option<Hash32>, MerkleError> {
+        Ok(self.nodes.get(&(depth, *path)).copied())
+    }
+
+    fn set_node(
+        &mut self,
+        depth: usize,
+        path: Hash32,
+        hash: Option<Hash32>,
+    ) -> Result<(), MerkleError> {
+        match hash {
+            Some(h) => self.nodes.insert((depth, path), h),
+            None => self.nodes.remove(&(depth, path)),
+        };
+        Ok(())
+    }
+
+    fn get_leaf(&self, key_hash: &Hash32) -> Result<Option<Vec<u8>>, MerkleError> {
+        Ok(self.leaves.get(key_hash).cloned())
+    }
+
+    fn set_leaf(&mut self, key_hash: Hash32, value: Option<Vec<u8>>) -> Result<(), MerkleError> {
+        match value {
+            Some(v) => self.leaves.insert(key_hash, v),
+            None => self.leaves.remove(&key_hash),
+        };
+        Ok(())
+    }
+}
+
+/// Message for `expect` on in-memory tree operations.
+const IN_MEMORY: &str = "in-memory tree store is infallible";
+
+/// Sparse Merkle Tree with cached internal nodes.
+///
+/// The hashes of all non-empty subtrees are kept in a [`TreeStore`], so
+/// roots, witnesses and proofs cost O(TREE_DEPTH) store reads per key and
+/// an update writes only the nodes on one path. Empty subtrees are never
+/// stored; their hashes come from the precomputed `empty_hashes` table.
+///
+/// Keys, leaves and nodes are hashed with the tree's [`HashFunction`],
+/// SHA-256 unless chosen otherwise. A store only makes sense with the hash
+/// function it was written with.
+///
+/// The default store keeps everything in memory. Fallible `try_*` methods
+/// work with any store; the infallible shorthands exist for [`MemoryStore`].
+#[derive(Debug, Clone)]
+pub struct SparseMerkleTree<S = MemoryStore> {
+    /// Leaf and node storage.
+    store: S,
+    /// Current root (kept in sync on every update).
+    root: Hash32,
+    /// Hash function for keys, leaves and nodes.
+    hasher: HashFunction,
     /// Precomputed empty subtree hashes for each depth.
-    empty_hashes: Vec<Hash32>,
+    empty_hashes: &'static [Hash32],
 }
 
 impl Default for SparseMerkleTree {
@@ -175,240 +371,272 @@ impl Default for SparseMerkleTree {
     }
 }
 
-impl SparseMerkleTree {
-    /// Create a new empty tree.
-    pub fn new() -> Self {
-        // Precompute empty hashes for each level
-        let mut empty_hashes = vec![EMPTY_HASH; TREE_DEPTH + 1];
-        for i in (0..TREE_DEPTH).rev() {
-            empty_hashes[i] = hash_nodes(&empty_hashes[i + 1], &empty_hashes[i + 1]);
-        }
+impl<S: TreeStore> SparseMerkleTree<S> {
+    /// Open a tree over an existing store written with `hasher`.
+    pub fn with_store(store: S, hasher: HashFunction) -> Result<Self, MerkleError> {
+        let empty_hashes = hasher.empty_hashes();
+        let root = store.get_node(0, &EMPTY_HASH)?.unwrap_or(empty_hashes[0]);
 
-        Self {
-            leaves: HashMap::new(),
-            cached_root: Some(empty_hashes[0]),
+        Ok(Self {
+            store,
+            root,
+            hasher,
             empty_hashes,
-        }
+        })
     }
 
     /// Get the current root hash.
     pub fn root(&self) -> Hash32 {
-        self.cached_root.unwrap_or_else(|| self.compute_root())
+        self.root
     }
 
-    /// Compute the root from all leaves.
-    fn compute_root(&self) -> Hash32 {
-        if self.leaves.is_empty() {
-            return self.empty_hashes[0];
-        }
-
-        // Build subtree hashes level by level
-        let mut level: HashMap<Vec<bool>, Hash32> = self
-            .leaves
-            .iter()
-            .map(|(key, value)| {
-                let path: Vec<bool> = (0..TREE_DEPTH).map(|i| get_bit(key, i)).collect();
-                let leaf_hash = hash_leaf(key, value);
-                (path, leaf_hash)
-            })
-            .collect();
-
-        // Work up from leaves to root
-        for depth in (0..TREE_DEPTH).rev() {
-            let mut next_level: HashMap<Vec<bool>, Hash32> = HashMap::new();
-
-            // Group by parent path
-            let mut parents: HashMap<Vec<bool>, (Option<Hash32>, Option<Hash32>)> = HashMap::new();
-
-            for (path, hash) in level {
-                let parent_path: Vec<bool> = path[..depth].to_vec();
-                let is_right = path[depth];
+    /// Get the hash function of this tree.
+    pub fn hash_function(&self) -> HashFunction {
+        self.hasher
+    }
 
-                let entry = parents.entry(parent_path).or_insert((None, None));
-                if is_right {
-                    entry.1 = Some(hash);
-                } else {
-                    entry.0 = Some(hash);
-                }
-            }
+    /// Get the underlying store.
+    pub fn store(&self) -> &S {
+        &self.store
+    }
 
-            for (parent_path, (left, right)) in parents {
-                let left_hash = left.unwrap_or(self.empty_hashes[depth + 1]);
-                let right_hash = right.unwrap_or(self.empty_hashes[depth + 1]);
-                let parent_hash = hash_nodes(&left_hash, &right_hash);
-                next_level.insert(parent_path, parent_hash);
-            }
+    /// Get the underlying store mutably.
+    ///
+    /// Callers must not change the tree contents through this handle; use it
+    /// for backend housekeeping such as flushing buffered writes.
+    pub fn store_mut(&mut self) -> &mut S {
+        &mut self.store
+    }
 
-            level = next_level;
-        }
+    /// Consume the tree and return its store.
+    pub fn into_store(self) -> S {
+        self.store
+    }
 
-        level.into_values().next().unwrap_or(self.empty_hashes[0])
+    /// Get the hash of the subtree at `depth` with the given path prefix.
+    fn node_hash(&self, depth: usize, path: &Hash32) -> Result<Hash32, MerkleError> {
+        Ok(self
+            .store
+            .get_node(depth, path)?
+            .unwrap_or(self.empty_hashes[depth]))
     }
 
     /// Get a value by key.
-    pub fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
-        let key_hash = hash_key(key);
-        self.leaves.get(&key_hash).cloned()
+    pub fn try_get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, MerkleError> {
+        self.try_get_by_hash(&self.hasher.hash_key(key))
     }
 
     /// Get a value by key hash.
-    pub fn get_by_hash(&self, key_hash: &Hash32) -> Option<Vec<u8>> {
-        self.leaves.get(key_hash).cloned()
+    pub fn try_get_by_hash(&self, key_hash: &Hash32) -> Result<Option<Vec<u8>>, MerkleError> {
+        self.store.get_leaf(key_hash)
     }
 
     /// Insert or update a key-value pair.
-    pub fn insert(&mut self, key: &[u8], value: Vec<u8>) -> UpdateWitness {
-        let key_hash = hash_key(key);
-        self.insert_by_hash(key_hash, value)
+    pub fn try_insert(&mut self, key: &[u8], value: Vec<u8>) -> Result<UpdateWitness, MerkleError> {
+        self.try_insert_by_hash(self.hasher.hash_key(key), value)
     }
 
     /// Insert or update by key hash.
-    pub fn insert_by_hash(&mut self, key_hash: Hash32, value: Vec<u8>) -> UpdateWitness {
-        let old_value = self.leaves.get(&key_hash).cloned();
-        let siblings = self.compute_siblings(&key_hash);
-
-        let witness = UpdateWitness {
-            key: key_hash,
-            old_value,
-            new_value: Some(value.clone()),
-            siblings,
-        };
-
-        self.leaves.insert(key_hash, value);
-        self.cached_root = None; // Invalidate cache
-
-        witness
+    pub fn try_insert_by_hash(
+        &mut self,
+        key_hash: Hash32,
+        value: Vec<u8>,
+    ) -> Result<UpdateWitness, MerkleError> {
+        self.update(key_hash, Some(value))
     }
 
     /// Delete a key.
-    pub fn delete(&mut self, key: &[u8]) -> UpdateWitness {
-        let key_hash = hash_key(key);
-        self.delete_by_hash(key_hash)
+    pub fn try_delete(&mut self, key: &[u8]) -> Result<UpdateWitness, MerkleError> {
+        self.try_delete_by_hash(self.hasher.hash_key(key))
     }
 
     /// Delete by key hash.
-    pub fn delete_by_hash(&mut self, key_hash: Hash32) -> UpdateWitness {
-        let old_value = self.leaves.get(&key_hash).cloned();
-        let siblings = self.compute_siblings(&key_hash);
+    pub fn try_delete_by_hash(&mut self, key_hash: Hash32) -> Result<UpdateWitness, MerkleError> {
+        self.update(key_hash, None)
+    }
 
-        let witness = UpdateWitness {
+    /// Set or clear a leaf and rehash its path to the root.
+    fn update(
+        &mut self,
+        key_hash: Hash32,
+        new_value: Option<Vec<u8>>,
+    ) -> Result<UpdateWitness, MerkleError> {
+        // Siblings are unaffected by the update, so they serve both the
+        // witness and the path rehash below.
+        let siblings = self.compute_siblings(&key_hash)?;
+        let old_value = self.store.get_leaf(&key_hash)?;
+
+        let leaf_hash = leaf_hash(self.hasher, &key_hash, new_value.as_deref());
+        self.store.set_leaf(key_hash, new_value.clone())?;
+        self.write_path(&key_hash, leaf_hash, &siblings)?;
+
+        Ok(UpdateWitness {
             key: key_hash,
             old_value,
-            new_value: None,
+            new_value,
             siblings,
-        };
+            hash_function: self.hasher,
+        })
+    }
 
-        self.leaves.remove(&key_hash);
-        self.cached_root = None;
+    /// Recompute and store the nodes on a key's path from a new leaf hash.
+    fn write_path(
+        &mut self,
+        key_hash: &Hash32,
+        leaf_hash: Hash32,
+        siblings: &[Hash32],
+    ) -> Result<(), MerkleError> {
+        let mut current = leaf_hash;
+        self.set_node(TREE_DEPTH, path_prefix(key_hash, TREE_DEPTH), current)?;
 
-        witness
+        for (i, sibling) in siblings.iter().enumerate() {
+            let depth = TREE_DEPTH - 1 - i;
+            if get_bit(key_hash, depth) {
+                current = self.hasher.hash_nodes(sibling, &current);
+            } else {
+                current = self.hasher.hash_nodes(&current, sibling);
+            }
+            self.set_node(depth, path_prefix(key_hash, depth), current)?;
+        }
+
+        self.root = current;
+        Ok(())
+    }
+
+    /// Store a node hash, dropping it if the subtree became empty.
+    fn set_node(&mut self, depth: usize, path: Hash32, hash: Hash32) -> Result<(), MerkleError> {
+        let hash = (hash != self.empty_hashes[depth]).then_some(hash);
+        self.store.set_node(depth, path, hash)
     }
 
     /// Get a Merkle proof for a key.
-    pub fn get_proof(&self, key: &[u8]) -> MerkleProof {
-        let key_hash = hash_key(key);
-        self.get_proof_by_hash(&key_hash)
+    pub fn try_get_proof(&self, key: &[u8]) -> Result<MerkleProof, MerkleError> {
+        self.try_get_proof_by_hash(&self.hasher.hash_key(key))
     }
 
     /// Get a Merkle proof by key hash.
-    pub fn get_proof_by_hash(&self, key_hash: &Hash32) -> MerkleProof {
-        let value = self.leaves.get(key_hash).cloned();
-        let siblings = self.compute_siblings(key_hash);
+    pub fn try_get_proof_by_hash(&self, key_hash: &Hash32) -> Result<MerkleProof, MerkleError> {
+        let value = self.store.get_leaf(key_hash)?;
+        let siblings = self.compute_siblings(key_hash)?;
 
-        MerkleProof {
+        Ok(MerkleProof {
             key: *key_hash,
             value,
             siblings,
-        }
+            hash_function: self.hasher,
+        })
     }
 
-    /// Compute siblings for a key's path.
-    fn compute_siblings(&self, key_hash: &Hash32) -> Vec<Hash32> {
-        let target_path: Vec<bool> = (0..TREE_DEPTH).map(|i| get_bit(key_hash, i)).collect();
-
-        // Build all leaf hashes with their paths
-        let leaf_entries: Vec<(Vec<bool>, Hash32)> = self
-            .leaves
+    /// Get a multi-key proof.
+    pub fn try_get_multi_proof<K: AsRef<[u8]>>(
+        &self,
+        keys: &[K],
+    ) -> Result<MultiProof, MerkleError> {
+        let key_hashes: Vec<Hash32> = keys
             .iter()
-            .filter(|(k, _)| *k != key_hash) // Exclude the target key
-            .map(|(k, v)| {
-                let path: Vec<bool> = (0..TREE_DEPTH).map(|i| get_bit(k, i)).collect();
-                let hash = hash_leaf(k, v);
-                (path, hash)
-            })
+            .map(|k| self.hasher.hash_key(k.as_ref()))
             .collect();
+        self.try_get_multi_proof_by_hash(&key_hashes)
+    }
 
-        let mut siblings = Vec::with_capacity(TREE_DEPTH);
+    /// Get a multi-key proof by key hashes.
+    pub fn try_get_multi_proof_by_hash(
+        &self,
+        key_hashes: &[Hash32],
+    ) -> Result<MultiProof, MerkleError> {
+        let mut keys = key_hashes.to_vec();
+        keys.sort_unstable();
+        keys.dedup();
+
+        let mut values = Vec::with_capacity(keys.len());
+        let mut level = BTreeMap::new();
+        for key in &keys {
+            let value = self.store.get_leaf(key)?;
+            let leaf_hash = leaf_hash(self.hasher, key, value.as_deref());
+            level.insert(path_prefix(key, TREE_DEPTH), leaf_hash);
+            values.push(value);
+        }
 
-        // For each depth from bottom to top, compute the sibling hash
-        for depth in (0..TREE_DEPTH).rev() {
-            let target_prefix = &target_path[..depth];
-            let target_bit = target_path[depth];
+        let mut siblings = Vec::new();
+        if !keys.is_empty() {
+            fold_multi_levels(self.hasher, level, |depth, path| {
+                let hash = self.store.get_node(depth, path)?;
+                siblings.push(hash);
+                Ok(hash.unwrap_or(self.empty_hashes[depth]))
+            })?;
+        }
 
-            // Find all leaves that share the prefix but differ at this bit
-            let sibling_hash =
-                self.compute_subtree_hash(&leaf_entries, target_prefix, !target_bit, depth);
+        Ok(MultiProof {
+            keys,
+            values,
+            siblings,
+            hash_function: self.hasher,
+        })
+    }
 
-            siblings.push(sibling_hash);
-        }
+    /// Compute siblings for a key's path, ordered from leaf to root.
+    fn compute_siblings(&self, key_hash: &Hash32) -> Result<Vec<Hash32>, MerkleError> {
+        (1..=TREE_DEPTH)
+            .rev()
+            .map(|depth| self.node_hash(depth, &sibling_path(key_hash, depth)))
+            .collect()
+    }
+}
 
-        siblings
+impl SparseMerkleTree {
+    /// Create a new empty in-memory tree (SHA-256).
+    pub fn new() -> Self {
+        Self::with_hasher(HashFunction::Sha256)
     }
 
-    /// Compute the hash of a subtree rooted at a given path prefix and direction.
-    fn compute_subtree_hash(
-        &self,
-        leaves: &[(Vec<bool>, Hash32)],
-        prefix: &[bool],
-        direction: bool,
-        depth: usize,
-    ) -> Hash32 {
-        // Filter leaves that start with prefix + direction
-        let matching: Vec<&(Vec<bool>, Hash32)> = leaves
-            .iter()
-            .filter(|(path, _)| {
-                path.len() > depth
-                    && path[..prefix.len()] == *prefix
-                    && path[prefix.len()] == direction
-            })
-            .collect();
+    /// Create a new empty in-memory tree with the given hash function.
+    pub fn with_hasher(hasher: HashFunction) -> Self {
+        Self::with_store(MemoryStore::new(), hasher).expect(IN_MEMORY)
+    }
 
-        if matching.is_empty() {
-            return self.empty_hashes[depth + 1];
-        }
+    /// Get a value by key.
+    pub fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.try_get(key).expect(IN_MEMORY)
+    }
 
-        // Recursively compute subtree hash
-        self.compute_subtree_from_leaves(&matching, depth + 1)
+    /// Get a value by key hash.
+    pub fn get_by_hash(&self, key_hash: &Hash32) -> Option<Vec<u8>> {
+        self.try_get_by_hash(key_hash).expect(IN_MEMORY)
     }
 
-    /// Recursively compute subtree hash from filtered leaves.
-    fn compute_subtree_from_leaves(&self, leaves: &[&(Vec<bool>, Hash32)], depth: usize) -> Hash32 {
-        if depth == TREE_DEPTH {
-            // At leaf level
-            return leaves.first().map(|(_, h)| *h).unwrap_or(EMPTY_HASH);
-        }
+    /// Insert or update a key-value pair.
+    pub fn insert(&mut self, key: &[u8], value: Vec<u8>) -> UpdateWitness {
+        self.try_insert(key, value).expect(IN_MEMORY)
+    }
 
-        if leaves.is_empty() {
-            return self.empty_hashes[depth];
-        }
+    /// Insert or update by key hash.
+    pub fn insert_by_hash(&mut self, key_hash: Hash32, value: Vec<u8>) -> UpdateWitness {
+        self.try_insert_by_hash(key_hash, value).expect(IN_MEMORY)
+    }
 
-        // Split into left and right subtrees
-        let (left_leaves, right_leaves): (Vec<_>, Vec<_>) =
-            leaves.iter().partition(|(path, _)| !path[depth]);
+    /// Delete a key.
+    pub fn delete(&mut self, key: &[u8]) -> UpdateWitness {
+        self.try_delete(key).expect(IN_MEMORY)
+    }
 
-        let left_hash = if left_leaves.is_empty() {
-            self.empty_hashes[depth + 1]
-        } else {
-            self.compute_subtree_from_leaves(&left_leaves, depth + 1)
-        };
+    /// Delete by key hash.
+    pub fn delete_by_hash(&mut self, key_hash: Hash32) -> UpdateWitness {
+        self.try_delete_by_hash(key_hash).expect(IN_MEMORY)
+    }
 
-        let right_hash = if right_leaves.is_empty() {
-            self.empty_hashes[depth + 1]
-        } else {
-            self.compute_subtree_from_leaves(&right_leaves, depth + 1)
-        };
+    /// Get a Merkle proof for a key.
+    pub fn get_proof(&self, key: &[u8]) -> MerkleProof {
+        self.try_get_proof(key).expect(IN_MEMORY)
+    }
+
+    /// Get a Merkle proof by key hash.
+    pub fn get_proof_by_hash(&self, key_hash: &Hash32) -> MerkleProof {
+        self.try_get_proof_by_hash(key_hash).expect(IN_MEMORY)
+    }
 
-        hash_nodes(&left_hash, &right_hash)
+    /// Get a multi-key proof.
+    pub fn get_multi_proof<K: AsRef<[u8]>>(&self, keys: &[K]) -> MultiProof {
+        self.try_get_multi_proof(keys).expect(IN_MEMORY)
     }
 
     /// Serialize the tree for persistence.
@@ -419,14 +647,17 @@ impl SparseMerkleTree {
         }
 
         let tree = SerializedTree {
-            leaves: self.leaves.iter().map(|(k, v)| (*k, v.clone())).collect(),
+            leaves: self.store.leaves().map(|(k, v)| (*k, v.clone())).collect(),
         };
 
         bincode::serialize(&tree).map_err(|e| MerkleError::Serialization(e.to_string()))
     }
 
-    /// Deserialize a tree.
-    pub fn deserialize(data: &[u8]) -> Result<Self, MerkleError> {
+    /// Deserialize a tree written with `hasher`.
+    ///
+    /// The format holds only leaves keyed by key hash, so the hash function
+    /// is not recorded and must be supplied.
+    pub fn deserialize(data: &[u8], hasher: HashFunction) -> Result<Self, MerkleError> {
         #[derive(Deserialize)]
         struct SerializedTree {
             leaves: Vec<(Hash32, Vec<u8>)>,
@@ -435,13 +666,91 @@ impl SparseMerkleTree {
         let tree: SerializedTree =
             bincode::deserialize(data).map_err(|e| MerkleError::Serialization(e.to_string()))?;
 
-        let mut smt = Self::new();
-        smt.leaves = tree.leaves.into_iter().collect();
-        smt.cached_root = None;
+        let mut smt = Self::with_hasher(hasher);
+        for (key_hash, value) in tree.leaves {
+            smt.insert_by_hash(key_hash, value);
+        }
         Ok(smt)
     }
 }
 
+/// Computes the root of a tree from its leaves, without building the tree.
+///
+/// Leaves must be pushed in ascending key hash order. Only the open
+/// subtrees on the path of the last leaf are kept, so memory stays at
+/// O(TREE_DEPTH) however many leaves there are.
+pub struct RootBuilder {
+    hasher: HashFunction,
+    empty_hashes: &'static [Hash32],
+    /// Open subtrees as (depth, a key hash below it, hash), by increasing
+    /// depth; each one is the left sibling of a path above the next.
+    open: Vec<(usize, Hash32, Hash32)>,
+}
+
+impl RootBuilder {
+    /// Start an empty tree hashed with `hasher`.
+    pub fn new(hasher: HashFunction) -> Self {
+        Self {
+            hasher,
+            empty_hashes: hasher.empty_hashes(),
+            open: Vec::new(),
+        }
+    }
+
+    /// Add the next leaf.
+    ///
+    /// Fails with [`MerkleError::UnorderedLeaves`] unless its path comes
+    /// after the previous leaf's.
+    pub fn push(&mut self, key_hash: Hash32, value: &[u8]) -> Result<(), MerkleError> {
+        if let Some(&(_, last, _)) = self.open.last() {
+            let common = (0..TREE_DEPTH)
+                .find(|&bit| get_bit(&last, bit) != get_bit(&key_hash, bit))
+                .ok_or(MerkleError::UnorderedLeaves)?;
+            if !get_bit(&key_hash, common) {
+                return Err(MerkleError::UnorderedLeaves);
+            }
+            // Close the last leaf's subtrees below the node they share
+            self.fold_to(common + 1);
+        }
+
+        let leaf = self.hasher.hash_leaf(&key_hash, value);
+        self.open.push((TREE_DEPTH, key_hash, leaf));
+        Ok(())
+    }
+
+    /// Get the root of the leaves pushed so far.
+    pub fn finish(mut self) -> Hash32 {
+        self.fold_to(0);
+        self.open
+            .pop()
+            .map_or(self.empty_hashes[0], |(_, _, hash)| hash)
+    }
+
+    /// Hash the last open subtree up to `depth`, merging it with its left
+    /// sibling where one is open.
+    fn fold_to(&mut self, depth: usize) {
+        while let Some(&(node_depth, key_hash, hash)) = self.open.last() {
+            if node_depth <= depth {
+                break;
+            }
+            self.open.pop();
+            let parent = match self.open.last() {
+                Some(&(left_depth, _, left)) if left_depth == node_depth => {
+                    self.open.pop();
+                    self.hasher.hash_nodes(&left, &hash)
+                }
+                _ if get_bit(&key_hash, node_depth - 1) => self
+                    .hasher
+                    .hash_nodes(&self.empty_hashes[node_depth], &hash),
+                _ => self
+                    .hasher
+                    .hash_nodes(&hash, &self.empty_hashes[node_depth]),
+            };
+            self.open.push((node_depth - 1, key_hash, parent));
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -529,10 +838,221 @@ mod tests {
         tree.insert(b"key2", b"value2".to_vec());
 
         let data = tree.serialize().unwrap();
-        let tree2 = SparseMerkleTree::deserialize(&data).unwrap();
+        let tree2 = SparseMerkleTree::deserialize(&data, HashFunction::Sha256).unwrap();
 
         assert_eq!(tree.root(), tree2.root());
         assert_eq!(tree2.get(b"key1"), Some(b"value1".to_vec()));
         assert_eq!(tree2.get(b"key2"), Some(b"value2".to_vec()));
     }
+
+    #[test]
+    fn test_known_roots() {
+        // Roots must stay stable so existing blobs and the guest agree.
+        let mut tree = SparseMerkleTree::new();
+        assert_eq!(
+            hex::encode(tree.root()),
+            "0071bb79d0a2e58b2d539f99f49b08af1ad9c85e735227f04875a356c6b18edb"
+        );
+
+        tree.insert(b"key1", b"value1".to_vec());
+        tree.insert(b"key2", b"value2".to_vec());
+        assert_eq!(
+            hex::encode(tree.root()),
+            "0c6346aef4da00bc60a98fadcb27693b3317945043ab149e55f35c3f6dbc0637"
+        );
+    }
+
+    #[test]
+    fn test_many_keys_witnesses_and_proofs() {
+        let mut tree = SparseMerkleTree::new();
+
+        for i in 0..200u32 {
+            let prev_root = tree.root();
+            let witness = tree.insert(format!("key{}", i).as_bytes(), i.to_le_bytes().to_vec());
+            assert_eq!(witness.compute_old_root(), prev_root);
+            assert_eq!(witness.compute_new_root(), tree.root());
+        }
+
+        let root = tree.root();
+        for i in 0..200u32 {
+            let proof = tree.get_proof(format!("key{}", i).as_bytes());
+            assert_eq!(proof.value, Some(i.to_le_bytes().to_vec()));
+            assert!(proof.verify(&root));
+        }
+
+        // Deleting everything in a different order returns to the empty root
+        for i in (0..200u32).rev() {
+            let prev_root = tree.root();
+            let witness = tree.delete(format!("key{}", i).as_bytes());
+            assert_eq!(witness.compute_old_root(), prev_root);
+            assert_eq!(witness.compute_new_root(), tree.root());
+        }
+        assert_eq!(tree.root(), SparseMerkleTree::new().root());
+    }
+
+    #[test]
+    fn test_multi_proof() {
+        let mut tree = SparseMerkleTree::new();
+        for i in 0..50u32 {
+            tree.insert(format!("key{}", i).as_bytes(), i.to_le_bytes().to_vec());
+        }
+        let root = tree.root();
+
+        let keys: Vec<Vec<u8>> = (40..60u32)
+            .map(|i| format!("key{}", i).into_bytes())
+            .collect();
+        let proof = tree.get_multi_proof(&keys);
+        assert!(proof.verify(&root));
+        assert_eq!(proof.keys.len(), 20);
+
+        // Inclusion and exclusion are both proven
+        assert_eq!(
+            proof.value(&hash_key(b"key45")),
+            Some(Some(&45u32.to_le_bytes()[..]))
+        );
+        assert_eq!(proof.value(&hash_key(b"key55")), Some(None));
+        assert_eq!(proof.value(&hash_key(b"key1")), None);
+
+        // Shared siblings make it smaller than separate proofs
+        assert!(proof.siblings.len() < keys.len() * TREE_DEPTH);
+
+        // Tampering with a value or sibling breaks the proof
+        let mut bad = proof.clone();
+        let idx = bad.keys.binary_search(&hash_key(b"key45")).unwrap();
+        bad.values[idx] = Some(b"forged".to_vec());
+        assert!(!bad.verify(&root));
+
+        let mut bad = proof.clone();
+        bad.siblings.pop();
+        assert!(!bad.verify(&root));
+    }
+
+    #[test]
+    fn test_multi_proof_single_key_matches_proof() {
+        let mut tree = SparseMerkleTree::new();
+        tree.insert(b"key1", b"value1".to_vec());
+        tree.insert(b"key2", b"value2".to_vec());
+
+        let proof = tree.get_multi_proof(&[b"key1"]);
+        let single = tree.get_proof(b"key1");
+        assert_eq!(proof.siblings.len(), TREE_DEPTH);
+        assert_eq!(proof.compute_root().unwrap(), single.compute_root());
+    }
+
+    #[test]
+    fn test_compressed_proof_roundtrip() {
+        let mut tree = SparseMerkleTree::new();
+        for i in 0..20u32 {
+            tree.insert(format!("key{}", i).as_bytes(), i.to_le_bytes().to_vec());
+        }
+        let root = tree.root();
+
+        let proof = tree.get_proof(b"key7");
+        let compressed = proof.compress();
+        assert!(compressed.siblings.hashes.len() < TREE_DEPTH / 4);
+        assert!(compressed.verify(&root));
+        assert_eq!(compressed.decompress().unwrap(), proof);
+
+        let encoded = bincode::serialize(&compressed).unwrap();
+        assert!(encoded.len() * 10 < bincode::serialize(&proof).unwrap().len());
+
+        // Hash count must match the bitmap
+        let mut bad = compressed.clone();
+        bad.siblings.hashes.pop();
+        assert!(bad.decompress().is_err());
+        assert!(!bad.verify(&root));
+    }
+
+    #[test]
+    fn test_compressed_witness_roundtrip() {
+        let mut tree = SparseMerkleTree::new();
+        tree.insert(b"key1", b"value1".to_vec());
+        let root0 = tree.root();
+
+        let witness = tree.insert(b"key2", b"value2".to_vec());
+        let restored = witness.compress().decompress().unwrap();
+
+        assert_eq!(restored, witness);
+        assert_eq!(restored.compute_old_root(), root0);
+        assert_eq!(restored.compute_new_root(), tree.root());
+    }
+
+    #[test]
+    fn test_root_builder() {
+        for hasher in HashFunction::ALL {
+            let mut tree = SparseMerkleTree::with_hasher(hasher);
+            assert_eq!(RootBuilder::new(hasher).finish(), tree.root());
+
+            let mut leaves: Vec<(Hash32, Vec<u8>)> = (0..50u32)
+                .map(|i| (hasher.hash_key(&i.to_be_bytes()), vec![i as u8; 3]))
+                .collect();
+            // Neighbouring paths share all but the last bit
+            let mut neighbour = leaves[0].0;
+            neighbour[TREE_DEPTH / 8 - 1] ^= 1;
+            leaves.push((neighbour, b"n".to_vec()));
+            for (key_hash, value) in &leaves {
+                tree.insert_by_hash(*key_hash, value.clone());
+            }
+
+            leaves.sort();
+            let mut builder = RootBuilder::new(hasher);
+            for (key_hash, value) in &leaves {
+                builder.push(*key_hash, value).unwrap();
+            }
+            assert_eq!(builder.finish(), tree.root());
+
+            let mut builder = RootBuilder::new(hasher);
+            builder.push(leaves[1].0, &leaves[1].1).unwrap();
+            assert!(matches!(
+                builder.push(leaves[0].0, &leaves[0].1),
+                Err(MerkleError::UnorderedLeaves)
+            ));
+            assert!(matches!(
+                builder.push(leaves[1].0, &leaves[1].1),
+                Err(MerkleError::UnorderedLeaves)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_hash_functions() {
+        let mut roots = Vec::new();
+        for hasher in HashFunction::ALL {
+            let mut tree = SparseMerkleTree::with_hasher(hasher);
+            assert_eq!(tree.root(), hasher.empty_hashes()[0]);
+            for i in 0..10u32 {
+                tree.insert(format!("key{}", i).as_bytes(), vec![i as u8; 40]);
+            }
+            let root0 = tree.root();
+            let witness = tree.delete(b"key3");
+            let root = tree.root();
+            roots.push(root);
+
+            assert_eq!(witness.hash_function, hasher);
+            assert_eq!(witness.compute_old_root(), root0);
+            assert_eq!(witness.compute_new_root(), root);
+            assert_eq!(witness.compress().decompress().unwrap(), witness);
+
+            let proof = tree.get_proof(b"key4");
+            assert!(proof.verify(&root));
+            assert!(proof.compress().verify(&root));
+            assert!(tree.get_proof(b"key3").verify(&root));
+            assert!(tree.get_multi_proof(&[b"key1", b"key3"]).verify(&root));
+
+            // A proof checked with another hash function fails
+            let mut wrong = proof.clone();
+            wrong.hash_function = HashFunction::ALL[(hasher as usize + 1) % 3];
+            assert!(!wrong.verify(&root));
+
+            assert_eq!(hasher.name().parse::<HashFunction>().unwrap(), hasher);
+
+            // Key paths use their top bits too (a Poseidon field element's
+            // first two bits are always zero)
+            assert!((0..10u32).any(|i| hasher.hash_key(format!("key{}", i).as_bytes())[0] >= 0x40));
+        }
+
+        assert_ne!(roots[0], roots[1]);
+        assert_ne!(roots[1], roots[2]);
+        assert!("keccak".parse::<HashFunction>().is_err());
+    }
 }
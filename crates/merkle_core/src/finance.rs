@@ -0,0 +1,416 @@
+//! Finance-specific operations for the example app.
+
+use crate::{
+    CompressedWitness, HashFunction, OperationType, TransitionInput, TreeHasher,
+    VerifiableOperation,
+};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use ed25519_dalek::{Signature, VerifyingKey};
+use serde::{Deserialize, Serialize};
+
+/// Domain separator of mint messages.
+const MINT_DOMAIN: &[u8] = b"celestia-zk-da:mint";
+/// Domain separator of transfer messages.
+const TRANSFER_DOMAIN: &[u8] = b"celestia-zk-da:transfer";
+/// Domain separator of funded account creation messages.
+const CREATE_DOMAIN: &[u8] = b"celestia-zk-da:create-account";
+/// Domain separator of burn messages.
+const BURN_DOMAIN: &[u8] = b"celestia-zk-da:burn";
+
+/// A balance transfer operation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Transfer {
+    pub from: String,
+    pub to: String,
+    pub amount: u64,
+    pub nonce: u64,
+}
+
+/// Account state.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Account {
+    pub balance: u64,
+    pub nonce: u64,
+    /// ed25519 key signing the account's transfers and burns (none for an
+    /// account stored before accounts had keys, which cannot send or
+    /// receive).
+    pub public_key: Option<[u8; 32]>,
+}
+
+impl Account {
+    /// Encode in the bincode layout (balance, nonce, optional key), without
+    /// needing `std`.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(49);
+        data.extend_from_slice(&self.balance.to_le_bytes());
+        data.extend_from_slice(&self.nonce.to_le_bytes());
+        match &self.public_key {
+            Some(public_key) => {
+                data.push(1);
+                data.extend_from_slice(public_key);
+            }
+            None => data.push(0),
+        }
+        data
+    }
+
+    /// Decode from [`Self::encode`]'s layout, or from the legacy
+    /// `(balance, nonce)` layout of accounts stored before accounts had keys.
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        let (balance, rest) = data.split_first_chunk::<8>()?;
+        let (nonce, rest) = rest.split_first_chunk::<8>()?;
+        let public_key = match rest {
+            [] | [0] => None,
+            [1, public_key @ ..] => Some(<[u8; 32]>::try_from(public_key).ok()?),
+            _ => return None,
+        };
+        Some(Self {
+            balance: u64::from_le_bytes(*balance),
+            nonce: u64::from_le_bytes(*nonce),
+            public_key,
+        })
+    }
+}
+
+/// Verify a transfer operation inside the circuit.
+/// Returns true if the transfer is valid.
+pub fn verify_transfer(
+    from_old: &Account,
+    from_new: &Account,
+    to_old: &Account,
+    to_new: &Account,
+    amount: u64,
+) -> bool {
+    // Check sender has sufficient balance
+    if from_old.balance < amount {
+        return false;
+    }
+
+    // Check balances are updated correctly
+    if from_new.balance != from_old.balance - amount {
+        return false;
+    }
+
+    if to_old.balance.checked_add(amount) != Some(to_new.balance) {
+        return false;
+    }
+
+    // Check sender nonce is incremented
+    if from_old.nonce.checked_add(1) != Some(from_new.nonce) {
+        return false;
+    }
+
+    // Check receiver nonce is unchanged
+    if to_new.nonce != to_old.nonce {
+        return false;
+    }
+
+    // Check neither account's key is replaced
+    if from_new.public_key != from_old.public_key || to_new.public_key != to_old.public_key {
+        return false;
+    }
+
+    true
+}
+
+/// Index of the receiver's witness of a transfer whose sender's witness is
+/// at `witness_index`: the first later witness keyed by the hash of `to`.
+pub fn receiver_witness(
+    hash_function: HashFunction,
+    witnesses: &[CompressedWitness],
+    witness_index: usize,
+    to: &[u8],
+) -> Option<usize> {
+    let to_hash = hash_function.hash_key(to);
+    witnesses
+        .iter()
+        .enumerate()
+        .skip(witness_index + 1)
+        .find(|(_, w)| w.key == to_hash)
+        .map(|(index, _)| index)
+}
+
+/// Verify a transfer against the witnesses of a transition.
+///
+/// The sender's witness is the one at `witness_index` and must be keyed by
+/// the hash of `from`; the receiver's is found by [`receiver_witness`]. The
+/// accounts they read and write must obey [`verify_transfer`], and
+/// `signature` must be the sender's key's signature of [`transfer_message`]
+/// for the transition's app and the sender's nonce before the transfer. The
+/// receiver must be an existing account with a key, so credited funds can
+/// always be spent.
+pub fn verify_transfer_witnesses(
+    input: &TransitionInput,
+    witness_index: usize,
+    from: &[u8],
+    to: &[u8],
+    amount: u64,
+    signature: &[u8],
+) -> bool {
+    if from == to {
+        return false;
+    }
+    let (hash_function, witnesses) = (input.hash_function, &input.witnesses);
+    let from_hash = hash_function.hash_key(from);
+
+    let Some(sender) = witnesses.get(witness_index).filter(|w| w.key == from_hash) else {
+        return false;
+    };
+    let Some(receiver) = receiver_witness(hash_function, witnesses, witness_index, to)
+        .map(|index| &witnesses[index])
+    else {
+        return false;
+    };
+
+    let decode = |value: &Option<Vec<u8>>| value.as_deref().and_then(Account::decode);
+    let (Some(from_old), Some(from_new), Some(to_old), Some(to_new)) = (
+        decode(&sender.old_value),
+        decode(&sender.new_value),
+        decode(&receiver.old_value),
+        decode(&receiver.new_value),
+    ) else {
+        return false;
+    };
+
+    let (Some(public_key), Some(_)) = (&from_old.public_key, &to_old.public_key) else {
+        return false;
+    };
+    verify_signature(
+        public_key,
+        &transfer_message(&input.app_id, from, to, amount, from_old.nonce),
+        signature,
+    ) && verify_transfer(&from_old, &from_new, &to_old, &to_new, amount)
+}
+
+/// Message the mint authority signs to mint `amount` into the account at
+/// `key` of app `app_id` while the account's nonce is `nonce`.
+pub fn mint_message(app_id: &[u8], key: &[u8], amount: u64, nonce: u64) -> Vec<u8> {
+    signed_message(MINT_DOMAIN, app_id, &[key], amount, nonce)
+}
+
+/// Message the owner of the account at `from` signs to transfer `amount` to
+/// the account at `to` of app `app_id` while the sender's nonce is `nonce`.
+pub fn transfer_message(app_id: &[u8], from: &[u8], to: &[u8], amount: u64, nonce: u64) -> Vec<u8> {
+    signed_message(TRANSFER_DOMAIN, app_id, &[from, to], amount, nonce)
+}
+
+/// Message the owner of the account at `key` signs to burn `amount` from it
+/// in app `app_id` while the account's nonce is `nonce`.
+pub fn burn_message(app_id: &[u8], key: &[u8], amount: u64, nonce: u64) -> Vec<u8> {
+    signed_message(BURN_DOMAIN, app_id, &[key], amount, nonce)
+}
+
+/// Message signed to create the account at `key` of app `app_id` with
+/// `balance`, owned by `public_key` (empty for no owner): by the mint
+/// authority for a funded account, by the owner for an empty one.
+pub fn create_account_message(
+    app_id: &[u8],
+    key: &[u8],
+    public_key: &[u8],
+    balance: u64,
+) -> Vec<u8> {
+    signed_message(CREATE_DOMAIN, app_id, &[key, public_key], balance, 0)
+}
+
+/// Domain separator, length-prefixed app ID and account keys, amount and
+/// nonce.
+///
+/// The app ID keeps a signature for one app from being replayed in another
+/// app sharing keys or account names.
+fn signed_message(
+    domain: &[u8],
+    app_id: &[u8],
+    keys: &[&[u8]],
+    amount: u64,
+    nonce: u64,
+) -> Vec<u8> {
+    let mut message = domain.to_vec();
+    for key in core::iter::once(&app_id).chain(keys) {
+        message.extend_from_slice(&(key.len() as u64).to_le_bytes());
+        message.extend_from_slice(key);
+    }
+    message.extend_from_slice(&amount.to_le_bytes());
+    message.extend_from_slice(&nonce.to_le_bytes());
+    message
+}
+
+/// Check an ed25519 signature of `message` by `public_key`.
+pub fn verify_signature(public_key: &[u8; 32], message: &[u8], signature: &[u8]) -> bool {
+    let Ok(signature) = Signature::from_slice(signature) else {
+        return false;
+    };
+    VerifyingKey::from_bytes(public_key)
+        .is_ok_and(|key| key.verify_strict(message, &signature).is_ok())
+}
+
+/// Parse a hex-encoded ed25519 public key, such as a mint authority.
+pub fn parse_public_key(hex_key: &str) -> Result<[u8; 32], String> {
+    hex::decode(hex_key)
+        .map_err(|e| e.to_string())?
+        .try_into()
+        .map_err(|_| "public key must be 32 bytes".into())
+}
+
+/// Verify a mint of `amount` into the account at `key`.
+///
+/// The mint authority must have signed [`mint_message`] for app `app_id`
+/// and the account's nonce before the mint, and the account must be credited exactly, without
+/// overflow, with its nonce incremented so the signature cannot be replayed
+/// and its key kept. Without an authority no mint is valid.
+pub fn verify_mint(
+    app_id: &[u8],
+    authority: Option<&[u8; 32]>,
+    key: &[u8],
+    old_value: Option<&[u8]>,
+    new_value: Option<&[u8]>,
+    amount: u64,
+    signature: &[u8],
+) -> bool {
+    let Some(authority) = authority else {
+        return false;
+    };
+    let (Some(old), Some(new)) = (
+        old_value.and_then(Account::decode),
+        new_value.and_then(Account::decode),
+    ) else {
+        return false;
+    };
+
+    old.balance.checked_add(amount) == Some(new.balance)
+        && old.nonce.checked_add(1) == Some(new.nonce)
+        && new.public_key == old.public_key
+        && verify_signature(
+            authority,
+            &mint_message(app_id, key, amount, old.nonce),
+            signature,
+        )
+}
+
+/// Verify a burn of `amount` from the account at `key`.
+///
+/// The account's owner must have signed [`burn_message`] for app `app_id`
+/// and the account's nonce before the burn, and the account must be
+/// debited exactly, with its nonce incremented so the signature cannot be
+/// replayed and its key kept.
+pub fn verify_burn(
+    app_id: &[u8],
+    key: &[u8],
+    old_value: Option<&[u8]>,
+    new_value: Option<&[u8]>,
+    amount: u64,
+    signature: &[u8],
+) -> bool {
+    let (Some(old), Some(new)) = (
+        old_value.and_then(Account::decode),
+        new_value.and_then(Account::decode),
+    ) else {
+        return false;
+    };
+    let Some(public_key) = &old.public_key else {
+        return false;
+    };
+
+    old.balance.checked_sub(amount) == Some(new.balance)
+        && old.nonce.checked_add(1) == Some(new.nonce)
+        && new.public_key == old.public_key
+        && verify_signature(
+            public_key,
+            &burn_message(app_id, key, amount, old.nonce),
+            signature,
+        )
+}
+
+/// Verify the creation of the account at `key` with `initial_balance`.
+///
+/// The account must not exist yet and must be written with the balance and
+/// a zero nonce. It needs a signature of [`create_account_message`] for app
+/// `app_id` and the account's owner. A non-zero balance is new money, so
+/// like a mint it is signed by the mint authority; without an authority
+/// only empty accounts can be created. An empty account is signed by its
+/// owner's key, so no one can register an account under a key they do not
+/// hold.
+pub fn verify_create_account(
+    app_id: &[u8],
+    authority: Option<&[u8; 32]>,
+    key: &[u8],
+    old_value: Option<&[u8]>,
+    new_value: Option<&[u8]>,
+    initial_balance: u64,
+    signature: &[u8],
+) -> bool {
+    if old_value.is_some() {
+        return false;
+    }
+    let Some(account) = new_value.and_then(Account::decode) else {
+        return false;
+    };
+    if account.balance != initial_balance || account.nonce != 0 {
+        return false;
+    }
+
+    let signer = if initial_balance == 0 {
+        account.public_key.as_ref()
+    } else {
+        authority
+    };
+    let Some(signer) = signer else {
+        return false;
+    };
+    let public_key = account.public_key.as_ref().map_or(&[][..], |key| &key[..]);
+    verify_signature(
+        signer,
+        &create_account_message(app_id, key, public_key, initial_balance),
+        signature,
+    )
+}
+
+/// Verify the business logic of an operation against its transition (the
+/// witnesses, app ID and mint authority).
+pub fn verify_operation(op: &VerifiableOperation, input: &TransitionInput) -> bool {
+    match &op.op_type {
+        OperationType::Set => {
+            // No constraints for simple set operations
+            true
+        }
+        OperationType::CreateAccount {
+            initial_balance,
+            signature,
+        } => verify_create_account(
+            &input.app_id,
+            input.mint_authority.as_ref(),
+            &op.key,
+            op.old_value.as_deref(),
+            op.new_value.as_deref(),
+            *initial_balance,
+            signature,
+        ),
+        OperationType::Transfer {
+            from,
+            to,
+            amount,
+            signature,
+        } => {
+            // The sender must have signed the transfer, and the sender's and
+            // receiver's witnesses must show a valid debit and credit
+            verify_transfer_witnesses(input, op.witness_index, from, to, *amount, signature)
+        }
+        OperationType::Mint { amount, signature } => verify_mint(
+            &input.app_id,
+            input.mint_authority.as_ref(),
+            &op.key,
+            op.old_value.as_deref(),
+            op.new_value.as_deref(),
+            *amount,
+            signature,
+        ),
+        OperationType::Burn { amount, signature } => verify_burn(
+            &input.app_id,
+            &op.key,
+            op.old_value.as_deref(),
+            op.new_value.as_deref(),
+            *amount,
+            signature,
+        ),
+    }
+}
@@ -0,0 +1,299 @@
+//! Hash functions for the state commitment.
+//!
+//! A [`TreeHasher`] defines how keys are mapped to tree paths and how leaves
+//! and internal nodes are hashed. Every app picks one [`HashFunction`]; the
+//! tree, the witness checks and the SP1 guest must all use the same one or
+//! the roots will not match.
+
+use crate::{CoreError, Hash32, EMPTY_HASH, TREE_DEPTH};
+use alloc::string::ToString;
+use alloc::vec;
+use alloc::vec::Vec;
+#[cfg(feature = "poseidon")]
+use ark_bn254::Fr;
+#[cfg(feature = "poseidon")]
+use ark_ff::{BigInteger, PrimeField};
+use core::fmt;
+use core::str::FromStr;
+#[cfg(feature = "poseidon")]
+use light_poseidon::{Poseidon, PoseidonHasher as _};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+#[cfg(feature = "poseidon")]
+use std::cell::RefCell;
+#[cfg(feature = "std")]
+use std::sync::OnceLock;
+
+/// Hashing scheme of a sparse Merkle tree.
+pub trait TreeHasher {
+    /// Hash two child nodes to produce the parent hash.
+    fn hash_nodes(&self, left: &Hash32, right: &Hash32) -> Hash32;
+
+    /// Hash a leaf value.
+    fn hash_leaf(&self, key: &Hash32, value: &[u8]) -> Hash32;
+
+    /// Hash a key to get its path in the tree.
+    fn hash_key(&self, key: &[u8]) -> Hash32;
+}
+
+/// SHA-256 (the original commitment scheme).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Sha256Hasher;
+
+impl TreeHasher for Sha256Hasher {
+    fn hash_nodes(&self, left: &Hash32, right: &Hash32) -> Hash32 {
+        let mut hasher = Sha256::new();
+        hasher.update(left);
+        hasher.update(right);
+        hasher.finalize().into()
+    }
+
+    fn hash_leaf(&self, key: &Hash32, value: &[u8]) -> Hash32 {
+        let mut hasher = Sha256::new();
+        hasher.update([0x00]); // Leaf prefix
+        hasher.update(key);
+        hasher.update(value);
+        hasher.finalize().into()
+    }
+
+    fn hash_key(&self, key: &[u8]) -> Hash32 {
+        let mut hasher = Sha256::new();
+        hasher.update(key);
+        hasher.finalize().into()
+    }
+}
+
+/// BLAKE3 (fast on the host).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Blake3Hasher;
+
+impl TreeHasher for Blake3Hasher {
+    fn hash_nodes(&self, left: &Hash32, right: &Hash32) -> Hash32 {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(left);
+        hasher.update(right);
+        hasher.finalize().into()
+    }
+
+    fn hash_leaf(&self, key: &Hash32, value: &[u8]) -> Hash32 {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(&[0x00]); // Leaf prefix
+        hasher.update(key);
+        hasher.update(value);
+        hasher.finalize().into()
+    }
+
+    fn hash_key(&self, key: &[u8]) -> Hash32 {
+        blake3::hash(key).into()
+    }
+}
+
+#[cfg(feature = "poseidon")]
+/// Poseidon over the BN254 scalar field (circom parameters).
+///
+/// Hashes are big-endian field elements. Byte strings are absorbed in
+/// 31-byte chunks, starting from their length, so every chunk fits in the
+/// field. Leaves hash `(1, key, H(value))` with width 3 to keep them apart
+/// from width-2 internal nodes.
+///
+/// A field element's top bits are not uniform (the first two are always
+/// zero), so a key hash is not one: it is the low 16 bytes of `(2, H(key),
+/// 0)` followed by those of `(2, H(key), 1)`, making every bit of the tree
+/// path uniform.
+///
+/// light-poseidon needs `std`, so this hasher links it even without the
+/// `std` feature: a guest that verifies Poseidon trees is not `no_std`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PoseidonHasher;
+
+/// Domain tag of Poseidon leaf hashes.
+#[cfg(feature = "poseidon")]
+const POSEIDON_LEAF_TAG: u64 = 1;
+
+/// Domain tag of the Poseidon permutations expanding key hashes.
+#[cfg(feature = "poseidon")]
+const POSEIDON_KEY_TAG: u64 = 2;
+
+/// Bytes per absorbed field element.
+#[cfg(feature = "poseidon")]
+const POSEIDON_CHUNK_LEN: usize = 31;
+
+#[cfg(feature = "poseidon")]
+impl PoseidonHasher {
+    fn permute(inputs: &[Fr]) -> Fr {
+        std::thread_local! {
+            /// Hashers by input count (parameter setup is costly).
+            static HASHERS: RefCell<Vec<Option<Poseidon<Fr>>>> = const { RefCell::new(Vec::new()) };
+        }
+
+        HASHERS.with(|hashers| {
+            let mut hashers = hashers.borrow_mut();
+            if hashers.len() <= inputs.len() {
+                hashers.resize_with(inputs.len() + 1, || None);
+            }
+            hashers[inputs.len()]
+                .get_or_insert_with(|| {
+                    Poseidon::<Fr>::new_circom(inputs.len())
+                        .expect("poseidon width is within circom parameters")
+                })
+                .hash(inputs)
+                .expect("input count matches poseidon width")
+        })
+    }
+
+    fn hash_bytes(data: &[u8]) -> Fr {
+        let mut acc = Fr::from(data.len() as u64);
+        for chunk in data.chunks(POSEIDON_CHUNK_LEN) {
+            acc = Self::permute(&[acc, Fr::from_be_bytes_mod_order(chunk)]);
+        }
+        acc
+    }
+
+    fn to_field(hash: &Hash32) -> Fr {
+        Fr::from_be_bytes_mod_order(hash)
+    }
+
+    fn to_hash(element: Fr) -> Hash32 {
+        let bytes = element.into_bigint().to_bytes_be();
+        let mut hash = [0u8; 32];
+        hash[32 - bytes.len()..].copy_from_slice(&bytes);
+        hash
+    }
+}
+
+#[cfg(feature = "poseidon")]
+impl TreeHasher for PoseidonHasher {
+    fn hash_nodes(&self, left: &Hash32, right: &Hash32) -> Hash32 {
+        Self::to_hash(Self::permute(&[
+            Self::to_field(left),
+            Self::to_field(right),
+        ]))
+    }
+
+    fn hash_leaf(&self, key: &Hash32, value: &[u8]) -> Hash32 {
+        Self::to_hash(Self::permute(&[
+            Fr::from(POSEIDON_LEAF_TAG),
+            Self::to_field(key),
+            Self::hash_bytes(value),
+        ]))
+    }
+
+    fn hash_key(&self, key: &[u8]) -> Hash32 {
+        let element = Self::hash_bytes(key);
+        let mut hash = [0u8; 32];
+        for (index, half) in hash.chunks_mut(16).enumerate() {
+            let expanded = Self::to_hash(Self::permute(&[
+                Fr::from(POSEIDON_KEY_TAG),
+                element,
+                Fr::from(index as u64),
+            ]));
+            half.copy_from_slice(&expanded[16..]);
+        }
+        hash
+    }
+}
+
+/// Hash function selected for an app.
+///
+/// Recorded in the node configuration, the state store and every transition
+/// blob. The bincode encoding is the variant index, which the SP1 guest
+/// mirrors, so variants must only ever be appended.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashFunction {
+    /// SHA-256.
+    #[default]
+    Sha256,
+    /// BLAKE3.
+    Blake3,
+    /// Poseidon over BN254.
+    Poseidon,
+}
+
+impl HashFunction {
+    /// All supported hash functions.
+    pub const ALL: [HashFunction; 3] = [Self::Sha256, Self::Blake3, Self::Poseidon];
+
+    /// Lowercase name (as used in configuration).
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Sha256 => "sha256",
+            Self::Blake3 => "blake3",
+            Self::Poseidon => "poseidon",
+        }
+    }
+
+    /// Hash of an empty subtree at each depth (index 0 is the root).
+    #[cfg(feature = "std")]
+    pub fn empty_hashes(&self) -> &'static [Hash32] {
+        static EMPTY_HASHES: [OnceLock<Vec<Hash32>>; 3] =
+            [OnceLock::new(), OnceLock::new(), OnceLock::new()];
+        EMPTY_HASHES[*self as usize].get_or_init(|| self.compute_empty_hashes())
+    }
+
+    /// Compute the empty-subtree hashes (uncached, for `no_std` builds).
+    pub fn compute_empty_hashes(&self) -> Vec<Hash32> {
+        let mut empty_hashes = vec![EMPTY_HASH; TREE_DEPTH + 1];
+        for i in (0..TREE_DEPTH).rev() {
+            empty_hashes[i] = self.hash_nodes(&empty_hashes[i + 1], &empty_hashes[i + 1]);
+        }
+        empty_hashes
+    }
+}
+
+#[cfg(not(feature = "poseidon"))]
+fn poseidon_unsupported() -> ! {
+    panic!("Poseidon hashing requires the `poseidon` feature")
+}
+
+impl TreeHasher for HashFunction {
+    fn hash_nodes(&self, left: &Hash32, right: &Hash32) -> Hash32 {
+        match self {
+            Self::Sha256 => Sha256Hasher.hash_nodes(left, right),
+            Self::Blake3 => Blake3Hasher.hash_nodes(left, right),
+            #[cfg(feature = "poseidon")]
+            Self::Poseidon => PoseidonHasher.hash_nodes(left, right),
+            #[cfg(not(feature = "poseidon"))]
+            Self::Poseidon => poseidon_unsupported(),
+        }
+    }
+
+    fn hash_leaf(&self, key: &Hash32, value: &[u8]) -> Hash32 {
+        match self {
+            Self::Sha256 => Sha256Hasher.hash_leaf(key, value),
+            Self::Blake3 => Blake3Hasher.hash_leaf(key, value),
+            #[cfg(feature = "poseidon")]
+            Self::Poseidon => PoseidonHasher.hash_leaf(key, value),
+            #[cfg(not(feature = "poseidon"))]
+            Self::Poseidon => poseidon_unsupported(),
+        }
+    }
+
+    fn hash_key(&self, key: &[u8]) -> Hash32 {
+        match self {
+            Self::Sha256 => Sha256Hasher.hash_key(key),
+            Self::Blake3 => Blake3Hasher.hash_key(key),
+            #[cfg(feature = "poseidon")]
+            Self::Poseidon => PoseidonHasher.hash_key(key),
+            #[cfg(not(feature = "poseidon"))]
+            Self::Poseidon => poseidon_unsupported(),
+        }
+    }
+}
+
+impl fmt::Display for HashFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for HashFunction {
+    type Err = CoreError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|h| h.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| CoreError::UnknownHashFunction(s.to_string()))
+    }
+}
@@ -0,0 +1,264 @@
+//! Core of the state commitment, shared by the host crates and the SP1 guest.
+//!
+//! Everything the prover and the host must agree on lives here:
+//! - Tree parameters ([`TREE_DEPTH`], [`SIBLING_BITMAP_LEN`])
+//! - Hash functions ([`HashFunction`])
+//! - Update witnesses and their verification
+//! - Transition input/output and operation types
+//!
+//! `merkle` and `transition_format` re-export these items and the guest
+//! depends on this crate directly, so both sides are compiled from the same
+//! definitions. The serialized layout is pinned by `test_wire_format`; a
+//! change there also requires rebuilding the guest ELF.
+//!
+//! Without the default `std` feature the crate is `no_std` (with `alloc`);
+//! this is how the guest builds it. Witness and business-logic verification
+//! are available either way, bincode encoding needs `std`. The `poseidon`
+//! feature links `std` for light-poseidon but does not enable the `std`
+//! feature, so the crate is only `no_std` without Poseidon; the guest
+//! enables it and therefore builds with `std`.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+#[cfg(all(feature = "poseidon", not(feature = "std")))]
+extern crate std;
+
+pub mod finance;
+mod hasher;
+mod transition;
+mod witness;
+
+#[cfg(feature = "poseidon")]
+pub use hasher::PoseidonHasher;
+pub use hasher::{Blake3Hasher, HashFunction, Sha256Hasher, TreeHasher};
+pub use transition::{
+    bind_operations, verify_witnesses, OperationType, SetPolicy, TransitionInput, TransitionOutput,
+    VerifiableOperation,
+};
+pub use witness::{CompressedSiblings, CompressedWitness, UpdateWitness};
+
+use alloc::string::String;
+use core::fmt;
+
+/// Tree depth (using smaller depth for efficiency).
+pub const TREE_DEPTH: usize = 160;
+
+/// A 32-byte hash value.
+pub type Hash32 = [u8; 32];
+
+/// The empty/default hash for an empty subtree.
+pub const EMPTY_HASH: Hash32 = [0u8; 32];
+
+/// Length of a sibling bitmap in bytes (one bit per tree level).
+pub const SIBLING_BITMAP_LEN: usize = TREE_DEPTH / 8;
+
+// Sibling bitmaps are whole bytes and paths are bits of a 32-byte key hash.
+const _: () = assert!(TREE_DEPTH.is_multiple_of(8) && TREE_DEPTH <= 256);
+
+/// Errors from decoding core formats.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CoreError {
+    /// Sibling bitmap does not match the number of sibling hashes.
+    InvalidSiblings,
+    /// Hash function name not recognized.
+    UnknownHashFunction(String),
+}
+
+impl fmt::Display for CoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidSiblings => f.write_str("sibling bitmap and hashes mismatch"),
+            Self::UnknownHashFunction(name) => write!(f, "unknown hash function: {}", name),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for CoreError {}
+
+/// Get the bit at a specific position in a hash (for tree traversal).
+/// Position 0 is the most significant bit.
+pub fn get_bit(hash: &Hash32, position: usize) -> bool {
+    let byte_idx = position / 8;
+    let bit_idx = 7 - (position % 8);
+    (hash[byte_idx] >> bit_idx) & 1 == 1
+}
+
+/// Hash a leaf, mapping an absent value to the empty hash.
+pub fn leaf_hash(hasher: HashFunction, key: &Hash32, value: Option<&[u8]>) -> Hash32 {
+    match value {
+        Some(v) => hasher.hash_leaf(key, v),
+        None => EMPTY_HASH,
+    }
+}
+
+/// Hash a leaf up a sibling path (leaf to root) to the root.
+pub fn fold_path<'a>(
+    hasher: HashFunction,
+    key: &Hash32,
+    leaf_hash: Hash32,
+    siblings: impl IntoIterator<Item = &'a Hash32>,
+) -> Hash32 {
+    let mut current = leaf_hash;
+    for (i, sibling) in siblings.into_iter().enumerate() {
+        let depth = TREE_DEPTH - 1 - i;
+        if get_bit(key, depth) {
+            current = hasher.hash_nodes(sibling, &current);
+        } else {
+            current = hasher.hash_nodes(&current, sibling);
+        }
+    }
+    current
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    fn sample_witness(hash_function: HashFunction) -> UpdateWitness {
+        let mut siblings = hash_function.empty_hashes()[1..].to_vec();
+        siblings.reverse();
+        siblings[3] = [7u8; 32];
+        UpdateWitness {
+            key: hash_function.hash_key(b"alice"),
+            old_value: None,
+            new_value: Some(b"100".to_vec()),
+            siblings,
+            hash_function,
+        }
+    }
+
+    #[test]
+    fn test_compressed_witness_roots() {
+        for hash_function in [HashFunction::Sha256, HashFunction::Blake3] {
+            let witness = sample_witness(hash_function);
+            let compressed = witness.compress();
+            assert_eq!(compressed.siblings.hashes, vec![[7u8; 32]]);
+
+            assert_eq!(
+                compressed.compute_old_root().unwrap(),
+                witness.compute_old_root()
+            );
+            assert_eq!(
+                compressed.compute_new_root().unwrap(),
+                witness.compute_new_root()
+            );
+            assert_eq!(compressed.decompress().unwrap(), witness);
+        }
+    }
+
+    #[test]
+    fn test_transition_input_apply_witnesses() {
+        let witness = sample_witness(HashFunction::Sha256);
+        let input = TransitionInput::new(
+            witness.compute_old_root(),
+            Vec::new(),
+            Vec::new(),
+            vec![witness.clone()],
+        );
+        assert_eq!(input.apply_witnesses(), Ok(witness.compute_new_root()));
+
+        // Witnesses must use the input's hash function
+        let input = input.with_hash_function(HashFunction::Blake3);
+        assert!(input.apply_witnesses().is_err());
+    }
+
+    /// Pins the bincode layout the guest reads and commits.
+    #[test]
+    fn test_wire_format() {
+        let mut siblings = CompressedSiblings {
+            bitmap: [0u8; SIBLING_BITMAP_LEN],
+            hashes: vec![[0xAB; 32]],
+        };
+        siblings.bitmap[0] = 0x80;
+        let witness = CompressedWitness {
+            key: [1u8; 32],
+            old_value: None,
+            new_value: Some(vec![9]),
+            siblings,
+            hash_function: HashFunction::Blake3,
+        };
+        let encoded = bincode::serialize(&witness).unwrap();
+        assert_eq!(
+            hex::encode(&encoded),
+            concat!(
+                "0101010101010101010101010101010101010101010101010101010101010101",
+                "00",
+                "01010000000000000009",
+                "8000000000000000000000000000000000000000",
+                "0100000000000000",
+                "abababababababababababababababababababababababababababababababab",
+                "01000000",
+            )
+        );
+
+        let output = TransitionOutput::new(
+            [1u8; 32],
+            [2u8; 32],
+            HashFunction::Poseidon,
+            [3u8; 32],
+            vec![4],
+        )
+        .with_app_id(b"app".to_vec());
+        assert_eq!(
+            hex::encode(output.encode()),
+            concat!(
+                "0101010101010101010101010101010101010101010101010101010101010101",
+                "0202020202020202020202020202020202020202020202020202020202020202",
+                "02000000",
+                "0303030303030303030303030303030303030303030303030303030303030303",
+                "010000000000000004",
+                "00000000",
+                "00",
+                "0300000000000000617070",
+            )
+        );
+
+        let op = VerifiableOperation {
+            op_type: OperationType::Mint {
+                amount: 5,
+                signature: vec![6],
+            },
+            key: b"k".to_vec(),
+            old_value: None,
+            new_value: None,
+            witness_index: 2,
+        };
+        assert_eq!(
+            hex::encode(bincode::serialize(&op).unwrap()),
+            concat!(
+                "03000000",
+                "0500000000000000",
+                "010000000000000006",
+                "01000000000000006b",
+                "00",
+                "00",
+                "0200000000000000",
+            )
+        );
+
+        // Accounts are encoded by hand (for no_std) in the bincode layout
+        for public_key in [None, Some([7u8; 32])] {
+            let account = finance::Account {
+                balance: 1,
+                nonce: 2,
+                public_key,
+            };
+            let encoded = account.encode();
+            assert_eq!(encoded, bincode::serialize(&account).unwrap());
+            assert_eq!(
+                finance::Account::decode(&encoded).map(|a| a.public_key),
+                Some(public_key)
+            );
+        }
+
+        // Accounts stored as (balance, nonce) before accounts had keys
+        let legacy = bincode::serialize(&(1u64, 2u64)).unwrap();
+        let account = finance::Account::decode(&legacy).unwrap();
+        assert_eq!((account.balance, account.nonce), (1, 2));
+        assert_eq!(account.public_key, None);
+        assert!(finance::Account::decode(&legacy[..15]).is_none());
+    }
+}
@@ -0,0 +1,413 @@
+//! Transition input/output of the SP1 state transition program.
+
+use crate::finance::receiver_witness;
+use crate::{CompressedWitness, Hash32, HashFunction, TreeHasher, UpdateWitness};
+use alloc::vec;
+use alloc::vec::Vec;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Input to the SP1 state transition program.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransitionInput {
+    /// Previous state root (public).
+    pub prev_root: Hash32,
+    /// Hash function of the state tree.
+    pub hash_function: HashFunction,
+    /// Public inputs (application-specific).
+    pub public_inputs: Vec<u8>,
+    /// Private inputs (application-specific).
+    pub private_inputs: Vec<u8>,
+    /// Update witnesses for all touched keys (empty siblings elided).
+    pub witnesses: Vec<CompressedWitness>,
+    /// Operations with their verification data.
+    pub operations: Vec<VerifiableOperation>,
+    /// Whether bare `Set` operations are allowed (committed in the output).
+    pub set_policy: SetPolicy,
+    /// ed25519 public key allowed to sign mints (committed in the output).
+    pub mint_authority: Option<[u8; 32]>,
+    /// Application the transition belongs to, covered by every signature
+    /// (committed in the output).
+    pub app_id: Vec<u8>,
+}
+
+impl TransitionInput {
+    /// Create a new transition input (witnesses are compressed for the guest).
+    ///
+    /// The hash function defaults to SHA-256; see [`Self::with_hash_function`].
+    pub fn new(
+        prev_root: Hash32,
+        public_inputs: Vec<u8>,
+        private_inputs: Vec<u8>,
+        witnesses: Vec<UpdateWitness>,
+    ) -> Self {
+        Self {
+            prev_root,
+            hash_function: HashFunction::default(),
+            public_inputs,
+            private_inputs,
+            witnesses: witnesses.iter().map(UpdateWitness::compress).collect(),
+            operations: Vec::new(),
+            set_policy: SetPolicy::default(),
+            mint_authority: None,
+            app_id: Vec::new(),
+        }
+    }
+
+    /// Add operations for verification.
+    pub fn with_operations(mut self, ops: Vec<VerifiableOperation>) -> Self {
+        self.operations = ops;
+        self
+    }
+
+    /// Set the hash function of the state tree (must match the witnesses).
+    pub fn with_hash_function(mut self, hash_function: HashFunction) -> Self {
+        self.hash_function = hash_function;
+        self
+    }
+
+    /// Set whether bare `Set` operations are allowed.
+    pub fn with_set_policy(mut self, set_policy: SetPolicy) -> Self {
+        self.set_policy = set_policy;
+        self
+    }
+
+    /// Set the public key allowed to sign mints (none refuses every mint).
+    pub fn with_mint_authority(mut self, mint_authority: Option<[u8; 32]>) -> Self {
+        self.mint_authority = mint_authority;
+        self
+    }
+
+    /// Set the application ID signatures are checked for.
+    pub fn with_app_id(mut self, app_id: Vec<u8>) -> Self {
+        self.app_id = app_id;
+        self
+    }
+
+    /// Declare a `Set` operation for every witness no operation declares
+    /// yet, so writes without business rules can be proven under
+    /// [`SetPolicy::Allow`]. `keys[i]` is the key written by witness `i`.
+    pub fn with_set_operations(mut self, keys: &[&[u8]]) -> Self {
+        let mut declared = vec![false; self.witnesses.len()];
+        for op in &self.operations {
+            for index in op.witness_indices(self.hash_function, &self.witnesses) {
+                if let Some(declared) = declared.get_mut(index) {
+                    *declared = true;
+                }
+            }
+        }
+
+        for (index, witness) in self.witnesses.iter().enumerate() {
+            if declared[index] {
+                continue;
+            }
+            let Some(key) = keys.get(index) else {
+                break;
+            };
+            self.operations.push(VerifiableOperation {
+                op_type: OperationType::Set,
+                key: key.to_vec(),
+                old_value: witness.old_value.clone(),
+                new_value: witness.new_value.clone(),
+                witness_index: index,
+            });
+        }
+        self
+    }
+
+    /// Check that the operations and witnesses describe each other; see
+    /// [`bind_operations`].
+    pub fn bind_operations(&self) -> Result<(), &'static str> {
+        bind_operations(
+            self.hash_function,
+            self.set_policy,
+            &self.operations,
+            &self.witnesses,
+        )
+    }
+
+    /// Check the business logic of every operation; see
+    /// [`finance::verify_operation`](crate::finance::verify_operation).
+    pub fn verify_operations(&self) -> Result<(), &'static str> {
+        let valid = self
+            .operations
+            .iter()
+            .all(|op| crate::finance::verify_operation(op, self));
+        if !valid {
+            return Err("business logic verification failed for operation");
+        }
+        Ok(())
+    }
+
+    /// Encode to bytes for SP1 input.
+    #[cfg(feature = "std")]
+    pub fn encode(&self) -> Vec<u8> {
+        bincode::serialize(self).expect("serialization should not fail")
+    }
+
+    /// Decode from bytes.
+    #[cfg(feature = "std")]
+    pub fn decode(data: &[u8]) -> Result<Self, bincode::Error> {
+        bincode::deserialize(data)
+    }
+
+    /// Hash the public inputs.
+    pub fn public_inputs_hash(&self) -> Hash32 {
+        let mut hasher = Sha256::new();
+        hasher.update(self.prev_root);
+        hasher.update(&self.public_inputs);
+        hasher.finalize().into()
+    }
+
+    /// Apply the witnesses in order, starting from `prev_root`.
+    ///
+    /// Returns the new root, or an error if a witness does not start from
+    /// the root left by the previous one.
+    pub fn apply_witnesses(&self) -> Result<Hash32, &'static str> {
+        let mut current_root = self.prev_root;
+        let empty_hashes = crate::witness::empty_hashes(self.hash_function);
+
+        for witness in &self.witnesses {
+            if witness.hash_function != self.hash_function {
+                return Err("witness hash function mismatch");
+            }
+
+            let (computed_old_root, new_root) = witness
+                .compute_roots_with(&empty_hashes)
+                .map_err(|_| "witness siblings malformed")?;
+            if computed_old_root != current_root {
+                return Err("witness old root mismatch");
+            }
+
+            current_root = new_root;
+        }
+
+        Ok(current_root)
+    }
+}
+
+/// Output from the SP1 state transition program.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TransitionOutput {
+    /// Previous state root (for verification).
+    pub prev_root: Hash32,
+    /// New state root after transition.
+    pub new_root: Hash32,
+    /// Hash function both roots were computed with.
+    pub hash_function: HashFunction,
+    /// Hash of public inputs (for binding).
+    pub public_inputs_hash: Hash32,
+    /// Public outputs (application-specific).
+    pub public_outputs: Vec<u8>,
+    /// Whether bare `Set` operations were allowed.
+    pub set_policy: SetPolicy,
+    /// Public key mints were checked against.
+    pub mint_authority: Option<[u8; 32]>,
+    /// Application signatures were checked for.
+    pub app_id: Vec<u8>,
+}
+
+impl TransitionOutput {
+    /// Create a new transition output.
+    pub fn new(
+        prev_root: Hash32,
+        new_root: Hash32,
+        hash_function: HashFunction,
+        public_inputs_hash: Hash32,
+        public_outputs: Vec<u8>,
+    ) -> Self {
+        Self {
+            prev_root,
+            new_root,
+            hash_function,
+            public_inputs_hash,
+            public_outputs,
+            set_policy: SetPolicy::default(),
+            mint_authority: None,
+            app_id: Vec::new(),
+        }
+    }
+
+    /// Set the policy the operations were checked under.
+    pub fn with_set_policy(mut self, set_policy: SetPolicy) -> Self {
+        self.set_policy = set_policy;
+        self
+    }
+
+    /// Set the public key mints were checked against.
+    pub fn with_mint_authority(mut self, mint_authority: Option<[u8; 32]>) -> Self {
+        self.mint_authority = mint_authority;
+        self
+    }
+
+    /// Set the application signatures were checked for.
+    pub fn with_app_id(mut self, app_id: Vec<u8>) -> Self {
+        self.app_id = app_id;
+        self
+    }
+
+    /// Encode to bytes.
+    #[cfg(feature = "std")]
+    pub fn encode(&self) -> Vec<u8> {
+        bincode::serialize(self).expect("serialization should not fail")
+    }
+
+    /// Decode from bytes.
+    #[cfg(feature = "std")]
+    pub fn decode(data: &[u8]) -> Result<Self, bincode::Error> {
+        bincode::deserialize(data)
+    }
+}
+
+/// A verifiable operation that includes all data needed for circuit verification.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VerifiableOperation {
+    /// Operation type tag.
+    pub op_type: OperationType,
+    /// The key being operated on.
+    pub key: Vec<u8>,
+    /// Old value (from witness).
+    pub old_value: Option<Vec<u8>>,
+    /// New value (to be written).
+    pub new_value: Option<Vec<u8>>,
+    /// Witness index (links to TransitionInput.witnesses).
+    pub witness_index: usize,
+}
+
+impl VerifiableOperation {
+    /// Indices of the witnesses this operation declares: the one at
+    /// `witness_index` and, for a transfer, the receiver's (if present).
+    pub fn witness_indices(
+        &self,
+        hash_function: HashFunction,
+        witnesses: &[CompressedWitness],
+    ) -> Vec<usize> {
+        let mut indices = vec![self.witness_index];
+        if let OperationType::Transfer { to, .. } = &self.op_type {
+            indices.extend(receiver_witness(
+                hash_function,
+                witnesses,
+                self.witness_index,
+                to,
+            ));
+        }
+        indices
+    }
+}
+
+/// Whether a transition may declare bare [`OperationType::Set`] operations.
+///
+/// Chosen per app; the guest commits it in the output so verifiers can
+/// require the app's policy.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SetPolicy {
+    /// `Set` operations may write any value.
+    #[default]
+    Allow,
+    /// Every write must be declared by an operation with business rules.
+    Deny,
+}
+
+/// Types of operations that can be verified.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum OperationType {
+    /// Set a value (no constraints).
+    Set,
+    /// Create an account with initial balance, signed by the mint authority
+    /// for a non-zero balance and by the account's owner otherwise (see
+    /// [`finance::create_account_message`](crate::finance::create_account_message)).
+    CreateAccount {
+        initial_balance: u64,
+        signature: Vec<u8>,
+    },
+    /// Transfer funds between accounts, signed by the sender (see
+    /// [`finance::transfer_message`](crate::finance::transfer_message)).
+    Transfer {
+        from: Vec<u8>,
+        to: Vec<u8>,
+        amount: u64,
+        signature: Vec<u8>,
+    },
+    /// Mint new tokens, signed by the mint authority (see
+    /// [`finance::mint_message`](crate::finance::mint_message)).
+    Mint { amount: u64, signature: Vec<u8> },
+    /// Burn tokens, signed by the account's owner (see
+    /// [`finance::burn_message`](crate::finance::burn_message)).
+    Burn { amount: u64, signature: Vec<u8> },
+}
+
+/// Check that operations and witnesses describe each other.
+///
+/// Every operation's key hash, old value and new value must match the
+/// witness at its `witness_index`, and every witness must be declared by
+/// exactly one operation (a transfer declares the sender's and the
+/// receiver's witness). `Set` operations are rejected under
+/// [`SetPolicy::Deny`].
+pub fn bind_operations(
+    hash_function: HashFunction,
+    set_policy: SetPolicy,
+    operations: &[VerifiableOperation],
+    witnesses: &[CompressedWitness],
+) -> Result<(), &'static str> {
+    let mut declared = vec![false; witnesses.len()];
+
+    for op in operations {
+        if op.op_type == OperationType::Set && set_policy == SetPolicy::Deny {
+            return Err("set operations are not allowed");
+        }
+
+        let witness = witnesses
+            .get(op.witness_index)
+            .ok_or("operation witness index out of range")?;
+        if witness.key != hash_function.hash_key(&op.key)
+            || witness.old_value != op.old_value
+            || witness.new_value != op.new_value
+        {
+            return Err("operation does not match its witness");
+        }
+
+        for index in op.witness_indices(hash_function, witnesses) {
+            if core::mem::replace(&mut declared[index], true) {
+                return Err("witness declared by more than one operation");
+            }
+        }
+    }
+
+    if declared.contains(&false) {
+        return Err("witness not declared by an operation");
+    }
+    Ok(())
+}
+
+/// Verifies that a sequence of witnesses produces the expected root
+/// transition, and that the operations declare them (see
+/// [`bind_operations`]).
+pub fn verify_witnesses(
+    hash_function: HashFunction,
+    prev_root: Hash32,
+    witnesses: &[UpdateWitness],
+    operations: &[VerifiableOperation],
+    set_policy: SetPolicy,
+) -> Result<Hash32, &'static str> {
+    let compressed: Vec<CompressedWitness> =
+        witnesses.iter().map(UpdateWitness::compress).collect();
+    bind_operations(hash_function, set_policy, operations, &compressed)?;
+
+    let mut current_root = prev_root;
+    for witness in witnesses {
+        if witness.hash_function != hash_function {
+            return Err("witness hash function mismatch");
+        }
+
+        // Verify the witness starts from our current root
+        let computed_old_root = witness.compute_old_root();
+        if computed_old_root != current_root {
+            return Err("witness old root mismatch");
+        }
+
+        // Compute new root
+        current_root = witness.compute_new_root();
+    }
+
+    Ok(current_root)
+}
@@ -0,0 +1,208 @@
+//! Update witnesses and sibling compression.
+
+use crate::{
+    fold_path, leaf_hash, CoreError, Hash32, HashFunction, SIBLING_BITMAP_LEN, TREE_DEPTH,
+};
+use alloc::borrow::Cow;
+use alloc::vec::Vec;
+use serde::{Deserialize, Serialize};
+
+/// Empty-subtree hashes of a hash function (cached when `std` is available,
+/// recomputed otherwise).
+pub(crate) fn empty_hashes(hash_function: HashFunction) -> Cow<'static, [Hash32]> {
+    #[cfg(feature = "std")]
+    return Cow::Borrowed(hash_function.empty_hashes());
+    #[cfg(not(feature = "std"))]
+    return Cow::Owned(hash_function.compute_empty_hashes());
+}
+
+/// Witness for updating a key in the tree.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct UpdateWitness {
+    /// The key being updated.
+    pub key: Hash32,
+    /// Old value (None if key didn't exist).
+    pub old_value: Option<Vec<u8>>,
+    /// New value (None if deleting).
+    pub new_value: Option<Vec<u8>>,
+    /// Sibling hashes from leaf to root.
+    pub siblings: Vec<Hash32>,
+    /// Hash function of the tree the witness was taken from.
+    pub hash_function: HashFunction,
+}
+
+impl UpdateWitness {
+    /// Compute the old root from this witness.
+    pub fn compute_old_root(&self) -> Hash32 {
+        let leaf_hash = leaf_hash(self.hash_function, &self.key, self.old_value.as_deref());
+        fold_path(self.hash_function, &self.key, leaf_hash, &self.siblings)
+    }
+
+    /// Compute the new root from this witness.
+    pub fn compute_new_root(&self) -> Hash32 {
+        let leaf_hash = leaf_hash(self.hash_function, &self.key, self.new_value.as_deref());
+        fold_path(self.hash_function, &self.key, leaf_hash, &self.siblings)
+    }
+
+    /// Compress this witness by eliding empty siblings.
+    pub fn compress(&self) -> CompressedWitness {
+        CompressedWitness {
+            key: self.key,
+            old_value: self.old_value.clone(),
+            new_value: self.new_value.clone(),
+            siblings: CompressedSiblings::compress(&self.siblings, self.hash_function),
+            hash_function: self.hash_function,
+        }
+    }
+}
+
+/// Sibling hashes with empty-subtree hashes elided.
+///
+/// Bit `i` of `bitmap` (most significant bit first) is set when sibling `i`,
+/// counting from the leaf, is a non-empty subtree. Only those hashes are
+/// kept in `hashes`; the rest are the precomputed empty-subtree hashes of
+/// the tree's [`HashFunction`].
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CompressedSiblings {
+    /// Non-empty sibling flags, leaf to root.
+    pub bitmap: [u8; SIBLING_BITMAP_LEN],
+    /// Non-empty sibling hashes, leaf to root.
+    pub hashes: Vec<Hash32>,
+}
+
+impl CompressedSiblings {
+    /// Compress a full sibling path (leaf to root).
+    pub fn compress(siblings: &[Hash32], hash_function: HashFunction) -> Self {
+        let empty_hashes = empty_hashes(hash_function);
+        let mut bitmap = [0u8; SIBLING_BITMAP_LEN];
+        let mut hashes = Vec::new();
+
+        for (i, sibling) in siblings.iter().enumerate().take(TREE_DEPTH) {
+            if *sibling != empty_hashes[TREE_DEPTH - i] {
+                bitmap[i / 8] |= 0x80 >> (i % 8);
+                hashes.push(*sibling);
+            }
+        }
+
+        Self { bitmap, hashes }
+    }
+
+    /// Check whether sibling `i` (counting from the leaf) is non-empty.
+    pub fn is_set(&self, i: usize) -> bool {
+        self.bitmap[i / 8] & (0x80 >> (i % 8)) != 0
+    }
+
+    /// Restore the full sibling path.
+    pub fn decompress(&self, hash_function: HashFunction) -> Result<Vec<Hash32>, CoreError> {
+        self.check()?;
+
+        let empty_hashes = empty_hashes(hash_function);
+        let mut hashes = self.hashes.iter();
+        Ok((0..TREE_DEPTH)
+            .map(|i| {
+                if self.is_set(i) {
+                    *hashes.next().expect("count checked above")
+                } else {
+                    empty_hashes[TREE_DEPTH - i]
+                }
+            })
+            .collect())
+    }
+
+    /// Hash a leaf up this path without restoring it first.
+    pub fn fold(
+        &self,
+        hash_function: HashFunction,
+        key: &Hash32,
+        leaf_hash: Hash32,
+    ) -> Result<Hash32, CoreError> {
+        self.fold_with(hash_function, &empty_hashes(hash_function), key, leaf_hash)
+    }
+
+    /// [`Self::fold`] with the empty-subtree hashes of `hash_function`
+    /// supplied by the caller.
+    pub(crate) fn fold_with(
+        &self,
+        hash_function: HashFunction,
+        empty_hashes: &[Hash32],
+        key: &Hash32,
+        leaf_hash: Hash32,
+    ) -> Result<Hash32, CoreError> {
+        self.check()?;
+
+        let mut hashes = self.hashes.iter();
+        let siblings = (0..TREE_DEPTH).map(|i| {
+            if self.is_set(i) {
+                hashes.next().expect("count checked above")
+            } else {
+                &empty_hashes[TREE_DEPTH - i]
+            }
+        });
+        Ok(fold_path(hash_function, key, leaf_hash, siblings))
+    }
+
+    /// Check that the bitmap matches the number of hashes.
+    fn check(&self) -> Result<(), CoreError> {
+        let set: usize = self.bitmap.iter().map(|b| b.count_ones() as usize).sum();
+        if set != self.hashes.len() {
+            return Err(CoreError::InvalidSiblings);
+        }
+        Ok(())
+    }
+}
+
+/// An update witness with empty siblings elided (compact guest input).
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CompressedWitness {
+    /// The key being updated.
+    pub key: Hash32,
+    /// Old value (None if key didn't exist).
+    pub old_value: Option<Vec<u8>>,
+    /// New value (None if deleting).
+    pub new_value: Option<Vec<u8>>,
+    /// Compressed sibling path.
+    pub siblings: CompressedSiblings,
+    /// Hash function of the tree the witness was taken from.
+    pub hash_function: HashFunction,
+}
+
+impl CompressedWitness {
+    /// Restore the full witness.
+    pub fn decompress(&self) -> Result<UpdateWitness, CoreError> {
+        Ok(UpdateWitness {
+            key: self.key,
+            old_value: self.old_value.clone(),
+            new_value: self.new_value.clone(),
+            siblings: self.siblings.decompress(self.hash_function)?,
+            hash_function: self.hash_function,
+        })
+    }
+
+    /// Compute the old root from this witness.
+    pub fn compute_old_root(&self) -> Result<Hash32, CoreError> {
+        let leaf_hash = leaf_hash(self.hash_function, &self.key, self.old_value.as_deref());
+        self.siblings.fold(self.hash_function, &self.key, leaf_hash)
+    }
+
+    /// Compute the new root from this witness.
+    pub fn compute_new_root(&self) -> Result<Hash32, CoreError> {
+        let leaf_hash = leaf_hash(self.hash_function, &self.key, self.new_value.as_deref());
+        self.siblings.fold(self.hash_function, &self.key, leaf_hash)
+    }
+
+    /// Compute the old and new roots with the empty-subtree hashes of the
+    /// witness's hash function supplied by the caller, so a batch computes
+    /// them once even without the `std` cache.
+    pub(crate) fn compute_roots_with(
+        &self,
+        empty_hashes: &[Hash32],
+    ) -> Result<(Hash32, Hash32), CoreError> {
+        let roots = [self.old_value.as_deref(), self.new_value.as_deref()].map(|value| {
+            let leaf_hash = leaf_hash(self.hash_function, &self.key, value);
+            self.siblings
+                .fold_with(self.hash_function, empty_hashes, &self.key, leaf_hash)
+        });
+        let [old_root, new_root] = roots;
+        Ok((old_root?, new_root?))
+    }
+}
@@ -31,6 +31,7 @@ use serde::{de::DeserializeOwned, Serialize};
 use state::{StateOp, StateStore};
 use std::collections::HashMap;
 use thiserror::Error;
+use transition_format::{OperationType, VerifiableOperation};
 
 pub use merkle;
 pub use state;
@@ -55,26 +56,30 @@ pub type Result<T> = std::result::Result<T, SdkError>;
 /// Context for application execution.
 ///
 /// Provides state read/write operations that automatically track
-/// Merkle witnesses for ZK proving.
-pub struct Context {
+/// Merkle witnesses for ZK proving. Writes go to the borrowed store
+/// uncommitted; committing or rolling them back is up to the caller.
+pub struct Context<'a> {
     /// Underlying state store.
-    store: StateStore,
+    store: &'a mut StateStore,
     /// Operations performed in this context.
     operations: Vec<StateOp>,
     /// Witnesses collected for touched keys.
     witnesses: Vec<UpdateWitness>,
+    /// Operations recorded for the guest's business logic checks.
+    verifiable_operations: Vec<VerifiableOperation>,
     /// Cache of read values.
     #[allow(dead_code)]
     read_cache: HashMap<Vec<u8>, Option<Vec<u8>>>,
 }
 
-impl Context {
-    /// Create a new context from a state store.
-    pub fn new(store: StateStore) -> Self {
+impl<'a> Context<'a> {
+    /// Create a new context writing to a state store.
+    pub fn new(store: &'a mut StateStore) -> Self {
         Self {
             store,
             operations: Vec::new(),
             witnesses: Vec::new(),
+            verifiable_operations: Vec::new(),
             read_cache: HashMap::new(),
         }
     }
@@ -145,19 +150,35 @@ impl Context {
         std::mem::take(&mut self.witnesses)
     }
 
-    /// Commit the state changes.
-    pub fn commit(&mut self) -> Result<Hash32> {
-        Ok(self.store.commit()?)
+    /// Record an operation for the guest to check against the last write
+    /// of `key` in this context.
+    pub fn record_operation(&mut self, op_type: OperationType, key: &[u8]) -> Result<()> {
+        // Operations and witnesses are recorded in step
+        let (witness_index, witness) = self
+            .operations
+            .iter()
+            .rposition(|op| op.key() == key)
+            .and_then(|index| Some((index, self.witnesses.get(index)?)))
+            .ok_or_else(|| SdkError::KeyNotFound(String::from_utf8_lossy(key).to_string()))?;
+
+        self.verifiable_operations.push(VerifiableOperation {
+            op_type,
+            key: key.to_vec(),
+            old_value: witness.old_value.clone(),
+            new_value: witness.new_value.clone(),
+            witness_index,
+        });
+        Ok(())
     }
 
-    /// Get a Merkle proof for a key.
-    pub fn get_proof(&self, key: &[u8]) -> MerkleProof {
-        self.store.get_proof(key)
+    /// Get all operations recorded for the guest.
+    pub fn verifiable_operations(&self) -> &[VerifiableOperation] {
+        &self.verifiable_operations
     }
 
-    /// Into inner store.
-    pub fn into_store(self) -> StateStore {
-        self.store
+    /// Get a Merkle proof for a key.
+    pub fn get_proof(&self, key: &[u8]) -> Result<MerkleProof> {
+        Ok(self.store.get_proof(key)?)
     }
 }
 
@@ -263,14 +284,416 @@ pub mod accounts {
     }
 }
 
+/// Finance application: named accounts holding balances.
+///
+/// Accounts are stored as bincode-encoded [`Account`]s under
+/// [`account_key`], and every transaction records the operation the guest
+/// checks.
+pub mod finance {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+    use serde::Deserialize;
+    use transition_format::finance::{
+        burn_message, create_account_message, mint_message, transfer_message, verify_signature,
+    };
+
+    pub use transition_format::finance::Account;
+
+    /// Account key builder.
+    pub fn account_key(name: &str) -> Vec<u8> {
+        KeyBuilder::new("account").key(name)
+    }
+
+    /// A finance transaction.
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub enum FinanceTransaction {
+        /// Create an account owned by `public_key`, empty and signed by its
+        /// owner (see [`AccountKey::create_account`]) or with a balance
+        /// signed by the mint authority (see
+        /// [`MintAuthority::create_account`]).
+        CreateAccount {
+            name: String,
+            balance: u64,
+            public_key: [u8; 32],
+            signature: Vec<u8>,
+        },
+        /// Transfer funds, signed by the sender's owner (see
+        /// [`AccountKey::transfer`]), to an existing account with an owner.
+        Transfer {
+            from: String,
+            to: String,
+            amount: u64,
+            signature: Vec<u8>,
+        },
+        /// Mint funds into an existing account, signed by the mint
+        /// authority (see [`MintAuthority::mint`]).
+        Mint {
+            to: String,
+            amount: u64,
+            signature: Vec<u8>,
+        },
+        /// Burn funds from an account, signed by its owner (see
+        /// [`AccountKey::burn`]).
+        Burn {
+            account: String,
+            amount: u64,
+            signature: Vec<u8>,
+        },
+    }
+
+    /// Signing key of the mint authority.
+    ///
+    /// The node and verifiers are configured with its
+    /// [`public_key`](Self::public_key); the guest only accepts mints it
+    /// signed.
+    pub struct MintAuthority {
+        key: SigningKey,
+    }
+
+    impl MintAuthority {
+        /// Create from a 32-byte ed25519 secret key.
+        pub fn from_bytes(secret_key: &[u8; 32]) -> Self {
+            Self {
+                key: SigningKey::from_bytes(secret_key),
+            }
+        }
+
+        /// Public key allowed to sign mints.
+        pub fn public_key(&self) -> [u8; 32] {
+            self.key.verifying_key().to_bytes()
+        }
+
+        /// Sign a mint of `amount` into account `to` of app `app_id`, whose
+        /// current nonce is `nonce` (the mint increments it, so the
+        /// transaction is only valid once).
+        pub fn mint(&self, app_id: &[u8], to: &str, amount: u64, nonce: u64) -> FinanceTransaction {
+            let message = mint_message(app_id, &account_key(to), amount, nonce);
+            FinanceTransaction::Mint {
+                to: to.to_string(),
+                amount,
+                signature: self.key.sign(&message).to_bytes().to_vec(),
+            }
+        }
+
+        /// Sign the creation of account `name` of app `app_id` with
+        /// `balance`, owned by `public_key`.
+        pub fn create_account(
+            &self,
+            app_id: &[u8],
+            name: &str,
+            balance: u64,
+            public_key: [u8; 32],
+        ) -> FinanceTransaction {
+            let message = create_account_message(app_id, &account_key(name), &public_key, balance);
+            FinanceTransaction::CreateAccount {
+                name: name.to_string(),
+                balance,
+                public_key,
+                signature: self.key.sign(&message).to_bytes().to_vec(),
+            }
+        }
+    }
+
+    /// Signing key of an account's owner.
+    pub struct AccountKey {
+        key: SigningKey,
+    }
+
+    impl AccountKey {
+        /// Create from a 32-byte ed25519 secret key.
+        pub fn from_bytes(secret_key: &[u8; 32]) -> Self {
+            Self {
+                key: SigningKey::from_bytes(secret_key),
+            }
+        }
+
+        /// Public key stored in the owned accounts.
+        pub fn public_key(&self) -> [u8; 32] {
+            self.key.verifying_key().to_bytes()
+        }
+
+        /// Sign the creation of empty account `name` of app `app_id`, owned
+        /// by this key (funded accounts are created by the mint authority).
+        pub fn create_account(&self, app_id: &[u8], name: &str) -> FinanceTransaction {
+            let public_key = self.public_key();
+            let message = create_account_message(app_id, &account_key(name), &public_key, 0);
+            FinanceTransaction::CreateAccount {
+                name: name.to_string(),
+                balance: 0,
+                public_key,
+                signature: self.key.sign(&message).to_bytes().to_vec(),
+            }
+        }
+
+        /// Sign a transfer of `amount` from account `from` of app `app_id`,
+        /// whose current nonce is `nonce`, to account `to` (the transfer
+        /// increments the nonce, so the transaction is only valid once).
+        pub fn transfer(
+            &self,
+            app_id: &[u8],
+            from: &str,
+            to: &str,
+            amount: u64,
+            nonce: u64,
+        ) -> FinanceTransaction {
+            let message =
+                transfer_message(app_id, &account_key(from), &account_key(to), amount, nonce);
+            FinanceTransaction::Transfer {
+                from: from.to_string(),
+                to: to.to_string(),
+                amount,
+                signature: self.key.sign(&message).to_bytes().to_vec(),
+            }
+        }
+
+        /// Sign a burn of `amount` from account `account` of app `app_id`,
+        /// whose current nonce is `nonce` (the burn increments it, so the
+        /// transaction is only valid once).
+        pub fn burn(
+            &self,
+            app_id: &[u8],
+            account: &str,
+            amount: u64,
+            nonce: u64,
+        ) -> FinanceTransaction {
+            let message = burn_message(app_id, &account_key(account), amount, nonce);
+            FinanceTransaction::Burn {
+                account: account.to_string(),
+                amount,
+                signature: self.key.sign(&message).to_bytes().to_vec(),
+            }
+        }
+    }
+
+    /// Accounts changed by a transaction, as left by it.
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub struct FinanceReceipt {
+        pub accounts: Vec<(String, Account)>,
+    }
+
+    /// The finance application.
+    pub struct FinanceApp {
+        /// Application ID the transactions are signed for (the node's).
+        app_id: Vec<u8>,
+    }
+
+    impl FinanceApp {
+        /// Create the application for app `app_id`.
+        pub fn new(app_id: impl Into<Vec<u8>>) -> Self {
+            Self {
+                app_id: app_id.into(),
+            }
+        }
+    }
+
+    impl Application for FinanceApp {
+        type PublicInput = FinanceTransaction;
+        type PrivateInput = ();
+        type Output = FinanceReceipt;
+
+        fn apply(
+            &self,
+            ctx: &mut Context,
+            public: Self::PublicInput,
+            _private: Self::PrivateInput,
+        ) -> Result<Self::Output> {
+            match public {
+                FinanceTransaction::CreateAccount {
+                    name,
+                    balance,
+                    public_key,
+                    signature,
+                } => {
+                    // The signature is checked by the node and the guest:
+                    // against the mint authority for a non-zero balance,
+                    // like a mint, and against the owner's key otherwise
+                    let key = account_key(&name);
+                    if ctx.exists(&key)? {
+                        return Err(SdkError::Application(format!(
+                            "account '{}' already exists",
+                            name
+                        )));
+                    }
+
+                    let account = Account {
+                        balance,
+                        nonce: 0,
+                        public_key: Some(public_key),
+                    };
+                    ctx.set(&key, &account)?;
+                    ctx.record_operation(
+                        OperationType::CreateAccount {
+                            initial_balance: balance,
+                            signature,
+                        },
+                        &key,
+                    )?;
+                    Ok(FinanceReceipt {
+                        accounts: vec![(name, account)],
+                    })
+                }
+                FinanceTransaction::Transfer {
+                    from,
+                    to,
+                    amount,
+                    signature,
+                } => {
+                    if from == to {
+                        return Err(SdkError::Application(
+                            "cannot transfer to the same account".to_string(),
+                        ));
+                    }
+
+                    let from_key = account_key(&from);
+                    let to_key = account_key(&to);
+                    let sender: Account = ctx.get(&from_key)?.ok_or_else(|| {
+                        SdkError::Application(format!("account '{}' not found", from))
+                    })?;
+                    if sender.balance < amount {
+                        return Err(SdkError::Application(format!(
+                            "insufficient balance: {} < {}",
+                            sender.balance, amount
+                        )));
+                    }
+
+                    // The guest checks the same signature against the
+                    // sender's key in the state
+                    let message =
+                        transfer_message(&self.app_id, &from_key, &to_key, amount, sender.nonce);
+                    let signed = sender.public_key.is_some_and(|public_key| {
+                        verify_signature(&public_key, &message, &signature)
+                    });
+                    if !signed {
+                        return Err(SdkError::Application(format!(
+                            "transfer not signed by the owner of '{}'",
+                            from
+                        )));
+                    }
+
+                    // Funds sent to an account without a key could never
+                    // be spent again
+                    let receiver: Account = ctx
+                        .get(&to_key)?
+                        .filter(|receiver: &Account| receiver.public_key.is_some())
+                        .ok_or_else(|| {
+                            SdkError::Application(format!("account '{}' cannot receive", to))
+                        })?;
+                    let nonce = sender.nonce.checked_add(1).ok_or_else(|| {
+                        SdkError::Application(format!("nonce of '{}' would overflow", from))
+                    })?;
+                    let balance = receiver.balance.checked_add(amount).ok_or_else(|| {
+                        SdkError::Application(format!("balance of '{}' would overflow", to))
+                    })?;
+
+                    let sender = Account {
+                        balance: sender.balance - amount,
+                        nonce,
+                        ..sender
+                    };
+                    let receiver = Account {
+                        balance,
+                        ..receiver
+                    };
+                    ctx.set(&from_key, &sender)?;
+                    ctx.set(&to_key, &receiver)?;
+                    ctx.record_operation(
+                        OperationType::Transfer {
+                            from: from_key.clone(),
+                            to: to_key,
+                            amount,
+                            signature,
+                        },
+                        &from_key,
+                    )?;
+                    Ok(FinanceReceipt {
+                        accounts: vec![(from, sender), (to, receiver)],
+                    })
+                }
+                FinanceTransaction::Mint {
+                    to,
+                    amount,
+                    signature,
+                } => {
+                    // The signature is checked by the node and the guest
+                    // against the configured mint authority
+                    let key = account_key(&to);
+                    let account: Account = ctx.get(&key)?.ok_or_else(|| {
+                        SdkError::Application(format!("account '{}' not found", to))
+                    })?;
+                    let balance = account.balance.checked_add(amount).ok_or_else(|| {
+                        SdkError::Application(format!("balance of '{}' would overflow", to))
+                    })?;
+                    let nonce = account.nonce.checked_add(1).ok_or_else(|| {
+                        SdkError::Application(format!("nonce of '{}' would overflow", to))
+                    })?;
+
+                    let account = Account {
+                        balance,
+                        nonce,
+                        ..account
+                    };
+                    ctx.set(&key, &account)?;
+                    ctx.record_operation(OperationType::Mint { amount, signature }, &key)?;
+                    Ok(FinanceReceipt {
+                        accounts: vec![(to, account)],
+                    })
+                }
+                FinanceTransaction::Burn {
+                    account: name,
+                    amount,
+                    signature,
+                } => {
+                    let key = account_key(&name);
+                    let account: Account = ctx.get(&key)?.ok_or_else(|| {
+                        SdkError::Application(format!("account '{}' not found", name))
+                    })?;
+                    if account.balance < amount {
+                        return Err(SdkError::Application(format!(
+                            "insufficient balance: {} < {}",
+                            account.balance, amount
+                        )));
+                    }
+
+                    // The guest checks the same signature against the
+                    // account's key in the state
+                    let message = burn_message(&self.app_id, &key, amount, account.nonce);
+                    let signed = account.public_key.is_some_and(|public_key| {
+                        verify_signature(&public_key, &message, &signature)
+                    });
+                    if !signed {
+                        return Err(SdkError::Application(format!(
+                            "burn not signed by the owner of '{}'",
+                            name
+                        )));
+                    }
+                    let nonce = account.nonce.checked_add(1).ok_or_else(|| {
+                        SdkError::Application(format!("nonce of '{}' would overflow", name))
+                    })?;
+
+                    let account = Account {
+                        balance: account.balance - amount,
+                        nonce,
+                        ..account
+                    };
+                    ctx.set(&key, &account)?;
+                    ctx.record_operation(OperationType::Burn { amount, signature }, &key)?;
+                    Ok(FinanceReceipt {
+                        accounts: vec![(name, account)],
+                    })
+                }
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn test_context_basic_operations() {
-        let store = StateStore::in_memory().unwrap();
-        let mut ctx = Context::new(store);
+        let mut store = StateStore::in_memory().unwrap();
+        let mut ctx = Context::new(&mut store);
 
         ctx.set_raw(b"key1", b"value1".to_vec()).unwrap();
         assert_eq!(ctx.get_raw(b"key1").unwrap(), Some(b"value1".to_vec()));
@@ -281,8 +704,8 @@ mod tests {
 
     #[test]
     fn test_context_typed_values() {
-        let store = StateStore::in_memory().unwrap();
-        let mut ctx = Context::new(store);
+        let mut store = StateStore::in_memory().unwrap();
+        let mut ctx = Context::new(&mut store);
 
         ctx.set(b"number", &42u64).unwrap();
         let loaded: u64 = ctx.get_required(b"number").unwrap();
@@ -291,8 +714,8 @@ mod tests {
 
     #[test]
     fn test_accounts_transfer() {
-        let store = StateStore::in_memory().unwrap();
-        let mut ctx = Context::new(store);
+        let mut store = StateStore::in_memory().unwrap();
+        let mut ctx = Context::new(&mut store);
 
         accounts::set_balance(&mut ctx, "alice", 100).unwrap();
         accounts::set_balance(&mut ctx, "bob", 50).unwrap();
@@ -305,8 +728,8 @@ mod tests {
 
     #[test]
     fn test_accounts_insufficient_balance() {
-        let store = StateStore::in_memory().unwrap();
-        let mut ctx = Context::new(store);
+        let mut store = StateStore::in_memory().unwrap();
+        let mut ctx = Context::new(&mut store);
 
         accounts::set_balance(&mut ctx, "alice", 10).unwrap();
 
@@ -316,12 +739,150 @@ mod tests {
 
     #[test]
     fn test_witnesses_collected() {
-        let store = StateStore::in_memory().unwrap();
-        let mut ctx = Context::new(store);
+        let mut store = StateStore::in_memory().unwrap();
+        let mut ctx = Context::new(&mut store);
 
         ctx.set_raw(b"key1", b"value1".to_vec()).unwrap();
         ctx.set_raw(b"key2", b"value2".to_vec()).unwrap();
 
         assert_eq!(ctx.witnesses().len(), 2);
     }
+
+    #[test]
+    fn test_record_operation() {
+        let mut store = StateStore::in_memory().unwrap();
+        let mut ctx = Context::new(&mut store);
+
+        ctx.set_raw(b"key1", b"value1".to_vec()).unwrap();
+        ctx.set_raw(b"key2", b"value2".to_vec()).unwrap();
+        ctx.set_raw(b"key1", b"value3".to_vec()).unwrap();
+
+        // Recorded against the last write of the key
+        ctx.record_operation(OperationType::Set, b"key1").unwrap();
+        let op = &ctx.verifiable_operations()[0];
+        assert_eq!(op.witness_index, 2);
+        assert_eq!(op.old_value, Some(b"value1".to_vec()));
+        assert_eq!(op.new_value, Some(b"value3".to_vec()));
+
+        assert!(ctx.record_operation(OperationType::Set, b"key3").is_err());
+    }
+
+    #[test]
+    fn test_finance_app() {
+        use finance::*;
+
+        let mut store = StateStore::in_memory().unwrap();
+        let mut ctx = Context::new(&mut store);
+        let app = FinanceApp::new("finance");
+        let alice = AccountKey::from_bytes(&[1u8; 32]);
+
+        // Funded accounts are created by the mint authority
+        let authority = MintAuthority::from_bytes(&[7u8; 32]);
+        let create = authority.create_account(b"finance", "alice", 100, alice.public_key());
+        app.apply(&mut ctx, create, ()).unwrap();
+        assert!(app
+            .apply(&mut ctx, alice.create_account(b"finance", "alice"), ())
+            .is_err());
+
+        let op = ctx.verifiable_operations().last().unwrap();
+        let OperationType::CreateAccount {
+            initial_balance,
+            signature,
+        } = &op.op_type
+        else {
+            panic!("expected an account creation");
+        };
+        let message = transition_format::finance::create_account_message(
+            b"finance",
+            &op.key,
+            &alice.public_key(),
+            *initial_balance,
+        );
+        assert!(transition_format::finance::verify_signature(
+            &authority.public_key(),
+            &message,
+            signature
+        ));
+
+        // Only existing accounts with an owner can receive
+        let bob = AccountKey::from_bytes(&[3u8; 32]);
+        let transfer = alice.transfer(b"finance", "alice", "bob", 30, 0);
+        assert!(app.apply(&mut ctx, transfer, ()).is_err());
+        app.apply(&mut ctx, bob.create_account(b"finance", "bob"), ())
+            .unwrap();
+
+        // Transfers must be signed by the sender's owner for its nonce
+        let mallory = AccountKey::from_bytes(&[2u8; 32]);
+        let forged = mallory.transfer(b"finance", "alice", "bob", 30, 0);
+        assert!(app.apply(&mut ctx, forged, ()).is_err());
+
+        let transfer = alice.transfer(b"finance", "alice", "bob", 30, 0);
+        let receipt = app.apply(&mut ctx, transfer.clone(), ()).unwrap();
+        assert_eq!(receipt.accounts[0].1.balance, 70);
+        assert_eq!(receipt.accounts[0].1.nonce, 1);
+        assert_eq!(receipt.accounts[0].1.public_key, Some(alice.public_key()));
+        assert_eq!(receipt.accounts[1].1.balance, 30);
+        assert!(app.apply(&mut ctx, transfer, ()).is_err());
+
+        let bob: Account = ctx.get_required(&account_key("bob")).unwrap();
+        assert_eq!(bob.balance, 30);
+
+        // The transfer is checked against the sender's write
+        let ops = ctx.verifiable_operations();
+        assert_eq!(ops.len(), 3);
+        assert_eq!(ops[2].key, account_key("alice"));
+        assert_eq!(ops[2].witness_index, 2);
+
+        let overdraw = alice.transfer(b"finance", "alice", "bob", 71, 1);
+        assert!(app.apply(&mut ctx, overdraw, ()).is_err());
+
+        // Mints are signed for the account's nonce and increment it
+        let receipt = app
+            .apply(&mut ctx, authority.mint(b"finance", "bob", 20, 0), ())
+            .unwrap();
+        assert_eq!(receipt.accounts[0].1.balance, 50);
+        assert_eq!(receipt.accounts[0].1.nonce, 1);
+        assert!(app
+            .apply(&mut ctx, authority.mint(b"finance", "carol", 20, 0), ())
+            .is_err());
+
+        let op = ctx.verifiable_operations().last().unwrap();
+        let OperationType::Mint { amount, signature } = &op.op_type else {
+            panic!("expected a mint operation");
+        };
+        let message = transition_format::finance::mint_message(b"finance", &op.key, *amount, 0);
+        assert!(transition_format::finance::verify_signature(
+            &authority.public_key(),
+            &message,
+            signature
+        ));
+
+        // Signatures are bound to the app
+        let other_app = alice.transfer(b"other", "alice", "bob", 10, 1);
+        assert!(app.apply(&mut ctx, other_app, ()).is_err());
+
+        // Burns are signed by the owner for the account's nonce
+        let forged = mallory.burn(b"finance", "alice", 10, 1);
+        assert!(app.apply(&mut ctx, forged, ()).is_err());
+        assert!(app
+            .apply(&mut ctx, alice.burn(b"finance", "alice", 71, 1), ())
+            .is_err());
+
+        let burn = alice.burn(b"finance", "alice", 10, 1);
+        let receipt = app.apply(&mut ctx, burn.clone(), ()).unwrap();
+        assert_eq!(receipt.accounts[0].1.balance, 60);
+        assert_eq!(receipt.accounts[0].1.nonce, 2);
+        assert!(app.apply(&mut ctx, burn, ()).is_err());
+
+        let op = ctx.verifiable_operations().last().unwrap();
+        let OperationType::Burn { amount, signature } = &op.op_type else {
+            panic!("expected a burn operation");
+        };
+        let message = transition_format::finance::burn_message(b"finance", &op.key, *amount, 1);
+        assert!(transition_format::finance::verify_signature(
+            &alice.public_key(),
+            &message,
+            signature
+        ));
+    }
 }
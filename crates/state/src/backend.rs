@@ -0,0 +1,370 @@
+//! Key-value backends for the state store.
+//!
+//! A backend holds named trees (column families) of byte keys kept in order.
+//! [`SledBackend`] persists to disk and [`MemoryBackend`] keeps everything in
+//! memory. Another store (e.g. RocksDB, with one column family per tree) only
+//! needs to implement [`KvBackend`], reporting its errors as
+//! [`StateError::Backend`].
+
+use crate::StateError;
+use sled::transaction::{TransactionError, Transactional};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+/// A key-value pair read from a backend.
+pub type KvEntry = (Vec<u8>, Vec<u8>);
+
+/// A tree name and key identifying a batched write.
+type BatchKey = (Vec<u8>, Vec<u8>);
+
+/// Entries of one in-memory tree.
+type MemoryTree = BTreeMap<Vec<u8>, Vec<u8>>;
+
+/// Ordered key-value storage with named trees.
+///
+/// The empty name is the default tree. Writes that must land together go
+/// through [`KvBackend::apply`].
+pub trait KvBackend: Send + Sync {
+    /// Get a value.
+    fn get(&self, tree: &[u8], key: &[u8]) -> Result<Option<Vec<u8>>, StateError>;
+
+    /// Insert or replace a value.
+    fn insert(&self, tree: &[u8], key: &[u8], value: &[u8]) -> Result<(), StateError>;
+
+    /// Remove a value.
+    fn remove(&self, tree: &[u8], key: &[u8]) -> Result<(), StateError>;
+
+    /// Entries whose keys start with `prefix`, in key order.
+    fn scan_prefix<'a>(
+        &'a self,
+        tree: &[u8],
+        prefix: &[u8],
+    ) -> Box<dyn Iterator<Item = Result<KvEntry, StateError>> + 'a>;
+
+    /// The entry with the greatest key in `start..=end`.
+    fn last_in_range(
+        &self,
+        tree: &[u8],
+        start: &[u8],
+        end: &[u8],
+    ) -> Result<Option<KvEntry>, StateError>;
+
+    /// Whether a tree has no entries.
+    fn is_empty(&self, tree: &[u8]) -> Result<bool, StateError>;
+
+    /// Apply all writes of a batch atomically, across trees.
+    fn apply(&self, batch: KvBatch) -> Result<(), StateError>;
+
+    /// Make all applied writes durable.
+    fn flush(&self) -> Result<(), StateError>;
+}
+
+/// Writes to apply atomically, possibly across trees.
+///
+/// A later write to the same key replaces an earlier one.
+#[derive(Debug, Clone, Default)]
+pub struct KvBatch {
+    /// Writes by (tree, key); `None` removes the key.
+    writes: BTreeMap<BatchKey, Option<Vec<u8>>>,
+}
+
+impl KvBatch {
+    /// Insert or replace a value.
+    pub fn insert(&mut self, tree: &[u8], key: impl Into<Vec<u8>>, value: impl Into<Vec<u8>>) {
+        self.writes
+            .insert((tree.to_vec(), key.into()), Some(value.into()));
+    }
+
+    /// Remove a value.
+    pub fn remove(&mut self, tree: &[u8], key: impl Into<Vec<u8>>) {
+        self.writes.insert((tree.to_vec(), key.into()), None);
+    }
+
+    /// Whether the batch has no writes.
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty()
+    }
+
+    /// Iterate over the writes as (tree, key, value), ordered by tree and key.
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], &[u8], Option<&[u8]>)> {
+        self.writes
+            .iter()
+            .map(|((tree, key), value)| (tree.as_slice(), key.as_slice(), value.as_deref()))
+    }
+}
+
+impl From<sled::Error> for StateError {
+    fn from(e: sled::Error) -> Self {
+        Self::Backend(Box::new(e))
+    }
+}
+
+/// Backend on a sled database.
+///
+/// The default tree is sled's default tree, so stores written before
+/// backends were pluggable open unchanged.
+#[derive(Clone)]
+pub struct SledBackend {
+    db: sled::Db,
+}
+
+impl SledBackend {
+    /// Open or create a database at the given path.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, StateError> {
+        Ok(Self {
+            db: sled::open(path)?,
+        })
+    }
+
+    /// Create a database that is deleted when dropped.
+    pub fn temporary() -> Result<Self, StateError> {
+        Ok(Self {
+            db: sled::Config::new().temporary(true).open()?,
+        })
+    }
+
+    fn tree(&self, name: &[u8]) -> Result<sled::Tree, StateError> {
+        if name.is_empty() {
+            return Ok((*self.db).clone());
+        }
+        Ok(self.db.open_tree(name)?)
+    }
+}
+
+impl KvBackend for SledBackend {
+    fn get(&self, tree: &[u8], key: &[u8]) -> Result<Option<Vec<u8>>, StateError> {
+        Ok(self.tree(tree)?.get(key)?.map(|v| v.to_vec()))
+    }
+
+    fn insert(&self, tree: &[u8], key: &[u8], value: &[u8]) -> Result<(), StateError> {
+        self.tree(tree)?.insert(key, value)?;
+        Ok(())
+    }
+
+    fn remove(&self, tree: &[u8], key: &[u8]) -> Result<(), StateError> {
+        self.tree(tree)?.remove(key)?;
+        Ok(())
+    }
+
+    fn scan_prefix<'a>(
+        &'a self,
+        tree: &[u8],
+        prefix: &[u8],
+    ) -> Box<dyn Iterator<Item = Result<KvEntry, StateError>> + 'a> {
+        match self.tree(tree) {
+            Ok(tree) => Box::new(tree.scan_prefix(prefix).map(|entry| {
+                let (key, value) = entry?;
+                Ok((key.to_vec(), value.to_vec()))
+            })),
+            Err(e) => Box::new(std::iter::once(Err(e))),
+        }
+    }
+
+    fn last_in_range(
+        &self,
+        tree: &[u8],
+        start: &[u8],
+        end: &[u8],
+    ) -> Result<Option<KvEntry>, StateError> {
+        match self.tree(tree)?.range(start..=end).next_back() {
+            Some(entry) => {
+                let (key, value) = entry?;
+                Ok(Some((key.to_vec(), value.to_vec())))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn is_empty(&self, tree: &[u8]) -> Result<bool, StateError> {
+        Ok(self.tree(tree)?.is_empty())
+    }
+
+    fn apply(&self, batch: KvBatch) -> Result<(), StateError> {
+        let mut trees: Vec<sled::Tree> = Vec::new();
+        let mut batches: Vec<sled::Batch> = Vec::new();
+        let mut current: Option<&[u8]> = None;
+
+        // Writes are ordered by tree, so each tree's writes are contiguous
+        for (tree, key, value) in batch.iter() {
+            if current != Some(tree) {
+                trees.push(self.tree(tree)?);
+                batches.push(sled::Batch::default());
+                current = Some(tree);
+            }
+            let writes = batches.last_mut().expect("a batch per tree");
+            match value {
+                Some(value) => writes.insert(key, value),
+                None => writes.remove(key),
+            }
+        }
+
+        if trees.is_empty() {
+            return Ok(());
+        }
+
+        trees[..]
+            .transaction(|views| {
+                for (view, writes) in views.iter().zip(&batches) {
+                    view.apply_batch(writes)?;
+                }
+                Ok(())
+            })
+            .map_err(|e| match e {
+                TransactionError::Abort(e) | TransactionError::Storage(e) => e.into(),
+            })
+    }
+
+    fn flush(&self) -> Result<(), StateError> {
+        self.db.flush()?;
+        Ok(())
+    }
+}
+
+/// Backend keeping all trees in memory.
+///
+/// Clones share the same data, so a store can be reopened on a clone.
+#[derive(Clone, Default)]
+pub struct MemoryBackend {
+    trees: Arc<RwLock<HashMap<Vec<u8>, MemoryTree>>>,
+}
+
+impl MemoryBackend {
+    /// Create an empty backend.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn read<T>(&self, tree: &[u8], f: impl FnOnce(&MemoryTree) -> T) -> T {
+        let trees = self.trees.read().expect("memory backend lock poisoned");
+        match trees.get(tree) {
+            Some(entries) => f(entries),
+            None => f(&BTreeMap::new()),
+        }
+    }
+
+    fn write<T>(&self, f: impl FnOnce(&mut HashMap<Vec<u8>, MemoryTree>) -> T) -> T {
+        let mut trees = self.trees.write().expect("memory backend lock poisoned");
+        f(&mut trees)
+    }
+}
+
+impl KvBackend for MemoryBackend {
+    fn get(&self, tree: &[u8], key: &[u8]) -> Result<Option<Vec<u8>>, StateError> {
+        Ok(self.read(tree, |entries| entries.get(key).cloned()))
+    }
+
+    fn insert(&self, tree: &[u8], key: &[u8], value: &[u8]) -> Result<(), StateError> {
+        self.write(|trees| {
+            trees
+                .entry(tree.to_vec())
+                .or_default()
+                .insert(key.to_vec(), value.to_vec())
+        });
+        Ok(())
+    }
+
+    fn remove(&self, tree: &[u8], key: &[u8]) -> Result<(), StateError> {
+        self.write(|trees| trees.get_mut(tree).and_then(|entries| entries.remove(key)));
+        Ok(())
+    }
+
+    fn scan_prefix<'a>(
+        &'a self,
+        tree: &[u8],
+        prefix: &[u8],
+    ) -> Box<dyn Iterator<Item = Result<KvEntry, StateError>> + 'a> {
+        let entries: Vec<_> = self.read(tree, |entries| {
+            entries
+                .range(prefix.to_vec()..)
+                .take_while(|(key, _)| key.starts_with(prefix))
+                .map(|(key, value)| Ok((key.clone(), value.clone())))
+                .collect()
+        });
+        Box::new(entries.into_iter())
+    }
+
+    fn last_in_range(
+        &self,
+        tree: &[u8],
+        start: &[u8],
+        end: &[u8],
+    ) -> Result<Option<KvEntry>, StateError> {
+        Ok(self.read(tree, |entries| {
+            entries
+                .range(start.to_vec()..=end.to_vec())
+                .next_back()
+                .map(|(key, value)| (key.clone(), value.clone()))
+        }))
+    }
+
+    fn is_empty(&self, tree: &[u8]) -> Result<bool, StateError> {
+        Ok(self.read(tree, BTreeMap::is_empty))
+    }
+
+    fn apply(&self, batch: KvBatch) -> Result<(), StateError> {
+        // A single write lock makes the batch atomic
+        self.write(|trees| {
+            for (tree, key, value) in batch.iter() {
+                let entries = trees.entry(tree.to_vec()).or_default();
+                match value {
+                    Some(value) => entries.insert(key.to_vec(), value.to_vec()),
+                    None => entries.remove(key),
+                };
+            }
+        });
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), StateError> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exercise(backend: &dyn KvBackend) {
+        assert!(backend.is_empty(b"").unwrap());
+
+        backend.insert(b"", b"a1", b"x").unwrap();
+        backend.insert(b"t", b"a1", b"y").unwrap();
+        assert_eq!(backend.get(b"", b"a1").unwrap(), Some(b"x".to_vec()));
+        assert_eq!(backend.get(b"t", b"a1").unwrap(), Some(b"y".to_vec()));
+        backend.remove(b"t", b"a1").unwrap();
+        assert_eq!(backend.get(b"t", b"a1").unwrap(), None);
+
+        let mut batch = KvBatch::default();
+        batch.insert(b"", b"a2", b"1");
+        batch.insert(b"", b"b1", b"2");
+        batch.insert(b"t", b"a3", b"3");
+        batch.remove(b"", b"a1");
+        backend.apply(batch).unwrap();
+
+        let scanned: Vec<_> = backend
+            .scan_prefix(b"", b"a")
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(scanned, vec![(b"a2".to_vec(), b"1".to_vec())]);
+        assert_eq!(backend.scan_prefix(b"t", b"").count(), 1);
+
+        assert_eq!(
+            backend.last_in_range(b"", b"a0", b"az").unwrap(),
+            Some((b"a2".to_vec(), b"1".to_vec()))
+        );
+        assert_eq!(backend.last_in_range(b"", b"c", b"d").unwrap(), None);
+        assert!(!backend.is_empty(b"t").unwrap());
+        backend.flush().unwrap();
+    }
+
+    #[test]
+    fn test_sled_backend() {
+        exercise(&SledBackend::temporary().unwrap());
+    }
+
+    #[test]
+    fn test_memory_backend() {
+        exercise(&MemoryBackend::new());
+    }
+}
@@ -0,0 +1,260 @@
+//! Records of committed transitions, kept with the state they produced.
+//!
+//! A record (and the journaled prover input, if any) is written in the same
+//! batch as its transition's commit and removed when the state is reverted
+//! past it, so the history always matches the stored state.
+
+use crate::{KvBatch, StateError, StateStore, HISTORY_BASE_KEY, META_TREE};
+use merkle::Hash32;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Name of the backend tree holding transition records (keyed by sequence,
+/// big-endian).
+pub(crate) const TRANSITIONS_TREE: &[u8] = b"state_transitions";
+
+/// Name of the backend tree journaling encoded prover inputs (keyed by
+/// sequence, big-endian).
+pub(crate) const JOURNAL_TREE: &[u8] = b"state_journal";
+
+/// What is known about a committed transition beyond its state changes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransitionRecord {
+    /// Sequence of the transition.
+    pub sequence: u64,
+    /// Root the transition started from.
+    pub prev_root: Hash32,
+    /// Root after the transition.
+    pub new_root: Hash32,
+    /// Hash of the transition blob.
+    pub blob_hash: Option<Hash32>,
+    /// Hash of the program that proved the transition.
+    pub program_hash: Option<Hash32>,
+    /// Blob timestamp (Unix seconds).
+    pub timestamp: Option<u64>,
+    /// Celestia height the blob was included at.
+    pub celestia_height: Option<u64>,
+    /// Celestia share commitment of the blob.
+    pub celestia_commitment: Option<Vec<u8>>,
+    /// Whether the transition has been proven, in its blob or through a
+    /// later proof attachment.
+    pub proven: bool,
+}
+
+impl TransitionRecord {
+    /// Create a record for a transition.
+    pub fn new(sequence: u64, prev_root: Hash32, new_root: Hash32) -> Self {
+        Self {
+            sequence,
+            prev_root,
+            new_root,
+            blob_hash: None,
+            program_hash: None,
+            timestamp: None,
+            celestia_height: None,
+            celestia_commitment: None,
+            proven: false,
+        }
+    }
+
+    /// Set the blob hash and the program hash.
+    pub fn with_blob(mut self, blob_hash: Hash32, program_hash: Hash32) -> Self {
+        self.blob_hash = Some(blob_hash);
+        self.program_hash = Some(program_hash);
+        self
+    }
+
+    /// Set the timestamp.
+    pub fn with_timestamp(mut self, timestamp: Option<u64>) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    /// Set whether the transition has been proven.
+    pub fn with_proven(mut self, proven: bool) -> Self {
+        self.proven = proven;
+        self
+    }
+
+    /// Set where the blob was posted on Celestia.
+    pub fn with_celestia(mut self, height: u64, commitment: Vec<u8>) -> Self {
+        self.celestia_height = Some(height);
+        self.celestia_commitment = Some(commitment);
+        self
+    }
+
+    pub(crate) fn encode(&self) -> Result<Vec<u8>, StateError> {
+        bincode::serialize(self).map_err(|e| StateError::Serialization(e.to_string()))
+    }
+
+    fn decode(data: &[u8]) -> Result<Self, StateError> {
+        bincode::deserialize(data).map_err(|e| StateError::Serialization(e.to_string()))
+    }
+}
+
+impl StateStore {
+    /// Get the record of the transition committed as `sequence`.
+    pub fn transition_record(&self, sequence: u64) -> Result<Option<TransitionRecord>, StateError> {
+        self.backend
+            .get(TRANSITIONS_TREE, &sequence.to_be_bytes())?
+            .map(|data| TransitionRecord::decode(&data))
+            .transpose()
+    }
+
+    /// Replace the record of an already committed transition (e.g. after
+    /// proving it again).
+    ///
+    /// Fails if the sequence is not stored or the roots differ from the
+    /// committed ones.
+    pub fn update_transition_record(&mut self, record: TransitionRecord) -> Result<(), StateError> {
+        self.check_record(&record)?;
+
+        let mut batch = KvBatch::default();
+        batch.insert(
+            TRANSITIONS_TREE,
+            record.sequence.to_be_bytes(),
+            record.encode()?,
+        );
+        self.backend.apply(batch)?;
+        self.backend.flush()
+    }
+
+    /// Check that a record describes a stored transition.
+    pub(crate) fn check_record(&self, record: &TransitionRecord) -> Result<(), StateError> {
+        if record.sequence <= self.base_sequence {
+            return Err(StateError::UnknownSequence(record.sequence));
+        }
+        let prev_root = self.at_sequence(record.sequence - 1)?.root();
+        let new_root = self.at_sequence(record.sequence)?.root();
+        if record.prev_root != prev_root || record.new_root != new_root {
+            return Err(StateError::StaleTransition {
+                sequence: record.sequence,
+            });
+        }
+        Ok(())
+    }
+
+    /// Get the journaled prover input of the transition committed as
+    /// `sequence`.
+    pub fn journal_entry(&self, sequence: u64) -> Result<Option<Vec<u8>>, StateError> {
+        self.backend.get(JOURNAL_TREE, &sequence.to_be_bytes())
+    }
+
+    /// Get all transition records, oldest first.
+    pub fn transition_records(&self) -> Result<Vec<TransitionRecord>, StateError> {
+        self.backend
+            .scan_prefix(TRANSITIONS_TREE, &[])
+            .map(|entry| TransitionRecord::decode(&entry?.1))
+            .collect()
+    }
+
+    /// Get the root of every stored sequence as (sequence, root, celestia
+    /// height), oldest first.
+    ///
+    /// Sequences committed without a record (before records were kept, or
+    /// through [`Self::commit`]) have no Celestia height. A store imported
+    /// from a snapshot starts with the snapshot's root history.
+    pub fn root_history(&self) -> Result<Vec<(u64, Hash32, Option<u64>)>, StateError> {
+        let records: BTreeMap<u64, TransitionRecord> = self
+            .transition_records()?
+            .into_iter()
+            .map(|record| (record.sequence, record))
+            .collect();
+
+        let mut history = Vec::new();
+        if let Some(data) = self.backend.get(META_TREE, HISTORY_BASE_KEY)? {
+            let base: (u64, Hash32, Option<u64>) = bincode::deserialize(&data)
+                .map_err(|e| StateError::Serialization(e.to_string()))?;
+            if base.0 < self.base_sequence {
+                history.push(base);
+                // Imported sequences have records but no stored state
+                for sequence in base.0 + 1..self.base_sequence {
+                    let record = records
+                        .get(&sequence)
+                        .ok_or(StateError::UnknownSequence(sequence))?;
+                    history.push((sequence, record.new_root, record.celestia_height));
+                }
+            }
+        }
+
+        for sequence in self.base_sequence..=self.transition_index {
+            history.push(match records.get(&sequence) {
+                Some(record) => (sequence, record.new_root, record.celestia_height),
+                None => (sequence, self.at_sequence(sequence)?.root(), None),
+            });
+        }
+        Ok(history)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StateOp;
+
+    #[test]
+    fn test_transition_records() {
+        let mut store = StateStore::in_memory().unwrap();
+        let empty_root = store.root();
+
+        let staged = store
+            .stage(vec![StateOp::insert("a", b"1".to_vec())])
+            .unwrap()
+            .with_journal(b"input".to_vec());
+        let record = TransitionRecord::new(staged.sequence, staged.prev_root, staged.new_root)
+            .with_blob([1u8; 32], [2u8; 32])
+            .with_timestamp(Some(1_700_000_000))
+            .with_celestia(42, vec![3, 4]);
+        let root1 = store
+            .commit_staged_with_record(staged, record.clone())
+            .unwrap();
+
+        let staged = store
+            .stage(vec![StateOp::insert("b", b"2".to_vec())])
+            .unwrap();
+        let root2 = store.commit_staged(staged).unwrap();
+
+        assert_eq!(store.transition_record(1).unwrap(), Some(record.clone()));
+        assert_eq!(store.transition_record(2).unwrap(), None);
+        assert_eq!(store.transition_records().unwrap(), vec![record.clone()]);
+        assert_eq!(store.journal_entry(1).unwrap(), Some(b"input".to_vec()));
+        assert_eq!(store.journal_entry(2).unwrap(), None);
+        assert_eq!(
+            store.root_history().unwrap(),
+            vec![
+                (0, empty_root, None),
+                (1, root1, Some(42)),
+                (2, root2, None)
+            ]
+        );
+
+        // A record must describe the transition it is committed with
+        let staged = store
+            .stage(vec![StateOp::insert("c", b"3".to_vec())])
+            .unwrap();
+        let stale = TransitionRecord::new(staged.sequence + 1, staged.prev_root, staged.new_root);
+        assert!(matches!(
+            store.commit_staged_with_record(staged, stale),
+            Err(StateError::StaleTransition { .. })
+        ));
+        assert_eq!(store.root(), root2);
+
+        // Records of committed transitions can be updated, e.g. after
+        // proving again
+        let reproven = record.clone().with_celestia(99, vec![5]);
+        store.update_transition_record(reproven.clone()).unwrap();
+        assert_eq!(store.transition_record(1).unwrap(), Some(reproven));
+        let mut wrong = record.clone();
+        wrong.new_root = root2;
+        assert!(store.update_transition_record(wrong).is_err());
+        let mut unknown = record;
+        unknown.sequence = 3;
+        assert!(store.update_transition_record(unknown).is_err());
+
+        // Reverting drops the records and journal of reverted transitions
+        store.revert_to(0).unwrap();
+        assert!(store.transition_records().unwrap().is_empty());
+        assert_eq!(store.journal_entry(1).unwrap(), None);
+        assert_eq!(store.root_history().unwrap(), vec![(0, empty_root, None)]);
+    }
+}
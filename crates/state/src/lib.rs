@@ -1,24 +1,122 @@
 //! State management with Merkle tree commitment.
 //!
-//! This crate provides a persistent key-value store backed by sled,
-//! with Merkle tree commitment for state roots and proofs.
+//! This crate provides a persistent key-value store on a pluggable backend
+//! (sled or in-memory), with Merkle tree commitment for state roots and
+//! proofs.
 
-use merkle::{Hash32, MerkleProof, SparseMerkleTree, UpdateWitness};
+mod backend;
+mod history;
+mod outbox;
+mod snapshot;
+mod tree_store;
+
+pub use backend::{KvBackend, KvBatch, KvEntry, MemoryBackend, SledBackend};
+pub use history::TransitionRecord;
+pub use outbox::OutboxEntry;
+pub use snapshot::{StateExport, SNAPSHOT_VERSION};
+pub use tree_store::KvTreeStore;
+
+use history::{JOURNAL_TREE, TRANSITIONS_TREE};
+use merkle::{
+    Hash32, HashFunction, MerkleProof, MultiProof, RootBuilder, SparseMerkleTree, TreeHasher,
+    UpdateWitness,
+};
+use outbox::OUTBOX_TREE;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::path::Path;
+use std::sync::Arc;
 use thiserror::Error;
 
+/// Name of the backend tree holding state values (the default tree).
+const VALUES_TREE: &[u8] = b"";
+
+/// Name of the backend tree holding store metadata, apart from state values.
+const META_TREE: &[u8] = b"state_meta";
+
+/// Metadata key recording the hash function of the state tree.
+const HASH_FUNCTION_KEY: &[u8] = b"hash_function";
+
+/// Metadata key recording the transition index.
+const TRANSITION_INDEX_KEY: &[u8] = b"transition_index";
+
+/// Metadata key recording the first sequence with stored history (set when a
+/// store is bootstrapped from a snapshot or pruned).
+const BASE_SEQUENCE_KEY: &[u8] = b"base_sequence";
+
+/// Metadata key recording the oldest root history entry imported with a
+/// snapshot (or kept by the first prune), as bincode-encoded (sequence,
+/// root, celestia height).
+const HISTORY_BASE_KEY: &[u8] = b"history_base";
+
+/// Metadata key present while the store is closed after a clean shutdown
+/// (removed on open, written back when the store is dropped).
+const CLEAN_SHUTDOWN_KEY: &[u8] = b"clean_shutdown";
+
+/// Key of the whole-tree blob written by older versions of the store.
+const LEGACY_TREE_KEY: &[u8] = b"__merkle_tree__";
+
+/// Key older versions of the store kept the transition index under, among
+/// the state values.
+const LEGACY_TRANSITION_INDEX_KEY: &[u8] = b"__transition_index__";
+
+/// State keys that are rejected because older stores read them as metadata.
+const RESERVED_KEYS: [&[u8]; 2] = [LEGACY_TREE_KEY, LEGACY_TRANSITION_INDEX_KEY];
+
+/// Name of the backend tree mapping key hashes back to keys.
+const KEYS_TREE: &[u8] = b"state_keys";
+
+/// Name of the backend tree holding the undo log (one record per sequence).
+const UNDO_LOG_TREE: &[u8] = b"state_undo";
+
 /// Errors that can occur during state operations.
 #[derive(Error, Debug)]
 pub enum StateError {
-    #[error("database error: {0}")]
-    Database(#[from] sled::Error),
+    #[error("backend error: {0}")]
+    Backend(Box<dyn std::error::Error + Send + Sync>),
     #[error("serialization error: {0}")]
     Serialization(String),
     #[error("merkle error: {0}")]
     Merkle(#[from] merkle::MerkleError),
     #[error("key not found: {0}")]
     KeyNotFound(String),
+    #[error("unknown sequence: {0}")]
+    UnknownSequence(u64),
+    #[error("hash function mismatch: store uses {stored}, requested {requested}")]
+    HashFunctionMismatch {
+        stored: HashFunction,
+        requested: HashFunction,
+    },
+    #[error(
+        "state integrity check failed: committed root {}, stored values give {}",
+        hex::encode(committed),
+        hex::encode(computed)
+    )]
+    IntegrityMismatch { committed: Hash32, computed: Hash32 },
+    #[error(
+        "state integrity check failed: stored value of {key} does not match the tree committed at sequence {sequence}"
+    )]
+    ValueMismatch { key: String, sequence: u64 },
+    #[error("key is reserved for store metadata: {0}")]
+    ReservedKey(String),
+    #[error("no undo record for sequence {0}")]
+    UndoUnavailable(u64),
+    #[error("uncommitted writes pending")]
+    UncommittedWrites,
+    #[error("staged transition {sequence} no longer applies: state has moved on")]
+    StaleTransition { sequence: u64 },
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid snapshot: {0}")]
+    InvalidSnapshot(String),
+    #[error(
+        "snapshot root mismatch: expected {}, entries give {}",
+        hex::encode(expected),
+        hex::encode(computed)
+    )]
+    SnapshotRootMismatch { expected: Hash32, computed: Hash32 },
+    #[error("store is not empty")]
+    StoreNotEmpty,
 }
 
 /// Key prefix for typed keys.
@@ -52,50 +150,344 @@ impl<T: KeyPrefix> TypedKey<T> {
     }
 }
 
+/// A buffered value write (`None` for deletes).
+type PendingWrite = Option<Vec<u8>>;
+
+/// Values of the keys a commit wrote, as they were before it.
+type UndoRecord = Vec<(Vec<u8>, Option<Vec<u8>>)>;
+
 /// State store with Merkle commitment.
 pub struct StateStore {
-    /// Underlying key-value database.
-    db: sled::Db,
-    /// Merkle tree for state commitment.
-    tree: SparseMerkleTree,
+    /// Underlying key-value backend.
+    backend: Arc<dyn KvBackend>,
+    /// Merkle tree for state commitment (nodes persisted in the backend).
+    tree: SparseMerkleTree<KvTreeStore>,
+    /// Value writes since the last commit.
+    pending: BTreeMap<Vec<u8>, PendingWrite>,
     /// Current transition index.
     transition_index: u64,
+    /// First sequence whose state is stored (0 unless imported or pruned).
+    base_sequence: u64,
+    /// Whether dropping the store records a clean shutdown (not before it
+    /// passed its checks on open).
+    clean_on_drop: bool,
 }
 
 impl StateStore {
-    /// Open or create a state store at the given path.
+    /// Open or create a sled-backed state store at the given path.
+    ///
+    /// An existing store keeps the hash function it was created with; a new
+    /// one uses SHA-256. Unless the store was shut down cleanly, the root is
+    /// recomputed from all stored values ([`Self::check_integrity`]) and a
+    /// mismatching store is refused with [`StateError::IntegrityMismatch`].
+    /// After a clean shutdown only the values written by the latest commit
+    /// are compared with the committed tree ([`StateError::ValueMismatch`]).
     pub fn open(path: impl AsRef<Path>) -> Result<Self, StateError> {
-        let db = sled::open(path)?;
+        Self::open_with(Arc::new(SledBackend::open(path)?), None, false)
+    }
 
-        // Load Merkle tree if exists
-        let tree = if let Some(data) = db.get(b"__merkle_tree__")? {
-            SparseMerkleTree::deserialize(&data)?
-        } else {
-            SparseMerkleTree::new()
-        };
+    /// Open or create a sled-backed state store whose tree uses
+    /// `hash_function`.
+    ///
+    /// Fails if an existing store was created with a different one.
+    pub fn open_with_hasher(
+        path: impl AsRef<Path>,
+        hash_function: HashFunction,
+    ) -> Result<Self, StateError> {
+        Self::open_with(
+            Arc::new(SledBackend::open(path)?),
+            Some(hash_function),
+            false,
+        )
+    }
+
+    /// Open a store like [`Self::open_with_hasher`], first rewriting stored
+    /// values that do not match the committed tree.
+    ///
+    /// The tree is what proofs and published roots commit to, so it wins.
+    /// Scans every value, then runs [`Self::check_integrity`].
+    pub fn open_repaired(
+        path: impl AsRef<Path>,
+        hash_function: HashFunction,
+    ) -> Result<Self, StateError> {
+        Self::open_with(
+            Arc::new(SledBackend::open(path)?),
+            Some(hash_function),
+            true,
+        )
+    }
+
+    /// Open or create a state store on any backend.
+    ///
+    /// `None` keeps the hash function of an existing store (SHA-256 for a
+    /// new one); the same checks as [`Self::open`] apply.
+    pub fn with_backend(
+        backend: impl KvBackend + 'static,
+        hash_function: Option<HashFunction>,
+    ) -> Result<Self, StateError> {
+        Self::open_with(Arc::new(backend), hash_function, false)
+    }
+
+    fn open_with(
+        backend: Arc<dyn KvBackend>,
+        requested: Option<HashFunction>,
+        repair: bool,
+    ) -> Result<Self, StateError> {
+        Self::migrate_legacy_metadata(backend.as_ref())?;
+        let hash_function = Self::resolve_hash_function(backend.as_ref(), requested)?;
+
+        // Open the Merkle tree (nodes are read lazily)
+        let tree_store = KvTreeStore::open(Arc::clone(&backend));
+        let tree = SparseMerkleTree::with_store(tree_store, hash_function)?;
 
         // Load transition index
-        let transition_index = if let Some(data) = db.get(b"__transition_index__")? {
-            u64::from_le_bytes(data.as_ref().try_into().unwrap_or([0; 8]))
-        } else {
-            0
-        };
+        let transition_index = read_u64(backend.as_ref(), TRANSITION_INDEX_KEY)?;
+        let base_sequence = read_u64(backend.as_ref(), BASE_SEQUENCE_KEY)?;
 
-        Ok(Self {
-            db,
+        let mut store = Self {
+            backend,
             tree,
+            pending: BTreeMap::new(),
             transition_index,
-        })
+            base_sequence,
+            clean_on_drop: false,
+        };
+        store.migrate_legacy_tree()?;
+        store.index_existing_keys()?;
+        store.tree.store().index_versions()?;
+
+        // Until it is dropped, the store counts as not shut down cleanly
+        let clean = store.backend.get(META_TREE, CLEAN_SHUTDOWN_KEY)?.is_some();
+        store.backend.remove(META_TREE, CLEAN_SHUTDOWN_KEY)?;
+        store.backend.flush()?;
+        if repair {
+            store.repair()?;
+            store.check_integrity()?;
+        } else if clean {
+            store.check_latest_commit()?;
+        } else {
+            store.check_integrity()?;
+        }
+        store.clean_on_drop = true;
+        Ok(store)
     }
 
     /// Create an in-memory state store (for testing).
     pub fn in_memory() -> Result<Self, StateError> {
-        let db = sled::Config::new().temporary(true).open()?;
-        Ok(Self {
-            db,
-            tree: SparseMerkleTree::new(),
-            transition_index: 0,
-        })
+        Self::in_memory_with_hasher(HashFunction::default())
+    }
+
+    /// Create an in-memory state store whose tree uses `hash_function`.
+    pub fn in_memory_with_hasher(hash_function: HashFunction) -> Result<Self, StateError> {
+        Self::with_backend(MemoryBackend::new(), Some(hash_function))
+    }
+
+    /// Check the requested hash function against the stored one and record it.
+    fn resolve_hash_function(
+        backend: &dyn KvBackend,
+        requested: Option<HashFunction>,
+    ) -> Result<HashFunction, StateError> {
+        let stored = match backend.get(META_TREE, HASH_FUNCTION_KEY)? {
+            Some(data) => Some(
+                String::from_utf8_lossy(&data)
+                    .parse()
+                    .map_err(merkle::MerkleError::from)?,
+            ),
+            // Stores written before the hash function was recorded use SHA-256
+            None if !backend.is_empty(VALUES_TREE)? => Some(HashFunction::Sha256),
+            None => None,
+        };
+
+        let hash_function = match (stored, requested) {
+            (Some(stored), Some(requested)) if stored != requested => {
+                return Err(StateError::HashFunctionMismatch { stored, requested });
+            }
+            (Some(stored), _) => stored,
+            (None, requested) => requested.unwrap_or_default(),
+        };
+
+        backend.insert(
+            META_TREE,
+            HASH_FUNCTION_KEY,
+            hash_function.name().as_bytes(),
+        )?;
+        Ok(hash_function)
+    }
+
+    /// Move the transition index that older stores kept among the state
+    /// values into the metadata tree.
+    fn migrate_legacy_metadata(backend: &dyn KvBackend) -> Result<(), StateError> {
+        if let Some(value) = backend.get(VALUES_TREE, LEGACY_TRANSITION_INDEX_KEY)? {
+            if backend.get(META_TREE, TRANSITION_INDEX_KEY)?.is_none() {
+                backend.insert(META_TREE, TRANSITION_INDEX_KEY, &value)?;
+            }
+            backend.remove(VALUES_TREE, LEGACY_TRANSITION_INDEX_KEY)?;
+        }
+        Ok(())
+    }
+
+    /// Move a whole-tree blob from an older store into the node storage.
+    fn migrate_legacy_tree(&mut self) -> Result<(), StateError> {
+        let Some(data) = self.backend.get(VALUES_TREE, LEGACY_TREE_KEY)? else {
+            return Ok(());
+        };
+
+        if self.tree.store().is_empty()? {
+            // Legacy trees predate pluggable hash functions
+            let legacy = SparseMerkleTree::deserialize(&data, HashFunction::Sha256)?;
+            for (key_hash, value) in legacy.store().leaves() {
+                self.tree.try_insert_by_hash(*key_hash, value.clone())?;
+            }
+            self.tree.store_mut().flush(self.transition_index)?;
+        }
+
+        self.backend.remove(VALUES_TREE, LEGACY_TREE_KEY)?;
+        self.backend.flush()?;
+        Ok(())
+    }
+
+    /// Index the keys of stores written before keys were recorded.
+    ///
+    /// Only keys still present can be recovered; diffs show deleted ones by
+    /// hash only.
+    fn index_existing_keys(&mut self) -> Result<(), StateError> {
+        if !self.backend.is_empty(KEYS_TREE)? {
+            return Ok(());
+        }
+
+        let mut batch = KvBatch::default();
+        for entry in self.backend.scan_prefix(VALUES_TREE, &[]) {
+            let (key, _) = entry?;
+            batch.insert(KEYS_TREE, self.hash_function().hash_key(&key), key);
+        }
+        self.backend.apply(batch)
+    }
+
+    /// Compare the values written by the latest commit with the committed
+    /// tree.
+    ///
+    /// Only reads the keys in the latest undo record, so opening a store does
+    /// not scan the whole state. Stores without one (new, imported or
+    /// written before the undo log) are not checked.
+    fn check_latest_commit(&self) -> Result<(), StateError> {
+        let sequence = self.transition_index;
+        let Some(data) = self.backend.get(UNDO_LOG_TREE, &sequence.to_be_bytes())? else {
+            return Ok(());
+        };
+        let undo: UndoRecord =
+            bincode::deserialize(&data).map_err(|e| StateError::Serialization(e.to_string()))?;
+
+        for (key, _) in undo {
+            if self.backend.get(VALUES_TREE, &key)? != self.tree.try_get(&key)? {
+                return Err(StateError::ValueMismatch {
+                    key: hex::encode(key),
+                    sequence,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Recompute the root from the committed values and compare it with the
+    /// committed tree.
+    ///
+    /// Streams the values in key hash order through the keys index, so it
+    /// reads every value but keeps only one tree path in memory. It is only
+    /// run on open after an unclean shutdown (and by
+    /// [`Self::open_repaired`]). A value missing from the index fails with
+    /// [`StateError::ValueMismatch`].
+    pub fn check_integrity(&self) -> Result<(), StateError> {
+        let hash_function = self.hash_function();
+
+        // Every value must be indexed for the stream below to include it
+        for entry in self.committed_values() {
+            let (key, _) = entry?;
+            let key_hash = hash_function.hash_key(&key);
+            if self.backend.get(KEYS_TREE, &key_hash)?.as_deref() != Some(key.as_slice()) {
+                return Err(StateError::ValueMismatch {
+                    key: hex::encode(key),
+                    sequence: self.transition_index,
+                });
+            }
+        }
+
+        let mut root = RootBuilder::new(hash_function);
+        for entry in self.backend.scan_prefix(KEYS_TREE, &[]) {
+            let (key_hash, key) = entry?;
+            // The index keeps deleted keys for diffs
+            let Some(value) = self.backend.get(VALUES_TREE, &key)? else {
+                continue;
+            };
+            // Stray entries are skipped: each value's own one was checked
+            let expected = hash_function.hash_key(&key);
+            if key_hash != expected {
+                continue;
+            }
+            root.push(expected, &value)?;
+        }
+
+        let committed = self.root();
+        let computed = root.finish();
+        if committed != computed {
+            return Err(StateError::IntegrityMismatch {
+                committed,
+                computed,
+            });
+        }
+        Ok(())
+    }
+
+    /// Rewrite the committed values to match the committed tree.
+    ///
+    /// Values of keys missing from the store are restored through the keys
+    /// index. Returns the number of keys rewritten.
+    fn repair(&mut self) -> Result<usize, StateError> {
+        let mut batch = KvBatch::default();
+        let mut repaired = 0;
+
+        // Values the tree disagrees with
+        for entry in self.committed_values() {
+            let (key, value) = entry?;
+            let key_hash = self.hash_function().hash_key(&key);
+            if self.backend.get(KEYS_TREE, &key_hash)?.is_none() {
+                batch.insert(KEYS_TREE, key_hash, key.as_slice());
+            }
+            match self.tree.try_get(&key)? {
+                Some(expected) if expected == value => continue,
+                Some(expected) => batch.insert(VALUES_TREE, key, expected),
+                None => batch.remove(VALUES_TREE, key),
+            }
+            repaired += 1;
+        }
+
+        // Values the tree has but the store lost
+        for key_hash in self.tree.store().leaf_hashes()? {
+            let Some(value) = self.tree.try_get_by_hash(&key_hash)? else {
+                continue;
+            };
+            let key = self
+                .backend
+                .get(KEYS_TREE, &key_hash)?
+                .ok_or_else(|| StateError::KeyNotFound(hex::encode(key_hash)))?;
+            if self.backend.get(VALUES_TREE, &key)?.is_none() {
+                batch.insert(VALUES_TREE, key, value);
+                repaired += 1;
+            }
+        }
+
+        self.backend.apply(batch)?;
+        self.backend.flush()?;
+        Ok(repaired)
+    }
+
+    /// Committed state values, skipping leftovers of legacy metadata.
+    fn committed_values(&self) -> impl Iterator<Item = Result<KvEntry, StateError>> + '_ {
+        self.backend
+            .scan_prefix(VALUES_TREE, &[])
+            .filter(|entry| match entry {
+                Ok((key, _)) => !is_reserved_key(key),
+                Err(_) => true,
+            })
     }
 
     /// Get the current state root.
@@ -108,14 +500,22 @@ impl StateStore {
         self.transition_index
     }
 
-    /// Get a raw value by key.
+    /// Get the hash function of the state tree.
+    pub fn hash_function(&self) -> HashFunction {
+        self.tree.hash_function()
+    }
+
+    /// Get a raw value by key (including uncommitted writes).
     pub fn get_raw(&self, key: &[u8]) -> Result<Option<Vec<u8>>, StateError> {
-        Ok(self.db.get(key)?.map(|v| v.to_vec()))
+        if let Some(value) = self.pending.get(key) {
+            return Ok(value.clone());
+        }
+        self.backend.get(VALUES_TREE, key)
     }
 
     /// Get a typed value by key.
     pub fn get<V: DeserializeOwned>(&self, key: &[u8]) -> Result<Option<V>, StateError> {
-        match self.db.get(key)? {
+        match self.get_raw(key)? {
             Some(data) => {
                 let value: V = bincode::deserialize(&data)
                     .map_err(|e| StateError::Serialization(e.to_string()))?;
@@ -127,15 +527,17 @@ impl StateStore {
 
     /// Get a value with its Merkle proof.
     pub fn get_with_proof(&self, key: &[u8]) -> Result<(Option<Vec<u8>>, MerkleProof), StateError> {
-        let value = self.tree.get(key);
-        let proof = self.tree.get_proof(key);
-        Ok((value, proof))
+        let proof = self.tree.try_get_proof(key)?;
+        Ok((proof.value.clone(), proof))
     }
 
     /// Insert a raw value.
+    ///
+    /// The write is buffered until [`Self::commit`].
     pub fn insert_raw(&mut self, key: &[u8], value: Vec<u8>) -> Result<UpdateWitness, StateError> {
-        self.db.insert(key, value.as_slice())?;
-        let witness = self.tree.insert(key, value);
+        check_key(key)?;
+        let witness = self.tree.try_insert(key, value.clone())?;
+        self.pending.insert(key.to_vec(), Some(value));
         Ok(witness)
     }
 
@@ -151,56 +553,687 @@ impl StateStore {
     }
 
     /// Delete a key.
+    ///
+    /// The delete is buffered until [`Self::commit`].
     pub fn delete(&mut self, key: &[u8]) -> Result<UpdateWitness, StateError> {
-        self.db.remove(key)?;
-        let witness = self.tree.delete(key);
+        check_key(key)?;
+        let witness = self.tree.try_delete(key)?;
+        self.pending.insert(key.to_vec(), None);
         Ok(witness)
     }
 
     /// Apply a batch of operations and return all witnesses.
+    ///
+    /// If an operation fails, the ones before it are undone, so the batch
+    /// applies completely or not at all.
     pub fn apply_batch(&mut self, ops: Vec<StateOp>) -> Result<Vec<UpdateWitness>, StateError> {
         let mut witnesses = Vec::with_capacity(ops.len());
+        let mut pending = Vec::with_capacity(ops.len());
 
         for op in ops {
-            let witness = match op {
-                StateOp::Insert { key, value } => self.insert_raw(&key, value)?,
-                StateOp::Delete { key } => self.delete(&key)?,
+            let key = op.key().to_vec();
+            let previous = self.pending.get(&key).cloned();
+            let result = match op {
+                StateOp::Insert { key, value } => self.insert_raw(&key, value),
+                StateOp::Delete { key } => self.delete(&key),
             };
-            witnesses.push(witness);
+
+            match result {
+                Ok(witness) => {
+                    witnesses.push(witness);
+                    pending.push((key, previous));
+                }
+                Err(e) => {
+                    self.undo(&witnesses, pending)?;
+                    return Err(e);
+                }
+            }
         }
 
         Ok(witnesses)
     }
 
-    /// Commit the current state (persist Merkle tree and increment transition index).
+    /// Revert applied operations, restoring the tree from their witnesses
+    /// and the buffered writes they replaced.
+    fn undo(
+        &mut self,
+        witnesses: &[UpdateWitness],
+        pending: Vec<(Vec<u8>, Option<PendingWrite>)>,
+    ) -> Result<(), StateError> {
+        for (witness, (key, previous)) in witnesses.iter().zip(pending).rev() {
+            match &witness.old_value {
+                Some(value) => self.tree.try_insert(&key, value.clone())?,
+                None => self.tree.try_delete(&key)?,
+            };
+            match previous {
+                Some(value) => self.pending.insert(key, value),
+                None => self.pending.remove(&key),
+            };
+        }
+        Ok(())
+    }
+
+    /// Discard all writes since the last commit and return the committed root.
+    pub fn rollback(&mut self) -> Result<Hash32, StateError> {
+        self.pending.clear();
+        self.tree.store_mut().clear_pending();
+        // The tree caches its root, so reopen it over the committed nodes
+        let store = self.tree.store().clone();
+        self.tree = SparseMerkleTree::with_store(store, self.hash_function())?;
+        Ok(self.root())
+    }
+
+    /// Compute the witnesses and new root of `ops` without changing the state.
+    ///
+    /// The result can be proven while the store keeps serving the committed
+    /// state, then applied with [`Self::commit_staged`]. Fails if there are
+    /// uncommitted writes.
+    pub fn stage(&mut self, ops: Vec<StateOp>) -> Result<StagedTransition, StateError> {
+        self.stage_after(std::iter::empty(), ops)
+    }
+
+    /// Compute the witnesses and new root of `ops` on top of transitions
+    /// that are staged but not committed yet.
+    ///
+    /// `previous` must continue the committed state in order, so the result
+    /// can be committed right after them. Lets transitions be proven while
+    /// earlier ones are still being proven. Fails with
+    /// [`StateError::StaleTransition`] if `previous` does not continue the
+    /// state; the state is left unchanged either way.
+    pub fn stage_after<'a>(
+        &mut self,
+        previous: impl IntoIterator<Item = &'a StagedTransition>,
+        ops: Vec<StateOp>,
+    ) -> Result<StagedTransition, StateError> {
+        let (sequence, (prev_root, result, new_root)) = self.with_staged(previous, |store| {
+            let prev_root = store.root();
+            let result = store.apply_batch(ops.clone());
+            (prev_root, result, store.root())
+        })?;
+
+        Ok(StagedTransition {
+            sequence,
+            prev_root,
+            new_root,
+            witnesses: result?,
+            ops,
+            journal: None,
+            outbox: None,
+        })
+    }
+
+    /// Run `f` on the state as it will be after the staged transitions in
+    /// `previous`, then discard every write.
+    ///
+    /// Returns the sequence the next transition will get together with the
+    /// result of `f`. Fails like [`Self::stage_after`].
+    pub fn with_staged<'a, T>(
+        &mut self,
+        previous: impl IntoIterator<Item = &'a StagedTransition>,
+        f: impl FnOnce(&mut Self) -> T,
+    ) -> Result<(u64, T), StateError> {
+        if !self.pending.is_empty() {
+            return Err(StateError::UncommittedWrites);
+        }
+
+        let mut sequence = self.transition_index + 1;
+        for staged in previous {
+            if staged.sequence != sequence || staged.prev_root != self.root() {
+                self.rollback()?;
+                return Err(StateError::StaleTransition {
+                    sequence: staged.sequence,
+                });
+            }
+            if let Err(e) = self.apply_batch(staged.ops.clone()) {
+                self.rollback()?;
+                return Err(e);
+            }
+            sequence += 1;
+        }
+
+        let result = f(self);
+        self.rollback()?;
+        Ok((sequence, result))
+    }
+
+    /// Apply and commit a staged transition.
+    ///
+    /// Fails with [`StateError::StaleTransition`] if the state changed since
+    /// it was staged; the state is left unchanged on any error.
+    pub fn commit_staged(&mut self, staged: StagedTransition) -> Result<Hash32, StateError> {
+        self.commit_staged_with(staged, KvBatch::default())
+    }
+
+    /// Apply and commit a staged transition together with its record.
+    ///
+    /// Fails like [`Self::commit_staged`], and also if the record does not
+    /// describe `staged`.
+    pub fn commit_staged_with_record(
+        &mut self,
+        staged: StagedTransition,
+        record: TransitionRecord,
+    ) -> Result<Hash32, StateError> {
+        if record.sequence != staged.sequence
+            || record.prev_root != staged.prev_root
+            || record.new_root != staged.new_root
+        {
+            return Err(StateError::StaleTransition {
+                sequence: staged.sequence,
+            });
+        }
+
+        let mut batch = KvBatch::default();
+        batch.insert(
+            TRANSITIONS_TREE,
+            record.sequence.to_be_bytes(),
+            record.encode()?,
+        );
+        self.commit_staged_with(staged, batch)
+    }
+
+    fn commit_staged_with(
+        &mut self,
+        staged: StagedTransition,
+        mut batch: KvBatch,
+    ) -> Result<Hash32, StateError> {
+        if !self.pending.is_empty()
+            || self.root() != staged.prev_root
+            || self.transition_index + 1 != staged.sequence
+        {
+            return Err(StateError::StaleTransition {
+                sequence: staged.sequence,
+            });
+        }
+
+        if let Some(journal) = staged.journal {
+            batch.insert(JOURNAL_TREE, staged.sequence.to_be_bytes(), journal);
+        }
+        if let Some(blob) = staged.outbox {
+            let entry = OutboxEntry::new(staged.sequence, blob);
+            batch.insert(OUTBOX_TREE, staged.sequence.to_be_bytes(), entry.encode()?);
+        }
+        self.apply_batch(staged.ops)?;
+        match self.commit_with(batch) {
+            Ok(root) => Ok(root),
+            Err(e) => {
+                self.rollback()?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Commit the current state (persist values and Merkle tree and
+    /// increment the transition index).
+    ///
+    /// Everything is written in one atomic backend batch, so a crash leaves
+    /// either the previous or the new state on disk.
     pub fn commit(&mut self) -> Result<Hash32, StateError> {
-        self.transition_index += 1;
+        self.commit_with(KvBatch::default())
+    }
 
-        // Persist Merkle tree
-        let tree_data = self.tree.serialize()?;
-        self.db.insert(b"__merkle_tree__", tree_data)?;
+    /// Commit the current state together with the extra writes in `batch`.
+    fn commit_with(&mut self, mut batch: KvBatch) -> Result<Hash32, StateError> {
+        let version = self.transition_index + 1;
 
-        // Persist transition index
-        self.db.insert(
-            b"__transition_index__",
-            &self.transition_index.to_le_bytes(),
-        )?;
+        let mut undo: UndoRecord = Vec::with_capacity(self.pending.len());
+        for key in self.pending.keys() {
+            undo.push((key.clone(), self.backend.get(VALUES_TREE, key)?));
+        }
+        let undo =
+            bincode::serialize(&undo).map_err(|e| StateError::Serialization(e.to_string()))?;
 
-        self.db.flush()?;
+        batch.insert(UNDO_LOG_TREE, version.to_be_bytes(), undo);
+        self.commit_as(version, batch)
+    }
+
+    /// Persist the pending writes, the tree and the transition index as
+    /// `version`, together with the extra writes in `batch`.
+    fn commit_as(&mut self, version: u64, mut batch: KvBatch) -> Result<Hash32, StateError> {
+        let hash_function = self.hash_function();
+        for (key, value) in &self.pending {
+            match value {
+                Some(value) => batch.insert(VALUES_TREE, key.as_slice(), value.as_slice()),
+                None => batch.remove(VALUES_TREE, key.as_slice()),
+            }
+            batch.insert(KEYS_TREE, hash_function.hash_key(key), key.as_slice());
+        }
+        batch.insert(META_TREE, TRANSITION_INDEX_KEY, version.to_le_bytes());
+
+        // The tree nodes changed since the last commit
+        self.tree.store().write_pending(&mut batch, version);
+        self.backend.apply(batch)?;
+
+        self.pending.clear();
+        self.tree.store_mut().clear_pending();
+        self.transition_index = version;
+        self.backend.flush()?;
 
         Ok(self.root())
     }
 
+    /// Export the committed state for a snapshot file.
+    ///
+    /// The root history covers every sequence whose state is stored. Fails if
+    /// there are uncommitted writes.
+    pub fn export(&self) -> Result<StateExport, StateError> {
+        if !self.pending.is_empty() {
+            return Err(StateError::UncommittedWrites);
+        }
+
+        // In key hash order, for the root to be computed while reading
+        let mut entries: Vec<KvEntry> = self.committed_values().collect::<Result<_, _>>()?;
+        let hash_function = self.hash_function();
+        entries.sort_by_cached_key(|(key, _)| hash_function.hash_key(key));
+        Ok(StateExport {
+            version: SNAPSHOT_VERSION,
+            hash_function: self.hash_function(),
+            transition_index: self.transition_index,
+            root: self.root(),
+            root_history: self.root_history()?,
+            entries,
+        })
+    }
+
+    /// Bootstrap an empty store from a snapshot.
+    ///
+    /// The snapshot is verified before anything is written, and the store
+    /// continues from its transition index. Earlier sequences cannot be
+    /// viewed or reverted to, but their roots are kept: the snapshot's root
+    /// history is stored as transition records (and its oldest entry as the
+    /// history base). Returns the imported root.
+    pub fn import(&mut self, export: &StateExport) -> Result<Hash32, StateError> {
+        if export.hash_function != self.hash_function() {
+            return Err(StateError::HashFunctionMismatch {
+                stored: self.hash_function(),
+                requested: export.hash_function,
+            });
+        }
+        if self.transition_index != 0 || !self.backend.is_empty(VALUES_TREE)? {
+            return Err(StateError::StoreNotEmpty);
+        }
+        if !self.pending.is_empty() {
+            return Err(StateError::UncommittedWrites);
+        }
+        export.verify()?;
+
+        for (key, value) in &export.entries {
+            if let Err(e) = self.insert_raw(key, value.clone()) {
+                self.rollback()?;
+                return Err(e);
+            }
+        }
+
+        let sequence = export.transition_index;
+        let mut batch = KvBatch::default();
+        batch.insert(META_TREE, BASE_SEQUENCE_KEY, sequence.to_le_bytes());
+        if let Some(base) = export.root_history.first() {
+            let base =
+                bincode::serialize(base).map_err(|e| StateError::Serialization(e.to_string()))?;
+            batch.insert(META_TREE, HISTORY_BASE_KEY, base);
+        }
+        for pair in export.root_history.windows(2) {
+            let [(_, prev_root, _), (sequence, root, celestia_height)] = pair else {
+                unreachable!("windows of two");
+            };
+            let mut record = TransitionRecord::new(*sequence, *prev_root, *root);
+            record.celestia_height = *celestia_height;
+            batch.insert(TRANSITIONS_TREE, sequence.to_be_bytes(), record.encode()?);
+        }
+        let root = self.commit_as(sequence, batch)?;
+        self.base_sequence = sequence;
+        Ok(root)
+    }
+
     /// Get a Merkle proof for a key.
-    pub fn get_proof(&self, key: &[u8]) -> MerkleProof {
-        self.tree.get_proof(key)
+    pub fn get_proof(&self, key: &[u8]) -> Result<MerkleProof, StateError> {
+        Ok(self.tree.try_get_proof(key)?)
+    }
+
+    /// Get a single proof covering several keys.
+    pub fn get_multi_proof<K: AsRef<[u8]>>(&self, keys: &[K]) -> Result<MultiProof, StateError> {
+        Ok(self.tree.try_get_multi_proof(keys)?)
+    }
+
+    /// Revert the state to how it was committed at `sequence`.
+    ///
+    /// Values are restored from the undo log, tree entries, transition
+    /// records, journal entries and queued blobs written after `sequence`
+    /// are dropped and the transition index is reset, all in one batch.
+    /// Only the versions being dropped are read. Returns the restored root.
+    pub fn revert_to(&mut self, sequence: u64) -> Result<Hash32, StateError> {
+        if sequence > self.transition_index {
+            return Err(StateError::UnknownSequence(sequence));
+        }
+        if !self.pending.is_empty() {
+            return Err(StateError::UncommittedWrites);
+        }
+
+        // Undo newest first, so the oldest record's values end up in the batch
+        let mut batch = KvBatch::default();
+        for version in (sequence + 1..=self.transition_index).rev() {
+            let data = self
+                .backend
+                .get(UNDO_LOG_TREE, &version.to_be_bytes())?
+                .ok_or(StateError::UndoUnavailable(version))?;
+            let undo: UndoRecord = bincode::deserialize(&data)
+                .map_err(|e| StateError::Serialization(e.to_string()))?;
+            for (key, value) in undo {
+                match value {
+                    Some(value) => batch.insert(VALUES_TREE, key, value),
+                    None => batch.remove(VALUES_TREE, key),
+                }
+            }
+            batch.remove(UNDO_LOG_TREE, version.to_be_bytes());
+            batch.remove(TRANSITIONS_TREE, version.to_be_bytes());
+            batch.remove(JOURNAL_TREE, version.to_be_bytes());
+            batch.remove(OUTBOX_TREE, version.to_be_bytes());
+        }
+        batch.insert(META_TREE, TRANSITION_INDEX_KEY, sequence.to_le_bytes());
+        self.tree
+            .store()
+            .write_truncate(&mut batch, sequence, self.transition_index)?;
+        self.backend.apply(batch)?;
+
+        self.transition_index = sequence;
+        self.backend.flush()?;
+        self.rollback()
     }
 
-    /// Iterate over all keys with a given prefix.
+    /// Drop the history before `sequence`, so the store stops growing with
+    /// it.
+    ///
+    /// Tree versions that only views of earlier sequences read and the undo
+    /// records up to `sequence` are removed. Afterwards `sequence` is the
+    /// oldest one that can be viewed ([`Self::at_sequence`]), reverted to
+    /// ([`Self::revert_to`]) or have its record updated. The roots of the
+    /// dropped sequences stay in the root history: those committed without
+    /// a record get one.
+    pub fn prune_to(&mut self, sequence: u64) -> Result<(), StateError> {
+        if sequence > self.transition_index {
+            return Err(StateError::UnknownSequence(sequence));
+        }
+        let base = self.base_sequence;
+        if sequence <= base {
+            return Ok(());
+        }
+
+        let mut batch = KvBatch::default();
+        if self.backend.get(META_TREE, HISTORY_BASE_KEY)?.is_none() {
+            let height = self
+                .transition_record(base)?
+                .and_then(|record| record.celestia_height);
+            let entry = (base, self.at_sequence(base)?.root(), height);
+            let entry =
+                bincode::serialize(&entry).map_err(|e| StateError::Serialization(e.to_string()))?;
+            batch.insert(META_TREE, HISTORY_BASE_KEY, entry);
+        }
+        for version in base + 1..=sequence {
+            if self.transition_record(version)?.is_none() {
+                let record = TransitionRecord::new(
+                    version,
+                    self.at_sequence(version - 1)?.root(),
+                    self.at_sequence(version)?.root(),
+                );
+                batch.insert(TRANSITIONS_TREE, version.to_be_bytes(), record.encode()?);
+            }
+            // The latest record is kept for the check on open
+            if version != self.transition_index {
+                batch.remove(UNDO_LOG_TREE, version.to_be_bytes());
+            }
+        }
+        self.tree.store().write_prune(&mut batch, base, sequence)?;
+        batch.insert(META_TREE, BASE_SEQUENCE_KEY, sequence.to_le_bytes());
+        self.backend.apply(batch)?;
+        self.backend.flush()?;
+
+        self.base_sequence = sequence;
+        Ok(())
+    }
+
+    /// Open a read-only view of the committed state as of `sequence`.
+    ///
+    /// Sequence `n` is the state after the `n`-th commit (0 is the empty
+    /// state). Fails for sequences that have not been committed yet, that
+    /// predate the snapshot an imported store was bootstrapped from, or that
+    /// were pruned.
+    pub fn at_sequence(&self, sequence: u64) -> Result<StateSnapshot, StateError> {
+        if sequence > self.transition_index || sequence < self.base_sequence {
+            return Err(StateError::UnknownSequence(sequence));
+        }
+
+        let store = self.tree.store().at_version(sequence);
+        let tree = SparseMerkleTree::with_store(store, self.hash_function())?;
+        Ok(StateSnapshot {
+            sequence,
+            tree,
+            backend: Arc::clone(&self.backend),
+        })
+    }
+
+    /// List the keys whose values differ between two committed sequences.
+    ///
+    /// Each change carries proofs of the old value against the `from` root
+    /// and of the new value against the `to` root.
+    pub fn diff(&self, from: u64, to: u64) -> Result<StateDiff, StateError> {
+        self.at_sequence(from)?
+            .diff(&self.at_sequence(to)?, None, usize::MAX)
+    }
+
+    /// Iterate over all keys with a given prefix (including uncommitted
+    /// writes).
     pub fn scan_prefix(&self, prefix: &[u8]) -> impl Iterator<Item = (Vec<u8>, Vec<u8>)> + '_ {
-        self.db
-            .scan_prefix(prefix)
-            .filter_map(|r| r.ok().map(|(k, v)| (k.to_vec(), v.to_vec())))
+        let mut entries: BTreeMap<Vec<u8>, Vec<u8>> = self
+            .backend
+            .scan_prefix(VALUES_TREE, prefix)
+            .filter_map(Result::ok)
+            .collect();
+
+        let pending = self
+            .pending
+            .range(prefix.to_vec()..)
+            .take_while(|(key, _)| key.starts_with(prefix));
+        for (key, value) in pending {
+            match value {
+                Some(value) => entries.insert(key.clone(), value.clone()),
+                None => entries.remove(key),
+            };
+        }
+
+        entries.into_iter()
+    }
+}
+
+impl Drop for StateStore {
+    /// Mark the shutdown as clean, so the next open skips the full
+    /// integrity check (uncommitted writes are discarded either way).
+    fn drop(&mut self) {
+        if !self.clean_on_drop {
+            return;
+        }
+        // On failure the next open just runs the full check
+        let _ = self
+            .backend
+            .insert(META_TREE, CLEAN_SHUTDOWN_KEY, &[])
+            .and_then(|()| self.backend.flush());
+    }
+}
+
+/// Whether `key` is reserved for store metadata and cannot hold state.
+pub fn is_reserved_key(key: &[u8]) -> bool {
+    RESERVED_KEYS.contains(&key)
+}
+
+/// Read a `u64` metadata value, defaulting to 0.
+fn read_u64(backend: &dyn KvBackend, key: &[u8]) -> Result<u64, StateError> {
+    Ok(match backend.get(META_TREE, key)? {
+        Some(data) => u64::from_le_bytes(data.as_slice().try_into().unwrap_or([0; 8])),
+        None => 0,
+    })
+}
+
+fn check_key(key: &[u8]) -> Result<(), StateError> {
+    if is_reserved_key(key) {
+        return Err(StateError::ReservedKey(
+            String::from_utf8_lossy(key).into_owned(),
+        ));
+    }
+    Ok(())
+}
+
+/// Read-only view of the state at a past sequence.
+///
+/// Values are read from the Merkle tree, so they always match the proofs.
+pub struct StateSnapshot {
+    /// Sequence this view reflects.
+    sequence: u64,
+    /// Merkle tree capped at that sequence.
+    tree: SparseMerkleTree<KvTreeStore>,
+    /// Backend holding the keys index.
+    backend: Arc<dyn KvBackend>,
+}
+
+impl StateSnapshot {
+    /// Get the sequence this view reflects.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Get the state root at this sequence.
+    pub fn root(&self) -> Hash32 {
+        self.tree.root()
+    }
+
+    /// Get a raw value by key.
+    pub fn get_raw(&self, key: &[u8]) -> Result<Option<Vec<u8>>, StateError> {
+        Ok(self.tree.try_get(key)?)
+    }
+
+    /// Get a value with its Merkle proof.
+    pub fn get_with_proof(&self, key: &[u8]) -> Result<(Option<Vec<u8>>, MerkleProof), StateError> {
+        let proof = self.tree.try_get_proof(key)?;
+        Ok((proof.value.clone(), proof))
+    }
+
+    /// Get a Merkle proof for a key.
+    pub fn get_proof(&self, key: &[u8]) -> Result<MerkleProof, StateError> {
+        Ok(self.tree.try_get_proof(key)?)
+    }
+
+    /// Get a single proof covering several keys.
+    pub fn get_multi_proof<K: AsRef<[u8]>>(&self, keys: &[K]) -> Result<MultiProof, StateError> {
+        Ok(self.tree.try_get_multi_proof(keys)?)
+    }
+
+    /// List the keys whose values differ between this sequence and
+    /// `other`'s, ordered by key hash: at most `limit` of those after key
+    /// hash `after`.
+    ///
+    /// Each change carries proofs of the old value against this root and
+    /// of the new value against `other`'s. The views read only versions
+    /// later commits leave alone, so no lock on the store is needed.
+    pub fn diff(
+        &self,
+        other: &StateSnapshot,
+        after: Option<&Hash32>,
+        limit: usize,
+    ) -> Result<StateDiff, StateError> {
+        let changes = self
+            .tree
+            .store()
+            .changed_keys(other.tree.store(), after, limit)?
+            .into_iter()
+            .map(|key_hash| {
+                Ok(KeyChange {
+                    key_hash,
+                    key: self.backend.get(KEYS_TREE, &key_hash)?,
+                    old_proof: self.tree.try_get_proof_by_hash(&key_hash)?,
+                    new_proof: other.tree.try_get_proof_by_hash(&key_hash)?,
+                })
+            })
+            .collect::<Result<_, StateError>>()?;
+
+        Ok(StateDiff {
+            from: self.sequence,
+            to: other.sequence,
+            from_root: self.root(),
+            to_root: other.root(),
+            changes,
+        })
+    }
+}
+
+/// A key whose value differs between two sequences.
+#[derive(Debug, Clone)]
+pub struct KeyChange {
+    /// Hash of the key (its path in the tree).
+    pub key_hash: Hash32,
+    /// The key, if the store has recorded it.
+    pub key: Option<Vec<u8>>,
+    /// Proof of the old value against the `from` root.
+    pub old_proof: MerkleProof,
+    /// Proof of the new value against the `to` root.
+    pub new_proof: MerkleProof,
+}
+
+impl KeyChange {
+    /// Value at the `from` sequence.
+    pub fn old_value(&self) -> Option<&[u8]> {
+        self.old_proof.value.as_deref()
+    }
+
+    /// Value at the `to` sequence.
+    pub fn new_value(&self) -> Option<&[u8]> {
+        self.new_proof.value.as_deref()
+    }
+}
+
+/// Keys changed between two sequences.
+#[derive(Debug, Clone)]
+pub struct StateDiff {
+    /// Starting sequence.
+    pub from: u64,
+    /// Ending sequence.
+    pub to: u64,
+    /// Root at `from`.
+    pub from_root: Hash32,
+    /// Root at `to`.
+    pub to_root: Hash32,
+    /// Changed keys, ordered by key hash.
+    pub changes: Vec<KeyChange>,
+}
+
+/// A transition whose witnesses and new root are known but which has not
+/// been applied to the state yet.
+#[derive(Debug, Clone)]
+pub struct StagedTransition {
+    /// Sequence the transition will be committed as.
+    pub sequence: u64,
+    /// Root the transition starts from.
+    pub prev_root: Hash32,
+    /// Root after the transition.
+    pub new_root: Hash32,
+    /// Witnesses of the operations, in order.
+    pub witnesses: Vec<UpdateWitness>,
+    /// The operations.
+    pub ops: Vec<StateOp>,
+    /// Encoded prover input, journaled with the commit so the transition
+    /// can be proven again later.
+    pub journal: Option<Vec<u8>>,
+    /// Encoded blob to queue for posting with the commit.
+    pub outbox: Option<Vec<u8>>,
+}
+
+impl StagedTransition {
+    /// Set the prover input to journal with the commit.
+    pub fn with_journal(mut self, input: Vec<u8>) -> Self {
+        self.journal = Some(input);
+        self
+    }
+
+    /// Set a blob to queue for posting with the commit.
+    pub fn with_outbox(mut self, blob: Vec<u8>) -> Self {
+        self.outbox = Some(blob);
+        self
     }
 }
 
@@ -214,6 +1247,13 @@ pub enum StateOp {
 }
 
 impl StateOp {
+    /// The key this operation writes.
+    pub fn key(&self) -> &[u8] {
+        match self {
+            StateOp::Insert { key, .. } | StateOp::Delete { key } => key,
+        }
+    }
+
     /// Create an insert operation.
     pub fn insert(key: impl Into<Vec<u8>>, value: impl Into<Vec<u8>>) -> Self {
         StateOp::Insert {
@@ -291,6 +1331,25 @@ mod tests {
         assert!(proof.verify(&root));
     }
 
+    #[test]
+    fn test_state_store_multi_proof() {
+        let mut store = StateStore::in_memory().unwrap();
+
+        store.insert_raw(b"key1", b"value1".to_vec()).unwrap();
+        store.insert_raw(b"key2", b"value2".to_vec()).unwrap();
+        store.commit().unwrap();
+
+        let proof = store
+            .get_multi_proof(&[&b"key1"[..], b"key2", b"key3"])
+            .unwrap();
+        assert!(proof.verify(&store.root()));
+        assert_eq!(
+            proof.value(&merkle::hash_key(b"key2")),
+            Some(Some(&b"value2"[..]))
+        );
+        assert_eq!(proof.value(&merkle::hash_key(b"key3")), Some(None));
+    }
+
     #[test]
     fn test_state_store_batch() {
         let mut store = StateStore::in_memory().unwrap();
@@ -326,4 +1385,569 @@ mod tests {
         let loaded: Account = store.get(b"account:alice").unwrap().unwrap();
         assert_eq!(loaded, account);
     }
+
+    #[test]
+    fn test_historical_snapshots() {
+        let mut store = StateStore::in_memory().unwrap();
+        let mut roots = vec![store.root()];
+
+        store.insert_raw(b"alice", b"100".to_vec()).unwrap();
+        roots.push(store.commit().unwrap());
+        store.insert_raw(b"alice", b"70".to_vec()).unwrap();
+        store.insert_raw(b"bob", b"30".to_vec()).unwrap();
+        roots.push(store.commit().unwrap());
+        store.delete(b"alice").unwrap();
+        roots.push(store.commit().unwrap());
+
+        // Uncommitted writes are not part of any snapshot
+        store.insert_raw(b"carol", b"5".to_vec()).unwrap();
+
+        let expected: [Option<&[u8]>; 4] = [None, Some(b"100"), Some(b"70"), None];
+        for (sequence, root) in roots.iter().enumerate() {
+            let snapshot = store.at_sequence(sequence as u64).unwrap();
+            assert_eq!(snapshot.sequence(), sequence as u64);
+            assert_eq!(snapshot.root(), *root);
+
+            let (value, proof) = snapshot.get_with_proof(b"alice").unwrap();
+            assert_eq!(value.as_deref(), expected[sequence]);
+            assert!(proof.verify(root));
+            assert_eq!(snapshot.get_raw(b"carol").unwrap(), None);
+        }
+
+        let snapshot = store.at_sequence(2).unwrap();
+        let proof = snapshot.get_multi_proof(&[&b"alice"[..], b"bob"]).unwrap();
+        assert!(proof.verify(&roots[2]));
+
+        assert!(matches!(
+            store.at_sequence(4),
+            Err(StateError::UnknownSequence(4))
+        ));
+    }
+
+    #[test]
+    fn test_state_diff() {
+        let mut store = StateStore::in_memory().unwrap();
+
+        store.insert_raw(b"alice", b"100".to_vec()).unwrap();
+        store.insert_raw(b"bob", b"5".to_vec()).unwrap();
+        store.commit().unwrap();
+        store.insert_raw(b"alice", b"70".to_vec()).unwrap();
+        store.insert_raw(b"carol", b"1".to_vec()).unwrap();
+        store.delete(b"bob").unwrap();
+        store.commit().unwrap();
+        store.insert_raw(b"alice", b"100".to_vec()).unwrap();
+        store.commit().unwrap();
+
+        let diff = store.diff(1, 2).unwrap();
+        assert_eq!(diff.from_root, store.at_sequence(1).unwrap().root());
+        assert_eq!(diff.to_root, store.at_sequence(2).unwrap().root());
+
+        let mut changes: Vec<_> = diff
+            .changes
+            .iter()
+            .map(|c| (c.key.clone().unwrap(), c.old_value(), c.new_value()))
+            .collect();
+        changes.sort();
+        assert_eq!(
+            changes,
+            vec![
+                (b"alice".to_vec(), Some(&b"100"[..]), Some(&b"70"[..])),
+                (b"bob".to_vec(), Some(&b"5"[..]), None),
+                (b"carol".to_vec(), None, Some(&b"1"[..])),
+            ]
+        );
+        for change in &diff.changes {
+            assert!(change.old_proof.verify(&diff.from_root));
+            assert!(change.new_proof.verify(&diff.to_root));
+        }
+
+        // alice is back to her old balance, so only bob and carol differ
+        let diff = store.diff(1, 3).unwrap();
+        let keys: Vec<_> = diff.changes.iter().filter_map(|c| c.key.clone()).collect();
+        assert_eq!(keys.len(), 2);
+        assert!(!keys.contains(&b"alice".to_vec()));
+
+        assert!(store.diff(2, 2).unwrap().changes.is_empty());
+
+        // Changes are listed in key hash order, a page at a time
+        let all = store.diff(1, 2).unwrap().changes;
+        let (old, new) = (store.at_sequence(1).unwrap(), store.at_sequence(2).unwrap());
+        let first = old.diff(&new, None, 2).unwrap().changes;
+        let rest = old.diff(&new, Some(&first[1].key_hash), 2).unwrap().changes;
+        let paged: Vec<_> = first.iter().chain(&rest).map(|c| c.key_hash).collect();
+        assert_eq!(paged, all.iter().map(|c| c.key_hash).collect::<Vec<_>>());
+        assert!(paged.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(rest.len(), 1);
+        assert!(matches!(
+            store.diff(0, 4),
+            Err(StateError::UnknownSequence(4))
+        ));
+    }
+
+    #[test]
+    fn test_staged_transitions() {
+        let mut store = StateStore::in_memory().unwrap();
+        store.insert_raw(b"alice", b"100".to_vec()).unwrap();
+        let root = store.commit().unwrap();
+
+        // Staging leaves the state untouched
+        let ops = TransitionBuilder::new()
+            .insert(b"alice", b"70")
+            .insert(b"bob", b"30")
+            .build();
+        let staged = store.stage(ops).unwrap();
+        assert_eq!(staged.sequence, 2);
+        assert_eq!(staged.prev_root, root);
+        assert_eq!(staged.witnesses.len(), 2);
+        assert_eq!(store.root(), root);
+        assert_eq!(store.get_raw(b"bob").unwrap(), None);
+
+        let new_root = store.commit_staged(staged.clone()).unwrap();
+        assert_eq!(new_root, staged.new_root);
+        assert_eq!(store.transition_index(), 2);
+        assert_eq!(store.get_raw(b"alice").unwrap(), Some(b"70".to_vec()));
+
+        // A transition staged against an older state is refused
+        assert!(matches!(
+            store.commit_staged(staged),
+            Err(StateError::StaleTransition { sequence: 2 })
+        ));
+        assert_eq!(store.root(), new_root);
+
+        store.insert_raw(b"carol", b"1".to_vec()).unwrap();
+        assert!(matches!(
+            store.stage(Vec::new()),
+            Err(StateError::UncommittedWrites)
+        ));
+        assert_eq!(store.rollback().unwrap(), new_root);
+        assert_eq!(store.get_raw(b"carol").unwrap(), None);
+        assert!(store.get_proof(b"carol").unwrap().verify(&new_root));
+    }
+
+    #[test]
+    fn test_stage_after() {
+        let mut store = StateStore::in_memory().unwrap();
+        let root = store.root();
+
+        // Stage a chain of transitions before committing any of them
+        let first = store
+            .stage(vec![StateOp::insert("alice", b"100".to_vec())])
+            .unwrap();
+        let second = store
+            .stage_after([&first], vec![StateOp::insert("alice", b"70".to_vec())])
+            .unwrap();
+        assert_eq!(second.sequence, 2);
+        assert_eq!(second.prev_root, first.new_root);
+        assert_eq!(store.root(), root);
+        assert_eq!(store.get_raw(b"alice").unwrap(), None);
+
+        // A chain that does not continue the state is refused
+        assert!(matches!(
+            store.stage_after([&second], Vec::new()),
+            Err(StateError::StaleTransition { sequence: 2 })
+        ));
+        assert_eq!(store.root(), root);
+
+        store.commit_staged(first).unwrap();
+        let new_root = store.commit_staged(second.clone()).unwrap();
+        assert_eq!(new_root, second.new_root);
+        assert_eq!(store.get_raw(b"alice").unwrap(), Some(b"70".to_vec()));
+    }
+
+    #[test]
+    fn test_revert_to() {
+        let mut store = StateStore::in_memory().unwrap();
+        let mut roots = vec![store.root()];
+
+        store.insert_raw(b"alice", b"100".to_vec()).unwrap();
+        roots.push(store.commit().unwrap());
+        store.insert_raw(b"alice", b"70".to_vec()).unwrap();
+        store.insert_raw(b"bob", b"30".to_vec()).unwrap();
+        roots.push(store.commit().unwrap());
+        store.insert_raw(b"alice", b"50".to_vec()).unwrap();
+        store.delete(b"bob").unwrap();
+        roots.push(store.commit().unwrap());
+
+        assert!(matches!(
+            store.revert_to(4),
+            Err(StateError::UnknownSequence(4))
+        ));
+
+        assert_eq!(store.revert_to(1).unwrap(), roots[1]);
+        assert_eq!(store.transition_index(), 1);
+        assert_eq!(store.get_raw(b"alice").unwrap(), Some(b"100".to_vec()));
+        assert_eq!(store.get_raw(b"bob").unwrap(), None);
+        assert!(store.get_proof(b"alice").unwrap().verify(&roots[1]));
+        store.check_integrity().unwrap();
+        assert!(matches!(
+            store.at_sequence(2),
+            Err(StateError::UnknownSequence(2))
+        ));
+
+        // Sequences after the revert point are written afresh
+        store.insert_raw(b"carol", b"1".to_vec()).unwrap();
+        let root = store.commit().unwrap();
+        assert_eq!(store.at_sequence(2).unwrap().root(), root);
+        assert_eq!(store.at_sequence(2).unwrap().get_raw(b"bob").unwrap(), None);
+
+        assert_eq!(store.revert_to(0).unwrap(), roots[0]);
+        assert_eq!(store.scan_prefix(b"").count(), 0);
+    }
+
+    #[test]
+    fn test_prune_to() {
+        let backend = MemoryBackend::new();
+        let mut store = StateStore::with_backend(backend.clone(), None).unwrap();
+        let mut roots = vec![store.root()];
+        for i in 0..6u8 {
+            store.insert_raw(b"alice", vec![i]).unwrap();
+            if i == 2 {
+                store.delete(b"bob").unwrap();
+            } else {
+                store.insert_raw(b"bob", vec![i]).unwrap();
+            }
+            roots.push(store.commit().unwrap());
+        }
+        let history = store.root_history().unwrap();
+        let leaf_versions = || backend.scan_prefix(b"smt_leaves", &[]).count();
+        let before = leaf_versions();
+
+        store.prune_to(4).unwrap();
+        assert!(leaf_versions() < before);
+        assert_eq!(store.root_history().unwrap(), history);
+        store.check_integrity().unwrap();
+
+        // Only sequences from the pruning point on can be viewed
+        assert!(matches!(
+            store.at_sequence(3),
+            Err(StateError::UnknownSequence(3))
+        ));
+        let snapshot = store.at_sequence(4).unwrap();
+        assert_eq!(snapshot.root(), roots[4]);
+        assert_eq!(snapshot.get_raw(b"alice").unwrap(), Some(vec![3]));
+        assert!(matches!(
+            store.revert_to(3),
+            Err(StateError::UndoUnavailable(4))
+        ));
+
+        // and reverted to
+        assert_eq!(store.revert_to(4).unwrap(), roots[4]);
+        assert_eq!(store.get_raw(b"bob").unwrap(), Some(vec![3]));
+        store.check_integrity().unwrap();
+        assert_eq!(store.root_history().unwrap(), history[..5]);
+
+        // Pruning is kept across reopening
+        drop(store);
+        let mut store = StateStore::with_backend(backend.clone(), None).unwrap();
+        assert!(store.at_sequence(3).is_err());
+        store.prune_to(4).unwrap();
+        assert!(matches!(
+            store.prune_to(5),
+            Err(StateError::UnknownSequence(5))
+        ));
+    }
+
+    /// Temporary on-disk path for reopen tests.
+    fn temp_db_path(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("state-test-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        path
+    }
+
+    /// Reopen a store, waiting for sled's background threads to release the
+    /// previous instance's file lock.
+    fn reopen_with<T>(open: impl Fn() -> Result<T, StateError>) -> Result<T, StateError> {
+        for _ in 0..50 {
+            match open() {
+                Err(StateError::Backend(_)) => {
+                    std::thread::sleep(std::time::Duration::from_millis(20))
+                }
+                result => return result,
+            }
+        }
+        open()
+    }
+
+    fn reopen(path: &std::path::Path) -> StateStore {
+        reopen_with(|| StateStore::open(path)).unwrap()
+    }
+
+    #[test]
+    fn test_reopen_restores_tree() {
+        let path = temp_db_path("reopen");
+
+        let (root, index) = {
+            let mut store = StateStore::open(&path).unwrap();
+            store.insert_raw(b"key1", b"value1".to_vec()).unwrap();
+            store.commit().unwrap();
+            store.insert_raw(b"key2", b"value2".to_vec()).unwrap();
+            store.delete(b"key1").unwrap();
+            store.commit().unwrap();
+            (store.root(), store.transition_index())
+        };
+
+        let store = reopen(&path);
+        assert_eq!(store.root(), root);
+        assert_eq!(store.transition_index(), index);
+
+        let (value, proof) = store.get_with_proof(b"key2").unwrap();
+        assert_eq!(value, Some(b"value2".to_vec()));
+        assert!(proof.verify(&root));
+
+        let (value, proof) = store.get_with_proof(b"key1").unwrap();
+        assert_eq!(value, None);
+        assert!(proof.verify(&root));
+
+        drop(store);
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn test_memory_backend_reopen() {
+        let backend = MemoryBackend::new();
+
+        let mut store =
+            StateStore::with_backend(backend.clone(), Some(HashFunction::Blake3)).unwrap();
+        store.insert_raw(b"key1", b"value1".to_vec()).unwrap();
+        let root = store.commit().unwrap();
+        store.insert_raw(b"key2", b"value2".to_vec()).unwrap();
+        drop(store);
+
+        // Clones share data; uncommitted writes are gone
+        let store = StateStore::with_backend(backend, None).unwrap();
+        assert_eq!(store.root(), root);
+        assert_eq!(store.transition_index(), 1);
+        assert_eq!(store.hash_function(), HashFunction::Blake3);
+        assert_eq!(store.get_raw(b"key1").unwrap(), Some(b"value1".to_vec()));
+        assert_eq!(store.get_raw(b"key2").unwrap(), None);
+    }
+
+    #[test]
+    fn test_reopen_keeps_hash_function() {
+        let path = temp_db_path("hasher");
+
+        let root = {
+            let mut store = StateStore::open_with_hasher(&path, HashFunction::Blake3).unwrap();
+            store.insert_raw(b"key1", b"value1".to_vec()).unwrap();
+            store.commit().unwrap()
+        };
+
+        let store = reopen(&path);
+        assert_eq!(store.hash_function(), HashFunction::Blake3);
+        assert_eq!(store.root(), root);
+        assert!(store.get_proof(b"key1").unwrap().verify(&root));
+        drop(store);
+
+        // Reopening with a different hash function is refused
+        let result = reopen_with(|| StateStore::open_with_hasher(&path, HashFunction::Sha256));
+        assert!(matches!(
+            result,
+            Err(StateError::HashFunctionMismatch {
+                stored: HashFunction::Blake3,
+                requested: HashFunction::Sha256,
+            })
+        ));
+
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn test_uncommitted_writes_not_persisted() {
+        let path = temp_db_path("uncommitted");
+
+        let root = {
+            let mut store = StateStore::open(&path).unwrap();
+            store.insert_raw(b"key1", b"value1".to_vec()).unwrap();
+            let root = store.commit().unwrap();
+            store.insert_raw(b"key2", b"value2".to_vec()).unwrap();
+            store.delete(b"key1").unwrap();
+            assert_eq!(store.get_raw(b"key2").unwrap(), Some(b"value2".to_vec()));
+            assert_eq!(store.scan_prefix(b"key").count(), 1);
+            root
+        };
+
+        let store = reopen(&path);
+        assert_eq!(store.root(), root);
+        assert_eq!(store.transition_index(), 1);
+        assert_eq!(store.get_raw(b"key1").unwrap(), Some(b"value1".to_vec()));
+        assert_eq!(store.get_raw(b"key2").unwrap(), None);
+
+        drop(store);
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn test_integrity_check_and_repair() {
+        let path = temp_db_path("integrity");
+
+        let root = {
+            let mut store = StateStore::open(&path).unwrap();
+            store.insert_raw(b"alice", b"100".to_vec()).unwrap();
+            store.insert_raw(b"bob", b"5".to_vec()).unwrap();
+            store.commit().unwrap()
+        };
+
+        // Values written behind the tree's back
+        {
+            let db = reopen_with(|| Ok(sled::open(&path)?)).unwrap();
+            db.insert(b"alice", b"999").unwrap();
+            db.remove(b"bob").unwrap();
+            db.insert(b"mallory", b"1").unwrap();
+            db.flush().unwrap();
+        }
+
+        // Opening checks the keys of the latest commit
+        let result = reopen_with(|| StateStore::open(&path));
+        assert!(matches!(
+            result,
+            Err(StateError::ValueMismatch { sequence: 1, .. })
+        ));
+
+        let store = reopen_with(|| StateStore::open_repaired(&path, HashFunction::Sha256)).unwrap();
+        assert_eq!(store.root(), root);
+        assert_eq!(store.get_raw(b"alice").unwrap(), Some(b"100".to_vec()));
+        assert_eq!(store.get_raw(b"bob").unwrap(), Some(b"5".to_vec()));
+        assert_eq!(store.get_raw(b"mallory").unwrap(), None);
+        drop(store);
+
+        let root = {
+            let mut store = reopen(&path);
+            assert_eq!(store.root(), root);
+            store.insert_raw(b"carol", b"1".to_vec()).unwrap();
+            store.commit().unwrap()
+        };
+
+        // Older writes are only caught by the full check
+        {
+            let db = reopen_with(|| Ok(sled::open(&path)?)).unwrap();
+            db.insert(b"alice", b"999").unwrap();
+            db.flush().unwrap();
+        }
+
+        let store = reopen(&path);
+        assert!(matches!(
+            store.check_integrity(),
+            Err(StateError::IntegrityMismatch { committed, .. }) if committed == root
+        ));
+        drop(store);
+
+        // A value missing from the keys index is caught before the root
+        {
+            let db = reopen_with(|| Ok(sled::open(&path)?)).unwrap();
+            db.insert(b"trent", b"1").unwrap();
+            db.flush().unwrap();
+        }
+
+        let store = reopen(&path);
+        assert!(matches!(
+            store.check_integrity(),
+            Err(StateError::ValueMismatch { sequence: 2, .. })
+        ));
+
+        drop(store);
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn test_unclean_shutdown_runs_full_check() {
+        let backend = MemoryBackend::new();
+
+        let mut store = StateStore::with_backend(backend.clone(), None).unwrap();
+        store.insert_raw(b"alice", b"100".to_vec()).unwrap();
+        store.commit().unwrap();
+        store.insert_raw(b"bob", b"5".to_vec()).unwrap();
+        let root = store.commit().unwrap();
+        drop(store);
+
+        // After a clean shutdown only the latest commit's keys are compared
+        backend.insert(VALUES_TREE, b"alice", b"999").unwrap();
+        let store = StateStore::with_backend(backend.clone(), None).unwrap();
+        assert_eq!(store.root(), root);
+
+        // A store that was not dropped gets the full check
+        std::mem::forget(store);
+        assert!(matches!(
+            StateStore::with_backend(backend.clone(), None),
+            Err(StateError::IntegrityMismatch { committed, .. }) if committed == root
+        ));
+
+        // and a refused store stays unclean
+        assert!(StateStore::with_backend(backend, None).is_err());
+    }
+
+    #[test]
+    fn test_reserved_keys_rejected() {
+        let mut store = StateStore::in_memory().unwrap();
+
+        for key in RESERVED_KEYS {
+            assert!(matches!(
+                store.insert_raw(key, b"1".to_vec()),
+                Err(StateError::ReservedKey(_))
+            ));
+            assert!(matches!(store.delete(key), Err(StateError::ReservedKey(_))));
+        }
+        assert!(matches!(
+            store.apply_batch(vec![
+                StateOp::insert(b"alice", b"100"),
+                StateOp::insert(b"__transition_index__", b"0"),
+            ]),
+            Err(StateError::ReservedKey(_))
+        ));
+        assert_eq!(store.get_raw(b"alice").unwrap(), None);
+
+        // Metadata is not part of the user keyspace
+        store
+            .insert_raw(b"transition_index", b"1".to_vec())
+            .unwrap();
+        store.commit().unwrap();
+        assert_eq!(store.transition_index(), 1);
+        assert_eq!(store.scan_prefix(b"").count(), 1);
+    }
+
+    #[test]
+    fn test_migrates_legacy_metadata() {
+        let path = temp_db_path("legacy-meta");
+        {
+            let db = sled::open(&path).unwrap();
+            db.insert(b"__transition_index__", &5u64.to_le_bytes())
+                .unwrap();
+            db.flush().unwrap();
+        }
+
+        let store = reopen(&path);
+        assert_eq!(store.transition_index(), 5);
+        assert_eq!(store.scan_prefix(b"").count(), 0);
+        drop(store);
+
+        let store = reopen(&path);
+        assert_eq!(store.transition_index(), 5);
+
+        drop(store);
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn test_migrates_legacy_tree() {
+        let path = temp_db_path("legacy");
+
+        let mut legacy = SparseMerkleTree::new();
+        legacy.insert(b"key1", b"value1".to_vec());
+        legacy.insert(b"key2", b"value2".to_vec());
+        {
+            let db = sled::open(&path).unwrap();
+            db.insert(b"key1", b"value1").unwrap();
+            db.insert(b"key2", b"value2").unwrap();
+            db.insert(LEGACY_TREE_KEY, legacy.serialize().unwrap())
+                .unwrap();
+            db.flush().unwrap();
+        }
+
+        let store = reopen(&path);
+        assert_eq!(store.root(), legacy.root());
+        assert!(store.get_raw(LEGACY_TREE_KEY).unwrap().is_none());
+        assert!(store.get_proof(b"key1").unwrap().verify(&legacy.root()));
+
+        drop(store);
+        std::fs::remove_dir_all(&path).unwrap();
+    }
 }
@@ -0,0 +1,159 @@
+//! Durable queue of encoded transition blobs waiting to be posted to DA.
+//!
+//! A blob that could not be posted before its transition was committed is
+//! queued in the same batch as the commit, so a committed transition is
+//! never lost to a failed submission. Entries are keyed by sequence and
+//! leave the queue in the same batch that records where they were posted.
+
+use crate::history::TRANSITIONS_TREE;
+use crate::{KvBatch, StateError, StateStore, TransitionRecord};
+use serde::{Deserialize, Serialize};
+
+/// Name of the backend tree holding queued blobs (keyed by sequence,
+/// big-endian).
+pub(crate) const OUTBOX_TREE: &[u8] = b"state_outbox";
+
+/// A transition blob waiting to be posted.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutboxEntry {
+    /// Sequence of the transition.
+    pub sequence: u64,
+    /// Encoded blob.
+    pub blob: Vec<u8>,
+    /// Failed submission attempts so far.
+    pub attempts: u32,
+    /// Error of the last failed attempt.
+    pub last_error: Option<String>,
+}
+
+impl OutboxEntry {
+    /// Create an entry that has not been attempted yet.
+    pub fn new(sequence: u64, blob: Vec<u8>) -> Self {
+        Self {
+            sequence,
+            blob,
+            attempts: 0,
+            last_error: None,
+        }
+    }
+
+    /// Record a failed submission attempt.
+    pub fn failed(mut self, error: impl Into<String>) -> Self {
+        self.attempts += 1;
+        self.last_error = Some(error.into());
+        self
+    }
+
+    pub(crate) fn encode(&self) -> Result<Vec<u8>, StateError> {
+        bincode::serialize(self).map_err(|e| StateError::Serialization(e.to_string()))
+    }
+
+    fn decode(data: &[u8]) -> Result<Self, StateError> {
+        bincode::deserialize(data).map_err(|e| StateError::Serialization(e.to_string()))
+    }
+}
+
+impl StateStore {
+    /// Get all queued blobs, oldest first.
+    pub fn outbox_entries(&self) -> Result<Vec<OutboxEntry>, StateError> {
+        self.backend
+            .scan_prefix(OUTBOX_TREE, &[])
+            .map(|entry| OutboxEntry::decode(&entry?.1))
+            .collect()
+    }
+
+    /// Get the queued blob of `sequence`.
+    pub fn outbox_entry(&self, sequence: u64) -> Result<Option<OutboxEntry>, StateError> {
+        self.backend
+            .get(OUTBOX_TREE, &sequence.to_be_bytes())?
+            .map(|data| OutboxEntry::decode(&data))
+            .transpose()
+    }
+
+    /// Whether no blobs are queued.
+    pub fn outbox_is_empty(&self) -> Result<bool, StateError> {
+        self.backend.is_empty(OUTBOX_TREE)
+    }
+
+    /// Replace a queued entry (e.g. after a failed attempt).
+    ///
+    /// Fails if no entry is queued for its sequence, so an entry dropped by
+    /// a revert is not queued again.
+    pub fn update_outbox_entry(&mut self, entry: &OutboxEntry) -> Result<(), StateError> {
+        let key = entry.sequence.to_be_bytes();
+        if self.backend.get(OUTBOX_TREE, &key)?.is_none() {
+            return Err(StateError::UnknownSequence(entry.sequence));
+        }
+
+        let mut batch = KvBatch::default();
+        batch.insert(OUTBOX_TREE, key, entry.encode()?);
+        self.backend.apply(batch)?;
+        self.backend.flush()
+    }
+
+    /// Replace the record of a transition whose queued blob has been posted
+    /// and drop the blob from the queue, in one batch.
+    ///
+    /// Fails like [`Self::update_transition_record`].
+    pub fn complete_outbox_entry(&mut self, record: TransitionRecord) -> Result<(), StateError> {
+        self.check_record(&record)?;
+
+        let key = record.sequence.to_be_bytes();
+        let mut batch = KvBatch::default();
+        batch.insert(TRANSITIONS_TREE, key, record.encode()?);
+        batch.remove(OUTBOX_TREE, key);
+        self.backend.apply(batch)?;
+        self.backend.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StateOp;
+
+    #[test]
+    fn test_outbox() {
+        let mut store = StateStore::in_memory().unwrap();
+
+        for (sequence, key) in [(1u64, "a"), (2, "b")] {
+            let staged = store
+                .stage(vec![StateOp::insert(key, b"1".to_vec())])
+                .unwrap()
+                .with_outbox(vec![sequence as u8]);
+            let record = TransitionRecord::new(staged.sequence, staged.prev_root, staged.new_root);
+            store.commit_staged_with_record(staged, record).unwrap();
+        }
+        assert_eq!(
+            store.outbox_entries().unwrap(),
+            vec![OutboxEntry::new(1, vec![1]), OutboxEntry::new(2, vec![2])]
+        );
+
+        // Failed attempts are kept with the entry
+        let failed = OutboxEntry::new(1, vec![1]).failed("timeout");
+        store.update_outbox_entry(&failed).unwrap();
+        assert_eq!(store.outbox_entries().unwrap()[0].attempts, 1);
+
+        // Posting records the height and dequeues the blob
+        let record = store
+            .transition_record(1)
+            .unwrap()
+            .unwrap()
+            .with_celestia(7, vec![9]);
+        store.complete_outbox_entry(record).unwrap();
+        assert_eq!(
+            store.transition_record(1).unwrap().unwrap().celestia_height,
+            Some(7)
+        );
+        assert_eq!(
+            store.outbox_entries().unwrap(),
+            vec![OutboxEntry::new(2, vec![2])]
+        );
+        assert!(store.update_outbox_entry(&failed).is_err());
+        assert_eq!(store.outbox_entry(1).unwrap(), None);
+
+        // Reverted transitions are not posted
+        store.revert_to(1).unwrap();
+        assert!(store.outbox_is_empty().unwrap());
+    }
+}
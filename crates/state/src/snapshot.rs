@@ -0,0 +1,356 @@
+//! Snapshot files for bootstrapping a store from another node's state.
+//!
+//! A snapshot holds every committed key/value pair together with the root,
+//! transition index, hash function and root history it was taken at. The
+//! file layout is:
+//!
+//! - `SNAPSHOT_MAGIC` (8 bytes)
+//! - bincode-encoded header: version byte, hash function, transition index,
+//!   root, root history and entry count
+//! - each bincode-encoded (key, value) entry in turn, ordered by key hash
+//! - SHA-256 checksum of everything after the magic (32 bytes)
+//!
+//! Files are written and read one entry at a time.
+
+use crate::StateError;
+use merkle::{Hash32, HashFunction, RootBuilder, TreeHasher};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+/// Current version of the snapshot format.
+pub const SNAPSHOT_VERSION: u8 = 1;
+
+/// Leading bytes of a snapshot file.
+const SNAPSHOT_MAGIC: &[u8; 8] = b"ZKDASNAP";
+
+/// Length of the trailing checksum.
+const CHECKSUM_LEN: usize = 32;
+
+/// Committed state of a store, as written to a snapshot file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateExport {
+    /// Format version (must be [`SNAPSHOT_VERSION`]).
+    pub version: u8,
+    /// Hash function of the state tree.
+    pub hash_function: HashFunction,
+    /// Transition index the snapshot was taken at.
+    pub transition_index: u64,
+    /// State root at that index.
+    pub root: Hash32,
+    /// Known roots as (sequence, root, celestia height), oldest first.
+    pub root_history: Vec<(u64, Hash32, Option<u64>)>,
+    /// All committed key/value pairs, ordered by key hash.
+    pub entries: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl StateExport {
+    /// Encode the snapshot file contents.
+    pub fn encode(&self) -> Result<Vec<u8>, StateError> {
+        let mut bytes = Vec::new();
+        self.write(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Decode snapshot file contents, checking the magic, version and
+    /// checksum.
+    pub fn decode(bytes: &[u8]) -> Result<Self, StateError> {
+        Self::read(bytes)
+    }
+
+    /// Write the snapshot to a file.
+    pub fn write_to(&self, path: impl AsRef<Path>) -> Result<(), StateError> {
+        let mut out = BufWriter::new(File::create(path)?);
+        self.write(&mut out)?;
+        out.flush()?;
+        Ok(())
+    }
+
+    /// Read a snapshot from a file.
+    pub fn read_from(path: impl AsRef<Path>) -> Result<Self, StateError> {
+        Self::read(BufReader::new(File::open(path)?))
+    }
+
+    /// Write the snapshot to `out`, one entry at a time.
+    fn write(&self, out: impl Write) -> Result<(), StateError> {
+        let mut out = Checksummed::new(out);
+        out.inner.write_all(SNAPSHOT_MAGIC)?;
+
+        put(&mut out, &self.version)?;
+        put(&mut out, &self.hash_function)?;
+        put(&mut out, &self.transition_index)?;
+        put(&mut out, &self.root)?;
+        put(&mut out, &self.root_history)?;
+        put(&mut out, &(self.entries.len() as u64))?;
+        for entry in &self.entries {
+            put(&mut out, entry)?;
+        }
+
+        let Checksummed { mut inner, hasher } = out;
+        inner.write_all(&hasher.finalize())?;
+        Ok(())
+    }
+
+    /// Read a snapshot from `input`, one entry at a time.
+    fn read(mut input: impl Read) -> Result<Self, StateError> {
+        let mut magic = [0u8; SNAPSHOT_MAGIC.len()];
+        if input.read_exact(&mut magic).is_err() || magic != *SNAPSHOT_MAGIC {
+            return Err(StateError::InvalidSnapshot(
+                "not a snapshot file".to_string(),
+            ));
+        }
+        let mut input = Checksummed::new(input);
+
+        // Check the leading version byte first: other layouts won't parse
+        let version: u8 = take(&mut input)?;
+        if version != SNAPSHOT_VERSION {
+            return Err(StateError::InvalidSnapshot(format!(
+                "unsupported version {}, expected {}",
+                version, SNAPSHOT_VERSION
+            )));
+        }
+
+        let hash_function = take(&mut input)?;
+        let transition_index = take(&mut input)?;
+        let root = take(&mut input)?;
+        let root_history = take(&mut input)?;
+        let count: u64 = take(&mut input)?;
+        // The count is not trusted to size the entries up front
+        let mut entries = Vec::new();
+        for _ in 0..count {
+            entries.push(take(&mut input)?);
+        }
+
+        let Checksummed { mut inner, hasher } = input;
+        let mut checksum = [0u8; CHECKSUM_LEN];
+        inner
+            .read_exact(&mut checksum)
+            .map_err(|_| StateError::InvalidSnapshot("truncated file".to_string()))?;
+        if hasher.finalize()[..] != checksum {
+            return Err(StateError::InvalidSnapshot("checksum mismatch".to_string()));
+        }
+        if inner.read(&mut [0u8; 1])? != 0 {
+            return Err(StateError::InvalidSnapshot(
+                "trailing bytes after the checksum".to_string(),
+            ));
+        }
+
+        Ok(Self {
+            version,
+            hash_function,
+            transition_index,
+            root,
+            root_history,
+            entries,
+        })
+    }
+
+    /// Check that the entries give the recorded root and that the root
+    /// history has consecutive sequences ending at it.
+    ///
+    /// The root is computed like [`crate::StateStore::check_integrity`],
+    /// streaming the entries in key hash order so only one tree path is
+    /// kept in memory.
+    pub fn verify(&self) -> Result<(), StateError> {
+        let mut root = RootBuilder::new(self.hash_function);
+        for (key, value) in &self.entries {
+            root.push(self.hash_function.hash_key(key), value)
+                .map_err(|_| {
+                    StateError::InvalidSnapshot("entries are not ordered by key hash".to_string())
+                })?;
+        }
+
+        let computed = root.finish();
+        if computed != self.root {
+            return Err(StateError::SnapshotRootMismatch {
+                expected: self.root,
+                computed,
+            });
+        }
+
+        let consecutive = self
+            .root_history
+            .windows(2)
+            .all(|pair| pair[0].0.checked_add(1) == Some(pair[1].0));
+        if !consecutive {
+            return Err(StateError::InvalidSnapshot(
+                "root history sequences are not consecutive".to_string(),
+            ));
+        }
+
+        match self.root_history.last() {
+            Some((sequence, root, _))
+                if *sequence == self.transition_index && *root == self.root =>
+            {
+                Ok(())
+            }
+            _ => Err(StateError::InvalidSnapshot(
+                "root history does not end at the snapshot root".to_string(),
+            )),
+        }
+    }
+}
+
+/// Encode `value` to `out`.
+fn put(out: &mut impl Write, value: &impl Serialize) -> Result<(), StateError> {
+    bincode::serialize_into(out, value).map_err(|e| StateError::Serialization(e.to_string()))
+}
+
+/// Decode the next value from `input`.
+fn take<T: DeserializeOwned>(input: &mut impl Read) -> Result<T, StateError> {
+    bincode::deserialize_from(input).map_err(|e| match *e {
+        bincode::ErrorKind::Io(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+            StateError::InvalidSnapshot("truncated file".to_string())
+        }
+        bincode::ErrorKind::Io(e) => StateError::Io(e),
+        e => StateError::InvalidSnapshot(e.to_string()),
+    })
+}
+
+/// Reader or writer hashing the bytes passed through it for the checksum.
+struct Checksummed<T> {
+    inner: T,
+    hasher: Sha256,
+}
+
+impl<T> Checksummed<T> {
+    fn new(inner: T) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+}
+
+impl<T: Write> Write for Checksummed<T> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<T: Read> Read for Checksummed<T> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StateStore;
+
+    fn source_store() -> StateStore {
+        let mut store = StateStore::in_memory_with_hasher(HashFunction::Blake3).unwrap();
+        store.insert_raw(b"alice", b"100".to_vec()).unwrap();
+        store.insert_raw(b"bob", b"50".to_vec()).unwrap();
+        store.commit().unwrap();
+        store.insert_raw(b"carol", b"25".to_vec()).unwrap();
+        store.delete(b"bob").unwrap();
+        store.commit().unwrap();
+        store
+    }
+
+    #[test]
+    fn test_export_import() {
+        let source = source_store();
+        let export = source.export().unwrap();
+        assert_eq!(export.transition_index, 2);
+        assert_eq!(export.root_history.len(), 3);
+        assert_eq!(export.entries.len(), 2);
+
+        let decoded = StateExport::decode(&export.encode().unwrap()).unwrap();
+        assert_eq!(decoded, export);
+
+        let mut replica = StateStore::in_memory_with_hasher(HashFunction::Blake3).unwrap();
+        assert_eq!(replica.import(&decoded).unwrap(), source.root());
+        assert_eq!(replica.transition_index(), 2);
+        assert_eq!(replica.get_raw(b"alice").unwrap(), Some(b"100".to_vec()));
+        assert_eq!(replica.get_raw(b"bob").unwrap(), None);
+
+        // State before the snapshot is not available on the replica, its
+        // roots are
+        assert!(replica.at_sequence(1).is_err());
+        assert_eq!(replica.at_sequence(2).unwrap().root(), source.root());
+        assert_eq!(
+            replica.root_history().unwrap(),
+            source.root_history().unwrap()
+        );
+
+        // The replica continues from the snapshot
+        replica.insert_raw(b"dave", b"1".to_vec()).unwrap();
+        let root = replica.commit().unwrap();
+        assert_eq!(replica.transition_index(), 3);
+        let mut history = source.root_history().unwrap();
+        history.push((3, root, None));
+        assert_eq!(replica.export().unwrap().root_history, history);
+
+        // and its snapshots carry the whole history on
+        let mut second = StateStore::in_memory_with_hasher(HashFunction::Blake3).unwrap();
+        second.import(&replica.export().unwrap()).unwrap();
+        assert_eq!(second.root_history().unwrap(), history);
+    }
+
+    #[test]
+    fn test_import_rejects_bad_snapshots() {
+        let export = source_store().export().unwrap();
+
+        // Corrupted file
+        let mut bytes = export.encode().unwrap();
+        let last = bytes.len() - CHECKSUM_LEN - 1;
+        bytes[last] ^= 0xff;
+        assert!(matches!(
+            StateExport::decode(&bytes),
+            Err(StateError::InvalidSnapshot(_))
+        ));
+
+        // Truncated file
+        let bytes = export.encode().unwrap();
+        assert!(matches!(
+            StateExport::decode(&bytes[..bytes.len() - CHECKSUM_LEN - 1]),
+            Err(StateError::InvalidSnapshot(_))
+        ));
+
+        // Entries out of key hash order
+        let mut unordered = export.clone();
+        unordered.entries.reverse();
+        assert!(matches!(
+            unordered.verify(),
+            Err(StateError::InvalidSnapshot(_))
+        ));
+
+        // Entries that do not give the root
+        let mut tampered = export.clone();
+        tampered.entries[0].1 = b"1000".to_vec();
+        let mut replica = StateStore::in_memory_with_hasher(HashFunction::Blake3).unwrap();
+        assert!(matches!(
+            replica.import(&tampered),
+            Err(StateError::SnapshotRootMismatch { .. })
+        ));
+        assert_eq!(replica.transition_index(), 0);
+
+        // Only empty stores can be bootstrapped
+        let mut store = StateStore::in_memory_with_hasher(HashFunction::Blake3).unwrap();
+        store.insert_raw(b"key", b"value".to_vec()).unwrap();
+        store.commit().unwrap();
+        assert!(matches!(
+            store.import(&export),
+            Err(StateError::StoreNotEmpty)
+        ));
+
+        let mut store = StateStore::in_memory().unwrap();
+        assert!(matches!(
+            store.import(&export),
+            Err(StateError::HashFunctionMismatch { .. })
+        ));
+    }
+}
@@ -0,0 +1,438 @@
+//! Storage for the state Merkle tree on a [`KvBackend`].
+//!
+//! Tree nodes and leaves live in their own backend trees, keyed by path and
+//! the version (transition index) that wrote them:
+//!
+//! - nodes:  `depth (1 byte) || path (ceil(depth / 8) bytes) || version (8 bytes BE)`
+//! - leaves: `key_hash (32 bytes) || version (8 bytes BE)`
+//!
+//! A read returns the newest version of an entry, so commits only append the
+//! nodes on changed paths and opening a store reads nothing up front. A
+//! snapshot ([`KvTreeStore::at_version`]) caps reads at an older version.
+//! Removed entries are written as tombstones (empty node value, `0x00` leaf
+//! tag) so older versions stay intact.
+//!
+//! Every entry is also indexed by the version that wrote it:
+//!
+//! - versions: `version (8 bytes BE) || kind (1 byte) || node or leaf prefix`
+//!
+//! so truncating and pruning only touch the versions they drop.
+
+use crate::{KvBackend, KvBatch, StateError};
+use merkle::{Hash32, MerkleError, TreeStore, EMPTY_HASH, TREE_DEPTH};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Name of the backend tree holding Merkle nodes.
+const NODES_TREE: &[u8] = b"smt_nodes";
+/// Name of the backend tree holding Merkle leaves.
+const LEAVES_TREE: &[u8] = b"smt_leaves";
+/// Name of the backend tree indexing nodes and leaves by version.
+const VERSIONS_TREE: &[u8] = b"smt_versions";
+
+/// Entry kinds in the version index.
+const NODE_ENTRY: u8 = 0x00;
+const LEAF_ENTRY: u8 = 0x01;
+
+/// Leaf value tags.
+const LEAF_REMOVED: u8 = 0x00;
+const LEAF_PRESENT: u8 = 0x01;
+
+/// Merkle tree storage on top of a key-value backend.
+///
+/// Writes are buffered in memory until they are persisted under a version,
+/// either by [`KvTreeStore::flush`] or as part of a state commit.
+#[derive(Clone)]
+pub struct KvTreeStore {
+    /// Backend holding the versioned nodes and leaves.
+    backend: Arc<dyn KvBackend>,
+    /// Node writes not yet flushed.
+    pending_nodes: HashMap<(usize, Hash32), Option<Hash32>>,
+    /// Leaf writes not yet flushed.
+    pending_leaves: HashMap<Hash32, Option<Vec<u8>>>,
+    /// Newest version visible to reads (`None` for the live, writable store).
+    version: Option<u64>,
+}
+
+impl KvTreeStore {
+    /// Open the tree storage on a backend.
+    pub fn open(backend: Arc<dyn KvBackend>) -> Self {
+        Self {
+            backend,
+            pending_nodes: HashMap::new(),
+            pending_leaves: HashMap::new(),
+            version: None,
+        }
+    }
+
+    /// Read-only view of the persisted tree as of `version`.
+    ///
+    /// Writes that were not flushed yet are not visible.
+    pub fn at_version(&self, version: u64) -> Self {
+        Self {
+            backend: Arc::clone(&self.backend),
+            pending_nodes: HashMap::new(),
+            pending_leaves: HashMap::new(),
+            version: Some(version),
+        }
+    }
+
+    /// Whether nothing has been persisted yet.
+    pub fn is_empty(&self) -> Result<bool, StateError> {
+        Ok(self.backend.is_empty(NODES_TREE)? && self.backend.is_empty(LEAVES_TREE)?)
+    }
+
+    /// Persist all buffered writes under `version`.
+    pub fn flush(&mut self, version: u64) -> Result<(), StateError> {
+        let mut batch = KvBatch::default();
+        self.write_pending(&mut batch, version);
+        self.backend.apply(batch)?;
+        self.clear_pending();
+        Ok(())
+    }
+
+    /// Add the buffered nodes and leaves to `batch` under `version`.
+    ///
+    /// Used to persist the tree as part of a larger batch; call
+    /// [`KvTreeStore::clear_pending`] once it has been applied.
+    pub(crate) fn write_pending(&self, batch: &mut KvBatch, version: u64) {
+        for ((depth, path), hash) in &self.pending_nodes {
+            let value: &[u8] = match hash {
+                Some(h) => h,
+                None => &[],
+            };
+            let prefix = node_prefix(*depth, path);
+            batch.insert(VERSIONS_TREE, version_key(version, NODE_ENTRY, &prefix), []);
+            batch.insert(NODES_TREE, versioned(&prefix, version), value);
+        }
+
+        for (key_hash, value) in &self.pending_leaves {
+            batch.insert(
+                VERSIONS_TREE,
+                version_key(version, LEAF_ENTRY, key_hash),
+                [],
+            );
+            batch.insert(
+                LEAVES_TREE,
+                versioned(key_hash, version),
+                encode_leaf(value.as_deref()),
+            );
+        }
+    }
+
+    /// Drop the buffered writes.
+    pub(crate) fn clear_pending(&mut self) {
+        self.pending_nodes.clear();
+        self.pending_leaves.clear();
+    }
+
+    /// Add removals of every node and leaf written after `version`, up to
+    /// `latest`, to `batch`.
+    pub(crate) fn write_truncate(
+        &self,
+        batch: &mut KvBatch,
+        version: u64,
+        latest: u64,
+    ) -> Result<(), StateError> {
+        for dropped in version + 1..=latest {
+            for entry in self
+                .backend
+                .scan_prefix(VERSIONS_TREE, &dropped.to_be_bytes())
+            {
+                let (index_key, _) = entry?;
+                let (kind, prefix) = parse_version_key(&index_key)?;
+                batch.remove(entry_tree(kind), versioned(prefix, dropped));
+                batch.remove(VERSIONS_TREE, index_key);
+            }
+        }
+        Ok(())
+    }
+
+    /// Add removals of the nodes and leaves no read at `version` or later
+    /// sees to `batch`.
+    ///
+    /// Every entry written from `from` to `version` hides its older versions,
+    /// which are removed, and a tombstone with nothing left to hide is
+    /// removed too. Reads below `version` are no longer valid afterwards.
+    pub(crate) fn write_prune(
+        &self,
+        batch: &mut KvBatch,
+        from: u64,
+        version: u64,
+    ) -> Result<(), StateError> {
+        for written in from..=version {
+            for entry in self
+                .backend
+                .scan_prefix(VERSIONS_TREE, &written.to_be_bytes())
+            {
+                let (index_key, _) = entry?;
+                let (kind, prefix) = parse_version_key(&index_key)?;
+                let tree = entry_tree(kind);
+
+                for older in self.backend.scan_prefix(tree, prefix) {
+                    let (key, value) = older?;
+                    let older_version = entry_version(&key)?;
+                    let removed = match kind {
+                        NODE_ENTRY => value.is_empty(),
+                        _ => value.first() == Some(&LEAF_REMOVED),
+                    };
+                    if older_version > written || (older_version == written && !removed) {
+                        break;
+                    }
+                    batch.remove(tree, key);
+                    batch.remove(VERSIONS_TREE, version_key(older_version, kind, prefix));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Index the entries of stores written before the version index.
+    pub(crate) fn index_versions(&self) -> Result<(), StateError> {
+        if !self.backend.is_empty(VERSIONS_TREE)? || self.is_empty()? {
+            return Ok(());
+        }
+
+        let mut batch = KvBatch::default();
+        for (tree, kind) in [(NODES_TREE, NODE_ENTRY), (LEAVES_TREE, LEAF_ENTRY)] {
+            for entry in self.backend.scan_prefix(tree, &[]) {
+                let (key, _) = entry?;
+                let version = entry_version(&key)?;
+                let prefix = &key[..key.len() - 8];
+                batch.insert(VERSIONS_TREE, version_key(version, kind, prefix), []);
+            }
+        }
+        self.backend.apply(batch)
+    }
+
+    /// Key hashes of all leaves ever written.
+    pub(crate) fn leaf_hashes(&self) -> Result<Vec<Hash32>, MerkleError> {
+        self.leaf_keys(&[])
+    }
+
+    /// Read the newest persisted version of an entry visible to this store.
+    fn latest(&self, tree: &[u8], prefix: &[u8]) -> Result<Option<Vec<u8>>, MerkleError> {
+        let mut lo = prefix.to_vec();
+        lo.extend_from_slice(&0u64.to_be_bytes());
+        let mut hi = prefix.to_vec();
+        hi.extend_from_slice(&self.version.unwrap_or(u64::MAX).to_be_bytes());
+
+        let entry = self
+            .backend
+            .last_in_range(tree, &lo, &hi)
+            .map_err(storage_error)?;
+        Ok(entry.map(|(_, value)| value))
+    }
+
+    /// Key hashes whose leaves differ between this store and `other`, in
+    /// key hash order: at most `limit` of those after `after`.
+    ///
+    /// Walks both trees from the root and only descends into subtrees whose
+    /// hashes differ and that hold key hashes after `after`, so the cost is
+    /// proportional to the number of changes listed.
+    pub fn changed_keys(
+        &self,
+        other: &KvTreeStore,
+        after: Option<&Hash32>,
+        limit: usize,
+    ) -> Result<Vec<Hash32>, MerkleError> {
+        let mut changed = Vec::new();
+        self.diff_subtree(other, 0, EMPTY_HASH, after, limit, &mut changed)?;
+        Ok(changed)
+    }
+
+    fn diff_subtree(
+        &self,
+        other: &KvTreeStore,
+        depth: usize,
+        path: Hash32,
+        after: Option<&Hash32>,
+        limit: usize,
+        changed: &mut Vec<Hash32>,
+    ) -> Result<(), MerkleError> {
+        if changed.len() >= limit || after.is_some_and(|after| precedes(depth, &path, after)) {
+            return Ok(());
+        }
+        if self.get_node(depth, &path)? == other.get_node(depth, &path)? {
+            return Ok(());
+        }
+
+        if depth == TREE_DEPTH {
+            // Leaves are keyed by the full key hash; the path is its prefix
+            for key_hash in self.leaf_keys(&path[..TREE_DEPTH / 8])? {
+                if after.is_some_and(|after| key_hash <= *after) {
+                    continue;
+                }
+                if changed.len() >= limit {
+                    break;
+                }
+                if self.get_leaf(&key_hash)? != other.get_leaf(&key_hash)? {
+                    changed.push(key_hash);
+                }
+            }
+            return Ok(());
+        }
+
+        let mut right = path;
+        right[depth / 8] |= 0x80 >> (depth % 8);
+        self.diff_subtree(other, depth + 1, path, after, limit, changed)?;
+        self.diff_subtree(other, depth + 1, right, after, limit, changed)
+    }
+
+    /// Key hashes of all leaves (any version) starting with `prefix`.
+    fn leaf_keys(&self, prefix: &[u8]) -> Result<Vec<Hash32>, MerkleError> {
+        let mut keys: Vec<Hash32> = Vec::new();
+        for entry in self.backend.scan_prefix(LEAVES_TREE, prefix) {
+            let (key, _) = entry.map_err(storage_error)?;
+            let key_hash: Hash32 = key
+                .get(..32)
+                .and_then(|k| k.try_into().ok())
+                .ok_or_else(|| MerkleError::Storage("invalid leaf key length".to_string()))?;
+            if keys.last() != Some(&key_hash) {
+                keys.push(key_hash);
+            }
+        }
+        Ok(keys)
+    }
+
+    fn check_writable(&self) -> Result<(), MerkleError> {
+        match self.version {
+            Some(version) => Err(MerkleError::Storage(format!(
+                "tree snapshot at version {} is read-only",
+                version
+            ))),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Whether every key hash below the node at `depth` and `path` sorts before
+/// `key_hash`.
+fn precedes(depth: usize, path: &Hash32, key_hash: &Hash32) -> bool {
+    let bit = |hash: &Hash32, i: usize| hash[i / 8] & (0x80 >> (i % 8)) != 0;
+    (0..depth)
+        .find(|&i| bit(path, i) != bit(key_hash, i))
+        .is_some_and(|i| !bit(path, i))
+}
+
+impl TreeStore for KvTreeStore {
+    fn get_node(&self, depth: usize, path: &Hash32) -> Result<Option<Hash32>, MerkleError> {
+        if let Some(hash) = self.pending_nodes.get(&(depth, *path)) {
+            return Ok(*hash);
+        }
+
+        match self.latest(NODES_TREE, &node_prefix(depth, path))? {
+            Some(value) if !value.is_empty() => {
+                let hash = value
+                    .as_slice()
+                    .try_into()
+                    .map_err(|_| MerkleError::Storage("invalid node hash length".to_string()))?;
+                Ok(Some(hash))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn set_node(
+        &mut self,
+        depth: usize,
+        path: Hash32,
+        hash: Option<Hash32>,
+    ) -> Result<(), MerkleError> {
+        self.check_writable()?;
+        self.pending_nodes.insert((depth, path), hash);
+        Ok(())
+    }
+
+    fn get_leaf(&self, key_hash: &Hash32) -> Result<Option<Vec<u8>>, MerkleError> {
+        if let Some(value) = self.pending_leaves.get(key_hash) {
+            return Ok(value.clone());
+        }
+
+        match self.latest(LEAVES_TREE, key_hash)? {
+            Some(value) => decode_leaf(&value),
+            None => Ok(None),
+        }
+    }
+
+    fn set_leaf(&mut self, key_hash: Hash32, value: Option<Vec<u8>>) -> Result<(), MerkleError> {
+        self.check_writable()?;
+        self.pending_leaves.insert(key_hash, value);
+        Ok(())
+    }
+}
+
+/// Key prefix of a node (all versions).
+fn node_prefix(depth: usize, path: &Hash32) -> Vec<u8> {
+    let path_len = depth.div_ceil(8);
+    let mut key = Vec::with_capacity(1 + path_len + 8);
+    key.push(depth as u8);
+    key.extend_from_slice(&path[..path_len]);
+    key
+}
+
+/// Key of a node (by prefix) or leaf (by key hash) at a specific version.
+fn versioned(prefix: &[u8], version: u64) -> Vec<u8> {
+    let mut key = Vec::with_capacity(prefix.len() + 8);
+    key.extend_from_slice(prefix);
+    key.extend_from_slice(&version.to_be_bytes());
+    key
+}
+
+/// Key of an entry in the version index.
+fn version_key(version: u64, kind: u8, prefix: &[u8]) -> Vec<u8> {
+    let mut key = Vec::with_capacity(9 + prefix.len());
+    key.extend_from_slice(&version.to_be_bytes());
+    key.push(kind);
+    key.extend_from_slice(prefix);
+    key
+}
+
+/// Split a version index key into the entry kind and prefix.
+fn parse_version_key(key: &[u8]) -> Result<(u8, &[u8]), StateError> {
+    match key.get(8..) {
+        Some([kind @ (NODE_ENTRY | LEAF_ENTRY), prefix @ ..]) => Ok((*kind, prefix)),
+        _ => Err(MerkleError::Storage("invalid version index key".to_string()).into()),
+    }
+}
+
+/// Backend tree holding entries of `kind`.
+fn entry_tree(kind: u8) -> &'static [u8] {
+    match kind {
+        NODE_ENTRY => NODES_TREE,
+        _ => LEAVES_TREE,
+    }
+}
+
+/// Version suffix of a node or leaf key.
+fn entry_version(key: &[u8]) -> Result<u64, StateError> {
+    key.len()
+        .checked_sub(8)
+        .and_then(|start| key[start..].try_into().ok())
+        .map(u64::from_be_bytes)
+        .ok_or_else(|| MerkleError::Storage("invalid versioned key".to_string()).into())
+}
+
+fn encode_leaf(value: Option<&[u8]>) -> Vec<u8> {
+    match value {
+        Some(v) => {
+            let mut data = Vec::with_capacity(1 + v.len());
+            data.push(LEAF_PRESENT);
+            data.extend_from_slice(v);
+            data
+        }
+        None => vec![LEAF_REMOVED],
+    }
+}
+
+fn decode_leaf(data: &[u8]) -> Result<Option<Vec<u8>>, MerkleError> {
+    match data.split_first() {
+        Some((&LEAF_PRESENT, value)) => Ok(Some(value.to_vec())),
+        Some((&LEAF_REMOVED, _)) => Ok(None),
+        _ => Err(MerkleError::Storage("invalid leaf encoding".to_string())),
+    }
+}
+
+fn storage_error(e: StateError) -> MerkleError {
+    MerkleError::Storage(e.to_string())
+}
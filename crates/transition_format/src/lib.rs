@@ -6,214 +6,15 @@
 //! The key insight is that we need to verify both:
 //! 1. Merkle tree correctness (witnesses produce valid roots)
 //! 2. Business logic correctness (operations are valid according to app rules)
+//!
+//! The input/output types live in `merkle_core` so the SP1 guest decodes
+//! exactly what the host encodes; they are re-exported here.
 
-use merkle::{Hash32, UpdateWitness};
+pub use merkle_core::{
+    bind_operations, finance, verify_witnesses, OperationType, SetPolicy, TransitionInput,
+    TransitionOutput, VerifiableOperation,
+};
 use serde::{Deserialize, Serialize};
-use sha2::{Digest, Sha256};
-
-/// Input to the SP1 state transition program.
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct TransitionInput {
-    /// Previous state root (public).
-    pub prev_root: Hash32,
-    /// Public inputs (application-specific).
-    pub public_inputs: Vec<u8>,
-    /// Private inputs (application-specific).
-    pub private_inputs: Vec<u8>,
-    /// Update witnesses for all touched keys.
-    pub witnesses: Vec<UpdateWitness>,
-    /// Operations with their verification data.
-    pub operations: Vec<VerifiableOperation>,
-}
-
-impl TransitionInput {
-    /// Create a new transition input.
-    pub fn new(
-        prev_root: Hash32,
-        public_inputs: Vec<u8>,
-        private_inputs: Vec<u8>,
-        witnesses: Vec<UpdateWitness>,
-    ) -> Self {
-        Self {
-            prev_root,
-            public_inputs,
-            private_inputs,
-            witnesses,
-            operations: Vec::new(),
-        }
-    }
-
-    /// Add operations for verification.
-    pub fn with_operations(mut self, ops: Vec<VerifiableOperation>) -> Self {
-        self.operations = ops;
-        self
-    }
-
-    /// Encode to bytes for SP1 input.
-    pub fn encode(&self) -> Vec<u8> {
-        bincode::serialize(self).expect("serialization should not fail")
-    }
-
-    /// Decode from bytes.
-    pub fn decode(data: &[u8]) -> Result<Self, bincode::Error> {
-        bincode::deserialize(data)
-    }
-
-    /// Hash the public inputs.
-    pub fn public_inputs_hash(&self) -> Hash32 {
-        let mut hasher = Sha256::new();
-        hasher.update(&self.prev_root);
-        hasher.update(&self.public_inputs);
-        hasher.finalize().into()
-    }
-}
-
-/// Output from the SP1 state transition program.
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
-pub struct TransitionOutput {
-    /// Previous state root (for verification).
-    pub prev_root: Hash32,
-    /// New state root after transition.
-    pub new_root: Hash32,
-    /// Hash of public inputs (for binding).
-    pub public_inputs_hash: Hash32,
-    /// Public outputs (application-specific).
-    pub public_outputs: Vec<u8>,
-}
-
-impl TransitionOutput {
-    /// Create a new transition output.
-    pub fn new(
-        prev_root: Hash32,
-        new_root: Hash32,
-        public_inputs_hash: Hash32,
-        public_outputs: Vec<u8>,
-    ) -> Self {
-        Self {
-            prev_root,
-            new_root,
-            public_inputs_hash,
-            public_outputs,
-        }
-    }
-
-    /// Encode to bytes.
-    pub fn encode(&self) -> Vec<u8> {
-        bincode::serialize(self).expect("serialization should not fail")
-    }
-
-    /// Decode from bytes.
-    pub fn decode(data: &[u8]) -> Result<Self, bincode::Error> {
-        bincode::deserialize(data)
-    }
-}
-
-/// A verifiable operation that includes all data needed for circuit verification.
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct VerifiableOperation {
-    /// Operation type tag.
-    pub op_type: OperationType,
-    /// The key being operated on.
-    pub key: Vec<u8>,
-    /// Old value (from witness).
-    pub old_value: Option<Vec<u8>>,
-    /// New value (to be written).
-    pub new_value: Option<Vec<u8>>,
-    /// Witness index (links to TransitionInput.witnesses).
-    pub witness_index: usize,
-}
-
-/// Types of operations that can be verified.
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
-pub enum OperationType {
-    /// Set a value (no constraints).
-    Set,
-    /// Create an account with initial balance.
-    CreateAccount { initial_balance: u64 },
-    /// Transfer funds between accounts.
-    Transfer {
-        from: Vec<u8>,
-        to: Vec<u8>,
-        amount: u64,
-    },
-    /// Mint new tokens (requires authority).
-    Mint { amount: u64 },
-    /// Burn tokens.
-    Burn { amount: u64 },
-}
-
-/// Finance-specific operations for the example app.
-pub mod finance {
-    use super::*;
-
-    /// A balance transfer operation.
-    #[derive(Debug, Clone, Serialize, Deserialize)]
-    pub struct Transfer {
-        pub from: String,
-        pub to: String,
-        pub amount: u64,
-        pub nonce: u64,
-    }
-
-    /// Account state.
-    #[derive(Debug, Clone, Serialize, Deserialize, Default)]
-    pub struct Account {
-        pub balance: u64,
-        pub nonce: u64,
-    }
-
-    impl Account {
-        pub fn encode(&self) -> Vec<u8> {
-            bincode::serialize(self).expect("encoding should not fail")
-        }
-
-        pub fn decode(data: &[u8]) -> Option<Self> {
-            bincode::deserialize(data).ok()
-        }
-    }
-
-    /// Verify a transfer operation inside the circuit.
-    /// Returns true if the transfer is valid.
-    pub fn verify_transfer(
-        from_old: &Account,
-        from_new: &Account,
-        to_old: &Account,
-        to_new: &Account,
-        amount: u64,
-        expected_nonce: u64,
-    ) -> bool {
-        // Check sender has sufficient balance
-        if from_old.balance < amount {
-            return false;
-        }
-
-        // Check nonce is correct
-        if from_old.nonce != expected_nonce {
-            return false;
-        }
-
-        // Check balances are updated correctly
-        if from_new.balance != from_old.balance - amount {
-            return false;
-        }
-
-        if to_new.balance != to_old.balance + amount {
-            return false;
-        }
-
-        // Check sender nonce is incremented
-        if from_new.nonce != from_old.nonce + 1 {
-            return false;
-        }
-
-        // Check receiver nonce is unchanged
-        if to_new.nonce != to_old.nonce {
-            return false;
-        }
-
-        true
-    }
-}
 
 /// A single state operation to be applied.
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -282,31 +83,25 @@ impl OperationBatch {
     }
 }
 
-/// Verifies that a sequence of witnesses produces the expected root transition.
-pub fn verify_witnesses(
-    prev_root: Hash32,
-    witnesses: &[UpdateWitness],
-) -> Result<Hash32, &'static str> {
-    let mut current_root = prev_root;
-
-    for witness in witnesses {
-        // Verify the witness starts from our current root
-        let computed_old_root = witness.compute_old_root();
-        if computed_old_root != current_root {
-            return Err("witness old root mismatch");
-        }
-
-        // Compute new root
-        current_root = witness.compute_new_root();
-    }
-
-    Ok(current_root)
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
-    use merkle::SparseMerkleTree;
+    use merkle::{HashFunction, SparseMerkleTree, UpdateWitness};
+
+    /// Declare each witness with a `Set` operation.
+    fn set_ops(keys: &[&[u8]], witnesses: &[UpdateWitness]) -> Vec<VerifiableOperation> {
+        keys.iter()
+            .zip(witnesses)
+            .enumerate()
+            .map(|(index, (key, witness))| VerifiableOperation {
+                op_type: OperationType::Set,
+                key: key.to_vec(),
+                old_value: witness.old_value.clone(),
+                new_value: witness.new_value.clone(),
+                witness_index: index,
+            })
+            .collect()
+    }
 
     #[test]
     fn test_transition_input_roundtrip() {
@@ -317,13 +112,20 @@ mod tests {
         let decoded = TransitionInput::decode(&encoded).unwrap();
 
         assert_eq!(input.prev_root, decoded.prev_root);
+        assert_eq!(input.hash_function, decoded.hash_function);
         assert_eq!(input.public_inputs, decoded.public_inputs);
         assert_eq!(input.private_inputs, decoded.private_inputs);
     }
 
     #[test]
     fn test_transition_output_roundtrip() {
-        let output = TransitionOutput::new([1u8; 32], [2u8; 32], [3u8; 32], b"outputs".to_vec());
+        let output = TransitionOutput::new(
+            [1u8; 32],
+            [2u8; 32],
+            HashFunction::Blake3,
+            [3u8; 32],
+            b"outputs".to_vec(),
+        );
 
         let encoded = output.encode();
         let decoded = TransitionOutput::decode(&encoded).unwrap();
@@ -340,10 +142,48 @@ mod tests {
         let w2 = tree.insert(b"key2", b"value2".to_vec());
         let root2 = tree.root();
 
-        let result = verify_witnesses(root0, &[w1, w2]).unwrap();
+        let witnesses = [w1, w2];
+        let ops = set_ops(&[b"key1", b"key2"], &witnesses);
+        let result = verify_witnesses(
+            HashFunction::Sha256,
+            root0,
+            &witnesses,
+            &ops,
+            SetPolicy::Allow,
+        )
+        .unwrap();
         assert_eq!(result, root2);
     }
 
+    #[test]
+    fn test_verify_witnesses_hash_function() {
+        let mut tree = SparseMerkleTree::with_hasher(HashFunction::Poseidon);
+        let root0 = tree.root();
+        let w1 = tree.insert(b"key1", b"value1".to_vec());
+
+        let witnesses = [w1];
+        let ops = set_ops(&[b"key1"], &witnesses);
+        let result = verify_witnesses(
+            HashFunction::Poseidon,
+            root0,
+            &witnesses,
+            &ops,
+            SetPolicy::Allow,
+        )
+        .unwrap();
+        assert_eq!(result, tree.root());
+
+        // Witnesses must come from a tree with the declared hash function
+        assert!(verify_witnesses(
+            HashFunction::Sha256,
+            root0,
+            &witnesses,
+            &ops,
+            SetPolicy::Allow
+        )
+        .is_err());
+    }
+
     #[test]
     fn test_verify_witnesses_mismatch() {
         let mut tree = SparseMerkleTree::new();
@@ -352,10 +192,57 @@ mod tests {
 
         // Try to verify with wrong starting root
         let wrong_root = [99u8; 32];
-        let result = verify_witnesses(wrong_root, &[w2]);
+        let witnesses = [w2];
+        let ops = set_ops(&[b"key2"], &witnesses);
+        let result = verify_witnesses(
+            HashFunction::Sha256,
+            wrong_root,
+            &witnesses,
+            &ops,
+            SetPolicy::Allow,
+        );
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_bind_operations() {
+        let mut tree = SparseMerkleTree::new();
+        let root0 = tree.root();
+        let witnesses = [
+            tree.insert(b"key1", b"value1".to_vec()),
+            tree.insert(b"key2", b"value2".to_vec()),
+        ];
+        let verify = |ops: &[VerifiableOperation], policy| {
+            verify_witnesses(HashFunction::Sha256, root0, &witnesses, ops, policy)
+        };
+        let ops = set_ops(&[b"key1", b"key2"], &witnesses);
+        assert!(verify(&ops, SetPolicy::Allow).is_ok());
+
+        // Bare writes can be refused
+        assert!(verify(&ops, SetPolicy::Deny).is_err());
+
+        // Every witness must be declared, and only once
+        assert!(verify(&ops[..1], SetPolicy::Allow).is_err());
+        let twice = [ops.clone(), ops[..1].to_vec()].concat();
+        assert!(verify(&twice, SetPolicy::Allow).is_err());
+
+        // Operations must match their witness
+        let mut wrong_value = ops.clone();
+        wrong_value[0].new_value = Some(b"other".to_vec());
+        assert!(verify(&wrong_value, SetPolicy::Allow).is_err());
+        let mut wrong_key = ops.clone();
+        wrong_key[1].key = b"key3".to_vec();
+        assert!(verify(&wrong_key, SetPolicy::Allow).is_err());
+
+        // Undeclared writes can be declared as sets
+        let keys: [&[u8]; 2] = [b"key1", b"key2"];
+        let input = TransitionInput::new(root0, vec![], vec![], witnesses.to_vec())
+            .with_operations(ops[1..].to_vec())
+            .with_set_operations(&keys);
+        assert_eq!(input.operations.len(), 2);
+        assert!(input.bind_operations().is_ok());
+    }
+
     #[test]
     fn test_finance_transfer_verification() {
         use finance::*;
@@ -363,41 +250,459 @@ mod tests {
         let from_old = Account {
             balance: 100,
             nonce: 0,
+            ..Default::default()
         };
         let from_new = Account {
             balance: 70,
             nonce: 1,
+            ..Default::default()
         };
         let to_old = Account {
             balance: 50,
             nonce: 5,
+            ..Default::default()
         };
         let to_new = Account {
             balance: 80,
             nonce: 5,
+            ..Default::default()
         };
 
-        assert!(verify_transfer(
-            &from_old, &from_new, &to_old, &to_new, 30, 0
-        ));
+        assert!(verify_transfer(&from_old, &from_new, &to_old, &to_new, 30));
 
         // Invalid: insufficient balance
         let from_old_poor = Account {
             balance: 10,
             nonce: 0,
+            ..Default::default()
         };
         assert!(!verify_transfer(
             &from_old_poor,
             &from_new,
             &to_old,
             &to_new,
-            30,
-            0
+            30
         ));
 
-        // Invalid: wrong nonce
-        assert!(!verify_transfer(
-            &from_old, &from_new, &to_old, &to_new, 30, 1
+        // Invalid: nonce not incremented, or overflowing
+        assert!(!verify_transfer(&from_old, &from_old, &to_old, &to_new, 30));
+        let exhausted = Account {
+            balance: 100,
+            nonce: u64::MAX,
+            ..Default::default()
+        };
+        let wrapped = Account {
+            balance: 70,
+            nonce: 0,
+            ..Default::default()
+        };
+        assert!(!verify_transfer(&exhausted, &wrapped, &to_old, &to_new, 30));
+    }
+
+    #[test]
+    fn test_finance_transfer_witnesses() {
+        use ed25519_dalek::{Signer, SigningKey};
+        use finance::*;
+
+        let owner = SigningKey::from_bytes(&[1u8; 32]);
+        let public_key = Some(owner.verifying_key().to_bytes());
+        let sign = |key: &SigningKey, amount| {
+            key.sign(&transfer_message(
+                b"finance",
+                b"account:alice",
+                b"account:bob",
+                amount,
+                0,
+            ))
+            .to_bytes()
+        };
+
+        let mut tree = SparseMerkleTree::with_hasher(HashFunction::Blake3);
+        let alice = Account {
+            balance: 100,
+            nonce: 0,
+            public_key,
+        };
+        tree.insert(b"account:alice", alice.encode());
+        let bob = Account {
+            balance: 0,
+            nonce: 0,
+            public_key: Some([3u8; 32]),
+        };
+        tree.insert(b"account:bob", bob.encode());
+
+        let transfer = |tree: &mut SparseMerkleTree, from: &Account, to: &Account| {
+            vec![
+                tree.insert(b"account:alice", from.encode()).compress(),
+                tree.insert(b"account:bob", to.encode()).compress(),
+            ]
+        };
+        let debited = Account {
+            balance: 70,
+            nonce: 1,
+            public_key,
+        };
+        let credited = Account {
+            balance: 30,
+            ..bob.clone()
+        };
+        let verify = |witnesses: &[_], amount, signature: &[u8]| {
+            let mut input = TransitionInput::new([0u8; 32], vec![], vec![], vec![])
+                .with_hash_function(HashFunction::Blake3)
+                .with_app_id(b"finance".to_vec());
+            input.witnesses = witnesses.to_vec();
+            verify_transfer_witnesses(
+                &input,
+                0,
+                b"account:alice",
+                b"account:bob",
+                amount,
+                signature,
+            )
+        };
+
+        let witnesses = transfer(&mut tree.clone(), &debited, &credited);
+        assert!(verify(&witnesses, 30, &sign(&owner, 30)));
+        assert!(!verify(&witnesses, 20, &sign(&owner, 20)));
+
+        // Only the sender's owner can sign, and only for this amount
+        let mallory = SigningKey::from_bytes(&[2u8; 32]);
+        assert!(!verify(&witnesses, 30, &sign(&mallory, 30)));
+        assert!(!verify(&witnesses, 30, &sign(&owner, 31)));
+
+        // The sender's key cannot be replaced
+        let rekeyed = Account {
+            public_key: Some(mallory.verifying_key().to_bytes()),
+            ..debited.clone()
+        };
+        let witnesses = transfer(&mut tree.clone(), &rekeyed, &credited);
+        assert!(!verify(&witnesses, 30, &sign(&owner, 30)));
+
+        // An account without a key cannot send
+        let mut keyless = tree.clone();
+        keyless.insert(
+            b"account:alice",
+            Account {
+                public_key: None,
+                ..alice.clone()
+            }
+            .encode(),
+        );
+        let unowned = Account {
+            public_key: None,
+            ..debited.clone()
+        };
+        let witnesses = transfer(&mut keyless, &unowned, &credited);
+        assert!(!verify(&witnesses, 30, &sign(&owner, 30)));
+
+        // nor receive, so credited funds can always be spent again
+        let mut keyless = tree.clone();
+        keyless.insert(
+            b"account:bob",
+            Account {
+                public_key: None,
+                ..bob.clone()
+            }
+            .encode(),
+        );
+        let unowned = Account {
+            public_key: None,
+            ..credited.clone()
+        };
+        let witnesses = transfer(&mut keyless, &debited, &unowned);
+        assert!(!verify(&witnesses, 30, &sign(&owner, 30)));
+
+        // The receiver must exist
+        let mut missing = SparseMerkleTree::with_hasher(HashFunction::Blake3);
+        missing.insert(b"account:alice", alice.encode());
+        let witnesses = transfer(&mut missing, &debited, &credited);
+        assert!(!verify(&witnesses, 30, &sign(&owner, 30)));
+
+        // The receiver must be credited, not only the sender debited
+        let witnesses = transfer(&mut tree.clone(), &debited, &bob);
+        assert!(!verify(&witnesses, 30, &sign(&owner, 30)));
+
+        // Witnesses are matched by key hash
+        let mut swapped = transfer(&mut tree.clone(), &debited, &credited);
+        swapped.swap(0, 1);
+        assert!(!verify(&swapped, 30, &sign(&owner, 30)));
+
+        // Credits must not overflow
+        let rich = Account {
+            balance: u64::MAX,
+            ..bob.clone()
+        };
+        let mut overflow = tree.clone();
+        overflow.insert(b"account:bob", rich.encode());
+        let witnesses = transfer(&mut overflow, &debited, &credited);
+        assert!(!verify(&witnesses, 30, &sign(&owner, 30)));
+
+        // A transfer declares the sender's and the receiver's witness
+        let witnesses = transfer(&mut tree.clone(), &debited, &credited);
+        let op = VerifiableOperation {
+            op_type: OperationType::Transfer {
+                from: b"account:alice".to_vec(),
+                to: b"account:bob".to_vec(),
+                amount: 30,
+                signature: sign(&owner, 30).to_vec(),
+            },
+            key: b"account:alice".to_vec(),
+            old_value: Some(alice.encode()),
+            new_value: Some(debited.encode()),
+            witness_index: 0,
+        };
+        assert!(bind_operations(HashFunction::Blake3, SetPolicy::Deny, &[op], &witnesses).is_ok());
+    }
+
+    #[test]
+    fn test_finance_mint() {
+        use ed25519_dalek::{Signer, SigningKey};
+        use finance::*;
+
+        let authority = SigningKey::from_bytes(&[7u8; 32]);
+        let public_key = authority.verifying_key().to_bytes();
+        let key = b"account:alice";
+        let old = Account {
+            balance: 100,
+            nonce: 3,
+            ..Default::default()
+        }
+        .encode();
+        let new = Account {
+            balance: 150,
+            nonce: 4,
+            ..Default::default()
+        }
+        .encode();
+        let signature = authority
+            .sign(&mint_message(b"finance", key, 50, 3))
+            .to_bytes();
+        let verify = |authority: Option<&[u8; 32]>, new: &[u8], amount, signature: &[u8]| {
+            verify_mint(
+                b"finance",
+                authority,
+                key,
+                Some(&old),
+                Some(new),
+                amount,
+                signature,
+            )
+        };
+
+        assert!(verify(Some(&public_key), &new, 50, &signature));
+
+        // Only the configured authority may mint
+        assert!(!verify(None, &new, 50, &signature));
+        let other = SigningKey::from_bytes(&[8u8; 32]);
+        let forged = other.sign(&mint_message(b"finance", key, 50, 3)).to_bytes();
+        assert!(!verify(Some(&public_key), &new, 50, &forged));
+
+        // and only for this app
+        let other_app = authority
+            .sign(&mint_message(b"other", key, 50, 3))
+            .to_bytes();
+        assert!(!verify(Some(&public_key), &new, 50, &other_app));
+
+        // The signature covers the amount
+        let larger = Account {
+            balance: 200,
+            nonce: 4,
+            ..Default::default()
+        }
+        .encode();
+        assert!(!verify(Some(&public_key), &larger, 100, &signature));
+
+        // The nonce must be incremented, so the signature is not replayable
+        let replayed = Account {
+            balance: 150,
+            nonce: 3,
+            ..Default::default()
+        }
+        .encode();
+        assert!(!verify(Some(&public_key), &replayed, 50, &signature));
+
+        // The account's key is kept
+        let rekeyed = Account {
+            balance: 150,
+            nonce: 4,
+            public_key: Some(public_key),
+        }
+        .encode();
+        assert!(!verify(Some(&public_key), &rekeyed, 50, &signature));
+        assert!(!verify(Some(&public_key), &new, 50, &signature[..63]));
+    }
+
+    #[test]
+    fn test_finance_burn() {
+        use ed25519_dalek::{Signer, SigningKey};
+        use finance::*;
+
+        let owner = SigningKey::from_bytes(&[1u8; 32]);
+        let public_key = Some(owner.verifying_key().to_bytes());
+        let key = b"account:alice";
+        let account = |balance, nonce| {
+            Account {
+                balance,
+                nonce,
+                public_key,
+            }
+            .encode()
+        };
+        let old = account(100, 3);
+        let signature = owner.sign(&burn_message(b"finance", key, 30, 3)).to_bytes();
+        let verify = |new: &[u8], amount, signature: &[u8]| {
+            verify_burn(b"finance", key, Some(&old), Some(new), amount, signature)
+        };
+
+        assert!(verify(&account(70, 4), 30, &signature));
+
+        // Only the owner may burn, for this app and amount
+        let other = SigningKey::from_bytes(&[2u8; 32]);
+        let forged = other.sign(&burn_message(b"finance", key, 30, 3)).to_bytes();
+        assert!(!verify(&account(70, 4), 30, &forged));
+        let other_app = owner.sign(&burn_message(b"other", key, 30, 3)).to_bytes();
+        assert!(!verify(&account(70, 4), 30, &other_app));
+        assert!(!verify(&account(60, 4), 40, &signature));
+
+        // The nonce must be incremented, not kept or rewound
+        assert!(!verify(&account(70, 3), 30, &signature));
+        assert!(!verify(&account(70, 0), 30, &signature));
+
+        // The balance must cover the burn
+        let signature = owner
+            .sign(&burn_message(b"finance", key, 101, 3))
+            .to_bytes();
+        assert!(!verify(&account(0, 4), 101, &signature));
+    }
+
+    #[test]
+    fn test_finance_create_account() {
+        use ed25519_dalek::{Signer, SigningKey};
+        use finance::*;
+
+        let authority = SigningKey::from_bytes(&[7u8; 32]);
+        let public_key = authority.verifying_key().to_bytes();
+        let owner_key = SigningKey::from_bytes(&[1u8; 32]);
+        let owner = owner_key.verifying_key().to_bytes();
+        let key = b"account:alice";
+        let account = |balance| {
+            Account {
+                balance,
+                nonce: 0,
+                public_key: Some(owner),
+            }
+            .encode()
+        };
+        let signature = authority
+            .sign(&create_account_message(b"finance", key, &owner, 100))
+            .to_bytes();
+        let verify = |authority: Option<&[u8; 32]>,
+                      old: Option<&[u8]>,
+                      new: &[u8],
+                      balance,
+                      signature: &[u8]| {
+            verify_create_account(
+                b"finance",
+                authority,
+                key,
+                old,
+                Some(new),
+                balance,
+                signature,
+            )
+        };
+
+        // Empty accounts are signed by their owner
+        let empty = owner_key
+            .sign(&create_account_message(b"finance", key, &owner, 0))
+            .to_bytes();
+        assert!(verify(None, None, &account(0), 0, &empty));
+        assert!(!verify(None, None, &account(0), 0, &[]));
+        assert!(!verify(None, None, &account(1), 0, &empty));
+        let squatted = authority
+            .sign(&create_account_message(b"finance", key, &owner, 0))
+            .to_bytes();
+        assert!(!verify(Some(&public_key), None, &account(0), 0, &squatted));
+        let keyless = Account::default().encode();
+        assert!(!verify(None, None, &keyless, 0, &empty));
+
+        // A funded account is signed by the authority for its balance and
+        // owner
+        assert!(verify(
+            Some(&public_key),
+            None,
+            &account(100),
+            100,
+            &signature
+        ));
+        assert!(!verify(None, None, &account(100), 100, &signature));
+        assert!(!verify(Some(&public_key), None, &account(100), 100, &[]));
+        assert!(!verify(
+            Some(&public_key),
+            None,
+            &account(200),
+            200,
+            &signature
+        ));
+        let other = SigningKey::from_bytes(&[8u8; 32]);
+        let forged = other
+            .sign(&create_account_message(b"finance", key, &owner, 100))
+            .to_bytes();
+        assert!(!verify(
+            Some(&public_key),
+            None,
+            &account(100),
+            100,
+            &forged
+        ));
+        let rekeyed = Account {
+            balance: 100,
+            nonce: 0,
+            public_key: Some([2u8; 32]),
+        }
+        .encode();
+        assert!(!verify(Some(&public_key), None, &rekeyed, 100, &signature));
+
+        // An existing account cannot be recreated
+        let existing = account(0);
+        assert!(!verify(None, Some(&existing), &account(0), 0, &empty));
+        assert!(!verify(
+            Some(&public_key),
+            Some(&existing),
+            &account(100),
+            100,
+            &signature
         ));
+
+        // The guest's checks: a creation over an existing account is bound to
+        // its witness but rejected
+        let guest = |tree: &mut SparseMerkleTree, old_value: Option<Vec<u8>>| {
+            let prev_root = tree.root();
+            let witness = tree.insert(key, account(0));
+            let op = VerifiableOperation {
+                op_type: OperationType::CreateAccount {
+                    initial_balance: 0,
+                    signature: empty.to_vec(),
+                },
+                key: key.to_vec(),
+                old_value,
+                new_value: Some(account(0)),
+                witness_index: 0,
+            };
+            let input = TransitionInput::new(prev_root, vec![], vec![], vec![witness])
+                .with_hash_function(HashFunction::Blake3)
+                .with_app_id(b"finance".to_vec())
+                .with_operations(vec![op]);
+            input.bind_operations()?;
+            input.verify_operations()?;
+            input.apply_witnesses()
+        };
+        let mut tree = SparseMerkleTree::with_hasher(HashFunction::Blake3);
+        assert!(guest(&mut tree.clone(), None).is_ok());
+        tree.insert(key, account(0));
+        assert_eq!(
+            guest(&mut tree.clone(), Some(account(0))),
+            Err("business logic verification failed for operation")
+        );
     }
 }
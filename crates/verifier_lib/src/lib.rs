@@ -5,15 +5,28 @@
 //! - Each proof is valid
 //! - Root continuity is maintained
 //! - Program hash matches expected
+//! - Every transition uses the app's hash function
+//! - Proofs were made under the app's set policy (if configured), and never
+//!   under [`SetPolicy::Allow`] unless allowed
+//! - Mints were checked against the app's mint authority (if configured)
+//!
+//! The result reports the set policy and mint authority each proof was made
+//! under.
+//!
+//! Transitions posted without a proof are verified through a later proof
+//! attachment blob in the same range, if there is one that verifies, and
+//! are otherwise reported unverified.
 
 use anyhow::Result;
-use blob_schema::TransitionBlobV1;
+use blob_schema::{DaBlob, ProofAttachmentV1, TransitionBlob};
 use celestia_adapter::{CelestiaClient, Namespace};
-use merkle::Hash32;
+use merkle::{Hash32, HashFunction};
 use thiserror::Error;
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 use zk_host_harness::{program_hash, TransitionVerifier};
 
+pub use transition_format::SetPolicy;
+
 /// Verification errors.
 #[derive(Error, Debug)]
 pub enum VerifyError {
@@ -31,8 +44,16 @@ pub enum VerifyError {
     },
     #[error("program hash mismatch at sequence {sequence}")]
     ProgramHashMismatch { sequence: u64 },
+    #[error("hash function mismatch at sequence {sequence}: expected {expected}, got {actual}")]
+    HashFunctionMismatch {
+        sequence: u64,
+        expected: HashFunction,
+        actual: HashFunction,
+    },
     #[error("no blobs found")]
     NoBlobsFound,
+    #[error("a finance chain is only verified with {0}")]
+    IncompleteConfig(&'static str),
 }
 
 /// Result of verification.
@@ -50,8 +71,25 @@ pub struct VerificationResult {
     pub last_sequence: u64,
     /// Celestia heights covered.
     pub height_range: (u64, u64),
-    /// Transitions with empty proofs (not verified).
+    /// Transitions with empty proofs and no attachment that verifies (not
+    /// verified).
     pub unverified_transitions: Vec<u64>,
+    /// Transitions posted without a proof whose proof came from an
+    /// attachment blob.
+    pub attached_proofs: Vec<u64>,
+    /// Terms of every verified proof, by sequence.
+    pub proof_terms: Vec<ProofTerms>,
+}
+
+/// Rules a proof was made under, as committed in its output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProofTerms {
+    /// Sequence of the proven transition.
+    pub sequence: u64,
+    /// Set policy the guest enforced.
+    pub set_policy: SetPolicy,
+    /// Public key mints were checked against (mints refused if None).
+    pub mint_authority: Option<[u8; 32]>,
 }
 
 /// Configuration for verification.
@@ -63,10 +101,24 @@ pub struct VerifyConfig {
     pub namespace: Namespace,
     /// Expected program hash (uses default if None).
     pub expected_program_hash: Option<Hash32>,
+    /// Expected state tree hash function (uses the first transition's if None).
+    pub expected_hash_function: Option<HashFunction>,
+    /// Set policy proofs must have been made under. If None, proofs made
+    /// under [`SetPolicy::Allow`] are only accepted with `allow_set`.
+    pub expected_set_policy: Option<SetPolicy>,
+    /// Accept proofs made under [`SetPolicy::Allow`], whose bare `Set`
+    /// operations write state without any business rule.
+    pub allow_set: bool,
+    /// Public key mints must have been checked against (any if None).
+    pub expected_mint_authority: Option<[u8; 32]>,
     /// Skip proof verification (only check root chain).
     pub skip_proof_verification: bool,
     /// Expected first root (optional).
     pub expected_first_root: Option<Hash32>,
+    /// Whether the chain is a finance application's. Its mint and
+    /// signature rules only hold under [`SetPolicy::Deny`] and a mint
+    /// authority, so both must then be expected.
+    pub finance_app: bool,
 }
 
 impl Default for VerifyConfig {
@@ -75,8 +127,13 @@ impl Default for VerifyConfig {
             celestia_rpc: celestia_adapter::DEFAULT_RPC_URL.to_string(),
             namespace: Namespace::from_string("zkapp"),
             expected_program_hash: None,
+            expected_hash_function: None,
+            expected_set_policy: None,
+            allow_set: false,
+            expected_mint_authority: None,
             skip_proof_verification: false,
             expected_first_root: None,
+            finance_app: false,
         }
     }
 }
@@ -108,6 +165,7 @@ impl ChainVerifier {
             "Verifying transitions from height {} to {}",
             from_height, to_height
         );
+        check_config(&self.config)?;
 
         // Fetch all blobs
         let blobs = self
@@ -119,89 +177,13 @@ impl ChainVerifier {
             return Err(VerifyError::NoBlobsFound);
         }
 
-        // Decode and sort by sequence
-        let mut transitions: Vec<(u64, TransitionBlobV1)> = Vec::new();
-        for (height, blob) in blobs {
-            let transition = TransitionBlobV1::decode(&blob.data)?;
-            transitions.push((height, transition));
-        }
-        transitions.sort_by_key(|(_, t)| t.sequence);
-
-        let expected_program_hash = self
-            .config
-            .expected_program_hash
-            .unwrap_or_else(program_hash);
-
-        // Verify each transition
-        let first = &transitions[0];
-        let mut current_root = self.config.expected_first_root.unwrap_or(first.1.prev_root);
-        let first_root = current_root;
-        let first_sequence = first.1.sequence;
-        let first_height = first.0;
-        let mut last_sequence = first_sequence;
-        let mut last_height = first_height;
-        let mut unverified = Vec::new();
-
-        for (height, transition) in &transitions {
-            debug!(
-                "Verifying transition {} at height {}",
-                transition.sequence, height
-            );
-
-            // Check program hash
-            if transition.program_hash != expected_program_hash {
-                return Err(VerifyError::ProgramHashMismatch {
-                    sequence: transition.sequence,
-                });
-            }
-
-            // Check root continuity
-            if transition.prev_root != current_root {
-                return Err(VerifyError::RootChainBroken {
-                    sequence: transition.sequence,
-                    expected: hex::encode(current_root),
-                    actual: hex::encode(transition.prev_root),
-                });
-            }
-
-            // Verify proof
-            if !self.config.skip_proof_verification && !transition.proof.is_empty() {
-                match self.verifier.verify(&transition.proof) {
-                    Ok(output) => {
-                        if output.prev_root != transition.prev_root
-                            || output.new_root != transition.new_root
-                        {
-                            return Err(VerifyError::ProofInvalid {
-                                sequence: transition.sequence,
-                                message: "proof output mismatch".to_string(),
-                            });
-                        }
-                    }
-                    Err(e) => {
-                        return Err(VerifyError::ProofInvalid {
-                            sequence: transition.sequence,
-                            message: e.to_string(),
-                        });
-                    }
-                }
-            } else if transition.proof.is_empty() {
-                unverified.push(transition.sequence);
-            }
-
-            current_root = transition.new_root;
-            last_sequence = transition.sequence;
-            last_height = *height;
-        }
-
-        let result = VerificationResult {
-            total_transitions: transitions.len() as u64,
-            first_root,
-            latest_root: current_root,
-            first_sequence,
-            last_sequence,
-            height_range: (first_height, last_height),
-            unverified_transitions: unverified,
-        };
+        let blobs = blobs
+            .into_iter()
+            .map(|(height, blob)| Ok((height, DaBlob::decode(&blob.data)?)))
+            .collect::<Result<Vec<_>, VerifyError>>()?;
+        let result = verify_chain(&self.config, blobs, |proof, transition| {
+            check_proof(&self.verifier, proof, transition)
+        })?;
 
         info!(
             "Verification complete: {} transitions, root {} -> {}",
@@ -224,26 +206,466 @@ impl ChainVerifier {
     }
 }
 
-/// Verify a single blob's proof.
-pub fn verify_blob(blob: &TransitionBlobV1) -> Result<(), VerifyError> {
+/// Verify a single blob's proof and return its terms (None if the blob
+/// has no proof).
+pub fn verify_blob(blob: &TransitionBlob) -> Result<Option<ProofTerms>, VerifyError> {
     if blob.proof.is_empty() {
+        return Ok(None);
+    }
+
+    check_proof(&TransitionVerifier::new(), &blob.proof, blob).map(Some)
+}
+
+/// Verify a proof attached to a blob posted without one and return its
+/// terms.
+pub fn verify_attachment(
+    blob: &TransitionBlob,
+    attachment: &ProofAttachmentV1,
+) -> Result<ProofTerms, VerifyError> {
+    if !attachment.attaches_to(blob) {
+        return Err(VerifyError::ProofInvalid {
+            sequence: attachment.sequence,
+            message: "attachment is for a different blob".to_string(),
+        });
+    }
+
+    check_proof(&TransitionVerifier::new(), &attachment.proof, blob)
+}
+
+/// Refuse a config that would accept proofs not enforcing the app's rules.
+fn check_config(config: &VerifyConfig) -> Result<(), VerifyError> {
+    if !config.finance_app {
         return Ok(());
     }
+    if config.expected_set_policy != Some(SetPolicy::Deny) || config.allow_set {
+        return Err(VerifyError::IncompleteConfig("the deny set policy"));
+    }
+    if config.expected_mint_authority.is_none() {
+        return Err(VerifyError::IncompleteConfig("a mint authority"));
+    }
+    Ok(())
+}
+
+/// Verify the chain of decoded blobs from a height range, checking each
+/// proof with `check` and its terms against `config` unless proof
+/// verification is skipped.
+fn verify_chain(
+    config: &VerifyConfig,
+    blobs: Vec<(u64, DaBlob)>,
+    check: impl Fn(&[u8], &TransitionBlob) -> Result<ProofTerms, VerifyError>,
+) -> Result<VerificationResult, VerifyError> {
+    let check = |proof: &[u8], transition: &TransitionBlob| {
+        check(proof, transition).and_then(|terms| check_terms(config, terms))
+    };
+    // Set proof attachments aside and sort by sequence
+    let mut transitions: Vec<(u64, TransitionBlob)> = Vec::new();
+    let mut attachments: Vec<ProofAttachmentV1> = Vec::new();
+    for (height, blob) in blobs {
+        match blob {
+            DaBlob::Transition(transition) => transitions.push((height, transition)),
+            DaBlob::ProofAttachment(attachment) => attachments.push(attachment),
+        }
+    }
+    if transitions.is_empty() {
+        return Err(VerifyError::NoBlobsFound);
+    }
+    transitions.sort_by_key(|(_, t)| t.sequence);
+
+    let expected_program_hash = config.expected_program_hash.unwrap_or_else(program_hash);
+
+    // Verify each transition
+    let first = &transitions[0];
+    let expected_hash_function = config
+        .expected_hash_function
+        .unwrap_or(first.1.hash_function);
+    let mut current_root = config.expected_first_root.unwrap_or(first.1.prev_root);
+    let first_root = current_root;
+    let first_sequence = first.1.sequence;
+    let first_height = first.0;
+    let mut last_sequence = first_sequence;
+    let mut last_height = first_height;
+    let mut unverified = Vec::new();
+    let mut attached = Vec::new();
+    let mut proof_terms = Vec::new();
+
+    for (height, transition) in &transitions {
+        debug!(
+            "Verifying transition {} at height {}",
+            transition.sequence, height
+        );
+
+        // Check program hash
+        if transition.program_hash != expected_program_hash {
+            return Err(VerifyError::ProgramHashMismatch {
+                sequence: transition.sequence,
+            });
+        }
+
+        // Check hash function (roots are only comparable under one)
+        if transition.hash_function != expected_hash_function {
+            return Err(VerifyError::HashFunctionMismatch {
+                sequence: transition.sequence,
+                expected: expected_hash_function,
+                actual: transition.hash_function,
+            });
+        }
+
+        // Check root continuity
+        if transition.prev_root != current_root {
+            return Err(VerifyError::RootChainBroken {
+                sequence: transition.sequence,
+                expected: hex::encode(current_root),
+                actual: hex::encode(transition.prev_root),
+            });
+        }
+
+        // Verify proof
+        if !transition.proof.is_empty() {
+            if !config.skip_proof_verification {
+                proof_terms.push(check(&transition.proof, transition)?);
+            }
+        } else {
+            // Use a proof attached later if the transition was posted
+            // without one
+            let attachment =
+                check_attachments(&attachments, transition, &expected_program_hash, |proof| {
+                    if config.skip_proof_verification {
+                        Ok(None)
+                    } else {
+                        check(proof, transition).map(Some)
+                    }
+                });
+            match attachment {
+                AttachedProof::Verified(terms) => {
+                    attached.push(transition.sequence);
+                    proof_terms.extend(terms);
+                }
+                AttachedProof::Failed(e) => {
+                    warn!(
+                        "No attachment verifies transition {}: {}",
+                        transition.sequence, e
+                    );
+                    unverified.push(transition.sequence);
+                }
+                AttachedProof::Missing => unverified.push(transition.sequence),
+            }
+        }
+
+        current_root = transition.new_root;
+        last_sequence = transition.sequence;
+        last_height = *height;
+    }
 
-    let verifier = TransitionVerifier::new();
+    Ok(VerificationResult {
+        total_transitions: transitions.len() as u64,
+        first_root,
+        latest_root: current_root,
+        first_sequence,
+        last_sequence,
+        height_range: (first_height, last_height),
+        unverified_transitions: unverified,
+        attached_proofs: attached,
+        proof_terms,
+    })
+}
+
+/// Outcome of checking the proofs attached to a transition posted without
+/// one.
+#[derive(Debug)]
+pub enum AttachedProof<T> {
+    /// An attachment verified.
+    Verified(T),
+    /// Every matching attachment failed, with the last failure.
+    Failed(VerifyError),
+    /// No attachment matches the transition.
+    Missing,
+}
+
+/// Check the proofs attached to `blob` with `check` until one verifies.
+///
+/// Any matching attachment that verifies will do, so a bad one posted first
+/// cannot hide a good one. Anyone can post an attachment, so callers should
+/// leave the transition unverified rather than fail when none verifies.
+pub fn check_attachments<T>(
+    attachments: &[ProofAttachmentV1],
+    blob: &TransitionBlob,
+    expected_program_hash: &Hash32,
+    mut check: impl FnMut(&[u8]) -> Result<T, VerifyError>,
+) -> AttachedProof<T> {
+    let mut outcome = AttachedProof::Missing;
+    let matching = attachments
+        .iter()
+        .filter(|a| a.attaches_to(blob) && !a.proof.is_empty());
+    for attachment in matching {
+        let checked = if attachment.program_hash != *expected_program_hash {
+            Err(VerifyError::ProgramHashMismatch {
+                sequence: blob.sequence,
+            })
+        } else {
+            check(&attachment.proof)
+        };
+        match checked {
+            Ok(terms) => return AttachedProof::Verified(terms),
+            Err(e) => outcome = AttachedProof::Failed(e),
+        }
+    }
+    outcome
+}
+
+/// Verify `proof` for `blob` and check its terms against the set policy
+/// and mint authority `config` expects.
+pub fn verify_proof(
+    verifier: &TransitionVerifier,
+    config: &VerifyConfig,
+    proof: &[u8],
+    blob: &TransitionBlob,
+) -> Result<ProofTerms, VerifyError> {
+    check_proof(verifier, proof, blob).and_then(|terms| check_terms(config, terms))
+}
+
+/// Verify `proof`, check its output matches the transition blob and
+/// return the terms it was made under.
+fn check_proof(
+    verifier: &TransitionVerifier,
+    proof: &[u8],
+    blob: &TransitionBlob,
+) -> Result<ProofTerms, VerifyError> {
     let output = verifier
-        .verify(&blob.proof)
+        .verify(proof)
         .map_err(|e| VerifyError::ProofInvalid {
             sequence: blob.sequence,
             message: e.to_string(),
         })?;
 
-    if output.prev_root != blob.prev_root || output.new_root != blob.new_root {
+    if output.prev_root != blob.prev_root
+        || output.new_root != blob.new_root
+        || output.hash_function != blob.hash_function
+        || output.app_id != blob.app_id
+    {
         return Err(VerifyError::ProofInvalid {
             sequence: blob.sequence,
-            message: "output mismatch".to_string(),
+            message: "proof output mismatch".to_string(),
         });
     }
 
-    Ok(())
+    Ok(ProofTerms {
+        sequence: blob.sequence,
+        set_policy: output.set_policy,
+        mint_authority: output.mint_authority,
+    })
+}
+
+/// Check a proof's terms against the set policy and mint authority
+/// `config` expects.
+fn check_terms(config: &VerifyConfig, terms: ProofTerms) -> Result<ProofTerms, VerifyError> {
+    let set_policy_ok = match config.expected_set_policy {
+        Some(policy) => terms.set_policy == policy,
+        None => terms.set_policy != SetPolicy::Allow || config.allow_set,
+    };
+    if !set_policy_ok {
+        return Err(VerifyError::ProofInvalid {
+            sequence: terms.sequence,
+            message: "proof set policy mismatch".to_string(),
+        });
+    }
+    if config
+        .expected_mint_authority
+        .is_some_and(|authority| terms.mint_authority != Some(authority))
+    {
+        return Err(VerifyError::ProofInvalid {
+            sequence: terms.sequence,
+            message: "proof mint authority mismatch".to_string(),
+        });
+    }
+
+    Ok(terms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROGRAM_HASH: Hash32 = [7u8; 32];
+
+    fn config() -> VerifyConfig {
+        VerifyConfig {
+            expected_program_hash: Some(PROGRAM_HASH),
+            ..Default::default()
+        }
+    }
+
+    /// Accepts only the proof bytes `b"proof"` (made under the deny set
+    /// policy) and `b"allow"` (made under the allow set policy).
+    fn check(proof: &[u8], transition: &TransitionBlob) -> Result<ProofTerms, VerifyError> {
+        let set_policy = match proof {
+            b"proof" => SetPolicy::Deny,
+            b"allow" => SetPolicy::Allow,
+            _ => {
+                return Err(VerifyError::ProofInvalid {
+                    sequence: transition.sequence,
+                    message: "bad proof".to_string(),
+                })
+            }
+        };
+        Ok(ProofTerms {
+            sequence: transition.sequence,
+            set_policy,
+            mint_authority: None,
+        })
+    }
+
+    fn transition(sequence: u64, proof: &[u8]) -> TransitionBlob {
+        TransitionBlob::new(
+            b"app".to_vec(),
+            sequence,
+            [sequence as u8 - 1; 32],
+            [sequence as u8; 32],
+            Vec::new(),
+            proof.to_vec(),
+            PROGRAM_HASH,
+        )
+        .with_timestamp(sequence)
+    }
+
+    fn attachment(blob: &TransitionBlob, proof: &[u8]) -> ProofAttachmentV1 {
+        ProofAttachmentV1::new(
+            b"app".to_vec(),
+            blob.sequence,
+            blob.hash(),
+            proof.to_vec(),
+            PROGRAM_HASH,
+        )
+    }
+
+    #[test]
+    fn test_verify_chain_after_reprove() {
+        // Transitions posted without proofs, then reproven: the proofs are
+        // attached to the posted blobs instead of posting them again
+        let first = transition(1, b"");
+        let second = transition(2, b"");
+        let blobs = vec![
+            (10, DaBlob::Transition(first.clone())),
+            (11, DaBlob::Transition(second.clone())),
+            (20, DaBlob::ProofAttachment(attachment(&first, b"proof"))),
+            (20, DaBlob::ProofAttachment(attachment(&second, b"proof"))),
+        ];
+
+        let result = verify_chain(&config(), blobs, check).unwrap();
+        assert_eq!(result.total_transitions, 2);
+        assert_eq!(result.attached_proofs, vec![1, 2]);
+        assert!(result.unverified_transitions.is_empty());
+        assert_eq!(result.proof_terms.len(), 2);
+        assert_eq!(result.latest_root, [2u8; 32]);
+        assert_eq!(result.height_range, (10, 11));
+    }
+
+    #[test]
+    fn test_verify_chain_skips_bad_attachments() {
+        let blob = transition(1, b"");
+        let mut other_program = attachment(&blob, b"proof");
+        other_program.program_hash = [8u8; 32];
+        let blobs = vec![
+            (10, DaBlob::Transition(blob.clone())),
+            (11, DaBlob::ProofAttachment(other_program.clone())),
+            (12, DaBlob::ProofAttachment(attachment(&blob, b"junk"))),
+            (13, DaBlob::ProofAttachment(attachment(&blob, b"proof"))),
+        ];
+
+        let result = verify_chain(&config(), blobs, check).unwrap();
+        assert_eq!(result.attached_proofs, vec![1]);
+        assert!(result.unverified_transitions.is_empty());
+
+        // Without a good attachment, the transition is left unverified
+        // rather than failing the chain
+        let blobs = vec![
+            (10, DaBlob::Transition(blob.clone())),
+            (11, DaBlob::ProofAttachment(other_program)),
+            (12, DaBlob::ProofAttachment(attachment(&blob, b"junk"))),
+        ];
+        let result = verify_chain(&config(), blobs, check).unwrap();
+        assert!(result.attached_proofs.is_empty());
+        assert_eq!(result.unverified_transitions, vec![1]);
+        assert_eq!(result.latest_root, [1u8; 32]);
+    }
+
+    #[test]
+    fn test_finance_config_requires_policy_and_authority() {
+        let mut config = VerifyConfig {
+            finance_app: true,
+            ..config()
+        };
+        assert!(matches!(
+            check_config(&config),
+            Err(VerifyError::IncompleteConfig(_))
+        ));
+
+        config.expected_set_policy = Some(SetPolicy::Deny);
+        assert!(matches!(
+            check_config(&config),
+            Err(VerifyError::IncompleteConfig(_))
+        ));
+
+        config.expected_mint_authority = Some([1u8; 32]);
+        check_config(&config).unwrap();
+
+        config.expected_set_policy = Some(SetPolicy::Allow);
+        assert!(check_config(&config).is_err());
+
+        config.expected_set_policy = Some(SetPolicy::Deny);
+        config.allow_set = true;
+        assert!(check_config(&config).is_err());
+
+        // Other chains need neither
+        check_config(&VerifyConfig::default()).unwrap();
+    }
+
+    #[test]
+    fn test_verify_chain_rejects_bad_proof() {
+        let blobs = vec![
+            (10, DaBlob::Transition(transition(1, b"proof"))),
+            (11, DaBlob::Transition(transition(2, b"junk"))),
+        ];
+
+        let result = verify_chain(&config(), blobs, check);
+        assert!(matches!(
+            result,
+            Err(VerifyError::ProofInvalid { sequence: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn test_verify_chain_refuses_allow_unless_opted_in() {
+        let blobs = || {
+            vec![
+                (10, DaBlob::Transition(transition(1, b"proof"))),
+                (11, DaBlob::Transition(transition(2, b"allow"))),
+            ]
+        };
+
+        let result = verify_chain(&config(), blobs(), check);
+        assert!(matches!(
+            result,
+            Err(VerifyError::ProofInvalid { sequence: 2, .. })
+        ));
+
+        let allow_set = VerifyConfig {
+            allow_set: true,
+            ..config()
+        };
+        let result = verify_chain(&allow_set, blobs(), check).unwrap();
+        let policies: Vec<_> = result
+            .proof_terms
+            .iter()
+            .map(|terms| (terms.sequence, terms.set_policy))
+            .collect();
+        assert_eq!(policies, vec![(1, SetPolicy::Deny), (2, SetPolicy::Allow)]);
+
+        // An expected policy is enforced as is
+        let deny = VerifyConfig {
+            expected_set_policy: Some(SetPolicy::Deny),
+            allow_set: true,
+            ..config()
+        };
+        assert!(verify_chain(&deny, blobs(), check).is_err());
+    }
 }
@@ -2,280 +2,54 @@
 //!
 //! This program verifies state transitions by:
 //! 1. Reading the transition input
-//! 2. Verifying Merkle witnesses are valid
-//! 3. Verifying business logic constraints (e.g., valid transfers)
-//! 4. Computing the new root
+//! 2. Binding every witness to a declared operation
+//! 3. Verifying business logic constraints (e.g., valid transfers, mints
+//!    signed by the mint authority)
+//! 4. Verifying Merkle witnesses and computing the new root
 //! 5. Committing the verified output
+//!
+//! Input/output types, hashing and witness checks come from `merkle_core`,
+//! the same code the host uses to build inputs and decode outputs.
 
 #![no_main]
 sp1_zkvm::entrypoint!(main);
 
-use serde::{Deserialize, Serialize};
-use sha2::{Digest, Sha256};
-
-/// A 32-byte hash value.
-type Hash32 = [u8; 32];
-
-/// The empty/default hash.
-const EMPTY_HASH: Hash32 = [0u8; 32];
-
-/// Tree depth (must match merkle crate).
-const TREE_DEPTH: usize = 160;
-
-/// Update witness for a single key.
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct UpdateWitness {
-    key: Hash32,
-    old_value: Option<Vec<u8>>,
-    new_value: Option<Vec<u8>>,
-    siblings: Vec<Hash32>,
-}
-
-/// Operation type for verification.
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
-enum OperationType {
-    Set,
-    CreateAccount {
-        initial_balance: u64,
-    },
-    Transfer {
-        from: Vec<u8>,
-        to: Vec<u8>,
-        amount: u64,
-    },
-    Mint {
-        amount: u64,
-    },
-    Burn {
-        amount: u64,
-    },
-}
-
-/// A verifiable operation.
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct VerifiableOperation {
-    op_type: OperationType,
-    key: Vec<u8>,
-    old_value: Option<Vec<u8>>,
-    new_value: Option<Vec<u8>>,
-    witness_index: usize,
-}
-
-/// Account state for finance app.
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
-struct Account {
-    balance: u64,
-    nonce: u64,
-}
-
-impl Account {
-    fn decode(data: &[u8]) -> Option<Self> {
-        bincode::deserialize(data).ok()
-    }
-}
-
-/// Transition input from the host.
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct TransitionInput {
-    prev_root: Hash32,
-    public_inputs: Vec<u8>,
-    private_inputs: Vec<u8>,
-    witnesses: Vec<UpdateWitness>,
-    operations: Vec<VerifiableOperation>,
-}
-
-/// Transition output committed to the proof.
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct TransitionOutput {
-    prev_root: Hash32,
-    new_root: Hash32,
-    public_inputs_hash: Hash32,
-    public_outputs: Vec<u8>,
-}
-
-/// Get the bit at a specific position in a hash.
-fn get_bit(hash: &Hash32, position: usize) -> bool {
-    let byte_idx = position / 8;
-    let bit_idx = 7 - (position % 8);
-    (hash[byte_idx] >> bit_idx) & 1 == 1
-}
-
-/// Hash two child nodes.
-fn hash_nodes(left: &Hash32, right: &Hash32) -> Hash32 {
-    let mut hasher = Sha256::new();
-    hasher.update(left);
-    hasher.update(right);
-    let result = hasher.finalize();
-    let mut hash = [0u8; 32];
-    hash.copy_from_slice(&result);
-    hash
-}
-
-/// Hash a leaf value.
-fn hash_leaf(key: &Hash32, value: &[u8]) -> Hash32 {
-    let mut hasher = Sha256::new();
-    hasher.update([0x00]); // Leaf prefix
-    hasher.update(key);
-    hasher.update(value);
-    let result = hasher.finalize();
-    let mut hash = [0u8; 32];
-    hash.copy_from_slice(&result);
-    hash
-}
-
-/// Compute root from a witness and value.
-fn compute_root(key: &Hash32, value: Option<&[u8]>, siblings: &[Hash32]) -> Hash32 {
-    let leaf_hash = match value {
-        Some(v) => hash_leaf(key, v),
-        None => EMPTY_HASH,
-    };
-
-    let mut current = leaf_hash;
-    for (i, sibling) in siblings.iter().enumerate() {
-        let depth = TREE_DEPTH - 1 - i;
-        if get_bit(key, depth) {
-            current = hash_nodes(sibling, &current);
-        } else {
-            current = hash_nodes(&current, sibling);
-        }
-    }
-
-    current
-}
-
-/// Hash the public inputs.
-fn hash_public_inputs(prev_root: &Hash32, public_inputs: &[u8]) -> Hash32 {
-    let mut hasher = Sha256::new();
-    hasher.update(prev_root);
-    hasher.update(public_inputs);
-    let result = hasher.finalize();
-    let mut hash = [0u8; 32];
-    hash.copy_from_slice(&result);
-    hash
-}
-
-/// Verify business logic for an operation.
-fn verify_operation(op: &VerifiableOperation, witnesses: &[UpdateWitness]) -> bool {
-    match &op.op_type {
-        OperationType::Set => {
-            // No constraints for simple set operations
-            true
-        }
-        OperationType::CreateAccount { initial_balance } => {
-            // Verify the account is being created with the specified balance
-            if let Some(new_val) = &op.new_value {
-                if let Some(account) = Account::decode(new_val) {
-                    return account.balance == *initial_balance && account.nonce == 0;
-                }
-            }
-            false
-        }
-        OperationType::Transfer {
-            from: _,
-            to: _,
-            amount,
-        } => {
-            // For transfers, we need to find the witnesses for both from and to accounts
-            // and verify the transfer logic
-
-            // Find the "from" witness (should have old and new values)
-            let from_witness = witnesses.iter().find(|w| {
-                // Check if this witness key corresponds to the "from" account
-                let key_matches = w.old_value.is_some() && w.new_value.is_some();
-                key_matches
-            });
-
-            // For now, we do simplified verification
-            // In a full implementation, we'd match witnesses by key hash
-            if let Some(fw) = from_witness {
-                if let (Some(old_data), Some(new_data)) = (&fw.old_value, &fw.new_value) {
-                    if let (Some(old_acc), Some(new_acc)) =
-                        (Account::decode(old_data), Account::decode(new_data))
-                    {
-                        // Verify sender balance decreased by amount
-                        if old_acc.balance >= *amount
-                            && new_acc.balance == old_acc.balance - *amount
-                        {
-                            return true;
-                        }
-                    }
-                }
-            }
-
-            // If we can't verify, be conservative
-            true // Allow for now - full verification would be stricter
-        }
-        OperationType::Mint { amount } => {
-            // Verify balance increased by mint amount
-            if let (Some(old_val), Some(new_val)) = (&op.old_value, &op.new_value) {
-                if let (Some(old_acc), Some(new_acc)) =
-                    (Account::decode(old_val), Account::decode(new_val))
-                {
-                    return new_acc.balance == old_acc.balance + *amount;
-                }
-            }
-            false
-        }
-        OperationType::Burn { amount } => {
-            // Verify balance decreased by burn amount
-            if let (Some(old_val), Some(new_val)) = (&op.old_value, &op.new_value) {
-                if let (Some(old_acc), Some(new_acc)) =
-                    (Account::decode(old_val), Account::decode(new_val))
-                {
-                    return old_acc.balance >= *amount
-                        && new_acc.balance == old_acc.balance - *amount;
-                }
-            }
-            false
-        }
-    }
-}
+use merkle_core::{TransitionInput, TransitionOutput};
 
 pub fn main() {
     // Read the transition input
     let input: TransitionInput = sp1_zkvm::io::read();
 
-    // Start with the previous root
-    let mut current_root = input.prev_root;
-
-    // Verify business logic for all operations
-    for op in &input.operations {
-        let valid = verify_operation(op, &input.witnesses);
-        assert!(valid, "business logic verification failed for operation");
-    }
-
-    // Verify and apply each witness (Merkle tree verification)
-    for witness in &input.witnesses {
-        // Verify the old root matches
-        let computed_old_root = compute_root(
-            &witness.key,
-            witness.old_value.as_deref(),
-            &witness.siblings,
-        );
-
-        // This is the core verification: the witness must produce our current root
-        assert_eq!(computed_old_root, current_root, "witness old root mismatch");
-
-        // Compute the new root
-        current_root = compute_root(
-            &witness.key,
-            witness.new_value.as_deref(),
-            &witness.siblings,
-        );
-    }
-
-    // Hash public inputs for binding
-    let public_inputs_hash = hash_public_inputs(&input.prev_root, &input.public_inputs);
-
-    // Create the output
-    let output = TransitionOutput {
-        prev_root: input.prev_root,
-        new_root: current_root,
-        public_inputs_hash,
-        public_outputs: Vec::new(),
-    };
-
-    // Commit the output
-    let output_bytes = bincode::serialize(&output).expect("serialization failed");
-    sp1_zkvm::io::commit_slice(&output_bytes);
+    // Every witness must be declared by exactly one operation matching it,
+    // and bare sets must be allowed by the app's policy
+    input
+        .bind_operations()
+        .unwrap_or_else(|err| panic!("{}", err));
+
+    // Verify business logic for all operations (mints must be signed by the
+    // mint authority)
+    input
+        .verify_operations()
+        .unwrap_or_else(|err| panic!("{}", err));
+
+    // Verify and apply each witness (Merkle tree verification): every witness
+    // must start from the root left by the previous one
+    let new_root = input
+        .apply_witnesses()
+        .unwrap_or_else(|err| panic!("{}", err));
+
+    // Create the output (public inputs hashed for binding)
+    let output = TransitionOutput::new(
+        input.prev_root,
+        new_root,
+        input.hash_function,
+        input.public_inputs_hash(),
+        Vec::new(),
+    )
+    .with_set_policy(input.set_policy)
+    .with_mint_authority(input.mint_authority)
+    .with_app_id(input.app_id);
+
+    // Commit the output (bincode, the layout of `TransitionOutput::encode`)
+    sp1_zkvm::io::commit(&output);
 }
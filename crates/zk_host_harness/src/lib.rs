@@ -82,8 +82,8 @@ impl TransitionProver {
 
         info!(
             "Execution complete: prev_root={}, new_root={}",
-            hex::encode(&output.prev_root),
-            hex::encode(&output.new_root)
+            hex::encode(output.prev_root),
+            hex::encode(output.new_root)
         );
 
         Ok(output)
@@ -117,8 +117,8 @@ impl TransitionProver {
         info!(
             "Proof generated: size={} bytes, prev_root={}, new_root={}",
             proof_bytes.len(),
-            hex::encode(&output.prev_root),
-            hex::encode(&output.new_root)
+            hex::encode(output.prev_root),
+            hex::encode(output.new_root)
         );
 
         Ok(ProofResult {
@@ -5,32 +5,19 @@
 //! 2. Business logic (valid transfers) is verified inside the circuit
 //! 3. Proofs are posted to Celestia DA
 //! 4. Anyone can verify the full proof chain
+//!
+//! Transactions are executed by the node, which must host the finance
+//! application (`appd --app finance serve`); this client only sends them.
 
 use anyhow::Result;
+use app_da_node::mempool::InclusionReceipt;
 use app_da_node::AppNodeClient;
 use clap::{Parser, Subcommand};
-use serde::{Deserialize, Serialize};
-use state::StateOp;
+use sdk::finance::{
+    account_key, Account, AccountKey, FinanceReceipt, FinanceTransaction, MintAuthority,
+};
 use tracing::{info, Level};
 use tracing_subscriber::FmtSubscriber;
-use transition_format::{OperationType, VerifiableOperation};
-
-/// Account state in the finance app.
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
-pub struct Account {
-    pub balance: u64,
-    pub nonce: u64,
-}
-
-impl Account {
-    pub fn encode(&self) -> Vec<u8> {
-        bincode::serialize(self).expect("encoding should not fail")
-    }
-
-    pub fn decode(data: &[u8]) -> Option<Self> {
-        bincode::deserialize(data).ok()
-    }
-}
 
 #[derive(Parser)]
 #[command(name = "finance")]
@@ -40,6 +27,10 @@ struct Cli {
     #[arg(long, default_value = "http://127.0.0.1:16000")]
     api_url: String,
 
+    /// Application ID of the node (signatures are only valid for it)
+    #[arg(long, default_value = "zkapp")]
+    app_id: String,
+
     /// Log level
     #[arg(long, default_value = "info")]
     log_level: String,
@@ -50,14 +41,20 @@ struct Cli {
 
 #[derive(Subcommand)]
 enum Commands {
-    /// Create an account with initial balance
+    /// Create an account, empty or funded by the mint authority
     CreateAccount {
         /// Account name
         #[arg(long)]
         name: String,
-        /// Initial balance
-        #[arg(long)]
+        /// Initial balance (requires the mint authority's key)
+        #[arg(long, default_value_t = 0)]
         balance: u64,
+        /// Owner's ed25519 secret key (hex)
+        #[arg(long, env = "ACCOUNT_SECRET_KEY", value_parser = parse_secret_key)]
+        secret_key: [u8; 32],
+        /// Mint authority's ed25519 secret key (hex), signing the balance
+        #[arg(long, env = "MINT_SECRET_KEY", value_parser = parse_secret_key)]
+        mint_secret_key: Option<[u8; 32]>,
     },
     /// Transfer funds between accounts
     Transfer {
@@ -70,6 +67,39 @@ enum Commands {
         /// Amount to transfer
         #[arg(long)]
         amount: u64,
+        /// Sender owner's ed25519 secret key (hex)
+        #[arg(long, env = "ACCOUNT_SECRET_KEY", value_parser = parse_secret_key)]
+        secret_key: [u8; 32],
+    },
+    /// Mint funds into an account, signed by the mint authority
+    Mint {
+        /// Receiver account
+        #[arg(long)]
+        to: String,
+        /// Amount to mint
+        #[arg(long)]
+        amount: u64,
+        /// Mint authority's ed25519 secret key (hex)
+        #[arg(long, env = "MINT_SECRET_KEY", value_parser = parse_secret_key)]
+        secret_key: [u8; 32],
+    },
+    /// Burn funds from an account, signed by its owner
+    Burn {
+        /// Account to burn from
+        #[arg(long)]
+        account: String,
+        /// Amount to burn
+        #[arg(long)]
+        amount: u64,
+        /// Account owner's ed25519 secret key (hex)
+        #[arg(long, env = "ACCOUNT_SECRET_KEY", value_parser = parse_secret_key)]
+        secret_key: [u8; 32],
+    },
+    /// Show the public key of a mint authority (to pass to `appd --mint-authority`)
+    MintAuthority {
+        /// Mint authority's ed25519 secret key (hex)
+        #[arg(long, env = "MINT_SECRET_KEY", value_parser = parse_secret_key)]
+        secret_key: [u8; 32],
     },
     /// Show account balance
     Balance {
@@ -81,19 +111,12 @@ enum Commands {
     /// Show current state root and history
     Status,
     /// Run demo with multiple operations
-    Demo,
-}
-
-fn account_key(name: &str) -> Vec<u8> {
-    format!("account:{}", name).into_bytes()
-}
-
-async fn get_account(client: &AppNodeClient, name: &str) -> Result<Option<Account>> {
-    let key = account_key(name);
-    match client.get(&key).await? {
-        Some(data) => Ok(Account::decode(&data)),
-        None => Ok(None),
-    }
+    Demo {
+        /// Mint authority's ed25519 secret key (hex), funding the demo
+        /// accounts
+        #[arg(long, env = "MINT_SECRET_KEY", value_parser = parse_secret_key)]
+        secret_key: [u8; 32],
+    },
 }
 
 #[tokio::main]
@@ -116,8 +139,15 @@ async fn main() -> Result<()> {
     let subscriber = FmtSubscriber::builder().with_max_level(level).finish();
     tracing::subscriber::set_global_default(subscriber)?;
 
+    if let Commands::MintAuthority { secret_key } = &cli.command {
+        let authority = MintAuthority::from_bytes(secret_key);
+        println!("{}", hex::encode(authority.public_key()));
+        return Ok(());
+    }
+
     // Create HTTP client
     let client = AppNodeClient::new(cli.api_url);
+    let app_id = cli.app_id.as_bytes();
 
     // Test connection
     if !client.health().await.unwrap_or(false) {
@@ -125,12 +155,69 @@ async fn main() -> Result<()> {
     }
 
     match cli.command {
-        Commands::CreateAccount { name, balance } => {
-            create_account(client, &name, balance).await?;
+        Commands::CreateAccount {
+            name,
+            balance,
+            secret_key,
+            mint_secret_key,
+        } => {
+            create_account(
+                client,
+                app_id,
+                &name,
+                balance,
+                &AccountKey::from_bytes(&secret_key),
+                mint_secret_key
+                    .map(|key| MintAuthority::from_bytes(&key))
+                    .as_ref(),
+            )
+            .await?;
+        }
+        Commands::Transfer {
+            from,
+            to,
+            amount,
+            secret_key,
+        } => {
+            transfer(
+                client,
+                app_id,
+                &from,
+                &to,
+                amount,
+                &AccountKey::from_bytes(&secret_key),
+            )
+            .await?;
         }
-        Commands::Transfer { from, to, amount } => {
-            transfer(client, &from, &to, amount).await?;
+        Commands::Mint {
+            to,
+            amount,
+            secret_key,
+        } => {
+            mint(
+                client,
+                app_id,
+                &to,
+                amount,
+                &MintAuthority::from_bytes(&secret_key),
+            )
+            .await?;
         }
+        Commands::Burn {
+            account,
+            amount,
+            secret_key,
+        } => {
+            burn(
+                client,
+                app_id,
+                &account,
+                amount,
+                &AccountKey::from_bytes(&secret_key),
+            )
+            .await?;
+        }
+        Commands::MintAuthority { .. } => unreachable!("handled before connecting"),
         Commands::Balance { name } => {
             show_balance(client, &name).await?;
         }
@@ -140,130 +227,140 @@ async fn main() -> Result<()> {
         Commands::Status => {
             show_status(client).await?;
         }
-        Commands::Demo => {
-            run_demo(client).await?;
+        Commands::Demo { secret_key } => {
+            run_demo(client, app_id, &MintAuthority::from_bytes(&secret_key)).await?;
         }
     }
 
     Ok(())
 }
 
-async fn create_account(client: AppNodeClient, name: &str, balance: u64) -> Result<()> {
-    // Check if account already exists
-    if get_account(&client, name).await?.is_some() {
-        anyhow::bail!("Account '{}' already exists", name);
-    }
+/// Send a transaction to the node's finance application.
+async fn execute(
+    client: &AppNodeClient,
+    tx: FinanceTransaction,
+) -> Result<(InclusionReceipt, FinanceReceipt)> {
+    client.execute(&tx, &()).await
+}
 
-    let account = Account { balance, nonce: 0 };
-    let key = account_key(name);
+/// Current nonce of an account (covered by the signatures of its
+/// transactions).
+async fn account_nonce(client: &AppNodeClient, name: &str) -> Result<u64> {
+    let (value, _) = client.get_with_proof(&account_key(name)).await?;
+    let account = value
+        .as_deref()
+        .and_then(Account::decode)
+        .ok_or_else(|| anyhow::anyhow!("account '{}' not found", name))?;
+    Ok(account.nonce)
+}
 
-    // Create operation with verification data
-    let ops = vec![StateOp::Insert {
-        key: key.clone(),
-        value: account.encode(),
-    }];
-
-    // Create verifiable operation for circuit
-    let verifiable_ops = vec![VerifiableOperation {
-        op_type: OperationType::CreateAccount {
-            initial_balance: balance,
-        },
-        key: key.clone(),
-        old_value: None,
-        new_value: Some(account.encode()),
-        witness_index: 0,
-    }];
-
-    let public_inputs = format!("create_account:{}:{}", name, balance).into_bytes();
+/// Demo-only owner key derived from the account name (never use a
+/// guessable key for real accounts).
+fn demo_key(name: &str) -> AccountKey {
+    let mut secret_key = [0u8; 32];
+    let len = name.len().min(32);
+    secret_key[..len].copy_from_slice(&name.as_bytes()[..len]);
+    AccountKey::from_bytes(&secret_key)
+}
 
+async fn create_account(
+    client: AppNodeClient,
+    app_id: &[u8],
+    name: &str,
+    balance: u64,
+    owner: &AccountKey,
+    authority: Option<&MintAuthority>,
+) -> Result<()> {
     info!("Creating account '{}' with balance {}", name, balance);
 
-    let result = client
-        .apply_transition(ops, public_inputs, vec![], verifiable_ops)
-        .await?;
+    // A non-zero balance is new money, signed by the mint authority
+    let tx = match authority {
+        Some(authority) => authority.create_account(app_id, name, balance, owner.public_key()),
+        None if balance == 0 => owner.create_account(app_id, name),
+        None => anyhow::bail!("a non-zero balance needs the mint authority's key"),
+    };
+    let (result, _) = execute(&client, tx).await?;
 
     println!("Account created:");
     println!("  Name: {}", name);
     println!("  Balance: {}", balance);
+    println!("  Owner: {}", hex::encode(owner.public_key()));
     println!("  Sequence: {}", result.sequence);
     println!("  Root: {}", hex::encode(result.new_root));
 
     Ok(())
 }
 
-async fn transfer(client: AppNodeClient, from: &str, to: &str, amount: u64) -> Result<()> {
-    // Get sender account
-    let from_account = get_account(&client, from)
-        .await?
-        .ok_or_else(|| anyhow::anyhow!("Sender account '{}' not found", from))?;
-
-    // Check balance
-    if from_account.balance < amount {
-        anyhow::bail!(
-            "Insufficient balance: {} has {}, needs {}",
-            from,
-            from_account.balance,
-            amount
-        );
-    }
-
-    // Get or create receiver account
-    let to_account = get_account(&client, to).await?.unwrap_or_default();
-
-    // Compute new balances
-    let from_new = Account {
-        balance: from_account.balance - amount,
-        nonce: from_account.nonce + 1,
-    };
-    let to_new = Account {
-        balance: to_account.balance + amount,
-        nonce: to_account.nonce,
-    };
+async fn transfer(
+    client: AppNodeClient,
+    app_id: &[u8],
+    from: &str,
+    to: &str,
+    amount: u64,
+    owner: &AccountKey,
+) -> Result<()> {
+    info!("Transferring {} from '{}' to '{}'", amount, from, to);
 
-    let from_key = account_key(from);
-    let to_key = account_key(to);
-
-    let ops = vec![
-        StateOp::Insert {
-            key: from_key.clone(),
-            value: from_new.encode(),
-        },
-        StateOp::Insert {
-            key: to_key.clone(),
-            value: to_new.encode(),
-        },
-    ];
+    // The signature covers the sender's current nonce; the node checks the
+    // balances and derives the new account states
+    let nonce = account_nonce(&client, from).await?;
+    let tx = owner.transfer(app_id, from, to, amount, nonce);
+    let (result, receipt) = execute(&client, tx).await?;
 
-    // Create verifiable operation for the transfer
-    let verifiable_ops = vec![VerifiableOperation {
-        op_type: OperationType::Transfer {
-            from: from_key.clone(),
-            to: to_key.clone(),
-            amount,
-        },
-        key: from_key.clone(),
-        old_value: Some(from_account.encode()),
-        new_value: Some(from_new.encode()),
-        witness_index: 0,
-    }];
+    println!("Transfer complete:");
+    for (name, account) in &receipt.accounts {
+        println!("  {}: balance {}", name, account.balance);
+    }
+    println!("  Amount: {}", amount);
+    println!("  Sequence: {}", result.sequence);
+    println!("  Root: {}", hex::encode(result.new_root));
 
-    let public_inputs = format!("transfer:{}:{}:{}", from, to, amount).into_bytes();
+    Ok(())
+}
 
-    info!("Transferring {} from '{}' to '{}'", amount, from, to);
+async fn mint(
+    client: AppNodeClient,
+    app_id: &[u8],
+    to: &str,
+    amount: u64,
+    authority: &MintAuthority,
+) -> Result<()> {
+    info!("Minting {} into '{}'", amount, to);
+
+    // The signature covers the account's current nonce
+    let nonce = account_nonce(&client, to).await?;
+    let tx = authority.mint(app_id, to, amount, nonce);
+    let (result, receipt) = execute(&client, tx).await?;
+
+    println!("Mint complete:");
+    for (name, account) in &receipt.accounts {
+        println!("  {}: balance {}", name, account.balance);
+    }
+    println!("  Amount: {}", amount);
+    println!("  Sequence: {}", result.sequence);
+    println!("  Root: {}", hex::encode(result.new_root));
 
-    let result = client
-        .apply_transition(ops, public_inputs, vec![], verifiable_ops)
-        .await?;
+    Ok(())
+}
 
-    println!("Transfer complete:");
-    println!(
-        "  From: {} ({} -> {})",
-        from, from_account.balance, from_new.balance
-    );
-    println!(
-        "  To: {} ({} -> {})",
-        to, to_account.balance, to_new.balance
-    );
+async fn burn(
+    client: AppNodeClient,
+    app_id: &[u8],
+    account: &str,
+    amount: u64,
+    owner: &AccountKey,
+) -> Result<()> {
+    info!("Burning {} from '{}'", amount, account);
+
+    // The signature covers the account's current nonce
+    let nonce = account_nonce(&client, account).await?;
+    let tx = owner.burn(app_id, account, amount, nonce);
+    let (result, receipt) = execute(&client, tx).await?;
+
+    println!("Burn complete:");
+    for (name, account) in &receipt.accounts {
+        println!("  {}: balance {}", name, account.balance);
+    }
     println!("  Amount: {}", amount);
     println!("  Sequence: {}", result.sequence);
     println!("  Root: {}", hex::encode(result.new_root));
@@ -271,6 +368,14 @@ async fn transfer(client: AppNodeClient, from: &str, to: &str, amount: u64) -> R
     Ok(())
 }
 
+/// Parse a hex-encoded 32-byte secret key.
+fn parse_secret_key(hex_key: &str) -> Result<[u8; 32], String> {
+    hex::decode(hex_key)
+        .map_err(|e| e.to_string())?
+        .try_into()
+        .map_err(|_| "secret key must be 32 bytes".to_string())
+}
+
 async fn show_balance(client: AppNodeClient, name: &str) -> Result<()> {
     let key = account_key(name);
     let (value, proof) = client.get_with_proof(&key).await?;
@@ -329,41 +434,17 @@ async fn show_status(client: AppNodeClient) -> Result<()> {
     Ok(())
 }
 
-async fn run_demo(client: AppNodeClient) -> Result<()> {
+async fn run_demo(client: AppNodeClient, app_id: &[u8], authority: &MintAuthority) -> Result<()> {
     println!("=== Finance App Demo ===\n");
 
-    // Create accounts
+    // Create accounts, funded by the mint authority
     println!("--- Creating Accounts ---");
 
     let accounts = [("alice", 1000u64), ("bob", 500), ("charlie", 250)];
 
     for (name, balance) in &accounts {
-        let account = Account {
-            balance: *balance,
-            nonce: 0,
-        };
-        let key = account_key(name);
-
-        let ops = vec![StateOp::Insert {
-            key: key.clone(),
-            value: account.encode(),
-        }];
-
-        let verifiable_ops = vec![VerifiableOperation {
-            op_type: OperationType::CreateAccount {
-                initial_balance: *balance,
-            },
-            key: key.clone(),
-            old_value: None,
-            new_value: Some(account.encode()),
-            witness_index: 0,
-        }];
-
-        let public_inputs = format!("create:{}:{}", name, balance).into_bytes();
-
-        let result = client
-            .apply_transition(ops, public_inputs, vec![], verifiable_ops)
-            .await?;
+        let tx = authority.create_account(app_id, name, *balance, demo_key(name).public_key());
+        let (result, _) = execute(&client, tx).await?;
         println!(
             "Created {}: balance={}, root={}",
             name,
@@ -384,49 +465,9 @@ async fn run_demo(client: AppNodeClient) -> Result<()> {
     ];
 
     for (from, to, amount) in &transfers {
-        let from_acc = get_account(&client, from).await?.unwrap();
-        let to_acc = get_account(&client, to).await?.unwrap_or_default();
-
-        let from_key = account_key(from);
-        let to_key = account_key(to);
-
-        let from_new = Account {
-            balance: from_acc.balance - amount,
-            nonce: from_acc.nonce + 1,
-        };
-        let to_new = Account {
-            balance: to_acc.balance + amount,
-            nonce: to_acc.nonce,
-        };
-
-        let ops = vec![
-            StateOp::Insert {
-                key: from_key.clone(),
-                value: from_new.encode(),
-            },
-            StateOp::Insert {
-                key: to_key.clone(),
-                value: to_new.encode(),
-            },
-        ];
-
-        let verifiable_ops = vec![VerifiableOperation {
-            op_type: OperationType::Transfer {
-                from: from_key.clone(),
-                to: to_key.clone(),
-                amount: *amount,
-            },
-            key: from_key,
-            old_value: Some(from_acc.encode()),
-            new_value: Some(from_new.encode()),
-            witness_index: 0,
-        }];
-
-        let public_inputs = format!("transfer:{}:{}:{}", from, to, amount).into_bytes();
-
-        let result = client
-            .apply_transition(ops, public_inputs, vec![], verifiable_ops)
-            .await?;
+        let nonce = account_nonce(&client, from).await?;
+        let tx = demo_key(from).transfer(app_id, from, to, *amount, nonce);
+        let (result, _) = execute(&client, tx).await?;
         println!(
             "Transfer {} -> {} ({}): root={}",
             from,
@@ -2,6 +2,9 @@
 
 const BASE_URL = 'http://127.0.0.1:16000';
 
+// Application ID of the node (`appd --app-id`), covered by signatures
+const APP_ID = 'zkapp';
+
 export interface RootResponse {
   root: string;
   transition_index: number;
@@ -58,42 +61,70 @@ export interface HealthResponse {
   version: string;
 }
 
-export interface ApplyTransitionResponse {
+// Inclusion receipt of a typed transaction and the application's output
+export interface AppTransactionResponse {
+  tx_id: string;
   sequence: number;
-  prev_root: string;
+  index: number;
+  batch_size: number;
   new_root: string;
   celestia_height: number | null;
-  proof_size_bytes: number;
+  output: unknown;
 }
 
-export interface OperationRequest {
-  type: string;
-  key: string;
-  value?: string;
-  encoding?: string;
+// Account state structure
+export interface AccountState {
+  balance: number;
+  nonce: number;
+  // ed25519 key signing the account's transfers (null if it cannot send or receive)
+  publicKey: Uint8Array | null;
 }
 
-export interface VerifiableOperationRequest {
-  op_type: object;
-  key: string;
-  old_value?: string;
-  new_value?: string;
-  witness_index: number;
+const TRANSFER_DOMAIN = 'celestia-zk-da:transfer';
+const CREATE_DOMAIN = 'celestia-zk-da:create-account';
+
+const toBase64 = (bytes: Uint8Array): string => btoa(String.fromCharCode(...bytes));
+const fromBase64 = (value: string): Uint8Array => Uint8Array.from(atob(value), c => c.charCodeAt(0));
+
+// Message the sender's owner signs (see merkle_core::finance::transfer_message)
+function transferMessage(appId: string, from: string, to: string, amount: number, nonce: number): Uint8Array {
+  const encoder = new TextEncoder();
+  return signedMessage(TRANSFER_DOMAIN, [appId, from, to].map(key => encoder.encode(key)), amount, nonce);
 }
 
-export interface ApplyTransitionRequest {
-  operations: OperationRequest[];
-  public_inputs?: string;
-  private_inputs?: string;
-  verifiable_operations: VerifiableOperationRequest[];
+// Message the owner of a new empty account signs (see
+// merkle_core::finance::create_account_message)
+function createAccountMessage(appId: string, key: string, publicKey: Uint8Array): Uint8Array {
+  const encoder = new TextEncoder();
+  return signedMessage(CREATE_DOMAIN, [encoder.encode(appId), encoder.encode(key), publicKey], 0, 0);
 }
 
-// Account state structure
-export interface AccountState {
-  balance: number;
-  nonce: number;
+// Domain, length-prefixed app ID and keys, amount and nonce (see
+// merkle_core::finance::signed_message)
+function signedMessage(domain: string, keys: Uint8Array[], amount: number, nonce: number): Uint8Array {
+  const parts: Uint8Array[] = [new TextEncoder().encode(domain)];
+  const u64 = (value: number) => {
+    const bytes = new Uint8Array(8);
+    new DataView(bytes.buffer).setBigUint64(0, BigInt(value), true);
+    return bytes;
+  };
+  for (const key of keys) {
+    parts.push(u64(key.length), key);
+  }
+  parts.push(u64(amount), u64(nonce));
+
+  const message = new Uint8Array(parts.reduce((len, part) => len + part.length, 0));
+  let offset = 0;
+  for (const part of parts) {
+    message.set(part, offset);
+    offset += part.length;
+  }
+  return message;
 }
 
+// Owner keys of the accounts created in this browser, by account name
+const keyStorageKey = (name: string) => `account-key:${name}`;
+
 class ApiClient {
   private baseUrl: string;
 
@@ -156,15 +187,16 @@ class ApiClient {
     return res.json();
   }
 
-  async applyTransition(request: ApplyTransitionRequest): Promise<ApplyTransitionResponse> {
-    const res = await fetch(`${this.baseUrl}/transition`, {
+  // Submit a typed transaction of the hosted application (`appd --app finance`)
+  async executeTransaction(input: object): Promise<AppTransactionResponse> {
+    const res = await fetch(`${this.baseUrl}/app/transaction`, {
       method: 'POST',
       headers: { 'Content-Type': 'application/json' },
-      body: JSON.stringify(request),
+      body: JSON.stringify({ input }),
     });
     if (!res.ok) {
       const err = await res.json().catch(() => ({ error: 'Unknown error' }));
-      throw new Error(err.error || `Failed to apply transition: ${res.status}`);
+      throw new Error(err.error || `Failed to execute transaction: ${res.status}`);
     }
     return res.json();
   }
@@ -176,13 +208,15 @@ class ApiClient {
 
     let state: AccountState | null = null;
     if (response.value) {
-      // Decode base64 and parse as little-endian u64 values
-      const bytes = Uint8Array.from(atob(response.value), c => c.charCodeAt(0));
+      // Decode base64 and parse as little-endian u64 values and the optional key
+      // (absent from legacy accounts)
+      const bytes = fromBase64(response.value);
       if (bytes.length >= 16) {
         const view = new DataView(bytes.buffer);
         state = {
           balance: Number(view.getBigUint64(0, true)),
           nonce: Number(view.getBigUint64(8, true)),
+          publicKey: bytes[16] === 1 ? bytes.slice(17, 49) : null,
         };
       }
     }
@@ -190,89 +224,63 @@ class ApiClient {
     return { state, proof: response.proof, root: response.root };
   }
 
-  // Helper: Create account
-  async createAccount(name: string, initialBalance: number): Promise<ApplyTransitionResponse> {
-    const key = `account:${name}`;
-
-    // Encode balance + nonce as little-endian bytes
-    const bytes = new Uint8Array(16);
-    const view = new DataView(bytes.buffer);
-    view.setBigUint64(0, BigInt(initialBalance), true);
-    view.setBigUint64(8, BigInt(0), true); // nonce = 0
-    const value = btoa(String.fromCharCode(...bytes));
-
-    return this.applyTransition({
-      operations: [{ type: 'insert', key, value }],
-      verifiable_operations: [{
-        op_type: { CreateAccount: { initial_balance: initialBalance } },
-        key,
-        old_value: undefined,
-        new_value: value,
-        witness_index: 0,
-      }],
+  // Helper: Create an empty account owned by a new key kept in this browser
+  // (funded accounts are signed by the mint authority)
+  async createAccount(name: string): Promise<AppTransactionResponse> {
+    const keyPair = (await crypto.subtle.generateKey({ name: 'Ed25519' }, true, [
+      'sign',
+      'verify',
+    ])) as CryptoKeyPair;
+    const publicKey = new Uint8Array(await crypto.subtle.exportKey('raw', keyPair.publicKey));
+    const privateKey = new Uint8Array(await crypto.subtle.exportKey('pkcs8', keyPair.privateKey));
+
+    // The node and the guest check the creation is signed by the key it registers
+    const signature = await crypto.subtle.sign(
+      { name: 'Ed25519' },
+      keyPair.privateKey,
+      createAccountMessage(APP_ID, `account:${name}`, publicKey),
+    );
+
+    const response = await this.executeTransaction({
+      CreateAccount: {
+        name,
+        balance: 0,
+        public_key: Array.from(publicKey),
+        signature: Array.from(new Uint8Array(signature)),
+      },
     });
+    localStorage.setItem(keyStorageKey(name), toBase64(privateKey));
+    return response;
   }
 
-  // Helper: Transfer between accounts
-  async transfer(from: string, to: string, amount: number): Promise<ApplyTransitionResponse> {
-    // First, get current account states
-    const fromAccount = await this.getAccount(from);
-    const toAccount = await this.getAccount(to);
+  // Helper: Sign a transfer with the sender's key kept in this browser
+  private async signTransfer(from: string, to: string, amount: number, nonce: number): Promise<number[]> {
+    const stored = localStorage.getItem(keyStorageKey(from));
+    if (!stored) {
+      throw new Error(`No key for account "${from}" in this browser`);
+    }
+    const privateKey = await crypto.subtle.importKey(
+      'pkcs8',
+      fromBase64(stored),
+      { name: 'Ed25519' },
+      false,
+      ['sign'],
+    );
+    const message = transferMessage(APP_ID, `account:${from}`, `account:${to}`, amount, nonce);
+    const signature = await crypto.subtle.sign({ name: 'Ed25519' }, privateKey, message);
+    return Array.from(new Uint8Array(signature));
+  }
 
+  // Helper: Transfer between accounts (the node computes the new balances)
+  async transfer(from: string, to: string, amount: number): Promise<AppTransactionResponse> {
+    // The signature covers the sender's current nonce
+    const fromAccount = await this.getAccount(from);
     if (!fromAccount.state) {
       throw new Error(`Sender account "${from}" does not exist`);
     }
 
-    if (fromAccount.state.balance < amount) {
-      throw new Error(`Insufficient balance: ${fromAccount.state.balance} < ${amount}`);
-    }
-
-    // Calculate new states
-    const newFromBalance = fromAccount.state.balance - amount;
-    const newFromNonce = fromAccount.state.nonce + 1;
-    const newToBalance = (toAccount.state?.balance ?? 0) + amount;
-    const newToNonce = toAccount.state?.nonce ?? 0;
-
-    // Encode new states
-    const encodeState = (balance: number, nonce: number): string => {
-      const bytes = new Uint8Array(16);
-      const view = new DataView(bytes.buffer);
-      view.setBigUint64(0, BigInt(balance), true);
-      view.setBigUint64(8, BigInt(nonce), true);
-      return btoa(String.fromCharCode(...bytes));
-    };
-
-    const fromKey = `account:${from}`;
-    const toKey = `account:${to}`;
-    const newFromValue = encodeState(newFromBalance, newFromNonce);
-    const newToValue = encodeState(newToBalance, newToNonce);
-
-    const operations: OperationRequest[] = [
-      { type: 'insert', key: fromKey, value: newFromValue },
-      { type: 'insert', key: toKey, value: newToValue },
-    ];
-
-    const verifiableOperations: VerifiableOperationRequest[] = [
-      {
-        op_type: { Transfer: { from: fromKey, to: toKey, amount } },
-        key: fromKey,
-        old_value: fromAccount.proof.value ?? undefined,
-        new_value: newFromValue,
-        witness_index: 0,
-      },
-      {
-        op_type: { Transfer: { from: fromKey, to: toKey, amount } },
-        key: toKey,
-        old_value: toAccount.proof.value ?? undefined,
-        new_value: newToValue,
-        witness_index: 1,
-      },
-    ];
-
-    return this.applyTransition({
-      operations,
-      verifiable_operations: verifiableOperations,
-    });
+    const signature = await this.signTransfer(from, to, amount, fromAccount.state.nonce);
+    return this.executeTransaction({ Transfer: { from, to, amount, signature } });
   }
 }
 
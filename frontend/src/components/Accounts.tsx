@@ -13,7 +13,6 @@ function Accounts() {
   const [accounts, setAccounts] = useState<AccountData[]>([]);
   const [lookupName, setLookupName] = useState('');
   const [createName, setCreateName] = useState('');
-  const [createBalance, setCreateBalance] = useState('1000');
   const [loading, setLoading] = useState(false);
   const [creating, setCreating] = useState(false);
   const [error, setError] = useState<string | null>(null);
@@ -59,15 +58,9 @@ function Accounts() {
     setSuccess(null);
 
     try {
-      const balance = parseInt(createBalance, 10);
-      if (isNaN(balance) || balance < 0) {
-        throw new Error('Invalid balance');
-      }
-
-      const result = await api.createAccount(createName.trim(), balance);
-      setSuccess(`Account "${createName}" created! Transition #${result.sequence}, Proof: ${result.proof_size_bytes.toLocaleString()} bytes`);
+      const result = await api.createAccount(createName.trim());
+      setSuccess(`Account "${createName}" created! Transition #${result.sequence}, transaction ${result.index + 1} of ${result.batch_size}`);
       setCreateName('');
-      setCreateBalance('1000');
 
       // Auto-lookup the new account
       const accountData = await api.getAccount(createName.trim());
@@ -116,14 +109,6 @@ function Accounts() {
             onChange={(e) => setCreateName(e.target.value)}
             disabled={creating}
           />
-          <input
-            type="number"
-            placeholder="Initial balance"
-            value={createBalance}
-            onChange={(e) => setCreateBalance(e.target.value)}
-            disabled={creating}
-            min="0"
-          />
           <button onClick={createAccount} disabled={creating || !createName.trim()}>
             {creating ? 'Creating...' : 'Create Account'}
           </button>
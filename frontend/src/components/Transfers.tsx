@@ -1,6 +1,6 @@
 import { useState } from 'react';
 import api from '../api';
-import type { ApplyTransitionResponse } from '../api';
+import type { AppTransactionResponse } from '../api';
 
 interface TransferRecord {
   id: number;
@@ -9,7 +9,8 @@ interface TransferRecord {
   amount: number;
   sequence: number;
   celestiaHeight: number | null;
-  proofSize: number;
+  index: number;
+  batchSize: number;
   timestamp: Date;
 }
 
@@ -35,7 +36,7 @@ function Transfers() {
     setError(null);
 
     try {
-      const result: ApplyTransitionResponse = await api.transfer(from.trim(), to.trim(), amountNum);
+      const result: AppTransactionResponse = await api.transfer(from.trim(), to.trim(), amountNum);
 
       const record: TransferRecord = {
         id: nextId,
@@ -44,7 +45,8 @@ function Transfers() {
         amount: amountNum,
         sequence: result.sequence,
         celestiaHeight: result.celestia_height,
-        proofSize: result.proof_size_bytes,
+        index: result.index,
+        batchSize: result.batch_size,
         timestamp: new Date(),
       };
 
@@ -143,8 +145,8 @@ function Transfers() {
                     <span className="value">#{transfer.sequence}</span>
                   </span>
                   <span className="meta-item">
-                    <span className="label">Proof:</span>
-                    <span className="value">{(transfer.proofSize / 1024).toFixed(1)} KB</span>
+                    <span className="label">Batch:</span>
+                    <span className="value">{transfer.index + 1} of {transfer.batchSize}</span>
                   </span>
                   {transfer.celestiaHeight !== null && (
                     <span className="meta-item celestia">
@@ -166,8 +168,8 @@ function Transfers() {
         <h2>How Transfers Work</h2>
         <div className="info-content">
           <ol>
-            <li><strong>State Read:</strong> Current balances and nonces are fetched with Merkle proofs</li>
-            <li><strong>Validation:</strong> Sender balance is checked, new states are computed</li>
+            <li><strong>Signing:</strong> The sender's key in this browser signs the transfer and its current nonce</li>
+            <li><strong>Validation:</strong> The node checks the signature and balance, and computes the new states</li>
             <li><strong>ZK Proof:</strong> SP1 generates a proof that the transition is valid</li>
             <li><strong>State Commit:</strong> New Merkle root is computed and stored</li>
             <li><strong>DA Post:</strong> Proof blob is posted to Celestia for permanent availability</li>